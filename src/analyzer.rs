@@ -0,0 +1,219 @@
+use crate::{
+    estimate::move_length,
+    profile::PrinterProfile,
+    state::{SpindleDirection, Step},
+    Command, GCodeModel, Id, Tag,
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Settings for reading a file as laser gcode, where S words on M3/M4
+/// and G1 set the laser power rather than a spindle speed
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LaserProfile {
+    /// S value for full power, 1000 on GRBL and 255 on Marlin
+    pub max_s: u32,
+    /// optical output at full power in watts
+    pub watts: f64,
+}
+
+impl LaserProfile {
+    /// GRBL style profile as targeted by LightBurn, S0-S1000
+    pub fn grbl(watts: f64) -> Self {
+        LaserProfile { max_s: 1000, watts }
+    }
+    /// fraction of full power for an S value
+    pub fn power(&self, s: u32) -> f64 {
+        (s as f64 / self.max_s.max(1) as f64).min(1.0)
+    }
+}
+
+/// Laser output over a single burning move
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct PowerSegment {
+    pub id: Id,
+    /// distance along all burning moves before this one, in mm
+    pub start: f64,
+    /// length of the move in mm
+    pub length: f64,
+    /// fraction of full power
+    pub power: f64,
+    /// M4 dynamic mode, where firmware scales power with actual speed
+    pub dynamic: bool,
+    /// energy delivered per mm of path in J/mm
+    pub energy_per_mm: f64,
+    /// energy delivered over the move in J
+    pub energy: f64,
+}
+
+/// Power over the path of a laser file
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PowerReport {
+    pub segments: Vec<PowerSegment>,
+    /// total length of moves with the laser on, in mm
+    pub burn_length: f64,
+    /// total length of moves with the laser off, in mm
+    pub travel_length: f64,
+    /// total energy delivered in J
+    pub total_energy: f64,
+}
+
+fn laser_segment(step: &Step, profile: &LaserProfile, start: f64) -> Option<PowerSegment> {
+    let state = &step.next;
+    let power = profile.power(state.spindle.speed);
+    let mm_per_sec = state.feedrate.mm_per_sec();
+    if state.spindle.direction == SpindleDirection::Off || power <= 0.0 || mm_per_sec <= 0.0 {
+        return None;
+    }
+    let length = move_length(&step.prev, state);
+    // at constant commanded speed dynamic power matches constant power,
+    // the difference only shows up while accelerating
+    let energy_per_mm = power * profile.watts / mm_per_sec;
+    Some(PowerSegment {
+        id: step.line.id,
+        start,
+        length,
+        power,
+        dynamic: state.spindle.direction == SpindleDirection::CounterClockwise,
+        energy_per_mm,
+        energy: energy_per_mm * length,
+    })
+}
+
+impl GCodeModel {
+    /// Interpret the file as laser gcode and report power and energy
+    /// delivered along the toolpath
+    pub fn laser_report(&self, profile: &LaserProfile) -> PowerReport {
+        let mut report = PowerReport::default();
+        for step in self.cursor() {
+            let burn = match step.line.command {
+                // rapids never fire the laser
+                Command::G0(_) => None,
+                Command::G1(_) => laser_segment(&step, profile, report.burn_length),
+                _ => continue,
+            };
+            match burn {
+                Some(segment) => {
+                    report.burn_length += segment.length;
+                    report.total_energy += segment.energy;
+                    report.segments.push(segment);
+                }
+                None => report.travel_length += move_length(&step.prev, &step.next),
+            }
+        }
+        report
+    }
+}
+
+/// Extrusion width implied by the filament pushed over one move
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct WidthEstimate {
+    pub id: Id,
+    /// height of the layer the move is in, in mm
+    pub layer_height: f64,
+    /// estimated width of the extruded line, in mm
+    pub width: f64,
+}
+
+/// Width of a line laid down with `area` mm² cross section at `height`,
+/// using the slicer model of a rectangle with semicircular sides
+fn line_width(area: f64, height: f64) -> f64 {
+    area / height + height * (1.0 - std::f64::consts::FRAC_PI_4)
+}
+
+impl GCodeModel {
+    /// Estimate the width of every extrusion move from its filament use,
+    /// length, and the height of its layer
+    pub fn extrusion_widths(&self, profile: &PrinterProfile) -> Vec<WidthEstimate> {
+        let filament_area = std::f64::consts::PI * (profile.filament_diameter / 2.0).powi(2);
+        let mut heights = vec![0.0; self.lines.len()];
+        let mut prev_z = 0.0;
+        for layer in self.layers() {
+            let z = layer.z.to_mm();
+            heights[layer.range()].fill(z - prev_z);
+            prev_z = z;
+        }
+        self.cursor()
+            .enumerate()
+            .filter(|(_, step)| step.line.command.tag() == Tag::Extrusion)
+            .filter_map(|(i, step)| {
+                let length = move_length(&step.prev, &step.next);
+                let layer_height = heights[i];
+                if length <= 0.0 || layer_height <= 0.0 {
+                    return None;
+                }
+                let volume = (step.next.e - step.prev.e).to_mm() * filament_area;
+                Some(WidthEstimate {
+                    id: step.line.id,
+                    layer_height,
+                    width: line_width(volume / length, layer_height),
+                })
+            })
+            .collect()
+    }
+    /// Extrusion moves narrower than `min_ratio` or wider than `max_ratio`
+    /// times the nozzle diameter, e.g. 0.5 and 2.5, which points to flow
+    /// or slicing bugs
+    pub fn abnormal_widths(
+        &self,
+        profile: &PrinterProfile,
+        min_ratio: f64,
+        max_ratio: f64,
+    ) -> Vec<WidthEstimate> {
+        let nozzle = profile.nozzle_diameter;
+        self.extrusion_widths(profile)
+            .into_iter()
+            .filter(|w| w.width < nozzle * min_ratio || w.width > nozzle * max_ratio)
+            .collect()
+    }
+}
+
+#[test]
+fn extrusion_width_test() {
+    let profile = PrinterProfile::prusa_mk4();
+    let filament_area = std::f64::consts::PI * (1.75_f64 / 2.0).powi(2);
+    // E needed for a 0.45mm wide, 0.2mm high, 100mm long line
+    let e = |width: f64, height: f64, length: f64| {
+        let area = (width - height) * height + std::f64::consts::PI * (height / 2.0).powi(2);
+        area * length / filament_area
+    };
+    let input = format!(
+        "M83\nG1 Z0.2\nG1 X100 E{:.5}\nG1 Y100 E{:.5}\nG1 Z0.4\nG1 X0 E{:.5}",
+        e(0.45, 0.2, 100.0),
+        e(1.5, 0.2, 100.0),
+        e(0.45, 0.2, 100.0)
+    );
+    let gcode: GCodeModel = input.parse().unwrap();
+    let widths = gcode.extrusion_widths(&profile);
+    assert_eq!(widths.len(), 3);
+    assert!((widths[0].width - 0.45).abs() < 1e-3);
+    assert!((widths[2].layer_height - 0.2).abs() < 1e-9);
+    let abnormal = gcode.abnormal_widths(&profile, 0.5, 2.5);
+    assert_eq!(abnormal.len(), 1);
+    assert_eq!(abnormal[0].id, Id(3));
+}
+
+#[test]
+fn laser_report_test() {
+    let gcode: GCodeModel = "M4 S0\nG1 X10 F600\nG1 X20 S500\nG1 Y10 S1000\nM5\nG1 X0 Y0"
+        .parse()
+        .unwrap();
+    let report = gcode.laser_report(&LaserProfile::grbl(10.0));
+    assert_eq!(report.segments.len(), 2);
+    assert_eq!(report.burn_length, 20.0);
+    // 10mm of travel at S0 and the diagonal home with the laser off
+    assert_eq!(report.travel_length, 10.0 + 20.0_f64.hypot(10.0));
+    let half = &report.segments[0];
+    assert_eq!(half.id, Id(2));
+    assert!(half.dynamic);
+    assert_eq!(half.power, 0.5);
+    // 5W at 10mm/s is 0.5J/mm
+    assert_eq!(half.energy_per_mm, 0.5);
+    assert_eq!(report.segments[1].start, 10.0);
+    assert_eq!(report.total_energy, 5.0 + 10.0);
+}
@@ -0,0 +1,248 @@
+use crate::{
+    state::MachineState, Annotations, Command, ExtrusionLength, Feedrate, GCodeLine, GCodeModel,
+    Length, Tag, G1,
+};
+use std::f64::consts::TAU;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Struct to store G2 (clockwise) and G3 (counter-clockwise) arc params.
+/// The centre is either offset from the start of the move by `I`/`J`, or
+/// found from the radius `R`, where a negative radius picks the arc longer
+/// than a half circle. `Z` moves along the arc to make a helix.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ArcMove {
+    pub clockwise: bool,
+    pub x: Option<Length>,
+    pub y: Option<Length>,
+    pub z: Option<Length>,
+    pub i: Option<Length>,
+    pub j: Option<Length>,
+    pub r: Option<Length>,
+    pub e: Option<ExtrusionLength>,
+    pub f: Option<Feedrate>,
+    pub tag: Tag,
+}
+
+/// Centre, radius, start angle and signed sweep in radians of an arc
+struct Geometry {
+    center: (f64, f64),
+    radius: f64,
+    start: f64,
+    sweep: f64,
+}
+
+impl ArcMove {
+    /// Shape of the arc when run from `start`, the way Marlin reads it
+    fn geometry(&self, start: &MachineState) -> Geometry {
+        let mut end = *start;
+        end.apply(&Command::Arc(self.clone()));
+        let mm = |m: Length| m.to_mm();
+        let p0 = (mm(start.pos.x), mm(start.pos.y));
+        let p1 = (mm(end.pos.x), mm(end.pos.y));
+        let center = match self.r {
+            Some(r) if self.i.is_none() && self.j.is_none() => {
+                let r = r.to_mm();
+                let (dx, dy) = (p1.0 - p0.0, p1.1 - p0.1);
+                let d = dx.hypot(dy);
+                let h = ((r - 0.5 * d) * (r + 0.5 * d)).max(0.0).sqrt();
+                // the centre is to the right of the chord for a short
+                // clockwise arc, and to the left otherwise
+                let side = if self.clockwise ^ (r < 0.0) {
+                    -1.0
+                } else {
+                    1.0
+                };
+                let (sx, sy) = if d > 0.0 {
+                    (-dy / d, dx / d)
+                } else {
+                    (0.0, 0.0)
+                };
+                let mid = ((p0.0 + p1.0) / 2.0, (p0.1 + p1.1) / 2.0);
+                (mid.0 + side * h * sx, mid.1 + side * h * sy)
+            }
+            _ => {
+                let offset = |m: Option<Length>| m.map(mm).unwrap_or(0.0);
+                (p0.0 + offset(self.i), p0.1 + offset(self.j))
+            }
+        };
+        let (ax, ay) = (p0.0 - center.0, p0.1 - center.1);
+        let (bx, by) = (p1.0 - center.0, p1.1 - center.1);
+        let mut sweep = (ax * by - ay * bx).atan2(ax * bx + ay * by);
+        if sweep < 0.0 {
+            sweep += TAU;
+        }
+        if self.clockwise {
+            sweep -= TAU;
+        }
+        // ending where it starts is a full circle
+        if sweep == 0.0 && p0 == p1 {
+            sweep = TAU;
+        }
+        Geometry {
+            center,
+            radius: ax.hypot(ay),
+            start: ay.atan2(ax),
+            sweep,
+        }
+    }
+    /// Length in mm of the arc, or helix if it also moves Z, when run from
+    /// `start`
+    pub fn length(&self, start: &MachineState) -> f64 {
+        let Geometry { radius, sweep, .. } = self.geometry(start);
+        let mut end = *start;
+        end.apply(&Command::Arc(self.clone()));
+        (radius * sweep.abs()).hypot((end.pos.z - start.pos.z).to_mm())
+    }
+    /// Whether the arc moves the toolhead in XY when run from `start`,
+    /// which a full circle does even though it ends where it started
+    pub(crate) fn is_planar(&self, start: &MachineState) -> bool {
+        let Geometry { radius, sweep, .. } = self.geometry(start);
+        radius * sweep.abs() > 0.0
+    }
+    /// Split the arc into G1 moves no longer than `segment` starting from
+    /// `start`, written in the positioning modes of `start`, with Z and E
+    /// shared out evenly along it
+    pub fn linearize(&self, start: &MachineState, segment: Length) -> Vec<G1> {
+        let mut end = *start;
+        end.apply(&Command::Arc(self.clone()));
+        let Geometry {
+            center,
+            radius,
+            start: angle,
+            sweep,
+        } = self.geometry(start);
+        let segments = (self.length(start) / segment.to_mm().max(0.001))
+            .ceil()
+            .max(1.0) as usize;
+        let (dz, de) = (end.pos.z - start.pos.z, end.e - start.e);
+        let mut out = Vec::with_capacity(segments);
+        let mut from = *start;
+        for n in 1..=segments {
+            let t = n as f64 / segments as f64;
+            let (pos, e) = if n == segments {
+                // snap to the exact end so nothing drifts
+                (end.pos, end.e)
+            } else {
+                let a = angle + sweep * t;
+                let mut pos = end.pos;
+                pos.x = Length::from(center.0 + radius * a.cos());
+                pos.y = Length::from(center.1 + radius * a.sin());
+                pos.z = start.pos.z + Length::from(dz.to_mm() * t);
+                (pos, start.e + ExtrusionLength::from_mm(de.to_mm() * t))
+            };
+            let mut g1 = from.move_to(pos, self.e.map(|_| e));
+            g1.f = if n == 1 { self.f } else { None };
+            from.apply(&Command::G1(g1.clone()));
+            out.push(g1);
+        }
+        out
+    }
+}
+
+impl GCodeModel {
+    /// Replace every G2/G3 arc with G1 moves no longer than `segment`,
+    /// for firmware without arc support or analyses that need straight
+    /// moves. Comments stay on the first piece.
+    pub fn linearize_arcs(&mut self, segment: Length) {
+        self.record_transform("linearize_arcs", segment.to_string());
+        let mut state = MachineState::default();
+        let mut lines = Vec::with_capacity(self.lines.len());
+        for line in std::mem::take(&mut self.lines) {
+            let prev = state;
+            state.apply(&line.command);
+            let Command::Arc(arc) = &line.command else {
+                lines.push(line);
+                continue;
+            };
+            for (n, g1) in arc.linearize(&prev, segment).into_iter().enumerate() {
+                let first = n == 0;
+                lines.push(GCodeLine {
+                    id: if first {
+                        line.id
+                    } else {
+                        self.id_counter.get()
+                    },
+                    command: Command::G1(g1),
+                    comments: if first {
+                        line.comments.clone()
+                    } else {
+                        String::new()
+                    },
+                    annotations: if first {
+                        line.annotations.clone()
+                    } else {
+                        Annotations::default()
+                    },
+                });
+            }
+        }
+        self.lines = lines;
+        self.tag_g1();
+    }
+}
+
+#[test]
+fn arc_test() {
+    use crate::emit::Emit;
+    let mut gcode: GCodeModel =
+        "G1 X10 Y0 F600\nG3 X0 Y10 I-10 J0 E1.5 ; quarter\nG2 X10 Y0 R-10\nG2 I5"
+            .parse()
+            .unwrap();
+    assert_eq!(
+        gcode.emit(false),
+        "G1 X10 Y0 F600 \nG3 X0 Y10 I-10 J0 E1.5 ; quarter\nG2 X10 Y0 R-10 \nG2 I5 \n"
+    );
+    let steps = gcode.cursor().collect::<Vec<_>>();
+    assert_eq!(steps[1].line.command.tag(), Tag::Extrusion);
+    assert_eq!(steps[1].next.pos.y, Length::from(10.0));
+    let length = |i: usize| match &steps[i].line.command {
+        Command::Arc(arc) => arc.length(&steps[i].prev),
+        _ => unreachable!(),
+    };
+    let quarter = TAU * 10.0 / 4.0;
+    assert!((length(1) - quarter).abs() < 1e-9);
+    // R-10 back the long way round
+    assert!((length(2) - 3.0 * quarter).abs() < 1e-6);
+    // a full circle about a point 5mm away
+    assert!((length(3) - TAU * 5.0).abs() < 1e-9);
+
+    gcode.linearize_arcs(Length::from(1.0));
+    let pieces = gcode.lines[1..17]
+        .iter()
+        .filter(|line| line.command.tag() == Tag::Extrusion)
+        .count();
+    assert_eq!(pieces, 16);
+    assert_eq!(gcode.lines[1].comments, " quarter");
+    let emitted = gcode.emit(false);
+    assert!(emitted.lines().nth(16) == Some("G1 X0 Y10 E1.5 "));
+    assert!(!emitted.contains("G2") && !emitted.contains("G3"));
+
+    // full circle counts aren't modelled, so those arcs stay as written
+    let input = "G2 X10 Y10 I5 J0 P2\nG3 X1 Y1 K2\n";
+    let gcode: GCodeModel = input.parse().unwrap();
+    assert_eq!(
+        gcode.lines[0].command,
+        Command::Raw(String::from("G2 X10 Y10 I5 J0 P2"))
+    );
+    assert!(matches!(gcode.lines[1].command, Command::Raw(_)));
+    assert_eq!(gcode.emit(false), input);
+}
+
+#[test]
+fn full_circle_test() {
+    use crate::{geometry::Geometry, profile::PrinterProfile};
+    let gcode: GCodeModel = "G1 X10 Y0 F600\nG2 X10 Y0 I-10 J0 E5\nG2 I-5"
+        .parse()
+        .unwrap();
+    // an extruding circle ends where it started but still lays down filament
+    assert_eq!(gcode.lines[1].command.tag(), Tag::Extrusion);
+    assert_eq!(gcode.lines[2].command.tag(), Tag::Travel);
+    assert!(gcode.bounds(Geometry::Cartesian).is_some());
+    // the planner times circles by their length rather than their chord
+    let time = gcode.estimate_time_for(&PrinterProfile::ender_3());
+    let circles = TAU * 15.0 / 10.0;
+    assert!(time.as_secs_f64() > 1.0 + circles);
+}
@@ -0,0 +1,265 @@
+use crate::{
+    file::{decode, Decoding},
+    geometry::Bounds,
+    profile::PrinterProfile,
+    Command, CompatibilityInfo, ExtrusionLength, GCodeModel, GCodeParseError,
+};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// What `analyze` computes for each file
+#[derive(Clone, Debug, Default)]
+pub struct AnalysisConfig {
+    /// printer used for the time estimate and bounds, or commanded
+    /// feedrates and cartesian geometry if `None`
+    pub profile: Option<PrinterProfile>,
+    /// number of worker threads, 0 or 1 to analyze on the calling thread
+    pub threads: usize,
+    /// how each file's bytes are turned into text, as for
+    /// `GCodeModel::from_file_with`
+    pub decoding: Decoding,
+}
+
+/// Summary of one file
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stats {
+    pub lines: usize,
+    pub layers: usize,
+    pub estimated_time: Duration,
+    /// filament used, net of retractions, in mm
+    pub filament: f64,
+    pub bounds: Option<Bounds>,
+    pub compatibility: CompatibilityInfo,
+}
+
+/// Why a file couldn't be analyzed
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Utf8(std::string::FromUtf8Error),
+    Parse(GCodeParseError),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "failed to read file: {e}"),
+            Error::Utf8(e) => write!(f, "file is not valid UTF-8: {e}"),
+            Error::Parse(e) => write!(f, "failed to parse file: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<std::string::FromUtf8Error> for Error {
+    fn from(e: std::string::FromUtf8Error) -> Self {
+        Error::Utf8(e)
+    }
+}
+
+impl From<GCodeParseError> for Error {
+    fn from(e: GCodeParseError) -> Self {
+        Error::Parse(e)
+    }
+}
+
+impl GCodeModel {
+    /// Filament pushed over the whole file in mm, net of retractions,
+    /// counting across `G92 E` resets
+    pub fn filament_used(&self) -> ExtrusionLength {
+        self.extrusion_deltas().into_iter().sum()
+    }
+    /// Filament pushed by each tool, like `filament_used`, for files
+    /// printed with more than one extruder
+    pub fn filament_per_tool(&self) -> BTreeMap<u8, ExtrusionLength> {
+        let mut used = BTreeMap::new();
+        for (step, e) in self.cursor().zip(self.extrusion_deltas()) {
+            *used.entry(step.prev.tool).or_default() += e;
+        }
+        used
+    }
+    /// Filament pushed by each line, parallel to `lines`
+    pub(crate) fn extrusion_deltas(&self) -> Vec<ExtrusionLength> {
+        self.cursor()
+            .map(|step| match step.line.command {
+                // G92 E changes E without moving
+                Command::G92(_) => ExtrusionLength::ZERO,
+                _ => step.next.e - step.prev.e,
+            })
+            .collect()
+    }
+    /// Summary statistics of the model, see `batch::analyze`
+    pub fn stats(&self, profile: Option<&PrinterProfile>) -> Stats {
+        Stats {
+            lines: self.lines.len(),
+            layers: self.layers().len(),
+            estimated_time: match profile {
+                Some(profile) => self.estimate_time_for(profile),
+                None => self.estimate_time(),
+            },
+            filament: self.filament_used().to_mm(),
+            bounds: self.bounds(profile.map(|p| p.geometry).unwrap_or_default()),
+            compatibility: self.compatibility(),
+        }
+    }
+    /// Compute `stats` on a new thread that shares the model instead of
+    /// copying it, leaving the caller free to keep reading it meanwhile
+    pub fn spawn_stats(self: Arc<Self>, profile: Option<PrinterProfile>) -> JoinHandle<Stats> {
+        std::thread::spawn(move || self.stats(profile.as_ref()))
+    }
+}
+
+pub(crate) fn analyze_file(path: &Path, config: &AnalysisConfig) -> Result<Stats, Error> {
+    let model: GCodeModel = decode(std::fs::read(path)?, config.decoding)?.parse()?;
+    Ok(model.stats(config.profile.as_ref()))
+}
+
+/// Read, parse and summarize every file in `paths`, returning the results
+/// in the same order. With `config.threads` above 1 the files are shared
+/// out between that many threads as each one finishes its last file.
+pub fn analyze(
+    paths: impl IntoIterator<Item = impl Into<PathBuf>>,
+    config: &AnalysisConfig,
+) -> Vec<(PathBuf, Result<Stats, Error>)> {
+    let paths = paths.into_iter().map(Into::into).collect::<Vec<PathBuf>>();
+    let threads = config.threads.min(paths.len());
+    if threads <= 1 {
+        return paths
+            .into_iter()
+            .map(|path| {
+                let result = analyze_file(&path, config);
+                (path, result)
+            })
+            .collect();
+    }
+    let next = AtomicUsize::new(0);
+    let mut results = std::thread::scope(|scope| {
+        let workers = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(path) = paths.get(i) else {
+                            return done;
+                        };
+                        done.push((i, analyze_file(path, config)));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("analysis thread panicked"))
+            .collect::<Vec<_>>()
+    });
+    results.sort_by_key(|(i, _)| *i);
+    paths
+        .into_iter()
+        .zip(results)
+        .map(|(path, (_, result))| (path, result))
+        .collect()
+}
+
+#[test]
+fn filament_used_test() {
+    let gcode: GCodeModel = "G1 X10 E5\nG1 E4\nG1 X20 E6\nG92 E0\nG1 X30 E2\nM83\nG1 X40 E1"
+        .parse()
+        .unwrap();
+    assert_eq!(gcode.filament_used(), ExtrusionLength::from_mm(9.0));
+
+    let gcode: GCodeModel = "M83\nT0\nG1 X10 E1\nT1\nG1 X20 E2\nG1 E-0.5\nT0\nG1 X30 E1"
+        .parse()
+        .unwrap();
+    assert_eq!(gcode.lines[3].command, Command::ToolChange(1));
+    assert_eq!(
+        gcode.filament_per_tool().into_iter().collect::<Vec<_>>(),
+        [
+            (0, ExtrusionLength::from_mm(2.0)),
+            (1, ExtrusionLength::from_mm(1.5))
+        ]
+    );
+}
+
+#[test]
+fn spawn_stats_test() {
+    let model: Arc<GCodeModel> = Arc::new("G1 X10 E1 F600\nG1 X20 E2".parse().unwrap());
+    let workers = [None, Some(PrinterProfile::ender_3())]
+        .map(|profile| Arc::clone(&model).spawn_stats(profile));
+    let expected = [
+        model.stats(None),
+        model.stats(Some(&PrinterProfile::ender_3())),
+    ];
+    let stats = workers.map(|worker| worker.join().unwrap());
+    assert_eq!(stats, expected);
+    assert_eq!(Arc::strong_count(&model), 1);
+}
+
+#[test]
+fn batch_test() {
+    let dir = std::env::temp_dir().join(format!("g-win-batch-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut paths = Vec::new();
+    for i in 0..5 {
+        let path = dir.join(format!("{i}.gcode"));
+        std::fs::write(&path, format!("G1 Z0.2 F600\nG1 X{} E1", 10 * (i + 1))).unwrap();
+        paths.push(path);
+    }
+    paths.insert(2, dir.join("missing.gcode"));
+    let config = AnalysisConfig {
+        threads: 3,
+        ..Default::default()
+    };
+    let results = analyze(&paths, &config);
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(
+        results.iter().map(|(path, _)| path).collect::<Vec<_>>(),
+        paths.iter().collect::<Vec<_>>()
+    );
+    assert!(matches!(results[2].1, Err(Error::Io(_))));
+    let stats = results[4].1.as_ref().unwrap();
+    assert_eq!(stats.lines, 2);
+    assert_eq!(stats.filament, 1.0);
+    // 0.2mm of Z and 40mm of X at 10mm/s
+    assert_eq!(stats.estimated_time, Duration::from_secs_f64(4.02));
+
+    // files are decoded the same way `GCodeModel::from_file_with` does
+    let dir = std::env::temp_dir().join(format!("g-win-decode-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (bom, latin) = (dir.join("bom.gcode"), dir.join("latin.gcode"));
+    std::fs::write(&bom, b"\xEF\xBB\xBFG1 X10 E1").unwrap();
+    std::fs::write(&latin, b"G1 X10 E1 ; caf\xE9").unwrap();
+    let paths = [bom, latin];
+    let strict = analyze(&paths, &AnalysisConfig::default());
+    let lossy = AnalysisConfig {
+        decoding: Decoding {
+            lossy: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let lossy = analyze(&paths, &lossy);
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(strict[0].1.as_ref().unwrap().filament, 1.0);
+    assert!(matches!(strict[1].1, Err(Error::Utf8(_))));
+    assert_eq!(lossy[1].1.as_ref().unwrap().filament, 1.0);
+}
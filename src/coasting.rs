@@ -0,0 +1,369 @@
+use crate::{
+    state::{MachineState, Position, Positioning},
+    Annotations, Command, ExtrusionLength, GCodeLine, GCodeModel, Length, Tag, G1, G92,
+};
+
+/// Firmware independent treatment for the end of each extrusion run,
+/// to cut down on stringing and blobs
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AntiStringing {
+    /// Stop extruding `distance` before the end of each run and travel the
+    /// rest of the way, letting the pressure left in the nozzle finish the line
+    Coast { distance: Length },
+    /// Spread the retraction after each run over a move back along the
+    /// path that was just printed, at most `distance` long, turning plain
+    /// retractions into wipes without reslicing
+    WipeWhileRetract { distance: Length },
+    /// Like `WipeWhileRetract`, but only back along the last segment of
+    /// the run, so each retraction becomes one straight wipe of at most
+    /// `distance`
+    WipeLastSegment { distance: Length },
+}
+
+/// Point a fraction `t` of the way from `a` to `b`
+fn lerp(a: Position, b: Position, t: f64) -> Position {
+    let axis = |a: Length, b: Length| {
+        a + Length::from_nanos(((b - a).as_nanos() as f64 * t).round() as i64)
+    };
+    Position {
+        x: axis(a.x, b.x),
+        y: axis(a.y, b.y),
+        z: axis(a.z, b.z),
+    }
+}
+
+fn distance(a: Position, b: Position) -> f64 {
+    let dx = (b.x - a.x).to_mm();
+    let dy = (b.y - a.y).to_mm();
+    let dz = (b.z - a.z).to_mm();
+    (dx * dx + dy * dy + dz * dz).sqrt()
+}
+
+fn moves(step: &(MachineState, MachineState)) -> bool {
+    step.0.pos != step.1.pos || step.0.e != step.1.e
+}
+
+impl GCodeModel {
+    /// Apply `method` to the end of every run of consecutive extrusion moves,
+    /// returning the number of runs changed.
+    ///
+    /// Coasting keeps the toolhead path as is. In absolute E mode every
+    /// later E, up to the next `G92 E`, is moved down by the filament held
+    /// back, so the retraction after the run stays the same length as in
+    /// relative E mode. Wiping moves the toolhead, so runs printed with
+    /// relative XYZ positioning are left alone.
+    pub fn anti_stringing(&mut self, method: AntiStringing) -> usize {
+        self.record_transform("anti_stringing", format!("{method:?}"));
+        let states = self
+            .cursor()
+            .map(|step| (step.prev, step.next))
+            .collect::<Vec<_>>();
+        let is_extrusion =
+            |line: &GCodeLine| matches!(&line.command, Command::G1(g1) if g1.tag == Tag::Extrusion);
+        // each run as its extrusion line indices, last line first
+        let mut runs: Vec<Vec<usize>> = Vec::new();
+        let mut run = Vec::new();
+        for (i, line) in self.lines.iter().enumerate() {
+            if is_extrusion(line) {
+                run.push(i);
+            } else if moves(&states[i]) && !run.is_empty() {
+                run.reverse();
+                runs.push(std::mem::take(&mut run));
+            }
+        }
+        if !run.is_empty() {
+            run.reverse();
+            runs.push(run);
+        }
+
+        let mut changed = 0;
+        // last run first, as a coast or wipe may add lines after it
+        for run in runs.iter().rev() {
+            let done = match method {
+                AntiStringing::Coast { distance } => self.coast(run, &states, distance),
+                AntiStringing::WipeWhileRetract { distance } => self.wipe(run, &states, distance),
+                AntiStringing::WipeLastSegment { distance } => {
+                    self.wipe(&run[..1], &states, distance)
+                }
+            };
+            if done {
+                changed += 1;
+            }
+        }
+        self.tag_g1();
+        changed
+    }
+    fn coast(
+        &mut self,
+        run: &[usize],
+        states: &[(MachineState, MachineState)],
+        coast: Length,
+    ) -> bool {
+        let mut remaining = coast.to_mm();
+        if remaining <= 0.0 {
+            return false;
+        }
+        // filament no longer extruded, and where the lines after the run start
+        let mut withheld = ExtrusionLength::ZERO;
+        let mut after = run[0] + 1;
+        for &i in run {
+            let (prev, next) = states[i];
+            let Command::G1(G1 { f, s, .. }) = self.lines[i].command else {
+                continue;
+            };
+            let length = distance(prev.pos, next.pos);
+            if length <= remaining {
+                self.lines[i].command = Command::G1(G1 {
+                    f,
+                    s,
+                    ..prev.move_to(next.pos, None)
+                });
+                withheld += next.e - prev.e;
+                remaining -= length;
+                continue;
+            }
+            let t = (length - remaining) / length;
+            let mut mid = prev;
+            mid.pos = lerp(prev.pos, next.pos, t);
+            mid.e = prev.e + ExtrusionLength::from_mm((next.e - prev.e).to_mm() * t);
+            self.lines[i].command = Command::G1(G1 {
+                f,
+                s,
+                ..prev.move_to(mid.pos, Some(mid.e))
+            });
+            self.lines.insert(
+                i + 1,
+                GCodeLine {
+                    id: self.id_counter.get(),
+                    command: Command::G1(mid.move_to(next.pos, None)),
+                    comments: String::from(" coast"),
+                    annotations: Annotations::default(),
+                },
+            );
+            withheld += next.e - mid.e;
+            after += 1;
+            break;
+        }
+        let (_, end) = states[run[0]];
+        if end.e_positioning == Positioning::Absolute {
+            self.shift_e(after, withheld);
+        }
+        true
+    }
+    /// Move every absolute E from line `from` on down by `withheld`, up to
+    /// the next `G92 E` that resets the extruder position
+    fn shift_e(&mut self, from: usize, withheld: ExtrusionLength) {
+        let mut absolute = true;
+        for line in &mut self.lines[from..] {
+            let e = match &mut line.command {
+                Command::G92(G92 { e: Some(_), .. }) => break,
+                Command::M82 => {
+                    absolute = true;
+                    continue;
+                }
+                Command::M83 => {
+                    absolute = false;
+                    continue;
+                }
+                Command::G0(G1 { e, .. })
+                | Command::G1(G1 { e, .. })
+                | Command::G53(Some(G1 { e, .. }), _) => e,
+                Command::G5(g5) => &mut g5.e,
+                Command::Arc(arc) => &mut arc.e,
+                _ => continue,
+            };
+            if let (Some(e), true) = (e, absolute) {
+                *e = *e - withheld;
+            }
+        }
+    }
+    fn wipe(
+        &mut self,
+        run: &[usize],
+        states: &[(MachineState, MachineState)],
+        wipe: Length,
+    ) -> bool {
+        let end = run[0];
+        let Some(retract) = (end + 1..self.lines.len()).find(|&i| moves(&states[i])) else {
+            return false;
+        };
+        let (start, after) = states[retract];
+        // a G0 retraction is taken over like a G1 one, the wipe being a
+        // printing move either way
+        let command = &self.lines[retract].command;
+        let Some(&G1 { f, .. }) = command.as_move() else {
+            return false;
+        };
+        if command.tag() != Tag::Retraction || start.positioning != Positioning::Absolute {
+            return false;
+        }
+        // points back along the run, the last one cut short at the wipe distance
+        let mut remaining = wipe.to_mm();
+        let mut points = Vec::new();
+        for &i in run {
+            if remaining <= 0.0 {
+                break;
+            }
+            let (prev, next) = states[i];
+            let length = distance(next.pos, prev.pos);
+            if length > remaining {
+                points.push((lerp(next.pos, prev.pos, remaining / length), remaining));
+            } else if length > 0.0 {
+                points.push((prev.pos, length));
+            }
+            remaining -= length;
+        }
+        let total = points.iter().map(|(_, length)| length).sum::<f64>();
+        if total <= 0.0 {
+            return false;
+        }
+
+        let retraction = self.lines.remove(retract);
+        let de = (after.e - start.e).to_mm();
+        let mut state = start;
+        let mut done = 0.0;
+        let count = points.len();
+        for (n, (pos, length)) in points.into_iter().enumerate() {
+            done += length;
+            let e = if n + 1 == count {
+                after.e
+            } else {
+                start.e + ExtrusionLength::from_mm(de * done / total)
+            };
+            let g1 = state.move_to(pos, Some(e));
+            state.pos = pos;
+            state.e = e;
+            let first = n == 0;
+            self.lines.insert(
+                retract + n,
+                GCodeLine {
+                    id: if first {
+                        retraction.id
+                    } else {
+                        self.id_counter.get()
+                    },
+                    command: Command::G1(G1 {
+                        f: if first { f } else { None },
+                        ..g1
+                    }),
+                    comments: if first {
+                        retraction.comments.clone()
+                    } else {
+                        String::new()
+                    },
+                    annotations: if first {
+                        retraction.annotations.clone()
+                    } else {
+                        Annotations::default()
+                    },
+                },
+            );
+        }
+        true
+    }
+}
+
+#[test]
+fn wipe_last_segment_test() {
+    use crate::emit::Emit;
+    let input = "M83\nG1 X0 Y0\nG1 X10 E1\nG1 X12 E0.2\nG1 E-0.8 F2100 ; retract\nG1 X50 Y50";
+    let mut gcode: GCodeModel = input.parse().unwrap();
+    let distance = Length::from(5.0);
+    assert_eq!(
+        gcode.anti_stringing(AntiStringing::WipeLastSegment { distance }),
+        1
+    );
+    // stops at the start of the 2mm segment rather than turning the corner
+    assert_eq!(gcode.lines.len(), 6);
+    assert_eq!(gcode.lines[4].emit(false), "G1 X10 E-0.8 F2100 ; retract");
+    assert_eq!(gcode.lines[4].command.tag(), Tag::Wipe);
+
+    // and is cut short on a long one
+    let mut gcode: GCodeModel = input.parse().unwrap();
+    let distance = Length::from(1.5);
+    gcode.anti_stringing(AntiStringing::WipeLastSegment { distance });
+    assert_eq!(gcode.lines[4].emit(false), "G1 X10.5 E-0.8 F2100 ; retract");
+}
+
+#[test]
+fn coast_test() {
+    let input = "M83\nG1 X0 Y0\nG1 X10 E1\nG1 X20 E1\nG1 E-0.8 F2100\nG1 X50 Y50";
+    let mut gcode: GCodeModel = input.parse().unwrap();
+    let distance = Length::from(5.0);
+    assert_eq!(gcode.anti_stringing(AntiStringing::Coast { distance }), 1);
+    assert_eq!(gcode.lines.len(), 7);
+    let states = gcode.cursor().map(|step| step.next).collect::<Vec<_>>();
+    assert_eq!(states[3].pos.x, Length::from(15.0));
+    assert_eq!(states[3].e, ExtrusionLength::from_mm(1.5));
+    assert_eq!(gcode.lines[4].command.tag(), Tag::Travel);
+    assert_eq!(states[4].pos.x, Length::from(20.0));
+
+    // a long coast turns whole moves into travel
+    let mut gcode: GCodeModel = input.parse().unwrap();
+    let distance = Length::from(15.0);
+    gcode.anti_stringing(AntiStringing::Coast { distance });
+    let states = gcode.cursor().map(|step| step.next).collect::<Vec<_>>();
+    assert_eq!(states[2].pos.x, Length::from(5.0));
+    assert_eq!(states[4].e, ExtrusionLength::from_mm(0.5));
+
+    // with absolute E the retraction after a coast longer than it keeps
+    // its length, and later moves are shifted down to match
+    let input = "M82\nG1 X0 Y0\nG1 X10 E1\nG1 X20 E2\nG1 E1.2\nG1 X50\nG1 E2.2\nG92 E0\nG1 E0.5";
+    let mut gcode: GCodeModel = input.parse().unwrap();
+    gcode.anti_stringing(AntiStringing::Coast { distance });
+    let states = gcode.cursor().map(|step| step.next).collect::<Vec<_>>();
+    assert_eq!(states[4].e, ExtrusionLength::from_mm(0.5));
+    assert_eq!(gcode.lines[5].command.tag(), Tag::Retraction);
+    assert_eq!(states[5].e, ExtrusionLength::from_mm(-0.3));
+    assert_eq!(states[7].e, ExtrusionLength::from_mm(0.7));
+    // up to the next reset
+    assert_eq!(states[9].e, ExtrusionLength::from_mm(0.5));
+}
+
+#[test]
+fn wipe_test() {
+    use crate::emit::Emit;
+    let input = "M83\nG1 X0 Y0\nG1 X10 E1\nG1 X20 E1\nG1 E-0.8 F2100 ; retract\nG1 X50 Y50";
+    let mut gcode: GCodeModel = input.parse().unwrap();
+    let distance = Length::from(15.0);
+    assert_eq!(
+        gcode.anti_stringing(AntiStringing::WipeWhileRetract { distance }),
+        1
+    );
+    assert_eq!(
+        gcode.lines[4].emit(false),
+        "G1 X10 E-0.533333 F2100 ; retract"
+    );
+    assert_eq!(gcode.lines[5].command.tag(), Tag::Wipe);
+    let states = gcode.cursor().map(|step| step.next).collect::<Vec<_>>();
+    assert_eq!(states[5].pos.x, Length::from(5.0));
+    assert_eq!(states[5].e, ExtrusionLength::from_mm(1.2));
+
+    // a short wipe stays on the last segment of each run
+    let input =
+        "M83\nG1 X10 E1\nG1 E-0.8 F2100\nG1 X20 Y10\nG1 E0.8\nG1 X20 Y20 E1\nG1 E-0.8\nG1 X0";
+    let mut gcode: GCodeModel = input.parse().unwrap();
+    let distance = Length::from(2.0);
+    assert_eq!(
+        gcode.anti_stringing(AntiStringing::WipeWhileRetract { distance }),
+        2
+    );
+    assert_eq!(gcode.lines[2].emit(false), "G1 X8 E-0.8 F2100 ");
+    assert_eq!(gcode.lines[2].command.tag(), Tag::Wipe);
+    assert_eq!(gcode.lines[6].emit(false), "G1 Y18 E-0.8 ");
+
+    // nothing to wipe into without a retraction
+    let mut gcode: GCodeModel = "G1 X10 E1\nG1 X20".parse().unwrap();
+    assert_eq!(
+        gcode.anti_stringing(AntiStringing::WipeWhileRetract { distance }),
+        0
+    );
+    // a G0 retraction becomes a G1 wipe
+    let mut gcode: GCodeModel = "M83\nG1 X10 E1\nG0 E-0.8 F2100\nG1 X50".parse().unwrap();
+    assert_eq!(
+        gcode.anti_stringing(AntiStringing::WipeWhileRetract { distance }),
+        1
+    );
+    assert_eq!(gcode.lines[2].emit(false), "G1 X8 E-0.8 F2100 ");
+    assert_eq!(gcode.lines[2].command.tag(), Tag::Wipe);
+}
@@ -0,0 +1,186 @@
+use crate::{profile::PrinterProfile, Command, GCodeModel};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Struct to store a Prusa M862.x printer check, e.g. `M862.3 P "MK4"`
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct M862 {
+    /// which check, the digit after the dot:
+    /// 1 nozzle diameter, 2 printer type, 3 model, 4 firmware, 5 gcode level, 6 feature
+    pub check: u8,
+    /// parameters exactly as written
+    pub args: String,
+}
+
+impl M862 {
+    /// Value of the P parameter with any quotes removed
+    pub fn value(&self) -> Option<&str> {
+        let start = self.args.find('P')? + 1;
+        let value = self.args[start..].trim_start();
+        match value.strip_prefix('"') {
+            Some(quoted) => quoted.split('"').next(),
+            None => value.split_whitespace().next(),
+        }
+    }
+}
+
+/// Printer the file was sliced for, read from M862.x checks and
+/// slicer header comments such as Bambu Studio's `; printer_model = ...`
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompatibilityInfo {
+    pub printer_model: Option<String>,
+    /// nozzle diameter in mm
+    pub nozzle_diameter: Option<f64>,
+    /// Prusa printer type code from M862.2
+    pub printer_type: Option<u32>,
+    /// minimum firmware version from M862.4
+    pub firmware: Option<String>,
+}
+
+/// A way in which a file doesn't match the printer it is sent to
+#[derive(Clone, Debug, PartialEq)]
+pub enum Incompatibility {
+    PrinterModel { file: String, printer: String },
+    NozzleDiameter { file: f64, printer: f64 },
+}
+
+impl std::fmt::Display for Incompatibility {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Incompatibility::PrinterModel { file, printer } => {
+                write!(f, "file is for printer {file}, not {printer}")
+            }
+            Incompatibility::NozzleDiameter { file, printer } => {
+                write!(f, "file is for a {file}mm nozzle, not {printer}mm")
+            }
+        }
+    }
+}
+
+/// pull `key = value` out of a header comment
+fn header_value<'a>(comment: &'a str, key: &str) -> Option<&'a str> {
+    let (k, v) = comment.split_once('=')?;
+    (k.trim() == key).then(|| v.trim().trim_matches('"'))
+}
+
+impl CompatibilityInfo {
+    /// Compare against a printer, reporting every mismatch. Missing
+    /// information in the file is not treated as a mismatch.
+    pub fn check(&self, profile: &PrinterProfile) -> Vec<Incompatibility> {
+        let mut out = Vec::new();
+        if let Some(model) = &self.printer_model {
+            if !model.eq_ignore_ascii_case(&profile.model) {
+                out.push(Incompatibility::PrinterModel {
+                    file: model.clone(),
+                    printer: profile.model.clone(),
+                });
+            }
+        }
+        if let Some(nozzle) = self.nozzle_diameter {
+            if (nozzle - profile.nozzle_diameter).abs() > 1e-6 {
+                out.push(Incompatibility::NozzleDiameter {
+                    file: nozzle,
+                    printer: profile.nozzle_diameter,
+                });
+            }
+        }
+        out
+    }
+}
+
+impl GCodeModel {
+    /// Collect printer compatibility information, with M862.x
+    /// commands taking precedence over header comments
+    pub fn compatibility(&self) -> CompatibilityInfo {
+        let mut info = CompatibilityInfo::default();
+        let mut from_headers = CompatibilityInfo::default();
+        for line in &self.lines {
+            if let Command::M862(m862) = &line.command {
+                let Some(value) = m862.value() else {
+                    continue;
+                };
+                match m862.check {
+                    1 => info.nozzle_diameter = value.parse().ok(),
+                    2 => info.printer_type = value.parse().ok(),
+                    3 => info.printer_model = Some(value.to_string()),
+                    4 => info.firmware = Some(value.to_string()),
+                    _ => {}
+                }
+            }
+            let comment = self.comment(line);
+            if let Some(model) = header_value(comment, "printer_model") {
+                from_headers.printer_model.get_or_insert(model.to_string());
+            }
+            if let Some(nozzle) = header_value(comment, "nozzle_diameter") {
+                // multi-extruder headers list one diameter per tool
+                let first = nozzle.split(',').next().unwrap_or_default();
+                if let Ok(nozzle) = first.trim().parse() {
+                    from_headers.nozzle_diameter.get_or_insert(nozzle);
+                }
+            }
+        }
+        CompatibilityInfo {
+            printer_model: info.printer_model.or(from_headers.printer_model),
+            nozzle_diameter: info.nozzle_diameter.or(from_headers.nozzle_diameter),
+            ..info
+        }
+    }
+}
+
+#[test]
+fn m862_test() {
+    use crate::emit::Emit;
+    let gcode: GCodeModel =
+        "M862.3 P \"MK3S\" ; printer model check\nM862.1 P0.4\nM862.2 P302\nM862.4 P5.1.0"
+            .parse()
+            .unwrap();
+    let Command::M862(m862) = &gcode.lines[0].command else {
+        panic!("expected M862");
+    };
+    assert_eq!(m862.check, 3);
+    assert_eq!(m862.value(), Some("MK3S"));
+    assert_eq!(
+        gcode.lines[0].emit(false),
+        "M862.3 P \"MK3S\" ; printer model check"
+    );
+    let info = gcode.compatibility();
+    assert_eq!(info.printer_model.as_deref(), Some("MK3S"));
+    assert_eq!(info.nozzle_diameter, Some(0.4));
+    assert_eq!(info.printer_type, Some(302));
+    assert_eq!(info.firmware.as_deref(), Some("5.1.0"));
+}
+
+#[test]
+fn compatibility_check_test() {
+    let gcode: GCodeModel =
+        "; printer_model = Bambu Lab X1 Carbon\n; nozzle_diameter = 0.4,0.4\nG28"
+            .parse()
+            .unwrap();
+    let info = gcode.compatibility();
+    let x1c = PrinterProfile {
+        model: String::from("Bambu Lab X1 Carbon"),
+        nozzle_diameter: 0.4,
+        ..Default::default()
+    };
+    assert!(info.check(&x1c).is_empty());
+    let mk4 = PrinterProfile {
+        nozzle_diameter: 0.6,
+        ..PrinterProfile::prusa_mk4()
+    };
+    assert_eq!(
+        info.check(&mk4),
+        vec![
+            Incompatibility::PrinterModel {
+                file: String::from("Bambu Lab X1 Carbon"),
+                printer: String::from("MK4"),
+            },
+            Incompatibility::NozzleDiameter {
+                file: 0.4,
+                printer: 0.6,
+            },
+        ]
+    );
+}
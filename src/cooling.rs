@@ -0,0 +1,134 @@
+use crate::{Command, Feedrate, GCodeLine, GCodeModel, Length, Tag, G1};
+use std::time::Duration;
+
+/// How to lengthen a layer that prints faster than the minimum layer time
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Mitigation {
+    /// Scale down extrusion speeds in the layer, never going below `min_feedrate`
+    SlowDown { min_feedrate: Feedrate },
+    /// Wait out the remaining time with a G4 before the next layer starts
+    Dwell,
+}
+
+/// A layer that prints in less than the minimum layer time
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShortLayer {
+    /// index into `GCodeModel::layers`
+    pub layer: usize,
+    pub z: Length,
+    /// estimated time for the layer before any mitigation
+    pub time: Duration,
+}
+
+impl GCodeModel {
+    /// Estimated print time of each layer from `layers`
+    pub fn layer_times(&self) -> Vec<Duration> {
+        let durations = self.line_times();
+        self.layers()
+            .iter()
+            .map(|layer| durations[layer.range()].iter().sum())
+            .collect()
+    }
+    /// Layers that print in less than `min_layer_time`, which on small
+    /// parts don't get enough time to cool before the next layer lands
+    pub fn short_layers(&self, min_layer_time: Duration) -> Vec<ShortLayer> {
+        self.layers()
+            .into_iter()
+            .zip(self.layer_times())
+            .enumerate()
+            .filter(|(_, (_, time))| *time < min_layer_time)
+            .map(|(i, (layer, time))| ShortLayer {
+                layer: i,
+                z: layer.z,
+                time,
+            })
+            .collect()
+    }
+    /// Lengthen every short layer towards `min_layer_time`, returning the
+    /// layers that were changed. Dwells are not added after the last layer.
+    pub fn enforce_min_layer_time(
+        &mut self,
+        min_layer_time: Duration,
+        mitigation: Mitigation,
+    ) -> Vec<ShortLayer> {
+        let parameters = format!("{min_layer_time:?}, {mitigation:?}");
+        self.record_transform("enforce_min_layer_time", parameters);
+        let short = self.short_layers(min_layer_time);
+        let layers = self.layers();
+        let feedrates = self
+            .cursor()
+            .map(|step| step.next.feedrate)
+            .collect::<Vec<_>>();
+        // from the last layer, so a dwell added at the end of one doesn't move
+        // the layers before it
+        for short_layer in short.iter().rev() {
+            let layer = &layers[short_layer.layer];
+            match mitigation {
+                Mitigation::Dwell if layer.end < self.lines.len() => {
+                    self.lines.insert(
+                        layer.end,
+                        GCodeLine {
+                            id: self.id_counter.get(),
+                            command: Command::G4(min_layer_time - short_layer.time),
+                            comments: String::from(" min layer time"),
+                            annotations: Default::default(),
+                        },
+                    );
+                }
+                Mitigation::Dwell => {}
+                Mitigation::SlowDown { min_feedrate } => {
+                    let factor = short_layer.time.as_secs_f64() / min_layer_time.as_secs_f64();
+                    for i in layer.range() {
+                        let f = feedrates[i];
+                        // every move in the layer gets an explicit F so none inherit a scaled one
+                        if let Some(g1) = self.lines[i].command.as_move_mut() {
+                            g1.f = Some(if g1.tag == Tag::Extrusion {
+                                let scaled = Feedrate::from_mm_per_min(f.mm_per_min() * factor);
+                                scaled.max(min_feedrate.min(f))
+                            } else {
+                                f
+                            });
+                        }
+                    }
+                    // restore the original speed for the first move after the layer
+                    let next = self.lines[layer.end..]
+                        .iter_mut()
+                        .enumerate()
+                        .find_map(|(offset, line)| Some((offset, line.command.as_move_mut()?)));
+                    if let Some((offset, G1 { f, .. })) = next {
+                        f.get_or_insert(feedrates[layer.end + offset]);
+                    }
+                }
+            }
+        }
+        short
+    }
+}
+
+#[test]
+fn short_layer_test() {
+    // two 10s layers then a 1s layer
+    let input =
+        "G1 Z0.2 F600\nG1 X100 E5\nG1 Z0.4\nG1 X0 E10\nG1 Z0.6\nG1 X10 E11\nG1 Z0.8\nG1 X110 E16";
+    let gcode: GCodeModel = input.parse().unwrap();
+    let short = gcode.short_layers(Duration::from_secs(5));
+    assert_eq!(short.len(), 1);
+    assert_eq!(short[0].layer, 2);
+    assert_eq!(short[0].z, Length::from(0.6));
+
+    let mut dwell = gcode.clone();
+    dwell.enforce_min_layer_time(Duration::from_secs(5), Mitigation::Dwell);
+    assert!(dwell.short_layers(Duration::from_secs(5)).is_empty());
+    assert_eq!(dwell.lines.len(), gcode.lines.len() + 1);
+
+    let mut slow = gcode.clone();
+    let min_feedrate = Feedrate::from_mm_per_min(60.0);
+    slow.enforce_min_layer_time(
+        Duration::from_secs(5),
+        Mitigation::SlowDown { min_feedrate },
+    );
+    let times = slow.layer_times();
+    assert!(times[2] >= Duration::from_secs(4));
+    // the layer after keeps its original speed, including the 0.2mm Z move
+    assert_eq!(times[3], Duration::from_millis(10_020));
+}
@@ -0,0 +1,164 @@
+use crate::{GCodeModel, Length, Tag};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Upper bounds of the direction change histogram buckets, in degrees
+pub const ANGLE_BUCKETS: [f64; 6] = [30.0, 60.0, 90.0, 120.0, 150.0, 180.0];
+
+/// Upper bounds of the segment length histogram buckets, in mm, with a
+/// last bucket for anything longer
+pub const LENGTH_BUCKETS: [f64; 5] = [0.5, 1.0, 2.0, 5.0, 10.0];
+
+/// When a layer counts as zig-zag, see `GCodeModel::corner_stats`
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ZigZagLimits {
+    /// segments this short or shorter are short
+    pub short: Length,
+    /// turns of at least this many degrees are sharp
+    pub sharp: f64,
+    /// share of a layer's corners that must be sharp turns next to a short
+    /// segment
+    pub share: f64,
+}
+
+impl Default for ZigZagLimits {
+    fn default() -> Self {
+        ZigZagLimits {
+            short: Length::from(1.0),
+            sharp: 90.0,
+            share: 0.5,
+        }
+    }
+}
+
+/// Direction changes and segment lengths of the extrusions in one layer
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct CornerStats {
+    pub z: Length,
+    /// extrusion moves with some XY length
+    pub segments: usize,
+    /// turns between back to back extrusions, counted by `ANGLE_BUCKETS`
+    pub angles: [usize; ANGLE_BUCKETS.len()],
+    /// extrusions counted by `LENGTH_BUCKETS`
+    pub lengths: [usize; LENGTH_BUCKETS.len() + 1],
+    /// corners that are sharp turns next to a short segment
+    pub zig_zags: usize,
+    /// zig-zags make up at least the share of corners in the limits
+    pub flagged: bool,
+}
+
+impl CornerStats {
+    pub fn corners(&self) -> usize {
+        self.angles.iter().sum()
+    }
+    /// Share of the corners that are zig-zags, 0 if there are none
+    pub fn zig_zag_share(&self) -> f64 {
+        match self.corners() {
+            0 => 0.0,
+            n => self.zig_zags as f64 / n as f64,
+        }
+    }
+}
+
+/// Turn in degrees between two XY directions, 0 for straight on and 180
+/// for doubling back
+fn turn(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let cross = a.0 * b.1 - a.1 * b.0;
+    let dot = a.0 * b.0 + a.1 * b.1;
+    cross.atan2(dot).abs().to_degrees()
+}
+
+impl GCodeModel {
+    /// Histograms of the turns between consecutive extrusions and of
+    /// extrusion lengths for each layer, flagging layers dominated by short
+    /// segments with sharp turns between them, like dense gyroid or gap
+    /// fill, which shake the frame at the speeds they are printed. A
+    /// travel or anything else between two extrusions isn't a corner.
+    pub fn corner_stats(&self, limits: &ZigZagLimits) -> Vec<CornerStats> {
+        let layers = self.layers();
+        let steps = self.cursor().collect::<Vec<_>>();
+        layers
+            .iter()
+            .map(|layer| {
+                let mut stats = CornerStats {
+                    z: layer.z,
+                    segments: 0,
+                    angles: [0; ANGLE_BUCKETS.len()],
+                    lengths: [0; LENGTH_BUCKETS.len() + 1],
+                    zig_zags: 0,
+                    flagged: false,
+                };
+                // direction and length of the extrusion just before
+                let mut last: Option<((f64, f64), f64)> = None;
+                for step in &steps[layer.range()] {
+                    if step.line.command.tag() != Tag::Extrusion {
+                        last = None;
+                        continue;
+                    }
+                    let d = (
+                        (step.next.pos.x - step.prev.pos.x).to_mm(),
+                        (step.next.pos.y - step.prev.pos.y).to_mm(),
+                    );
+                    let length = d.0.hypot(d.1);
+                    if length == 0.0 {
+                        continue;
+                    }
+                    stats.segments += 1;
+                    let bucket = LENGTH_BUCKETS
+                        .iter()
+                        .position(|&max| length <= max)
+                        .unwrap_or(LENGTH_BUCKETS.len());
+                    stats.lengths[bucket] += 1;
+                    if let Some((dir, prev)) = last {
+                        let angle = turn(dir, d);
+                        let bucket = ANGLE_BUCKETS
+                            .iter()
+                            .position(|&max| angle <= max)
+                            .unwrap_or(ANGLE_BUCKETS.len() - 1);
+                        stats.angles[bucket] += 1;
+                        let short = limits.short.to_mm();
+                        if angle >= limits.sharp && (length <= short || prev <= short) {
+                            stats.zig_zags += 1;
+                        }
+                    }
+                    last = Some((d, length));
+                }
+                stats.flagged = stats.corners() > 0 && stats.zig_zag_share() >= limits.share;
+                stats
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn corner_stats_test() {
+    // a square perimeter, then a layer of 0.5mm zig-zags
+    let mut input =
+        String::from("G1 Z0.2\nG1 X0 Y0\nG1 X20 E1\nG1 Y20 E2\nG1 X0 E3\nG1 Y0 E4\nG1 Z0.4\n");
+    for i in 0..10 {
+        let y = if i % 2 == 0 { 0.5 } else { 0.0 };
+        input += &format!("G1 X{} Y{y} E{}\n", 0.5 * (i + 1) as f64, 5 + i);
+    }
+    let gcode: GCodeModel = input.parse().unwrap();
+    let stats = gcode.corner_stats(&ZigZagLimits::default());
+    assert_eq!(stats.len(), 2);
+    let square = &stats[0];
+    assert_eq!(square.segments, 4);
+    assert_eq!(square.angles, [0, 0, 3, 0, 0, 0]);
+    assert_eq!(square.lengths, [0, 0, 0, 0, 0, 4]);
+    assert_eq!(square.zig_zags, 0);
+    assert!(!square.flagged);
+    let zig_zag = &stats[1];
+    assert_eq!(zig_zag.z, Length::from(0.4));
+    assert_eq!(zig_zag.segments, 10);
+    // the Z move breaks the chain from the square, so ten segments make
+    // nine corners
+    assert_eq!(zig_zag.angles, [0, 0, 9, 0, 0, 0]);
+    assert_eq!(zig_zag.lengths, [0, 10, 0, 0, 0, 0]);
+    assert_eq!(zig_zag.zig_zags, 9);
+    assert!(zig_zag.flagged);
+    assert_eq!(zig_zag.zig_zag_share(), 1.0);
+}
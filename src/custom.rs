@@ -0,0 +1,176 @@
+use std::{any::Any, collections::HashMap, sync::Arc};
+
+/// A typed payload for a command that g-win doesn't handle itself,
+/// produced by a parser registered on `ParserConfig`
+pub trait CustomCommand: std::fmt::Debug + Send + Sync + 'static {
+    /// Gcode text for this command, without comments
+    fn emit(&self) -> String;
+    /// Access to the concrete type so callers can downcast
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// Turns the text of a line into a custom command. The line is passed
+/// as written in the file with comments removed; returning `None` leaves
+/// the line as `Command::Raw`.
+pub trait CommandParser: Send + Sync {
+    fn parse(&self, line: &str) -> Option<Box<dyn CustomCommand>>;
+}
+
+impl<F> CommandParser for F
+where
+    F: Fn(&str) -> Option<Box<dyn CustomCommand>> + Send + Sync,
+{
+    fn parse(&self, line: &str) -> Option<Box<dyn CustomCommand>> {
+        self(line)
+    }
+}
+
+/// Shared handle to a custom command stored in a model. Two handles
+/// compare equal when they emit the same gcode.
+#[derive(Clone, Debug)]
+pub struct Custom(Arc<dyn CustomCommand>);
+
+impl Custom {
+    pub fn new(command: impl CustomCommand) -> Self {
+        Custom(Arc::new(command))
+    }
+    pub fn emit(&self) -> String {
+        self.0.emit()
+    }
+    /// Get the concrete payload if it is of type `T`
+    pub fn downcast_ref<T: 'static>(&self) -> Option<&T> {
+        self.0.as_any().downcast_ref::<T>()
+    }
+}
+
+impl From<Box<dyn CustomCommand>> for Custom {
+    fn from(command: Box<dyn CustomCommand>) -> Self {
+        Custom(Arc::from(command))
+    }
+}
+
+impl PartialEq for Custom {
+    fn eq(&self, other: &Self) -> bool {
+        self.emit() == other.emit()
+    }
+}
+
+impl Eq for Custom {}
+
+impl std::hash::Hash for Custom {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.emit().hash(state);
+    }
+}
+
+/// Write a custom command as the text it emits, since the parser that made
+/// it isn't around when deserializing, so it reads back as `Command::Raw`
+#[cfg(feature = "serde")]
+pub(crate) fn serialize_as_raw<S: serde::Serializer>(
+    custom: &Custom,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&custom.emit())
+}
+
+/// Parsers for additional commands keyed by their first word, e.g. `"M900"`,
+/// `"M862.3"` or a Klipper macro like `"PRINT_START"`. Built in commands
+/// always take precedence over registered ones.
+#[derive(Clone, Default)]
+pub struct CommandRegistry(HashMap<String, Arc<dyn CommandParser>>);
+
+impl CommandRegistry {
+    pub fn register(&mut self, word: &str, parser: impl CommandParser + 'static) {
+        self.0.insert(word.to_uppercase(), Arc::new(parser));
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub(crate) fn parse(&self, word: &str, line: &str) -> Option<Custom> {
+        self.0
+            .get(&word.to_uppercase())
+            .and_then(|parser| parser.parse(line))
+            .map(Custom::from)
+    }
+}
+
+impl std::fmt::Debug for CommandRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+#[test]
+fn registry_test() {
+    use crate::{emit::Emit, Command, GCodeModel, ParserConfig};
+
+    #[derive(Debug, PartialEq)]
+    struct LinearAdvance(f32);
+    impl CustomCommand for LinearAdvance {
+        fn emit(&self) -> String {
+            format!("M900 K{}", self.0)
+        }
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    let mut config = ParserConfig::default();
+    config
+        .commands
+        .register("M900", |line: &str| -> Option<Box<dyn CustomCommand>> {
+            let k = line.trim().strip_prefix("M900")?.trim().strip_prefix('K')?;
+            Some(Box::new(LinearAdvance(k.parse().ok()?)))
+        });
+    let gcode = GCodeModel::parse_with_config("M900 K0.05 ; la\nM900 X\nM901", &config).unwrap();
+    let Command::Custom(custom) = &gcode.lines[0].command else {
+        panic!("expected custom command");
+    };
+    assert_eq!(
+        custom.downcast_ref::<LinearAdvance>(),
+        Some(&LinearAdvance(0.05))
+    );
+    assert_eq!(gcode.lines[0].emit(false), "M900 K0.05; la");
+    // unparseable and unregistered commands stay raw
+    assert_eq!(gcode.lines[1].command, Command::Raw("M900 X".to_string()));
+    assert_eq!(gcode.lines[2].command, Command::Raw("M901".to_string()));
+}
+
+#[test]
+fn registry_macro_test() {
+    use crate::{emit::Emit, Command, GCodeModel, ParserConfig};
+
+    #[derive(Debug, PartialEq)]
+    struct PrintStart(String);
+    impl CustomCommand for PrintStart {
+        fn emit(&self) -> String {
+            format!("PRINT_START {}", self.0)
+        }
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    let mut config = ParserConfig::default();
+    config.commands.register(
+        "print_start",
+        |line: &str| -> Option<Box<dyn CustomCommand>> {
+            let args = line.trim().strip_prefix("PRINT_START")?.trim();
+            Some(Box::new(PrintStart(args.to_string())))
+        },
+    );
+    let input = "PRINT_START BED=60 EXTRUDER=215\nPRINT_END\n";
+    let gcode = GCodeModel::parse_with_config(input, &config).unwrap();
+    let Command::Custom(custom) = &gcode.lines[0].command else {
+        panic!("expected custom command");
+    };
+    assert_eq!(
+        custom.downcast_ref::<PrintStart>(),
+        Some(&PrintStart("BED=60 EXTRUDER=215".to_string()))
+    );
+    assert_eq!(
+        gcode.lines[1].command,
+        Command::Raw("PRINT_END".to_string())
+    );
+    assert_eq!(gcode.emit(false), input);
+}
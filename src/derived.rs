@@ -0,0 +1,165 @@
+use crate::{emit::Emit, Annotations, Command, Counter, GCodeLine, GCodeModel};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+/// A variant of a shared base model, stored as the edits made to it.
+/// Indices always refer to lines of the base, and the full model is only
+/// built by `materialize` or when emitting, so producing many variants of
+/// one file (e.g. a temperature sweep) doesn't copy it for each.
+#[derive(Clone, Debug)]
+pub struct DerivedModel {
+    base: Arc<GCodeModel>,
+    replaced: BTreeMap<usize, GCodeLine>,
+    inserted: BTreeMap<usize, Vec<GCodeLine>>,
+    removed: BTreeSet<usize>,
+    id_counter: Counter,
+}
+
+impl DerivedModel {
+    pub fn new(base: Arc<GCodeModel>) -> Self {
+        let id_counter = base.id_counter.clone();
+        DerivedModel {
+            base,
+            replaced: BTreeMap::new(),
+            inserted: BTreeMap::new(),
+            removed: BTreeSet::new(),
+            id_counter,
+        }
+    }
+    pub fn base(&self) -> &Arc<GCodeModel> {
+        &self.base
+    }
+    /// True if no edits have been made
+    pub fn is_unchanged(&self) -> bool {
+        self.replaced.is_empty() && self.inserted.is_empty() && self.removed.is_empty()
+    }
+    /// Line `i` of the base with any replacement applied, or `None` if it
+    /// was removed or is out of range
+    pub fn line(&self, i: usize) -> Option<&GCodeLine> {
+        if self.removed.contains(&i) {
+            return None;
+        }
+        self.replaced.get(&i).or_else(|| self.base.lines.get(i))
+    }
+    /// Replace the command on line `i`, keeping its id, comments and
+    /// annotations. Returns false and does nothing if there is no such line.
+    pub fn replace(&mut self, i: usize, command: Command) -> bool {
+        let Some(line) = self.line(i) else {
+            return false;
+        };
+        let line = GCodeLine {
+            command,
+            ..line.clone()
+        };
+        self.replaced.insert(i, line);
+        true
+    }
+    /// Change every command whose line matches `filter` like
+    /// `GCodeModel::map_commands`, storing only the lines `f` changed.
+    /// Returns the number of lines matched.
+    pub fn map_commands(
+        &mut self,
+        filter: impl Fn(&GCodeLine) -> bool,
+        mut f: impl FnMut(&mut Command),
+    ) -> usize {
+        let mut changed = 0;
+        for i in 0..self.base.lines.len() {
+            let Some(line) = self.line(i).filter(|line| filter(line)) else {
+                continue;
+            };
+            let mut command = line.command.clone();
+            f(&mut command);
+            if command != line.command {
+                self.replace(i, command);
+            }
+            changed += 1;
+        }
+        changed
+    }
+    /// Add a line with a new id before line `i` of the base, or at the end
+    /// if `i` is past the last line. Lines inserted at the same index keep
+    /// the order they were added in.
+    pub fn insert(&mut self, i: usize, command: Command, comments: &str) {
+        let i = i.min(self.base.lines.len());
+        let line = GCodeLine {
+            id: self.id_counter.get(),
+            command,
+            comments: comments.to_string(),
+            annotations: Annotations::default(),
+        };
+        self.inserted.entry(i).or_default().push(line);
+    }
+    /// Drop line `i` of the base, returning false if it was already gone
+    pub fn remove(&mut self, i: usize) -> bool {
+        i < self.base.lines.len() && self.removed.insert(i)
+    }
+    /// Build the full model with every edit applied and re-tagged
+    pub fn materialize(&self) -> GCodeModel {
+        let mut lines = Vec::with_capacity(self.base.lines.len());
+        for i in 0..=self.base.lines.len() {
+            if let Some(inserted) = self.inserted.get(&i) {
+                lines.extend(inserted.iter().cloned());
+            }
+            if let Some(line) = self.line(i) {
+                lines.push(line.clone());
+            }
+        }
+        let mut model = GCodeModel {
+            lines,
+            rel_xyz: self.base.rel_xyz,
+            rel_e: self.base.rel_e,
+            id_counter: self.id_counter.clone(),
+            comments: self.base.comments.clone(),
+            line_ending: self.base.line_ending,
+            provenance: self.base.provenance.clone(),
+            state_index: Default::default(),
+        };
+        model.tag_g1();
+        model
+    }
+}
+
+impl Emit for DerivedModel {
+    fn emit(&self, debug: bool) -> String {
+        self.materialize().emit(debug)
+    }
+}
+
+#[test]
+fn derived_model_test() {
+    let base: Arc<GCodeModel> = Arc::new("M104 S200\nG1 X10 E1 F600\nG1 X20 E2".parse().unwrap());
+    let variants = [200, 210, 220].map(|temp| {
+        let mut variant = DerivedModel::new(Arc::clone(&base));
+        variant.replace(0, Command::Raw(format!("M104 S{temp}")));
+        variant
+    });
+    assert_eq!(Arc::strong_count(&base), 4);
+    assert_eq!(
+        variants[2].emit(false),
+        "M104 S220\nG1 X10 E1 F600 \nG1 X20 E2 \n"
+    );
+    assert_eq!(variants[1].line(0).unwrap().id, base.lines[0].id);
+
+    let mut variant = DerivedModel::new(Arc::clone(&base));
+    assert!(variant.is_unchanged());
+    variant.insert(1, Command::G91, "");
+    variant.insert(9, Command::Raw("M84".to_string()), " done");
+    assert!(variant.remove(2));
+    assert!(!variant.remove(2));
+    let changed = variant.map_commands(
+        |line| line.command.as_move().is_some(),
+        |command| {
+            if let Some(g1) = command.as_move_mut() {
+                g1.f = None;
+            }
+        },
+    );
+    assert_eq!(changed, 1);
+    let model = variant.materialize();
+    assert_eq!(model.emit(false), "M104 S200\nG91\nG1 X10 E1 \nM84; done\n");
+    assert_eq!(model.lines[1].id.get(), base.id_counter.clone().get().get());
+    // the base is untouched
+    assert_eq!(base.lines.len(), 3);
+}
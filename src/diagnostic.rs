@@ -0,0 +1,364 @@
+use crate::{
+    lint::{Finding, Lint, PreambleRules, Shutdown},
+    profile::PrinterProfile,
+    ExtrusionLength, GCodeModel, GCodeParseError, Id, Incompatibility,
+};
+use std::ops::Range;
+
+pub use crate::lint::Severity;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Largest retraction `GCodeModel::diagnose` allows before absolute E going
+/// backwards is reported, in mm
+const MAX_RETRACTION: f64 = 10.0;
+
+/// What a diagnostic points at
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Location {
+    /// a line of the model
+    Line(Id),
+    /// a byte range of the parsed text, for input that didn't parse
+    Span(Range<usize>),
+    /// the file as a whole
+    File,
+}
+
+/// One problem found in a file, from the parser, a lint or a check
+/// against a printer, in a form that can be filtered by `code` and
+/// rendered the same way whatever found it
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// stable machine-readable code, a category and a name separated by a
+    /// slash, e.g. `lint/e-backwards`
+    pub code: String,
+    pub message: String,
+    pub location: Location,
+}
+
+impl Diagnostic {
+    /// The part of `code` before the slash: `parse`, `lint`, `compat` or
+    /// `bounds`
+    pub fn category(&self) -> &str {
+        self.code.split('/').next().unwrap_or(&self.code)
+    }
+    /// The line it points at, if any
+    pub fn id(&self) -> Option<Id> {
+        match self.location {
+            Location::Line(id) => Some(id),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            Severity::Allow => "allow",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    /// `warning[lint/e-backwards]: absolute E goes back ...`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)
+    }
+}
+
+impl Lint {
+    /// Code of the lint's diagnostics
+    pub fn code(&self) -> &'static str {
+        match self {
+            Lint::EBackwards { .. } => "lint/e-backwards",
+            Lint::InchUnits => "lint/inch-units",
+            Lint::MotionBeforeHome => "lint/motion-before-home",
+            Lint::ExtrusionBeforeTemperature { .. } => "lint/extrusion-before-temperature",
+            Lint::LevelingBeforeHome => "lint/leveling-before-home",
+            Lint::FeedrateAboveLimit { .. } => "lint/feedrate-above-limit",
+            Lint::AxisSpeedAboveLimit { .. } => "lint/axis-speed-above-limit",
+            Lint::OutsideSoftEndstop { .. } => "bounds/outside-soft-endstop",
+            Lint::VolumetricSpeedAboveLimit { .. } => "lint/volumetric-speed-above-limit",
+            Lint::TemperatureOutOfRange { .. } => "lint/temperature-out-of-range",
+            Lint::LayerTimeBelowMinimum { .. } => "lint/layer-time-below-minimum",
+            Lint::ToolheadCollision { .. } => "bounds/toolhead-collision",
+            Lint::GantryCollision { .. } => "bounds/gantry-collision",
+        }
+    }
+}
+
+impl From<Finding> for Diagnostic {
+    fn from(finding: Finding) -> Self {
+        Diagnostic {
+            severity: finding.severity,
+            code: finding.lint.code().to_string(),
+            message: finding.lint.to_string(),
+            location: Location::Line(finding.id),
+        }
+    }
+}
+
+impl From<&GCodeParseError> for Diagnostic {
+    fn from(e: &GCodeParseError) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code: match e.limit {
+                Some(_) => "parse/limit",
+                None => "parse/invalid",
+            }
+            .to_string(),
+            message: e.message.clone(),
+            location: Location::Span(e.span.clone()),
+        }
+    }
+}
+
+impl From<&Incompatibility> for Diagnostic {
+    fn from(incompatibility: &Incompatibility) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code: match incompatibility {
+                Incompatibility::PrinterModel { .. } => "compat/printer-model",
+                Incompatibility::NozzleDiameter { .. } => "compat/nozzle-diameter",
+            }
+            .to_string(),
+            message: incompatibility.to_string(),
+            location: Location::File,
+        }
+    }
+}
+
+impl From<Shutdown> for Diagnostic {
+    fn from(shutdown: Shutdown) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            code: match shutdown {
+                Shutdown::Hotend => "lint/hotend-left-on",
+                Shutdown::Bed => "lint/bed-left-on",
+                Shutdown::Fan => "lint/fan-left-on",
+                Shutdown::Steppers => "lint/steppers-left-on",
+            }
+            .to_string(),
+            message: shutdown.to_string(),
+            location: Location::File,
+        }
+    }
+}
+
+/// `s` as a quoted JSON string
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\r' => out += "\\r",
+            '\t' => out += "\\t",
+            c if u32::from(c) < 0x20 => out += &format!("\\u{:04x}", u32::from(c)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The diagnostics as a JSON array of objects with `severity`, `code`,
+/// `message` and, for those that point into the file, `line` (numbered
+/// from 1 in `model`) or the byte range `start` to `end`
+pub fn to_json(diagnostics: &[Diagnostic], model: &GCodeModel) -> String {
+    let lines = line_numbers(model);
+    let entries = diagnostics
+        .iter()
+        .map(|d| {
+            let location = match &d.location {
+                Location::Line(id) => match lines.get(id) {
+                    Some(line) => format!(r#","line":{line}"#),
+                    None => String::new(),
+                },
+                Location::Span(span) => format!(r#","start":{},"end":{}"#, span.start, span.end),
+                Location::File => String::new(),
+            };
+            format!(
+                r#"{{"severity":{},"code":{},"message":{}{location}}}"#,
+                json_string(&d.severity.to_string()),
+                json_string(&d.code),
+                json_string(&d.message),
+            )
+        })
+        .collect::<Vec<_>>();
+    format!("[{}]", entries.join(","))
+}
+
+/// Line number from 1 of each id in `model`
+fn line_numbers(model: &GCodeModel) -> std::collections::HashMap<Id, usize> {
+    let lines = model.lines.iter().enumerate();
+    lines.map(|(i, line)| (line.id, i + 1)).collect()
+}
+
+/// The diagnostics as a SARIF 2.1.0 log with one run, for code review
+/// tools and CI systems that read static analysis results. `uri` names the
+/// file `model` was read from.
+pub fn to_sarif(diagnostics: &[Diagnostic], model: &GCodeModel, uri: &str) -> String {
+    let lines = line_numbers(model);
+    let mut codes = diagnostics
+        .iter()
+        .map(|d| d.code.as_str())
+        .collect::<Vec<_>>();
+    codes.sort_unstable();
+    codes.dedup();
+    let rules = codes
+        .iter()
+        .map(|code| format!(r#"{{"id":{}}}"#, json_string(code)))
+        .collect::<Vec<_>>();
+    let artifact = format!(r#""artifactLocation":{{"uri":{}}}"#, json_string(uri));
+    let results = diagnostics
+        .iter()
+        .map(|d| {
+            let level = match d.severity {
+                Severity::Allow => "none",
+                Severity::Warning => "warning",
+                Severity::Error => "error",
+            };
+            let region = match &d.location {
+                Location::Line(id) => lines
+                    .get(id)
+                    .map(|line| format!(r#","region":{{"startLine":{line}}}"#)),
+                Location::Span(span) => Some(format!(
+                    r#","region":{{"charOffset":{},"charLength":{}}}"#,
+                    span.start,
+                    span.len()
+                )),
+                Location::File => None,
+            };
+            format!(
+                r#"{{"ruleId":{},"level":"{level}","message":{{"text":{}}},"locations":[{{"physicalLocation":{{{artifact}{}}}}}]}}"#,
+                json_string(&d.code),
+                json_string(&d.message),
+                region.unwrap_or_default(),
+            )
+        })
+        .collect::<Vec<_>>();
+    format!(
+        r#"{{"$schema":"https://json.schemastore.org/sarif-2.1.0.json","version":"2.1.0","runs":[{{"tool":{{"driver":{{"name":"g-win","version":"{}","informationUri":"https://github.com/mj10021/g-win/","rules":[{}]}}}},"results":[{}]}}]}}"#,
+        env!("CARGO_PKG_VERSION"),
+        rules.join(","),
+        results.join(","),
+    )
+}
+
+impl GCodeModel {
+    /// Run every general check against `profile`: the units, preamble,
+    /// absolute E, feedrate, soft endstop and end state lints, the slicer's
+    /// printer settings and whether the print fits the build volume. Drop
+    /// codes or categories from the result to suppress them.
+    pub fn diagnose(&self, profile: &PrinterProfile) -> Vec<Diagnostic> {
+        let mut findings = self.check_units();
+        findings.extend(self.check_preamble(&PreambleRules::default()));
+        findings.extend(self.check_absolute_e(ExtrusionLength::from_mm(MAX_RETRACTION)));
+        findings.extend(self.check_feedrates(profile));
+        findings.extend(self.check_soft_endstops(&profile.soft_endstops()));
+        let mut diagnostics = findings
+            .into_iter()
+            .map(Diagnostic::from)
+            .collect::<Vec<_>>();
+        diagnostics.extend(
+            self.check_end_state(&Shutdown::ALL)
+                .into_iter()
+                .map(Diagnostic::from),
+        );
+        let compat = self.compatibility().check(profile);
+        diagnostics.extend(compat.iter().map(Diagnostic::from));
+        if let Some(bounds) = self.bounds(profile.geometry) {
+            if !profile.fits(&bounds) {
+                diagnostics.push(Diagnostic {
+                    severity: Severity::Error,
+                    code: String::from("bounds/outside-build-volume"),
+                    message: format!("print doesn't fit on a {}", profile.model),
+                    location: Location::File,
+                });
+            }
+        }
+        diagnostics.retain(|diagnostic| diagnostic.severity != Severity::Allow);
+        diagnostics
+    }
+}
+
+#[test]
+fn diagnostic_test() {
+    let gcode: GCodeModel = "G28\nM104 S215\nM140 S60\nG1 X300 Y10 Z0.2 F600\nG1 X10 E5"
+        .parse()
+        .unwrap();
+    let diagnostics = gcode.diagnose(&PrinterProfile::prusa_mk4());
+    let codes = diagnostics
+        .iter()
+        .map(|d| d.code.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        codes,
+        [
+            "bounds/outside-soft-endstop",
+            "lint/hotend-left-on",
+            "lint/bed-left-on",
+            "lint/steppers-left-on",
+            "bounds/outside-build-volume",
+        ]
+    );
+    assert_eq!(diagnostics[0].id(), Some(gcode.lines[3].id));
+    assert_eq!(diagnostics[0].category(), "bounds");
+    assert_eq!(
+        diagnostics[1].to_string(),
+        "warning[lint/hotend-left-on]: hotend is left on, missing M104 S0"
+    );
+
+    let config = crate::ParserConfig {
+        max_lines: Some(1),
+        ..Default::default()
+    };
+    let e = GCodeModel::parse_with_config("G1 X1\nG1 X2\n", &config).unwrap_err();
+    let diagnostic = Diagnostic::from(&e);
+    assert_eq!(diagnostic.code, "parse/limit");
+    assert_eq!(diagnostic.category(), "parse");
+    assert!(matches!(diagnostic.location, Location::Span(_)));
+}
+
+#[test]
+fn export_test() {
+    let gcode: GCodeModel = "G28\nG1 X300 F600 ; \"far\"\nM84".parse().unwrap();
+    let diagnostics = [
+        Diagnostic {
+            severity: Severity::Error,
+            code: String::from("bounds/outside-soft-endstop"),
+            message: String::from("X moves to \"300mm\""),
+            location: Location::Line(gcode.lines[1].id),
+        },
+        Diagnostic {
+            severity: Severity::Warning,
+            code: String::from("lint/hotend-left-on"),
+            message: String::from("hotend is left on"),
+            location: Location::File,
+        },
+    ];
+    assert_eq!(
+        to_json(&diagnostics, &gcode),
+        r#"[{"severity":"error","code":"bounds/outside-soft-endstop","message":"X moves to \"300mm\"","line":2},{"severity":"warning","code":"lint/hotend-left-on","message":"hotend is left on"}]"#
+    );
+    let sarif = to_sarif(&diagnostics, &gcode, "part.gcode");
+    assert!(sarif.starts_with(
+        r#"{"$schema":"https://json.schemastore.org/sarif-2.1.0.json","version":"2.1.0""#
+    ));
+    assert!(sarif.contains(
+        r#""rules":[{"id":"bounds/outside-soft-endstop"},{"id":"lint/hotend-left-on"}]"#
+    ));
+    assert!(sarif.contains(r#"{"ruleId":"bounds/outside-soft-endstop","level":"error","message":{"text":"X moves to \"300mm\""},"locations":[{"physicalLocation":{"artifactLocation":{"uri":"part.gcode"},"region":{"startLine":2}}}]}"#));
+    assert!(sarif.contains(
+        r#""locations":[{"physicalLocation":{"artifactLocation":{"uri":"part.gcode"}}}]}]}]}"#
+    ));
+    assert_eq!(json_string("a\tb\u{1}"), r#""a\tb\u0001""#);
+}
@@ -1,4 +1,14 @@
-use crate::{Command, GCodeLine, GCodeModel, G1};
+use crate::{
+    estimate::step_duration,
+    parsers::sets_g1_mode,
+    progress::Monitor,
+    state::{Cursor, FeedMode, MachineState, Step, Wcs},
+    ArcMove, Command, G53Motion, GCodeLine, GCodeModel, Id, G1, G5, M420,
+};
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 /// Trait objects that can be emitted to valid gcode, with an optional debug line appended
 pub trait Emit {
@@ -8,46 +18,673 @@ pub trait Emit {
 impl Emit for Command {
     fn emit(&self, debug: bool) -> String {
         match self {
+            Command::G0(g1) => params("G0", &g1.words()),
             Command::G1(g1) => g1.emit(debug),
+            Command::Home(home) => {
+                let [x, y, z] = &home.positions;
+                let flags = [
+                    (home.x, format!("X{x}")),
+                    (home.y, format!("Y{y}")),
+                    (home.z, format!("Z{z}")),
+                    (home.skip_leveling, String::from("W")),
+                ];
+                let words = flags
+                    .into_iter()
+                    .filter_map(|(set, word)| set.then_some(word))
+                    .chain(home.options.split_whitespace().map(String::from))
+                    .collect::<Vec<_>>();
+                if words.is_empty() {
+                    "G28".to_string()
+                } else {
+                    words
+                        .iter()
+                        .fold(String::from("G28 "), |out, word| out + word + " ")
+                }
+            }
+            Command::G4(dwell) => g4(dwell),
+            Command::G5(g5) => g5.emit(debug),
+            Command::Arc(arc) => arc.emit(debug),
+            Command::G53(Some(g1), motion) => params(g53_word(*motion), &g1.words()),
+            Command::G53(None, _) => "G53".to_string(),
+            Command::Wcs(wcs) => wcs_word(*wcs).to_string(),
             Command::G90 => "G90".to_string(),
             Command::G91 => "G91".to_string(),
+            Command::G92(g92) => params(
+                "G92",
+                &[
+                    ('X', g92.x.map(f64::from)),
+                    ('Y', g92.y.map(f64::from)),
+                    ('Z', g92.z.map(f64::from)),
+                    ('E', g92.e.map(|e| e.to_mm())),
+                ],
+            )
+            .trim_end()
+            .to_string(),
+            Command::G93 => "G93".to_string(),
+            Command::G94 => "G94".to_string(),
+            Command::G95 => "G95".to_string(),
             Command::M82 => "M82".to_string(),
             Command::M83 => "M83".to_string(),
+            Command::M3(speed) => spindle("M3", speed),
+            Command::M4(speed) => spindle("M4", speed),
+            Command::M5 => "M5".to_string(),
+            Command::M7 => "M7".to_string(),
+            Command::M8 => "M8".to_string(),
+            Command::M9 => "M9".to_string(),
+            Command::ToolChange(tool) => format!("T{tool}"),
+            Command::M106(m106) => params(
+                "M106",
+                &[
+                    ('P', m106.index.map(f64::from)),
+                    ('S', m106.speed.map(f64::from)),
+                ],
+            )
+            .trim_end()
+            .to_string(),
+            Command::M107(Some(index)) => format!("M107 P{index}"),
+            Command::M107(None) => "M107".to_string(),
+            Command::M117(msg) => message("M117", msg),
+            Command::M118(msg) => message("M118", msg),
+            Command::G29(args) => message("G29", args),
+            Command::M420(m420) => m420.emit(debug),
+            Command::M862(m862) => message(&format!("M862.{}", m862.check), &m862.args),
+            Command::M808(Some(count)) => format!("M808 L{count}"),
+            Command::M808(None) => "M808".to_string(),
             Command::Raw(s) => s.clone(),
+            Command::Blank => String::new(),
+            Command::Custom(c) => c.emit(),
         }
     }
 }
 
+fn spindle(word: &str, speed: &Option<u32>) -> String {
+    match speed {
+        Some(speed) => format!("{word} S{speed}"),
+        None => word.to_string(),
+    }
+}
+
+fn message(word: &str, msg: &str) -> String {
+    if msg.is_empty() {
+        word.to_string()
+    } else {
+        format!("{word} {msg}")
+    }
+}
+
 impl Emit for GCodeLine {
     fn emit(&self, debug: bool) -> String {
-        let comments = if self.comments.is_empty() {
-            String::from("")
+        with_comments(&self.command, &self.comments, debug)
+    }
+}
+
+fn with_comments(command: &Command, comments: &str, debug: bool) -> String {
+    let comments = if comments.is_empty() {
+        String::from("")
+    } else {
+        format!(";{}", comments)
+    };
+    command.emit(debug) + comments.as_str()
+}
+
+impl GCodeModel {
+    /// A line of this model as gcode, including any comments kept in
+    /// `GCodeModel::comments`
+    pub(crate) fn emit_line(&self, line: &GCodeLine, debug: bool) -> String {
+        with_comments(&line.command, self.comment(line), debug)
+    }
+    /// `emit_line` with the options of `config`, for a line run from the
+    /// state `prev`. `g1_mode` is whether G1 is the modal motion command
+    /// before the line.
+    fn emit_line_with(
+        &self,
+        line: &GCodeLine,
+        prev: &MachineState,
+        g1_mode: bool,
+        config: &EmitConfig,
+    ) -> String {
+        let elide = |g1: &G1| {
+            let mut g1 = g1.clone();
+            // a move with only an F word would be left empty
+            let moves = g1.x.is_some() || g1.y.is_some() || g1.z.is_some() || g1.e.is_some();
+            if config.elide_feedrate
+                && moves
+                && prev.feed_mode == FeedMode::UnitsPerMinute
+                && g1.f == Some(prev.feedrate)
+            {
+                g1.f = None;
+            }
+            g1
+        };
+        let command = match &line.command {
+            Command::G0(g1) => elide(g1).emit_ordered("G0", config),
+            Command::G1(g1) => {
+                let g1 = elide(g1).emit_ordered("G1", config);
+                // only words the parser's `modal_moves` reads can stand alone
+                match g1.strip_prefix("G1 ") {
+                    Some(bare)
+                        if config.bare_moves
+                            && g1_mode
+                            && bare.starts_with(['X', 'Y', 'Z', 'E', 'F']) =>
+                    {
+                        bare.to_string()
+                    }
+                    _ => g1,
+                }
+            }
+            Command::G53(Some(g1), motion) => elide(g1).emit_ordered(g53_word(*motion), config),
+            _ => return self.emit_line(line, config.debug),
+        };
+        let comments = self.comment(line);
+        if comments.is_empty() {
+            command
         } else {
-            format!(";{}", self.comments)
+            format!("{command};{comments}")
+        }
+    }
+    /// Write the modal feedrate into every G0 and G1 move that relies on it,
+    /// undoing `EmitConfig::elide_feedrate` for firmware and tools that
+    /// don't track F between lines. Moves before the first F are left
+    /// alone. Returns the number of moves changed.
+    pub fn explicit_feedrates(&mut self) -> usize {
+        self.record_transform("explicit_feedrates", String::new());
+        let feedrates = self
+            .cursor()
+            .map(|step| match step.line.command.as_move() {
+                Some(g1) if g1.f.is_none() && step.prev.feedrate != Default::default() => {
+                    Some(step.prev.feedrate)
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let mut changed = 0;
+        for (line, f) in self.lines.iter_mut().zip(feedrates) {
+            if let (Some(g1), Some(f)) = (line.command.as_move_mut(), f) {
+                g1.f = Some(f);
+                changed += 1;
+            }
+        }
+        changed
+    }
+}
+
+/// `G53` and the motion word after it, if any
+fn g53_word(motion: G53Motion) -> &'static str {
+    match motion {
+        G53Motion::Bare => "G53",
+        G53Motion::G0 => "G53 G0",
+        G53Motion::G1 => "G53 G1",
+    }
+}
+
+/// The word selecting `wcs`, `G54` to `G59`
+fn wcs_word(wcs: Wcs) -> &'static str {
+    match wcs {
+        Wcs::G54 => "G54",
+        Wcs::G55 => "G55",
+        Wcs::G56 => "G56",
+        Wcs::G57 => "G57",
+        Wcs::G58 => "G58",
+        Wcs::G59 => "G59",
+    }
+}
+
+/// A G4 dwell for exactly `dwell`, in whole seconds where it can be
+fn g4(dwell: &Duration) -> String {
+    if dwell.subsec_nanos() == 0 && !dwell.is_zero() {
+        format!("G4 S{}", dwell.as_secs())
+    } else {
+        format!("G4 P{}", dwell.as_nanos() as f64 / 1e6)
+    }
+}
+
+/// write a command word followed by each parameter that is set
+fn params(word: &str, params: &[(char, Option<f64>)]) -> String {
+    let mut out = format!("{} ", word);
+    for (letter, param) in params {
+        if let Some(param) = param {
+            out += format!("{}{} ", letter, param).as_str();
+        }
+    }
+    out
+}
+
+impl G1 {
+    /// Each word of the move in the usual X Y Z E F S order
+    fn words(&self) -> [(char, Option<f64>); 6] {
+        let G1 {
+            x, y, z, e, f, s, ..
+        } = self;
+        [
+            ('X', x.map(f64::from)),
+            ('Y', y.map(f64::from)),
+            ('Z', z.map(f64::from)),
+            ('E', e.map(|e| e.to_mm())),
+            ('F', f.map(|f| f.mm_per_min())),
+            ('S', s.map(f64::from)),
+        ]
+    }
+    /// The move after `word` with its words ordered as `config` asks
+    fn emit_ordered(&self, word: &str, config: &EmitConfig) -> String {
+        let mut words = self.words();
+        let rank = |letter: char| {
+            let order = config.param_order.unwrap_or("");
+            match order.chars().position(|c| c.eq_ignore_ascii_case(&letter)) {
+                _ if config.feedrate_first && letter == 'F' => 0,
+                Some(i) => 1 + i,
+                // letters left out keep their usual order after the rest
+                None => 1 + order.len(),
+            }
         };
-        self.command.emit(debug) + comments.as_str()
+        // stable, so ties keep the usual order
+        words.sort_by_key(|(letter, _)| rank(*letter));
+        params(word, &words)
     }
 }
 
 impl Emit for G1 {
     fn emit(&self, _debug: bool) -> String {
-        let mut out = String::from("G1 ");
-        let G1 { x, y, z, e, f, .. } = self;
-        let params = vec![('X', x), ('Y', y), ('Z', z), ('E', e), ('F', f)];
-        for (letter, param) in params {
-            if let Some(param) = param {
-                out += format!("{}{} ", letter, f32::from(*param)).as_str();
-            }
+        params("G1", &self.words())
+    }
+}
+
+impl Emit for G5 {
+    fn emit(&self, _debug: bool) -> String {
+        let G5 {
+            i,
+            j,
+            p,
+            q,
+            x,
+            y,
+            e,
+            f,
+        } = self;
+        params(
+            "G5",
+            &[
+                ('I', i.map(f64::from)),
+                ('J', j.map(f64::from)),
+                ('P', p.map(f64::from)),
+                ('Q', q.map(f64::from)),
+                ('X', x.map(f64::from)),
+                ('Y', y.map(f64::from)),
+                ('E', e.map(|e| e.to_mm())),
+                ('F', f.map(|f| f.mm_per_min())),
+            ],
+        )
+    }
+}
+
+impl Emit for ArcMove {
+    fn emit(&self, _debug: bool) -> String {
+        let ArcMove {
+            clockwise,
+            x,
+            y,
+            z,
+            i,
+            j,
+            r,
+            e,
+            f,
+            ..
+        } = self;
+        params(
+            if *clockwise { "G2" } else { "G3" },
+            &[
+                ('X', x.map(f64::from)),
+                ('Y', y.map(f64::from)),
+                ('Z', z.map(f64::from)),
+                ('I', i.map(f64::from)),
+                ('J', j.map(f64::from)),
+                ('R', r.map(f64::from)),
+                ('E', e.map(|e| e.to_mm())),
+                ('F', f.map(|f| f.mm_per_min())),
+            ],
+        )
+    }
+}
+
+impl Emit for M420 {
+    fn emit(&self, _debug: bool) -> String {
+        let M420 {
+            enable,
+            fade_height,
+            slot,
+            verbose,
+        } = self;
+        let mut out = params(
+            "M420",
+            &[
+                ('S', enable.map(|s| if s { 1.0 } else { 0.0 })),
+                ('Z', fade_height.map(f64::from)),
+                ('L', slot.map(f64::from)),
+            ],
+        );
+        if *verbose {
+            out += "V ";
         }
         out
     }
 }
 
+/// A line followed by its estimated duration and the time into the
+/// print when it finishes
+fn debug_line(line: &str, time: Duration, elapsed: Duration) -> String {
+    format!(
+        "{} ; time {:.3}s elapsed {:.3}s",
+        line.trim_end(),
+        time.as_secs_f64(),
+        elapsed.as_secs_f64()
+    )
+}
+
+/// Line ending written after each line of a model
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+    /// The ending used by most lines of `input`, `Lf` on a tie
+    pub fn detect(input: &str) -> Self {
+        let lines = input.matches('\n').count();
+        let crlf = input.matches("\r\n").count();
+        if crlf > lines - crlf {
+            LineEnding::CrLf
+        } else {
+            LineEnding::Lf
+        }
+    }
+}
+
+/// Options for `GCodeModel::emit_with_config`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct EmitConfig {
+    /// end each line with its estimated duration and the time into the
+    /// print when it finishes
+    pub debug: bool,
+    /// line ending to write instead of the one the model was parsed with
+    pub line_ending: Option<LineEnding>,
+    /// start with a block of comments summarizing the model, see
+    /// `GCodeModel::summary_header`
+    pub header: bool,
+    /// end with a comment for each transform in `GCodeModel::provenance`
+    pub provenance: bool,
+    /// letters of G1 words in the order to write them, e.g. `"FXYZE"`.
+    /// Letters left out follow in the usual X Y Z E F S order, which is
+    /// used for all of them if this is `None`.
+    pub param_order: Option<&'static str>,
+    /// write F before every other word of a G1, whatever `param_order`
+    /// says, for firmware that applies the feedrate as it reads it
+    pub feedrate_first: bool,
+    /// leave out the F of a G1 move when it repeats the modal feedrate,
+    /// see `GCodeModel::explicit_feedrates` for the reverse
+    pub elide_feedrate: bool,
+    /// leave out the G1 word of moves that follow another G1, e.g.
+    /// `X10 Y5`, for dialects that read them with `ParserConfig::modal_moves`
+    pub bare_moves: bool,
+}
+
+impl GCodeModel {
+    /// Write the whole model as gcode, with the header and provenance
+    /// comments `config` asks for around the lines from `emit_iter`
+    pub fn emit_with_config(&self, config: &EmitConfig) -> String {
+        Monitor::unmonitored(|monitor| self.emit_monitored(config, monitor))
+    }
+}
+
+/// How far a host got through streaming a model: `offset` bytes of the
+/// line with `id` had been sent
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Bookmark {
+    pub id: Id,
+    pub offset: usize,
+}
+
+/// The lines of a model emitted one at a time, as the bytes to send and
+/// the id of the line they came from, see `GCodeModel::emit_iter`
+#[derive(Clone, Debug)]
+pub struct EmitIter<'a> {
+    model: &'a GCodeModel,
+    config: EmitConfig,
+    cursor: Cursor<'a>,
+    g1_mode: bool,
+    elapsed: Duration,
+    /// bytes of the next line that were already sent
+    skip: usize,
+}
+
+impl EmitIter<'_> {
+    /// Carry the modal state and elapsed time past a line
+    fn advance(&mut self, step: &Step) -> Duration {
+        self.g1_mode = sets_g1_mode(&step.line.command).unwrap_or(self.g1_mode);
+        let time = step_duration(step);
+        self.elapsed += time;
+        time
+    }
+}
+
+impl Iterator for EmitIter<'_> {
+    type Item = (Vec<u8>, Id);
+    fn next(&mut self) -> Option<Self::Item> {
+        let ending = self.config.line_ending.unwrap_or(self.model.line_ending);
+        loop {
+            let step = self.cursor.next()?;
+            let mut line =
+                self.model
+                    .emit_line_with(step.line, &step.prev, self.g1_mode, &self.config);
+            let time = self.advance(&step);
+            if self.config.debug {
+                line = debug_line(&line, time, self.elapsed);
+            }
+            let mut bytes = (line + ending.as_str()).into_bytes();
+            bytes.drain(..self.skip.min(bytes.len()));
+            self.skip = 0;
+            // a line sent in full before the bookmark has nothing left
+            if !bytes.is_empty() {
+                return Some((bytes, step.line.id));
+            }
+        }
+    }
+}
+
+impl GCodeModel {
+    /// Emit each line as it is asked for, for streaming to a printer.
+    /// The header and provenance comments of `config` aren't part of the
+    /// stream since they don't come from a line.
+    pub fn emit_iter(&self, config: &EmitConfig) -> EmitIter<'_> {
+        EmitIter {
+            model: self,
+            config: *config,
+            cursor: self.cursor(),
+            g1_mode: false,
+            elapsed: Duration::ZERO,
+            skip: 0,
+        }
+    }
+    /// `emit_iter` picking up where a stream was cut off, with the rest of
+    /// the bookmarked line and every line after it. `None` if the line
+    /// isn't in the model.
+    pub fn emit_iter_from(&self, config: &EmitConfig, bookmark: Bookmark) -> Option<EmitIter<'_>> {
+        let index = self.lines.iter().position(|line| line.id == bookmark.id)?;
+        let mut iter = self.emit_iter(config);
+        for step in iter.cursor.by_ref().take(index).collect::<Vec<_>>() {
+            iter.advance(&step);
+        }
+        iter.skip = bookmark.offset;
+        Some(iter)
+    }
+    /// The bookmark `offset` bytes into the stream from `emit_iter`, or
+    /// `None` if the stream is no longer than that
+    pub fn bookmark(&self, config: &EmitConfig, offset: usize) -> Option<Bookmark> {
+        let mut sent = 0;
+        for (bytes, id) in self.emit_iter(config) {
+            if sent + bytes.len() > offset {
+                return Some(Bookmark {
+                    id,
+                    offset: offset - sent,
+                });
+            }
+            sent += bytes.len();
+        }
+        None
+    }
+}
+
 impl Emit for GCodeModel {
+    /// Emit with the line ending the model was parsed with, see
+    /// `emit_with_config`
     fn emit(&self, debug: bool) -> String {
-        self.lines
-            .iter()
-            .map(|line| line.emit(debug) + "\n")
-            .collect()
+        self.emit_with_config(&EmitConfig {
+            debug,
+            ..Default::default()
+        })
     }
 }
+
+#[test]
+fn message_emit_test() {
+    let input = "M117 Printing  part 1\nM118 //action:cancel\nM117";
+    let gcode: GCodeModel = input.parse().unwrap();
+    assert_eq!(gcode.emit(false), format!("{input}\n"));
+}
+
+#[test]
+fn line_ending_test() {
+    let input = "G90\r\nG1 X10 ; move\r\nM82\n";
+    let gcode: GCodeModel = input.parse().unwrap();
+    assert_eq!(gcode.line_ending, LineEnding::CrLf);
+    assert_eq!(gcode.emit(false), "G90\r\nG1 X10 ; move\r\nM82\r\n");
+    let config = EmitConfig {
+        line_ending: Some(LineEnding::Lf),
+        ..Default::default()
+    };
+    assert_eq!(gcode.emit_with_config(&config), "G90\nG1 X10 ; move\nM82\n");
+    assert_eq!(LineEnding::detect("a\r\nb\nc\n"), LineEnding::Lf);
+}
+
+#[test]
+fn param_order_test() {
+    let gcode: GCodeModel = "G1 X10 Y5 E1 F600 ; first\nG53 G1 Z5 F300\nG90"
+        .parse()
+        .unwrap();
+    let emit = |param_order, feedrate_first| {
+        gcode.emit_with_config(&EmitConfig {
+            param_order,
+            feedrate_first,
+            ..Default::default()
+        })
+    };
+    assert_eq!(emit(None, false), gcode.emit(false));
+    assert_eq!(
+        emit(None, true),
+        "G1 F600 X10 Y5 E1 ; first\nG53 G1 F300 Z5 \nG90\n"
+    );
+    assert_eq!(
+        emit(Some("eyx"), false),
+        "G1 E1 Y5 X10 F600 ; first\nG53 G1 Z5 F300 \nG90\n"
+    );
+    assert_eq!(
+        emit(Some("EF"), true),
+        "G1 F600 E1 X10 Y5 ; first\nG53 G1 F300 Z5 \nG90\n"
+    );
+}
+
+#[test]
+fn feedrate_elision_test() {
+    let input = "G1 X10 E1 F600\nG1 X20 E2 F600\nG1 F600\nG1 X30 F1200\nG93\nG1 X40 F1200";
+    let mut gcode: GCodeModel = input.parse().unwrap();
+    let config = EmitConfig {
+        elide_feedrate: true,
+        ..Default::default()
+    };
+    let elided = gcode.emit_with_config(&config);
+    assert_eq!(
+        elided,
+        "G1 X10 E1 F600 \nG1 X20 E2 \nG1 F600 \nG1 X30 F1200 \nG93\nG1 X40 F1200 \n"
+    );
+    let mut reparsed: GCodeModel = elided.parse().unwrap();
+    assert_eq!(reparsed.explicit_feedrates(), 1);
+    assert_eq!(reparsed.emit(false), gcode.emit(false));
+    assert_eq!(gcode.explicit_feedrates(), 0);
+
+    // rapids get their F back too
+    let gcode: GCodeModel = "G1 X10 F600\nG0 X30 F600".parse().unwrap();
+    let elided = gcode.emit_with_config(&config);
+    assert_eq!(elided, "G1 X10 F600 \nG0 X30 \n");
+    let mut reparsed: GCodeModel = elided.parse().unwrap();
+    assert_eq!(reparsed.explicit_feedrates(), 1);
+    assert_eq!(reparsed.emit(false), gcode.emit(false));
+}
+
+#[test]
+fn bare_moves_test() {
+    use crate::ParserConfig;
+    let input = "G1 X1 F600\nG1 X2 Y3 ; corner\nG1 S100\nG0 X0\nG1 Z1\nM104 S200\nG1 Z2";
+    let gcode: GCodeModel = input.parse().unwrap();
+    let config = EmitConfig {
+        bare_moves: true,
+        ..Default::default()
+    };
+    let emitted = gcode.emit_with_config(&config);
+    assert_eq!(
+        emitted,
+        "G1 X1 F600 \nX2 Y3 ; corner\nG1 S100 \nG0 X0 \nG1 Z1 \nM104 S200\nZ2 \n"
+    );
+    let config = ParserConfig {
+        modal_moves: true,
+        ..Default::default()
+    };
+    let reparsed = GCodeModel::parse_with_config(&emitted, &config).unwrap();
+    assert_eq!(reparsed.emit(false), gcode.emit(false));
+}
+
+#[test]
+fn emit_iter_test() {
+    let gcode: GCodeModel = "G28\nG1 X10 E1 F600 ; wall\nG1 X20 E2\nM84"
+        .parse()
+        .unwrap();
+    let config = EmitConfig {
+        bare_moves: true,
+        ..Default::default()
+    };
+    let stream = gcode
+        .emit_iter(&config)
+        .flat_map(|(bytes, _)| bytes)
+        .collect::<Vec<_>>();
+    assert_eq!(stream, gcode.emit_with_config(&config).into_bytes());
+
+    // cut off part way through the third line
+    let bookmark = gcode.bookmark(&config, 30).unwrap();
+    assert_eq!(
+        bookmark,
+        Bookmark {
+            id: gcode.lines[2].id,
+            offset: 4
+        }
+    );
+    let rest = gcode
+        .emit_iter_from(&config, bookmark)
+        .unwrap()
+        .collect::<Vec<_>>();
+    assert_eq!(rest[0], (b"E2 \n".to_vec(), gcode.lines[2].id));
+    assert_eq!(rest[1].1, gcode.lines[3].id);
+    let resumed = rest.into_iter().flat_map(|(bytes, _)| bytes);
+    assert!(stream[..30]
+        .iter()
+        .copied()
+        .chain(resumed)
+        .eq(stream.iter().copied()));
+    assert_eq!(gcode.bookmark(&config, stream.len()), None);
+}
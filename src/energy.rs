@@ -0,0 +1,158 @@
+use crate::{estimate::step_duration, parsers::split_raw, Command, GCodeModel};
+use std::time::Duration;
+
+/// Simple model of a heater that runs at full power until it reaches its
+/// target, then draws enough to make up for heat lost to the room
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HeaterModel {
+    /// power at full duty, in W
+    pub watts: f64,
+    /// how fast it warms up at full duty, in °C/s
+    pub heat_rate: f64,
+    /// power needed to hold a temperature, in W per °C above ambient
+    pub hold_watts_per_degree: f64,
+}
+
+/// Heaters and standing power draw of a printer
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ThermalProfile {
+    pub hotend: HeaterModel,
+    pub bed: HeaterModel,
+    /// room temperature in °C
+    pub ambient: f64,
+    /// electronics, motors and fans, drawn for the whole print, in W
+    pub base_watts: f64,
+}
+
+impl Default for ThermalProfile {
+    /// A typical bed slinger with a 40W hotend and a 24V bed
+    fn default() -> Self {
+        ThermalProfile {
+            hotend: HeaterModel {
+                watts: 40.0,
+                heat_rate: 3.0,
+                hold_watts_per_degree: 0.12,
+            },
+            bed: HeaterModel {
+                watts: 220.0,
+                heat_rate: 0.5,
+                hold_watts_per_degree: 1.5,
+            },
+            ambient: 20.0,
+            base_watts: 10.0,
+        }
+    }
+}
+
+/// Estimated energy use of a print, in Wh
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct EnergyReport {
+    pub hotend: f64,
+    pub bed: f64,
+    pub base: f64,
+    /// print time including waits for the heaters
+    pub duration: Duration,
+}
+
+impl EnergyReport {
+    pub fn total(&self) -> f64 {
+        self.hotend + self.bed + self.base
+    }
+}
+
+struct Heater {
+    model: HeaterModel,
+    temp: f64,
+    target: f64,
+    joules: f64,
+}
+
+impl Heater {
+    fn new(model: HeaterModel, ambient: f64) -> Self {
+        Heater {
+            model,
+            temp: ambient,
+            target: ambient,
+            joules: 0.0,
+        }
+    }
+    fn set_target(&mut self, target: Option<f32>, ambient: f64) {
+        self.target = target.map_or(ambient, |t| f64::from(t).max(ambient));
+        // cooling down is treated as instant and free
+        self.temp = self.temp.min(self.target);
+    }
+    /// Seconds until the heater reaches its target
+    fn time_to_target(&self) -> f64 {
+        (self.target - self.temp).max(0.0) / self.model.heat_rate
+    }
+    fn run(&mut self, mut secs: f64, ambient: f64) {
+        let heating = self.time_to_target().min(secs);
+        self.joules += self.model.watts * heating;
+        self.temp += self.model.heat_rate * heating;
+        secs -= heating;
+        let hold = self.model.hold_watts_per_degree * (self.target - ambient);
+        self.joules += hold.min(self.model.watts) * secs;
+    }
+}
+
+impl GCodeModel {
+    /// Estimate the energy used by a print from its heater targets and
+    /// `estimate_time`, adding the time spent waiting in M109 and M190
+    pub fn estimate_energy(&self, profile: &ThermalProfile) -> EnergyReport {
+        let ambient = profile.ambient;
+        let mut hotend = Heater::new(profile.hotend, ambient);
+        let mut bed = Heater::new(profile.bed, ambient);
+        let mut secs = 0.0;
+        for step in self.cursor() {
+            hotend.set_target(step.next.temps.hotend, ambient);
+            bed.set_target(step.next.temps.bed, ambient);
+            let wait = match &step.line.command {
+                Command::Raw(raw) => match split_raw(raw).map(|(word, _)| word).as_deref() {
+                    Some("M109") => hotend.time_to_target(),
+                    Some("M190") => bed.time_to_target(),
+                    _ => 0.0,
+                },
+                _ => 0.0,
+            };
+            let dt = wait + step_duration(&step).as_secs_f64();
+            hotend.run(dt, ambient);
+            bed.run(dt, ambient);
+            secs += dt;
+        }
+        let wh = |joules: f64| joules / 3600.0;
+        EnergyReport {
+            hotend: wh(hotend.joules),
+            bed: wh(bed.joules),
+            base: wh(profile.base_watts * secs),
+            duration: Duration::from_secs_f64(secs),
+        }
+    }
+}
+
+#[test]
+fn energy_test() {
+    let profile = ThermalProfile {
+        hotend: HeaterModel {
+            watts: 40.0,
+            heat_rate: 2.0,
+            hold_watts_per_degree: 0.1,
+        },
+        bed: HeaterModel {
+            watts: 100.0,
+            heat_rate: 0.5,
+            hold_watts_per_degree: 1.0,
+        },
+        ambient: 20.0,
+        base_watts: 10.0,
+    };
+    let gcode: GCodeModel = "M140 S60\nM190 S60\nM104 S200\nM109 S200\nG1 X600 F600"
+        .parse()
+        .unwrap();
+    let report = gcode.estimate_energy(&profile);
+    // 80s bed heat up, 90s hotend heat up, then a 60s move
+    assert_eq!(report.duration, Duration::from_secs(230));
+    // 90s at full power, then holding 180°C above ambient for 60s
+    assert!((report.hotend - (40.0 * 90.0 + 18.0 * 60.0) / 3600.0).abs() < 1e-9);
+    assert!((report.bed - (100.0 * 80.0 + 40.0 * 150.0) / 3600.0).abs() < 1e-9);
+    assert!((report.total() - report.hotend - report.bed - 2300.0 / 3600.0).abs() < 1e-9);
+}
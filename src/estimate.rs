@@ -0,0 +1,768 @@
+use crate::{
+    profile::{CornerModel, MachineLimits, PrinterProfile},
+    progress::{Cancelled, Monitor, Stage},
+    state::{classify, FeedMode, MachineState, Step},
+    Command, GCodeModel, Id, Tag,
+};
+use std::time::Duration;
+
+/// Straight line distance of a move in mm, falling back to the extruder
+/// travel for moves that only drive E
+pub fn move_length(prev: &MachineState, next: &MachineState) -> f64 {
+    let dx = (next.pos.x - prev.pos.x).to_mm();
+    let dy = (next.pos.y - prev.pos.y).to_mm();
+    let dz = (next.pos.z - prev.pos.z).to_mm();
+    let xyz = (dx * dx + dy * dy + dz * dz).sqrt();
+    if xyz > 0.0 {
+        xyz
+    } else {
+        (next.e - prev.e).to_mm().abs()
+    }
+}
+
+/// Time for one line at its commanded feedrate, ignoring acceleration,
+/// or the dwell time for G4.
+/// F is read according to the active feed mode, so inverse-time moves
+/// take `1 / F` minutes and per-revolution moves scale with spindle speed.
+pub fn step_duration(step: &Step) -> Duration {
+    if let Command::G4(dwell) = step.line.command {
+        return dwell;
+    }
+    if !matches!(
+        step.line.command,
+        Command::G0(_)
+            | Command::G1(_)
+            | Command::G5(_)
+            | Command::Arc(_)
+            | Command::G53(Some(_), _)
+    ) {
+        return Duration::ZERO;
+    }
+    let length = match &step.line.command {
+        Command::Arc(arc) => arc.length(&step.prev),
+        _ => move_length(&step.prev, &step.next),
+    };
+    let f = step.next.feedrate.mm_per_min();
+    if length <= 0.0 || f <= 0.0 {
+        return Duration::ZERO;
+    }
+    let minutes = match step.next.feed_mode {
+        FeedMode::UnitsPerMinute => length / f,
+        FeedMode::InverseTime => 1.0 / f,
+        FeedMode::UnitsPerRevolution => match step.next.spindle.speed {
+            0 => return Duration::ZERO,
+            rpm => length / (f * rpm as f64),
+        },
+    };
+    Duration::from_secs_f64(minutes * 60.0)
+}
+
+/// Time for one line with the speed of each axis capped at the machine's
+/// maximum feedrate, still ignoring acceleration
+pub fn limited_step_duration(step: &Step, limits: &MachineLimits) -> Duration {
+    let commanded = step_duration(step);
+    if commanded.is_zero() {
+        return commanded;
+    }
+    let (prev, next) = (&step.prev, &step.next);
+    let max = &limits.max_feedrate;
+    let axes = [
+        ((next.pos.x - prev.pos.x).to_mm(), max.x),
+        ((next.pos.y - prev.pos.y).to_mm(), max.y),
+        ((next.pos.z - prev.pos.z).to_mm(), max.z),
+        ((next.e - prev.e).to_mm(), max.e),
+    ];
+    axes.iter()
+        .filter(|(_, max)| *max > 0.0)
+        .map(|(distance, max)| Duration::from_secs_f64(distance.abs() / max))
+        .fold(commanded, Duration::max)
+}
+
+/// A move as the planner sees it, see `plan_move`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct Motion {
+    length: f64,
+    /// cruising speed in mm/s
+    speed: f64,
+    /// in mm/s², 0 for instant speed changes
+    accel: f64,
+    /// unit vector of the move over X, Y, Z and E
+    dir: [f64; 4],
+}
+
+/// How a line is timed on a real machine
+pub(crate) enum Segment {
+    /// takes no time and doesn't stop the toolhead
+    Idle,
+    /// takes a fixed time and leaves the toolhead at rest, like a dwell
+    Fixed(Duration),
+    Move(Motion),
+}
+
+/// Speed, acceleration and direction of a line. The acceleration follows
+/// the last M204 or `SET_VELOCITY_LIMIT` for the kind of move, falling
+/// back to `limits`, and is capped by each axis' maximum acceleration and
+/// stretched by the input shaper's smoothing.
+pub(crate) fn plan_move(step: &Step, limits: &MachineLimits) -> Segment {
+    let limited = limited_step_duration(step, limits);
+    if matches!(step.line.command, Command::G4(_))
+        || (!limited.is_zero() && step.next.feed_mode != FeedMode::UnitsPerMinute)
+    {
+        return Segment::Fixed(limited);
+    }
+    if limited.is_zero() {
+        return Segment::Idle;
+    }
+    let (prev, next) = (&step.prev, &step.next);
+    let length = match &step.line.command {
+        Command::Arc(arc) => arc.length(prev),
+        _ => move_length(prev, next),
+    };
+    let mut speed = length / limited.as_secs_f64();
+    if let Some(max) = next.velocity_limit.filter(|max| *max > 0.0) {
+        speed = speed.min(f64::from(max));
+    }
+    let accel = &next.accel;
+    let (set, configured) = match classify(prev, &step.line.command) {
+        Tag::Retraction | Tag::DeRetraction => (accel.retract, limits.retract_acceleration),
+        Tag::Extrusion | Tag::Wipe => (accel.print, limits.print_acceleration),
+        _ => (accel.travel, limits.travel_acceleration),
+    };
+    // E only counts for moves that don't drive the other axes, as in `move_length`
+    let e = if prev.pos == next.pos {
+        (next.e - prev.e).to_mm()
+    } else {
+        0.0
+    };
+    let chord = [
+        (next.pos.x - prev.pos.x).to_mm(),
+        (next.pos.y - prev.pos.y).to_mm(),
+        (next.pos.z - prev.pos.z).to_mm(),
+        e,
+    ];
+    // arcs are taken to head along their chord, and a full circle, which
+    // has none, to stop at either end
+    let chord_length = chord.iter().map(|c| c * c).sum::<f64>().sqrt();
+    let dir = match chord_length > 0.0 {
+        true => chord.map(|c| c / chord_length),
+        false => [0.0; 4],
+    };
+    let max = &limits.max_acceleration;
+    let mut accel = [max.x, max.y, max.z, max.e]
+        .iter()
+        .zip(dir)
+        .filter(|(max, dir)| **max > 0.0 && *dir != 0.0)
+        // scale each axis' limit up to the acceleration along the move
+        .map(|(max, dir)| max / dir.abs())
+        .fold(set.map_or(configured, f64::from), f64::min)
+        .max(0.0);
+    if let Some(shaper) = limits.input_shaper {
+        accel /= 1.0 + shaper.smoothing.max(0.0);
+    }
+    Segment::Move(Motion {
+        length,
+        speed,
+        accel,
+        dir,
+    })
+}
+
+/// Fastest speed in mm/s through the junction between two moves with
+/// directions `from` and `to`, where `None` means the toolhead is at rest
+fn corner_speed(
+    limits: &MachineLimits,
+    accel: f64,
+    from: Option<[f64; 4]>,
+    to: Option<[f64; 4]>,
+) -> f64 {
+    let (from, to) = (from.unwrap_or_default(), to.unwrap_or_default());
+    let at_rest = from == [0.0; 4] || to == [0.0; 4];
+    // sine of half the angle between the moves, 1 for straight on
+    let dot: f64 = from.iter().zip(to).map(|(a, b)| a * b).sum();
+    let sin = (0.5 * (1.0 + dot)).clamp(0.0, 1.0).sqrt();
+    let deviation = |scale: f64| {
+        if sin >= 1.0 {
+            f64::INFINITY
+        } else {
+            (scale * sin / (1.0 - sin)).sqrt()
+        }
+    };
+    match limits.corner {
+        CornerModel::Jerk => {
+            let jerk = &limits.jerk;
+            [jerk.x, jerk.y, jerk.z, jerk.e]
+                .iter()
+                .zip(from.iter().zip(to))
+                .map(|(jerk, (a, b))| (jerk, (b - a).abs()))
+                .filter(|(_, change)| *change > 0.0)
+                .map(|(jerk, change)| jerk.max(0.0) / change)
+                .fold(f64::INFINITY, f64::min)
+        }
+        _ if at_rest => 0.0,
+        CornerModel::JunctionDeviation(deviation_mm) => deviation(accel * deviation_mm),
+        // Klipper turns the square corner velocity into a junction deviation
+        CornerModel::SquareCornerVelocity(scv) => deviation(scv * scv * (2f64.sqrt() - 1.0)),
+    }
+}
+
+/// Fastest speed in mm/s a move entering at `entry` and leaving at `exit`
+/// gets to, short of its cruising speed if it is too short to reach it
+fn peak_speed(motion: &Motion, entry: f64, exit: f64) -> f64 {
+    let Motion {
+        length,
+        speed,
+        accel,
+        ..
+    } = *motion;
+    if accel <= 0.0 {
+        return speed;
+    }
+    let (entry, exit) = (entry.min(speed), exit.min(speed));
+    let up = (speed * speed - entry * entry) / (2.0 * accel);
+    let down = (speed * speed - exit * exit) / (2.0 * accel);
+    if up + down <= length {
+        return speed;
+    }
+    // too short to even get from one end speed to the other otherwise
+    (accel * length + 0.5 * (entry * entry + exit * exit))
+        .sqrt()
+        .max(entry.max(exit))
+}
+
+/// Seconds for a move that enters at `entry` mm/s and leaves at `exit`,
+/// speeding up to its cruising speed if there is room
+fn ramp_time(motion: &Motion, entry: f64, exit: f64) -> f64 {
+    let Motion {
+        length,
+        speed,
+        accel,
+        ..
+    } = *motion;
+    if accel <= 0.0 {
+        return length / speed;
+    }
+    let (entry, exit) = (entry.min(speed), exit.min(speed));
+    let up = (speed * speed - entry * entry) / (2.0 * accel);
+    let down = (speed * speed - exit * exit) / (2.0 * accel);
+    if up + down <= length {
+        return (speed - entry) / accel + (speed - exit) / accel + (length - up - down) / speed;
+    }
+    // never reaches cruising speed
+    let peak = (accel * length + 0.5 * (entry * entry + exit * exit)).sqrt();
+    if peak < entry.max(exit) {
+        // too short to even get from one end speed to the other
+        2.0 * length / (entry + exit)
+    } else {
+        (peak - entry) / accel + (peak - exit) / accel
+    }
+}
+
+/// Speed through the junction between two moves, capped by both
+fn junction(limits: &MachineLimits, from: Option<&Motion>, to: Option<&Motion>) -> f64 {
+    let accel = [from, to]
+        .iter()
+        .flatten()
+        .map(|motion| motion.accel)
+        .fold(f64::INFINITY, f64::min);
+    let speed = [from, to]
+        .iter()
+        .flatten()
+        .map(|motion| motion.speed)
+        .fold(f64::INFINITY, f64::min);
+    corner_speed(limits, accel, from.map(|m| m.dir), to.map(|m| m.dir)).min(speed)
+}
+
+/// Time for one line on a trapezoidal speed profile, starting and ending
+/// at rest, see `estimate_time_for` for moves that flow into each other
+pub fn accelerated_step_duration(step: &Step, limits: &MachineLimits) -> Duration {
+    match plan_move(step, limits) {
+        Segment::Idle => Duration::ZERO,
+        Segment::Fixed(duration) => duration,
+        Segment::Move(motion) => {
+            let end = junction(limits, None, Some(&motion));
+            Duration::from_secs_f64(ramp_time(&motion, end, end))
+        }
+    }
+}
+
+/// How a line runs once its neighbours are known, see `plan`
+pub(crate) enum Planned {
+    Idle,
+    Fixed(Duration),
+    /// a move entering and leaving at the given speeds in mm/s
+    Move {
+        motion: Motion,
+        entry: f64,
+        exit: f64,
+    },
+}
+
+/// Distance covered, speed and acceleration part way through a move
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct Sample {
+    /// in mm from the start of the move
+    pub distance: f64,
+    /// in mm/s
+    pub speed: f64,
+    /// in mm/s², negative while slowing down
+    pub accel: f64,
+}
+
+impl Planned {
+    pub(crate) fn secs(&self) -> f64 {
+        match self {
+            Planned::Idle => 0.0,
+            Planned::Fixed(duration) => duration.as_secs_f64(),
+            Planned::Move {
+                motion,
+                entry,
+                exit,
+            } => ramp_time(motion, *entry, *exit),
+        }
+    }
+    /// Where the toolhead is on the move `t` seconds after it starts,
+    /// following the same trapezoid as `ramp_time`. Anything that isn't a
+    /// move stands still.
+    pub(crate) fn sample(&self, t: f64) -> Sample {
+        let Planned::Move {
+            motion,
+            entry,
+            exit,
+        } = self
+        else {
+            return Sample {
+                distance: 0.0,
+                speed: 0.0,
+                accel: 0.0,
+            };
+        };
+        let Motion {
+            length,
+            speed,
+            accel,
+            ..
+        } = *motion;
+        let t = t.clamp(0.0, self.secs());
+        let at = |distance: f64, speed: f64, accel: f64| Sample {
+            distance: distance.min(length),
+            speed,
+            accel,
+        };
+        if accel <= 0.0 {
+            return at(speed * t, speed, 0.0);
+        }
+        let peak = peak_speed(motion, *entry, *exit);
+        let (entry, exit) = (entry.min(speed), exit.min(speed));
+        if peak <= entry.max(exit) {
+            // one steady change from the entry speed to the exit speed
+            let a = (exit * exit - entry * entry) / (2.0 * length);
+            return at(entry * t + 0.5 * a * t * t, entry + a * t, a);
+        }
+        let (t1, t3) = ((peak - entry) / accel, (peak - exit) / accel);
+        let d1 = (peak * peak - entry * entry) / (2.0 * accel);
+        let d3 = (peak * peak - exit * exit) / (2.0 * accel);
+        let t2 = (length - d1 - d3).max(0.0) / peak;
+        if t < t1 {
+            at(entry * t + 0.5 * accel * t * t, entry + accel * t, accel)
+        } else if t < t1 + t2 {
+            at(d1 + peak * (t - t1), peak, 0.0)
+        } else {
+            let t = (t - t1 - t2).min(t3);
+            let distance = d1 + peak * t2 + peak * t - 0.5 * accel * t * t;
+            at(distance, peak - accel * t, -accel)
+        }
+    }
+}
+
+/// Entry and exit speeds for each of `segments`, letting each move flow
+/// into the next at the speed the printer's `CornerModel` allows
+pub(crate) fn plan(segments: &[Segment], limits: &MachineLimits) -> Vec<Planned> {
+    let mut last = None;
+    let mut planned = Vec::with_capacity(segments.len());
+    for (i, segment) in segments.iter().enumerate() {
+        planned.push(match segment {
+            Segment::Idle => Planned::Idle,
+            Segment::Fixed(duration) => {
+                last = None;
+                Planned::Fixed(*duration)
+            }
+            Segment::Move(motion) => {
+                let next = segments[i + 1..]
+                    .iter()
+                    .find_map(|segment| match segment {
+                        Segment::Idle => None,
+                        Segment::Fixed(_) => Some(None),
+                        Segment::Move(next) => Some(Some(next)),
+                    })
+                    .flatten();
+                let entry = junction(limits, last, Some(motion));
+                let exit = junction(limits, Some(motion), next);
+                last = Some(motion);
+                Planned::Move {
+                    motion: *motion,
+                    entry,
+                    exit,
+                }
+            }
+        });
+    }
+    planned
+}
+
+/// How fast a move really gets on a printer, see `GCodeModel::true_speeds`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TrueSpeed {
+    pub id: Id,
+    /// in mm
+    pub length: f64,
+    /// speed asked for by F in mm/s
+    pub commanded: f64,
+    /// fastest speed the move reaches in mm/s
+    pub peak: f64,
+}
+
+impl TrueSpeed {
+    /// Whether the move gets to within 1% of its commanded speed
+    pub fn reached(&self) -> bool {
+        self.peak >= self.commanded * 0.99
+    }
+}
+
+impl GCodeModel {
+    /// The peak speed of every move on a specific printer, planned as in
+    /// `estimate_time_for`. Short moves run out of room to accelerate
+    /// before they reach their F, and axis and velocity limits cap the
+    /// rest, which is why raising slicer speeds often changes nothing.
+    pub fn true_speeds(&self, profile: &PrinterProfile) -> Vec<TrueSpeed> {
+        let limits = &profile.limits;
+        let steps = self.cursor().collect::<Vec<_>>();
+        let segments = steps
+            .iter()
+            .map(|step| plan_move(step, limits))
+            .collect::<Vec<_>>();
+        steps
+            .iter()
+            .zip(plan(&segments, limits))
+            .filter_map(|(step, planned)| match planned {
+                Planned::Move {
+                    motion,
+                    entry,
+                    exit,
+                } => Some(TrueSpeed {
+                    id: step.line.id,
+                    length: motion.length,
+                    commanded: step.next.feedrate.mm_per_sec(),
+                    peak: peak_speed(&motion, entry, exit),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+    /// The moves from `true_speeds` that never reach their commanded speed
+    pub fn speed_shortfalls(&self, profile: &PrinterProfile) -> Vec<TrueSpeed> {
+        let mut speeds = self.true_speeds(profile);
+        speeds.retain(|speed| !speed.reached());
+        speeds
+    }
+    /// Estimate the total print time from commanded feedrates
+    pub fn estimate_time(&self) -> Duration {
+        self.cursor().map(|step| step_duration(&step)).sum()
+    }
+    /// Estimate the total print time on a specific printer, respecting
+    /// its per-axis speed limits and accelerating at the values set in
+    /// the file. Moves flow into each other at the speed the printer's
+    /// `CornerModel` allows for the angle between them.
+    pub fn estimate_time_for(&self, profile: &PrinterProfile) -> Duration {
+        Monitor::unmonitored(|monitor| self.estimate_time_for_monitored(profile, monitor))
+    }
+    /// `estimate_time_for` with progress reports, stopping early if
+    /// cancelled
+    pub fn estimate_time_for_monitored(
+        &self,
+        profile: &PrinterProfile,
+        monitor: &mut Monitor,
+    ) -> Result<Duration, Cancelled> {
+        let limits = &profile.limits;
+        let total = self.lines.len();
+        let mut segments = Vec::with_capacity(total);
+        monitor.update(Stage::Simulate, 0, total)?;
+        for step in self.cursor() {
+            segments.push(plan_move(&step, limits));
+            monitor.update(Stage::Simulate, segments.len(), total)?;
+        }
+        let mut secs = 0.0;
+        for (i, planned) in plan(&segments, limits).iter().enumerate() {
+            monitor.update(Stage::Estimate, i, total)?;
+            secs += planned.secs();
+        }
+        monitor.update(Stage::Estimate, total, total)?;
+        Ok(Duration::from_secs_f64(secs))
+    }
+    /// Estimated duration of each line, parallel to `lines`
+    pub fn line_times(&self) -> Vec<Duration> {
+        self.cursor().map(|step| step_duration(&step)).collect()
+    }
+    /// Estimated time into the print at which each line finishes,
+    /// parallel to `lines`
+    pub fn elapsed_times(&self) -> Vec<Duration> {
+        let mut elapsed = Duration::ZERO;
+        self.line_times()
+            .into_iter()
+            .map(|time| {
+                elapsed += time;
+                elapsed
+            })
+            .collect()
+    }
+    /// Line being run `time` into the print by `estimate_time`, or `None`
+    /// once the print is over. Lines that take no time are never returned.
+    pub fn line_at_time(&self, time: Duration) -> Option<Id> {
+        let mut elapsed = Duration::ZERO;
+        for step in self.cursor() {
+            elapsed += step_duration(&step);
+            if elapsed > time {
+                return Some(step.line.id);
+            }
+        }
+        None
+    }
+    /// Time into the print by `estimate_time` at which the line with `id`
+    /// starts, or `None` if it isn't in the model
+    pub fn time_of_line(&self, id: Id) -> Option<Duration> {
+        let mut elapsed = Duration::ZERO;
+        for step in self.cursor() {
+            if step.line.id == id {
+                return Some(elapsed);
+            }
+            elapsed += step_duration(&step);
+        }
+        None
+    }
+}
+
+#[test]
+fn line_times_test() {
+    use crate::emit::Emit;
+    let gcode: GCodeModel = "G1 X10 F600\nM106 S255\nG1 X20 ; move\nG4 P500"
+        .parse()
+        .unwrap();
+    let secs = |times: Vec<Duration>| times.iter().map(Duration::as_secs_f64).collect::<Vec<_>>();
+    assert_eq!(secs(gcode.line_times()), [1.0, 0.0, 1.0, 0.5]);
+    assert_eq!(secs(gcode.elapsed_times()), [1.0, 1.0, 2.0, 2.5]);
+    assert_eq!(
+        gcode.emit(true),
+        "G1 X10 F600 ; time 1.000s elapsed 1.000s\nM106 S255 ; time 0.000s elapsed 1.000s\nG1 X20 ; move ; time 1.000s elapsed 2.000s\nG4 P500 ; time 0.500s elapsed 2.500s\n"
+    );
+}
+
+#[test]
+fn time_lookup_test() {
+    let gcode: GCodeModel = "G1 X10 F600\nM106 S255\nG1 X20\nG4 P500\nG1 X30"
+        .parse()
+        .unwrap();
+    let ids = gcode.lines.iter().map(|line| line.id).collect::<Vec<_>>();
+    assert_eq!(gcode.line_at_time(Duration::ZERO), Some(ids[0]));
+    assert_eq!(
+        gcode.line_at_time(Duration::from_millis(1500)),
+        Some(ids[2])
+    );
+    assert_eq!(
+        gcode.line_at_time(Duration::from_millis(2200)),
+        Some(ids[3])
+    );
+    assert_eq!(gcode.line_at_time(Duration::from_secs(4)), None);
+    assert_eq!(gcode.time_of_line(ids[2]), Some(Duration::from_secs(1)));
+    assert_eq!(
+        gcode.time_of_line(ids[4]),
+        Some(Duration::from_millis(2500))
+    );
+    // a line starts when the one before it ends
+    let start = gcode.time_of_line(ids[4]).unwrap();
+    assert_eq!(gcode.line_at_time(start), Some(ids[4]));
+}
+
+#[test]
+fn feed_mode_test() {
+    // 10mm at 600mm/min is one second
+    let gcode: GCodeModel = "G1 X10 F600".parse().unwrap();
+    assert_eq!(gcode.estimate_time(), Duration::from_secs(1));
+    // inverse time: F2 means the move takes half a minute regardless of length
+    let gcode: GCodeModel = "G93\nG1 X10 F2\nG1 X1000 F2".parse().unwrap();
+    assert_eq!(gcode.estimate_time(), Duration::from_secs(60));
+    // per revolution: 0.1mm/rev at 600rpm is 60mm/min
+    let gcode: GCodeModel = "M3 S600\nG95\nG1 X10 F0.1".parse().unwrap();
+    assert_eq!(gcode.estimate_time(), Duration::from_secs(10));
+    // switching back to units per minute
+    let gcode: GCodeModel = "G93\nG1 X10 F2\nG94\nG1 X20 F600".parse().unwrap();
+    assert_eq!(gcode.estimate_time(), Duration::from_secs(31));
+    // a move after the mode word isn't dropped
+    let gcode: GCodeModel = "G94 G1 X10 F100".parse().unwrap();
+    assert_eq!(
+        gcode.lines[0].command,
+        Command::Raw(String::from("G94 G1 X10 F100"))
+    );
+}
+
+#[test]
+fn axis_limit_test() {
+    // 60mm of Z at 6000mm/min would take 0.6s but the Ender's Z tops out at 5mm/s
+    let gcode: GCodeModel = "G1 Z60 F6000\nG1 X100 F6000".parse().unwrap();
+    let profile = PrinterProfile::ender_3();
+    assert_eq!(gcode.estimate_time(), Duration::from_secs_f64(1.6));
+    let limited: Duration = gcode
+        .cursor()
+        .map(|step| limited_step_duration(&step, &profile.limits))
+        .sum();
+    assert_eq!(limited, Duration::from_secs(12) + Duration::from_secs(1));
+    // ramping Z at 100mm/s² from its 0.4mm/s jerk, then X at 500mm/s² from
+    // the same 0.4mm/s through the corner down to its own 8mm/s jerk
+    let z = 2.0 * 4.6 / 100.0 + (60.0 - 2.0 * 0.1242) / 5.0;
+    let x = 99.6 / 500.0 + 92.0 / 500.0 + (100.0 - 9.99984 - 9.936) / 100.0;
+    let secs = gcode.estimate_time_for(&profile).as_secs_f64();
+    assert!((secs - z - x).abs() < 1e-6);
+}
+
+#[test]
+fn acceleration_change_test() {
+    let mut profile = PrinterProfile::ender_3();
+    profile.limits.max_acceleration.x = 0.0;
+    profile.limits.jerk.x = 0.0;
+    // 10mm at 100mm/s is all speeding up and slowing down at 1000mm/s²,
+    // and never reaches full speed at 100mm/s²
+    let gcode: GCodeModel = "G1 X10 F6000\nM204 T100\nG1 X0".parse().unwrap();
+    let times = gcode
+        .cursor()
+        .map(|step| accelerated_step_duration(&step, &profile.limits).as_secs_f64())
+        .collect::<Vec<_>>();
+    assert!((times[0] - 0.2).abs() < 1e-9);
+    assert!((times[2] - 2.0 * 10f64.sqrt() / 10.0).abs() < 1e-9);
+    // Klipper sets one acceleration for everything
+    let gcode: GCodeModel = "SET_VELOCITY_LIMIT ACCEL=100 VELOCITY=50\nG1 X100 F6000"
+        .parse()
+        .unwrap();
+    let step = gcode.cursor().nth(1).unwrap();
+    assert_eq!(step.next.accel.print, Some(100.0));
+    let secs = accelerated_step_duration(&step, &profile.limits).as_secs_f64();
+    assert!((secs - 2.5).abs() < 1e-9);
+}
+
+#[test]
+fn input_shaper_test() {
+    use crate::profile::InputShaper;
+    let mut profile = PrinterProfile::voron_2_4();
+    let gcode: GCodeModel = "G1 X100 F12000".parse().unwrap();
+    let step = gcode.cursor().next().unwrap();
+    profile.limits.input_shaper = None;
+    let plain = accelerated_step_duration(&step, &profile.limits).as_secs_f64();
+    // 200mm/s from rest at 3000mm/s²
+    assert!(
+        (plain - (2.0 * 200.0 / 3000.0 + (100.0 - 2.0 * 40000.0 / 6000.0) / 200.0)).abs() < 1e-9
+    );
+    profile.limits.input_shaper = Some(InputShaper { smoothing: 0.2 });
+    let shaped = accelerated_step_duration(&step, &profile.limits).as_secs_f64();
+    // ramps take 20% longer and cover 20% more distance
+    assert!(
+        (shaped - (1.2 * 2.0 * 200.0 / 3000.0 + (100.0 - 1.2 * 2.0 * 40000.0 / 6000.0) / 200.0))
+            .abs()
+            < 1e-9
+    );
+    assert!(shaped > plain);
+    // and whole files take longer with it, whatever the corner model
+    let square: GCodeModel = "G1 X20 F6000\nG1 Y20\nG1 X0\nG1 Y0".parse().unwrap();
+    let shaped = square.estimate_time_for(&profile);
+    profile.limits.input_shaper = None;
+    assert!(shaped > square.estimate_time_for(&profile));
+}
+
+#[test]
+fn corner_model_test() {
+    let mut limits = PrinterProfile::ender_3().limits;
+    let (x, y, back) = (
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [-1.0, 0.0, 0.0, 0.0],
+    );
+    let corner = |limits: &MachineLimits, from, to| corner_speed(limits, 1000.0, from, to);
+    // jerk allows 8mm/s on each axis, including starting from rest
+    assert_eq!(corner(&limits, Some(x), Some(y)), 8.0);
+    assert_eq!(corner(&limits, None, Some(x)), 8.0);
+    assert_eq!(corner(&limits, Some(x), Some(back)), 4.0);
+    limits.corner = CornerModel::SquareCornerVelocity(5.0);
+    assert!((corner(&limits, Some(x), Some(y)) - 5.0).abs() < 1e-9);
+    assert_eq!(corner(&limits, Some(x), Some(x)), f64::INFINITY);
+    assert_eq!(corner(&limits, Some(x), Some(back)), 0.0);
+    assert_eq!(corner(&limits, None, Some(x)), 0.0);
+    limits.corner = CornerModel::JunctionDeviation(0.05);
+    let sin = 0.5f64.sqrt();
+    let expected = (1000.0 * 0.05 * sin / (1.0 - sin)).sqrt();
+    assert!((corner(&limits, Some(x), Some(y)) - expected).abs() < 1e-9);
+
+    // a tighter junction deviation slows every corner of a square
+    let square: GCodeModel = "G1 X20 F6000\nG1 Y20\nG1 X0\nG1 Y0".parse().unwrap();
+    let mut profile = PrinterProfile::ender_3();
+    let mut time = |corner| {
+        profile.limits.corner = corner;
+        square.estimate_time_for(&profile)
+    };
+    let loose = time(CornerModel::JunctionDeviation(0.1));
+    let tight = time(CornerModel::JunctionDeviation(0.01));
+    assert!(tight > loose);
+    assert!(time(CornerModel::Jerk) < tight);
+}
+
+#[test]
+fn cancel_estimate_test() {
+    use std::sync::atomic::AtomicBool;
+    let gcode: GCodeModel = "G1 X10 F600\nG1 X20".parse().unwrap();
+    let profile = PrinterProfile::ender_3();
+    let cancel = AtomicBool::new(false);
+    let mut monitor = Monitor::new().cancel(&cancel);
+    let time = gcode.estimate_time_for_monitored(&profile, &mut monitor);
+    assert_eq!(time, Ok(gcode.estimate_time_for(&profile)));
+    cancel.store(true, std::sync::atomic::Ordering::Relaxed);
+    let time = gcode.estimate_time_for_monitored(&profile, &mut monitor);
+    assert_eq!(time, Err(Cancelled));
+}
+
+#[test]
+fn dwell_test() {
+    use crate::emit::Emit;
+    let gcode: GCodeModel = "G4 P500\nG4 S2\nG4".parse().unwrap();
+    assert_eq!(
+        gcode.lines[0].command,
+        Command::G4(Duration::from_millis(500))
+    );
+    assert_eq!(gcode.estimate_time(), Duration::from_millis(2500));
+    // dwells emit exactly, and malformed ones stay as written
+    let gcode: GCodeModel = "G4 P0.5\nG4 S1.0005\nG4 S2\nG4\nG4 X1".parse().unwrap();
+    assert_eq!(
+        gcode.lines[1].command,
+        Command::G4(Duration::from_micros(1_000_500))
+    );
+    assert_eq!(
+        gcode.emit(false),
+        "G4 P0.5\nG4 P1000.5\nG4 S2\nG4 P0\nG4 X1\n"
+    );
+}
+
+#[test]
+fn true_speed_test() {
+    let gcode: GCodeModel = "G1 X100 F6000\nG1 Y1\nG1 X0 Y2 F30000".parse().unwrap();
+    let mut profile = PrinterProfile::ender_3();
+    profile.limits.corner = CornerModel::JunctionDeviation(0.0);
+    let speeds = gcode.true_speeds(&profile);
+    assert_eq!(speeds.len(), 3);
+    // plenty of room to reach 100mm/s
+    assert_eq!(speeds[0].peak, 100.0);
+    assert!(speeds[0].reached());
+    // 1mm from rest to rest at the Y axis' 500mm/s² only gets to sqrt(500 * 1)
+    assert!((speeds[1].peak - 500f64.sqrt()).abs() < 1e-9);
+    // 100mm isn't enough room to get to 500mm/s and back
+    assert_eq!(speeds[2].commanded, 500.0);
+    assert!((speeds[2].peak - (500.0 * speeds[2].length).sqrt()).abs() < 0.1);
+    let shortfalls = gcode.speed_shortfalls(&profile);
+    assert_eq!(
+        shortfalls.iter().map(|s| s.id).collect::<Vec<_>>(),
+        [gcode.lines[1].id, gcode.lines[2].id]
+    );
+}
@@ -0,0 +1,124 @@
+use crate::{
+    estimate::{move_length, plan, plan_move, Planned, Sample},
+    profile::PrinterProfile,
+    GCodeModel,
+};
+use std::{fmt::Write, time::Duration};
+
+const HEADER: &str = "time,x,y,z,e,speed,acceleration,flow,fan,hotend,bed";
+
+/// The simulated print sampled every `dt`, as CSV with one row per sample
+/// and the columns:
+///
+/// - `time` in s
+/// - `x`, `y`, `z` and `e` positions in mm
+/// - `speed` in mm/s and `acceleration` in mm/s² along the path, negative
+///   while slowing down
+/// - `flow`, the volumetric flow in mm³/s
+/// - `fan` from 0 to 255
+/// - `hotend` and `bed` targets in °C, empty until set
+///
+/// Moves follow the same trapezoidal profiles as `estimate_time_for`, so
+/// the last row is at or just past the estimated print time. Meant for
+/// plotting, e.g. to find the moves behind ringing or flow problems.
+///
+/// # Panics
+/// If `dt` is zero
+pub fn simulation_csv(model: &GCodeModel, profile: &PrinterProfile, dt: Duration) -> String {
+    assert!(!dt.is_zero(), "sampling interval must not be zero");
+    let limits = &profile.limits;
+    let filament_area = std::f64::consts::PI * (profile.filament_diameter / 2.0).powi(2);
+    let steps = model.cursor().collect::<Vec<_>>();
+    let segments = steps
+        .iter()
+        .map(|step| plan_move(step, limits))
+        .collect::<Vec<_>>();
+    let planned = plan(&segments, limits);
+    let total = planned.iter().map(Planned::secs).sum::<f64>();
+    let dt = dt.as_secs_f64();
+
+    let mut out = String::from(HEADER) + "\n";
+    let (mut line, mut start) = (0, 0.0);
+    for n in 0.. {
+        let time = n as f64 * dt;
+        // move on to the line running at `time`, or stay on the last one
+        while line + 1 < steps.len() && start + planned[line].secs() <= time {
+            start += planned[line].secs();
+            line += 1;
+        }
+        let Some(step) = steps.get(line) else {
+            break;
+        };
+        let secs = planned[line].secs();
+        let length = move_length(&step.prev, &step.next);
+        let sample = match planned[line] {
+            // moves timed by G93/G95 run at a steady speed
+            Planned::Fixed(_) if length > 0.0 && secs > 0.0 => Sample {
+                distance: length * ((time - start) / secs).min(1.0),
+                speed: length / secs,
+                accel: 0.0,
+            },
+            ref planned => planned.sample(time - start),
+        };
+        let (prev, next) = (&step.prev, &step.next);
+        let done = if secs > 0.0 && length > 0.0 && time < start + secs {
+            sample.distance / length
+        } else {
+            1.0
+        };
+        let along = |a: f64, b: f64| a + (b - a) * done;
+        let e_rate = if length > 0.0 {
+            (next.e - prev.e).to_mm() / length
+        } else {
+            0.0
+        };
+        let temp = |t: Option<f32>| t.map(|t| t.to_string()).unwrap_or_default();
+        let _ = writeln!(
+            out,
+            "{time:.4},{:.4},{:.4},{:.4},{:.5},{:.3},{:.1},{:.3},{},{},{}",
+            along(prev.pos.x.to_mm(), next.pos.x.to_mm()),
+            along(prev.pos.y.to_mm(), next.pos.y.to_mm()),
+            along(prev.pos.z.to_mm(), next.pos.z.to_mm()),
+            along(prev.e.to_mm(), next.e.to_mm()),
+            sample.speed,
+            sample.accel,
+            (e_rate * sample.speed * filament_area).max(0.0),
+            next.fan,
+            temp(next.temps.hotend),
+            temp(next.temps.bed),
+        );
+        if time >= total {
+            break;
+        }
+    }
+    out
+}
+
+#[test]
+fn simulation_csv_test() {
+    let gcode: GCodeModel = "M104 S210\nM204 P1000\nG1 X10 E1 F600\nM106 S255\nG4 P500"
+        .parse()
+        .unwrap();
+    let mut profile = PrinterProfile::prusa_mk4();
+    profile.limits.print_acceleration = 1000.0;
+    let csv = simulation_csv(&gcode, &profile, Duration::from_millis(100));
+    let rows = csv.lines().collect::<Vec<_>>();
+    assert_eq!(rows[0], HEADER);
+    assert_eq!(
+        rows[1],
+        "0.0000,0.0000,0.0000,0.0000,0.00000,8.000,1000.0,1.924,0,210,"
+    );
+    // starts at the 8mm/s jerk limit and reaches 10mm/s after 2ms and 0.018mm
+    let cruising = rows[5].split(',').collect::<Vec<_>>();
+    assert_eq!(cruising[..3], ["0.4000", "3.9980", "0.0000"]);
+    assert_eq!(cruising[5..7], ["10.000", "0.0"]);
+    assert_eq!(
+        cruising[7],
+        format!("{:.3}", 0.1 * 10.0 * std::f64::consts::PI * 0.875 * 0.875)
+    );
+    // dwelling with the fan on
+    let last = rows.last().unwrap().split(',').collect::<Vec<_>>();
+    assert_eq!(last[1], "10.0000");
+    assert_eq!(last[5..9], ["0.000", "0.0", "0.000", "255"]);
+    assert_eq!(rows.len(), 18);
+}
@@ -1,19 +1,195 @@
-use crate::Path;
+use crate::{GCodeParseError, Path};
+use std::io::{BufRead, BufReader, Read};
+
+/// How the bytes of a file are turned into text before parsing
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Decoding {
+    /// drop a UTF-8 byte order mark at the start of the file
+    pub strip_bom: bool,
+    /// replace bytes that aren't valid UTF-8, e.g. Latin-1 characters in
+    /// comments written by old Windows slicers, rather than failing
+    pub lossy: bool,
+}
+
+impl Default for Decoding {
+    fn default() -> Self {
+        Decoding {
+            strip_bom: true,
+            lossy: false,
+        }
+    }
+}
 
 // check that path is to a file with the correct extension and read to String
 pub fn open_gcode_file(path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    open_gcode_file_with(path, Decoding::default())
+}
+
+pub fn open_gcode_file_with(
+    path: &Path,
+    decoding: Decoding,
+) -> Result<String, Box<dyn std::error::Error>> {
     // check path extension
     if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
         match extension {
-            "gcode" => return Ok(String::from_utf8(std::fs::read(path)?)?),
+            "gcode" => return Ok(decode(std::fs::read(path)?, decoding)?),
             _ => return Err(Box::from(format!("invalid file extension: {}", extension))),
         }
     }
     Err(Box::from("unable to parse file extension"))
 }
 
+pub(crate) fn decode(
+    mut bytes: Vec<u8>,
+    decoding: Decoding,
+) -> Result<String, std::string::FromUtf8Error> {
+    if decoding.strip_bom && bytes.starts_with(b"\xEF\xBB\xBF") {
+        bytes.drain(..3);
+    }
+    if decoding.lossy {
+        return Ok(String::from_utf8_lossy(&bytes).into_owned());
+    }
+    String::from_utf8(bytes)
+}
+
+/// Options for `GCodeModel::from_reader`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ReaderConfig {
+    /// bytes read from the underlying reader at a time
+    pub buffer_size: usize,
+    /// longest line accepted in bytes, not counting the line ending. Binary
+    /// files passed off as gcode can hold "lines" many MB long.
+    pub max_line_length: usize,
+    pub decoding: Decoding,
+}
+
+impl Default for ReaderConfig {
+    fn default() -> Self {
+        ReaderConfig {
+            buffer_size: 64 * 1024,
+            max_line_length: 64 * 1024,
+            decoding: Decoding::default(),
+        }
+    }
+}
+
+/// Why `GCodeModel::from_reader` failed
+#[derive(Debug)]
+pub enum ReadError {
+    Io(std::io::Error),
+    /// line `line`, counting from 1, is longer than `max_line_length`
+    LineTooLong {
+        line: usize,
+        limit: usize,
+    },
+    /// line `line`, counting from 1, isn't valid UTF-8
+    InvalidUtf8 {
+        line: usize,
+    },
+    Parse(GCodeParseError),
+}
+
+impl std::fmt::Display for ReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadError::Io(e) => write!(f, "failed to read input: {e}"),
+            ReadError::LineTooLong { line, limit } => {
+                write!(f, "line {line} is longer than {limit} bytes")
+            }
+            ReadError::InvalidUtf8 { line } => write!(f, "line {line} is not valid UTF-8"),
+            ReadError::Parse(e) => write!(f, "failed to parse input: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+impl From<std::io::Error> for ReadError {
+    fn from(e: std::io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+impl From<GCodeParseError> for ReadError {
+    fn from(e: GCodeParseError) -> Self {
+        ReadError::Parse(e)
+    }
+}
+
+/// Read all of `reader` as text a line at a time, never buffering more
+/// than one line past `max_line_length`
+pub(crate) fn read_text(reader: impl Read, config: &ReaderConfig) -> Result<String, ReadError> {
+    let mut reader = BufReader::with_capacity(config.buffer_size.max(1), reader);
+    let mut text = String::new();
+    let mut buf = Vec::new();
+    for line in 1.. {
+        buf.clear();
+        // room for the longest line and a CRLF
+        let limit = config.max_line_length as u64 + 2;
+        if (&mut reader).take(limit).read_until(b'\n', &mut buf)? == 0 {
+            break;
+        }
+        let ending = if buf.ends_with(b"\r\n") {
+            2
+        } else {
+            usize::from(buf.ends_with(b"\n") || buf.ends_with(b"\r"))
+        };
+        if buf.len() - ending > config.max_line_length {
+            return Err(ReadError::LineTooLong {
+                line,
+                limit: config.max_line_length,
+            });
+        }
+        if line == 1 && config.decoding.strip_bom && buf.starts_with(b"\xEF\xBB\xBF") {
+            buf.drain(..3);
+        }
+        match std::str::from_utf8(&buf) {
+            Ok(s) => text += s,
+            Err(_) if config.decoding.lossy => text += &String::from_utf8_lossy(&buf),
+            Err(_) => return Err(ReadError::InvalidUtf8 { line }),
+        }
+    }
+    Ok(text)
+}
+
 #[test]
 fn open_gcode_file_test() {
     let path = Path::new("src/tests/test.gcode");
     let _ = open_gcode_file(&path).unwrap();
 }
+
+#[test]
+fn decode_test() {
+    let bytes = b"\xEF\xBB\xBFG90 ; caf\xE9\n".to_vec();
+    assert!(decode(bytes.clone(), Decoding::default()).is_err());
+    let lossy = Decoding {
+        lossy: true,
+        ..Default::default()
+    };
+    assert_eq!(decode(bytes.clone(), lossy).unwrap(), "G90 ; caf\u{FFFD}\n");
+    let keep_bom = Decoding {
+        strip_bom: false,
+        lossy: true,
+    };
+    assert!(decode(bytes, keep_bom).unwrap().starts_with('\u{FEFF}'));
+}
+
+#[test]
+fn read_text_test() {
+    let config = ReaderConfig {
+        buffer_size: 4,
+        max_line_length: 8,
+        ..Default::default()
+    };
+    let text = read_text(&b"\xEF\xBB\xBFG90\r\nG1 X100\nM82"[..], &config).unwrap();
+    assert_eq!(text, "G90\r\nG1 X100\nM82");
+    let long = [b"G90\n".as_slice(), &[b'A'; 1000]].concat();
+    assert!(matches!(
+        read_text(long.as_slice(), &config),
+        Err(ReadError::LineTooLong { line: 2, limit: 8 })
+    ));
+    assert!(matches!(
+        read_text(&b"G90\n; caf\xE9"[..], &config),
+        Err(ReadError::InvalidUtf8 { line: 2 })
+    ));
+}
@@ -0,0 +1,65 @@
+use crate::{emit::Emit, Command, GCodeModel};
+
+/// 64 bit FNV-1a, which unlike `DefaultHasher` is fixed across Rust
+/// versions, so hashes can be stored and compared later
+pub(crate) struct Fnv(pub(crate) u64);
+
+impl Fnv {
+    pub(crate) fn new() -> Self {
+        Fnv(0xcbf29ce484222325)
+    }
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+impl GCodeModel {
+    /// Hash of the commands alone, ignoring comments, blank lines, spacing
+    /// and how numbers were written, so two files that drive the machine
+    /// the same way hash the same. Stable across runs and versions, for use
+    /// as a cache or deduplication key.
+    pub fn content_hash(&self) -> u64 {
+        let mut hash = Fnv::new();
+        for line in &self.lines {
+            let text = match &line.command {
+                Command::Blank => continue,
+                Command::Raw(raw) if raw.trim().is_empty() => continue,
+                // raw text can't be normalized beyond its spacing, but the
+                // command word is case insensitive
+                Command::Raw(raw) => {
+                    let mut words = raw.split_whitespace();
+                    let word = words.next().unwrap_or_default().to_uppercase();
+                    std::iter::once(word)
+                        .chain(words.map(String::from))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                }
+                command => command
+                    .emit(false)
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            };
+            hash.write(text.as_bytes());
+            hash.write(b"\n");
+        }
+        hash.0
+    }
+}
+
+#[test]
+fn content_hash_test() {
+    let a: GCodeModel = "G1 X10.0 Y5 F600 ; wall\nM104   S200\n\nm107"
+        .parse()
+        .unwrap();
+    let b: GCodeModel = "; sliced elsewhere\nG1 X10 Y5.000 F600\r\nM104 S200\r\nM107"
+        .parse()
+        .unwrap();
+    let c: GCodeModel = "G1 X10 Y6 F600\nM104 S200\nM107".parse().unwrap();
+    assert_eq!(a.content_hash(), b.content_hash());
+    assert_ne!(a.content_hash(), c.content_hash());
+    assert_eq!(GCodeModel::default().content_hash(), 0xcbf29ce484222325);
+}
@@ -0,0 +1,150 @@
+use crate::{
+    estimate::move_length, geometry::Bounds, layers::Layer, parsers::split_raw,
+    profile::PrinterProfile, Command, GCodeModel, Tag,
+};
+use std::collections::HashMap;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Lowest and highest of a value over the first layer
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Span<T> {
+    pub min: T,
+    pub max: T,
+}
+
+impl<T: Copy + PartialOrd> Span<T> {
+    fn include(span: &mut Option<Span<T>>, value: T) {
+        match span {
+            Some(span) if value < span.min => span.min = value,
+            Some(span) if value > span.max => span.max = value,
+            Some(_) => {}
+            None => {
+                *span = Some(Span {
+                    min: value,
+                    max: value,
+                })
+            }
+        }
+    }
+}
+
+/// How the first layer is printed, see `GCodeModel::first_layer_report`
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct FirstLayerReport {
+    pub layer: Layer,
+    /// box around the layer's extrusions
+    pub bounds: Option<Bounds>,
+    /// area covered by extrusions in mm², from their length and
+    /// estimated width
+    pub area: f64,
+    /// `area` as a fraction of the bed
+    pub coverage: f64,
+    /// commanded speed of extrusion moves in mm/s
+    pub speed: Option<Span<f64>>,
+    /// fan speed, 0-255, over the layer's extrusions
+    pub fan: Option<Span<u8>>,
+    /// hotend and bed targets in °C while the layer prints, `None` if
+    /// never set
+    pub hotend: Option<Span<f32>>,
+    pub bed: Option<Span<f32>>,
+    /// whether the bed is probed or a saved mesh loaded before the layer
+    pub mesh_leveling: bool,
+}
+
+impl GCodeModel {
+    /// Report on the first layer, where most failed prints go wrong, or
+    /// `None` if nothing is extruded
+    pub fn first_layer_report(&self, profile: &PrinterProfile) -> Option<FirstLayerReport> {
+        let layer = self.layers().into_iter().next()?;
+        let widths = self
+            .extrusion_widths(profile)
+            .into_iter()
+            .map(|w| (w.id, w.width))
+            .collect::<HashMap<_, _>>();
+        let mut report = FirstLayerReport {
+            bounds: None,
+            area: 0.0,
+            coverage: 0.0,
+            speed: None,
+            fan: None,
+            hotend: None,
+            bed: None,
+            mesh_leveling: false,
+            layer: layer.clone(),
+        };
+        let mut bounds: Option<Bounds> = None;
+        for step in self.cursor().take(layer.end) {
+            if step.line.command.tag() != Tag::Extrusion {
+                let levels = match &step.line.command {
+                    Command::G29(_) => true,
+                    Command::M420(m420) => m420.enable == Some(true),
+                    Command::Raw(raw) => split_raw(raw).is_some_and(|(word, rest)| {
+                        word == "BED_MESH_CALIBRATE"
+                            || (word == "BED_MESH_PROFILE" && rest.to_uppercase().contains("LOAD"))
+                    }),
+                    _ => false,
+                };
+                report.mesh_leveling |= levels && report.speed.is_none();
+                continue;
+            }
+            let length = move_length(&step.prev, &step.next);
+            report.area += length * widths.get(&step.line.id).copied().unwrap_or_default();
+            Span::include(&mut report.speed, step.next.feedrate.mm_per_sec());
+            Span::include(&mut report.fan, step.next.fan);
+            if let Some(hotend) = step.next.temps.hotend {
+                Span::include(&mut report.hotend, hotend);
+            }
+            if let Some(bed) = step.next.temps.bed {
+                Span::include(&mut report.bed, bed);
+            }
+            for pos in [step.prev.pos, step.next.pos] {
+                match bounds.as_mut() {
+                    Some(bounds) => bounds.include(pos),
+                    None => bounds = Some(Bounds { min: pos, max: pos }),
+                }
+            }
+        }
+        report.bounds = bounds;
+        report.coverage = report.area / profile.bed_shape.area();
+        Some(report)
+    }
+}
+
+#[test]
+fn first_layer_report_test() {
+    let input = "M140 S60\nM104 S215\nG28\nG29\nM106 S0\nG1 Z0.2 F600\nM83\nG1 X10 Y10 F1200\nG1 X110 E3.3 F900\nM106 S128\nG1 Y20 E0.33 F1800\nG1 Z0.4\nG1 X10 E3.3";
+    let gcode: GCodeModel = input.parse().unwrap();
+    let report = gcode
+        .first_layer_report(&PrinterProfile::prusa_mk4())
+        .unwrap();
+    assert_eq!(report.layer.end, 11);
+    assert_eq!(
+        report.speed,
+        Some(Span {
+            min: 15.0,
+            max: 30.0
+        })
+    );
+    assert_eq!(report.fan, Some(Span { min: 0, max: 128 }));
+    assert_eq!(
+        report.hotend,
+        Some(Span {
+            min: 215.0,
+            max: 215.0
+        })
+    );
+    assert_eq!(report.bed.map(|bed| bed.max), Some(60.0));
+    assert!(report.mesh_leveling);
+    assert!(report.area > 40.0 && report.area < 60.0);
+    assert!(report.coverage > 0.0 && report.coverage < 0.01);
+    let bounds = report.bounds.unwrap();
+    assert_eq!((bounds.min.x.to_mm(), bounds.max.y.to_mm()), (10.0, 20.0));
+
+    let unleveled: GCodeModel = "G28\nG1 Z0.2\nG1 X10 E1\nG29".parse().unwrap();
+    let report = unleveled.first_layer_report(&PrinterProfile::prusa_mk4());
+    assert!(!report.unwrap().mesh_leveling);
+}
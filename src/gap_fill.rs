@@ -0,0 +1,276 @@
+use crate::{
+    state::{MachineState, Positioning},
+    Command, ExtrusionLength, GCodeModel, Length, Tag, G1, G92,
+};
+use std::collections::HashMap;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Which runs `GCodeModel::merge_gap_fill` joins up
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GapFillLimits {
+    /// travels this short or shorter are printed through
+    pub max_gap: Length,
+    /// only extrusions this short or shorter on both sides of the travel
+    /// count as gap fill
+    pub max_extrusion: Length,
+}
+
+impl Default for GapFillLimits {
+    fn default() -> Self {
+        GapFillLimits {
+            max_gap: Length::from(0.5),
+            max_extrusion: Length::from(2.0),
+        }
+    }
+}
+
+fn xy(prev: &MachineState, next: &MachineState) -> (f64, f64) {
+    (
+        (next.pos.x - prev.pos.x).to_mm(),
+        (next.pos.y - prev.pos.y).to_mm(),
+    )
+}
+
+fn xy_length(prev: &MachineState, next: &MachineState) -> f64 {
+    let (dx, dy) = xy(prev, next);
+    dx.hypot(dy)
+}
+
+/// PrusaSlicer calls it `Gap fill` and OrcaSlicer `Gap infill`; Cura
+/// doesn't mark it separately
+fn is_gap_fill(feature: &str) -> bool {
+    feature.to_lowercase().contains("gap")
+}
+
+impl GCodeModel {
+    /// Print through the tiny travels between the short extrusions of gap
+    /// fill (lines under a `;TYPE:Gap fill` or `;TYPE:Gap infill`
+    /// comment), so the nozzle doesn't stop and start (and blob) for
+    /// every piece. Each travel becomes an extrusion at the flow of the
+    /// one before it, and every later absolute E grows by the filament
+    /// added. Only flat travels with nothing but lines that don't move
+    /// between the two extrusions are merged. Afterwards, neighbouring
+    /// gap fill extrusions that carry on in a straight line at the same
+    /// flow and feedrate are joined into one. Returns the number of
+    /// travels printed through.
+    pub fn merge_gap_fill(&mut self, limits: &GapFillLimits) -> usize {
+        self.record_transform("merge_gap_fill", format!("{limits:?}"));
+        let states = self
+            .cursor()
+            .map(|step| (step.prev, step.next))
+            .collect::<Vec<_>>();
+        let moves = |i: usize| states[i].0.pos != states[i].1.pos || states[i].0.e != states[i].1.e;
+        let short =
+            |i: usize| xy_length(&states[i].0, &states[i].1) <= limits.max_extrusion.to_mm();
+        let tag = |i: usize| self.lines[i].command.tag();
+        let mut current = false;
+        let gap_fill = self
+            .lines
+            .iter()
+            .map(|line| {
+                if let Some(feature) = self.feature(line) {
+                    current = is_gap_fill(feature);
+                }
+                current
+            })
+            .collect::<Vec<_>>();
+        // filament to add on each merged travel, by index
+        let mut bridges = HashMap::new();
+        // the extrusion after each merged travel, by index
+        let mut after = HashMap::new();
+        let mut last: Option<usize> = None;
+        let mut travel: Option<usize> = None;
+        for i in 0..self.lines.len() {
+            if !moves(i) {
+                continue;
+            }
+            match (tag(i), last, travel) {
+                (Tag::Extrusion, Some(a), Some(t))
+                    if short(a) && short(i) && gap_fill[a] && gap_fill[t] && gap_fill[i] =>
+                {
+                    let (prev, next) = states[a];
+                    let flow = (next.e - prev.e).to_mm() / xy_length(&prev, &next);
+                    let gap = xy_length(&states[t].0, &states[t].1);
+                    bridges.insert(t, ExtrusionLength::from_mm(flow * gap));
+                    after.insert(t, i);
+                }
+                (Tag::Travel, Some(_), None)
+                    if self.lines[i].command.as_move().is_some()
+                        && states[i].0.pos.z == states[i].1.pos.z
+                        && xy_length(&states[i].0, &states[i].1) <= limits.max_gap.to_mm() =>
+                {
+                    travel = Some(i);
+                    continue;
+                }
+                _ => {}
+            }
+            last = (tag(i) == Tag::Extrusion).then_some(i);
+            travel = None;
+        }
+
+        // filament added so far, which every later absolute E includes
+        let mut offset = ExtrusionLength::ZERO;
+        for (i, (prev, _)) in states.iter().enumerate() {
+            let absolute = prev.e_positioning == Positioning::Absolute;
+            if let Some(&bridge) = bridges.get(&i) {
+                let Some(g1) = self.lines[i].command.as_move().cloned() else {
+                    continue;
+                };
+                // the extrusion after keeps the feedrate it ran at
+                let b = after[&i];
+                if g1.f.is_some() {
+                    if let Some(next) = self.lines[b].command.as_move_mut() {
+                        next.f = next.f.or(Some(states[b].0.feedrate));
+                    }
+                }
+                let e = if absolute {
+                    prev.e + offset + bridge
+                } else {
+                    bridge
+                };
+                self.lines[i].command = Command::G1(G1 {
+                    e: Some(e),
+                    f: None,
+                    ..g1
+                });
+                offset += bridge;
+                continue;
+            }
+            let e = match &mut self.lines[i].command {
+                Command::G92(G92 { e: Some(_), .. }) => {
+                    offset = ExtrusionLength::ZERO;
+                    continue;
+                }
+                Command::G0(G1 { e, .. })
+                | Command::G1(G1 { e, .. })
+                | Command::G53(Some(G1 { e, .. }), _) => e,
+                Command::G5(g5) => &mut g5.e,
+                Command::Arc(arc) => &mut arc.e,
+                _ => continue,
+            };
+            if let (Some(e), true) = (e, absolute) {
+                *e += offset;
+            }
+        }
+        if !bridges.is_empty() {
+            self.tag_g1();
+            self.join_gap_fill(&gap_fill);
+        }
+        bridges.len()
+    }
+    /// Fold each gap fill extrusion into the next line when that one
+    /// carries on in the same direction at the same flow and feedrate, so
+    /// the merged runs take one line instead of one per piece. Returns
+    /// the number of lines removed.
+    fn join_gap_fill(&mut self, gap_fill: &[bool]) -> usize {
+        let states = self
+            .cursor()
+            .map(|step| (step.prev, step.next))
+            .collect::<Vec<_>>();
+        let flow = |i: usize| {
+            let (prev, next) = states[i];
+            (next.e - prev.e).to_mm() / xy_length(&prev, &next)
+        };
+        let joins = |i: usize, j: usize| {
+            let (prev, next) = states[i];
+            let line = &self.lines[i];
+            if line.command.as_move().is_none()
+                || self.lines[j].command.as_move().is_none()
+                || line.command.tag() != Tag::Extrusion
+                || self.lines[j].command.tag() != Tag::Extrusion
+                || !gap_fill[i]
+                || !gap_fill[j]
+                || !self.comment(line).is_empty()
+                || !line.annotations.is_empty()
+                || prev.positioning != Positioning::Absolute
+                || prev.pos.z != next.pos.z
+                || states[j].0.pos.z != states[j].1.pos.z
+                || next.feedrate != states[j].1.feedrate
+            {
+                return false;
+            }
+            let (ax, ay) = xy(&prev, &next);
+            let (bx, by) = xy(&states[j].0, &states[j].1);
+            let (a, b) = (ax.hypot(ay), bx.hypot(by));
+            if a == 0.0 || b == 0.0 {
+                return false;
+            }
+            let straight = (ax * by - ay * bx).abs() <= 1e-3 * a * b && ax * bx + ay * by > 0.0;
+            let same_flow = (flow(i) - flow(j)).abs() <= 0.01 * flow(i).abs().max(flow(j).abs());
+            straight && same_flow
+        };
+        let dropped = (0..self.lines.len().saturating_sub(1))
+            .filter(|&i| joins(i, i + 1))
+            .collect::<Vec<_>>();
+        // front to back, so a run of joins keeps adding up into its last line
+        for &i in &dropped {
+            let Some(g1) = self.lines[i].command.as_move().cloned() else {
+                continue;
+            };
+            let relative = states[i].0.e_positioning == Positioning::Relative;
+            if let Some(next) = self.lines[i + 1].command.as_move_mut() {
+                next.f = next.f.or(g1.f);
+                if relative {
+                    next.e = Some(
+                        next.e.unwrap_or(ExtrusionLength::ZERO)
+                            + g1.e.unwrap_or(ExtrusionLength::ZERO),
+                    );
+                }
+            }
+        }
+        for &i in dropped.iter().rev() {
+            self.lines.remove(i);
+        }
+        dropped.len()
+    }
+}
+
+#[test]
+fn merge_gap_fill_test() {
+    use crate::emit::Emit;
+    let limits = GapFillLimits::default();
+    let input = "G1 X0 Y0\n;TYPE:Gap fill\nG1 X1 E0.1 F1200\nG1 X1.2 F9000\nG1 X2 E0.18\nG1 X2.5\nG1 X3 E0.23 F1200\nG1 X10 F9000\nG1 X11 E0.33 F1200";
+    let mut gcode: GCodeModel = input.parse().unwrap();
+    let filament = gcode.filament_used();
+    assert_eq!(gcode.merge_gap_fill(&limits), 2);
+    assert_eq!(
+        gcode.emit(false),
+        "G1 X0 Y0 \n;TYPE:Gap fill\nG1 X1.2 E0.12 F1200 \nG1 X2.5 E0.25 F9000 \nG1 X3 E0.3 F1200 \nG1 X10 F9000 \nG1 X11 E0.4 F1200 \n"
+    );
+    assert_eq!(gcode.lines[3].command.tag(), Tag::Extrusion);
+    assert_eq!(
+        gcode.filament_used(),
+        filament + ExtrusionLength::from_mm(0.07)
+    );
+
+    // at one feedrate the whole straight run becomes a single line
+    let mut gcode: GCodeModel = "G1 X0 Y0\n;TYPE:Gap fill\nG1 X1 E0.1 F1200\nG1 X1.2\nG1 X2 E0.18\nG1 X2.5\nG1 X3 E0.23\nG1 X10\nG1 X11 E0.33"
+        .parse()
+        .unwrap();
+    assert_eq!(gcode.merge_gap_fill(&limits), 2);
+    assert_eq!(
+        gcode.emit(false),
+        "G1 X0 Y0 \n;TYPE:Gap fill\nG1 X3 E0.3 F1200 \nG1 X10 \nG1 X11 E0.4 \n"
+    );
+
+    // extrusions outside gap fill are left alone
+    let mut gcode: GCodeModel = input
+        .replace(";TYPE:Gap fill", ";TYPE:Solid infill")
+        .parse()
+        .unwrap();
+    assert_eq!(gcode.merge_gap_fill(&limits), 0);
+    assert_eq!(gcode.lines.len(), 9);
+
+    // relative E, and long extrusions either side aren't gap fill
+    let mut gcode: GCodeModel =
+        "M83\n;TYPE:Gap infill\nG1 X1 E0.1\nG1 X1.2\nG1 X2 E0.08\nG1 X2.2\nG1 X12 E1"
+            .parse()
+            .unwrap();
+    assert_eq!(gcode.merge_gap_fill(&limits), 1);
+    assert_eq!(gcode.lines.len(), 5);
+    assert_eq!(gcode.lines[2].emit(false), "G1 X2 E0.2 ");
+    assert_eq!(gcode.lines[3].command.tag(), Tag::Travel);
+}
@@ -0,0 +1,171 @@
+use crate::{state::Position, GCodeModel, Length, Tag};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// How machine coordinates map onto physical space when resolving positions
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Geometry {
+    /// X, Y and Z are orthogonal and match the world frame
+    #[default]
+    Cartesian,
+    /// Belt printer with the gantry (machine Y) tilted `angle` degrees
+    /// up from the belt and machine Z driving the belt, so the printable
+    /// length along Z is unbounded
+    Belt { angle: f64 },
+}
+
+impl Geometry {
+    /// Map a machine position into the world frame, where world Y runs
+    /// along the belt and world Z is height above it
+    pub fn world(&self, pos: &Position) -> Position {
+        match self {
+            Geometry::Cartesian => *pos,
+            Geometry::Belt { angle } => {
+                let (sin, cos) = angle.to_radians().sin_cos();
+                let (y, z) = (pos.y.to_mm(), pos.z.to_mm());
+                Position {
+                    x: pos.x,
+                    y: Length::from(z + y * cos),
+                    z: Length::from(y * sin),
+                }
+            }
+        }
+    }
+}
+
+/// Axis-aligned box around a set of positions
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Bounds {
+    pub min: Position,
+    pub max: Position,
+}
+
+impl Bounds {
+    fn point(pos: Position) -> Self {
+        Bounds { min: pos, max: pos }
+    }
+    /// Grow the box to include `pos`
+    pub fn include(&mut self, pos: Position) {
+        self.min.x = self.min.x.min(pos.x);
+        self.min.y = self.min.y.min(pos.y);
+        self.min.z = self.min.z.min(pos.z);
+        self.max.x = self.max.x.max(pos.x);
+        self.max.y = self.max.y.max(pos.y);
+        self.max.z = self.max.z.max(pos.z);
+    }
+}
+
+/// Closed polygon in the XY plane with vertices in mm, where the last
+/// vertex joins back to the first
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Polygon {
+    pub points: Vec<[f64; 2]>,
+}
+
+impl Polygon {
+    fn edges(&self) -> impl Iterator<Item = ([f64; 2], [f64; 2])> + '_ {
+        let next = self.points.iter().cycle().skip(1);
+        self.points.iter().copied().zip(next.copied())
+    }
+    /// Area in mm², positive if the vertices run counterclockwise
+    pub fn signed_area(&self) -> f64 {
+        self.edges()
+            .map(|([x0, y0], [x1, y1])| x0 * y1 - x1 * y0)
+            .sum::<f64>()
+            / 2.0
+    }
+    pub fn area(&self) -> f64 {
+        self.signed_area().abs()
+    }
+    /// Length of the outline in mm
+    pub fn perimeter(&self) -> f64 {
+        self.edges()
+            .map(|([x0, y0], [x1, y1])| (x1 - x0).hypot(y1 - y0))
+            .sum()
+    }
+    /// Whether `point` is inside the polygon, by the even-odd rule
+    pub fn contains(&self, [x, y]: [f64; 2]) -> bool {
+        self.edges()
+            .filter(|([x0, y0], [x1, y1])| {
+                (y0 > &y) != (y1 > &y) && x < x0 + (y - y0) * (x1 - x0) / (y1 - y0)
+            })
+            .count()
+            % 2
+            == 1
+    }
+}
+
+impl GCodeModel {
+    /// Bounding box of every extrusion move in world coordinates,
+    /// or `None` if nothing is extruded
+    pub fn bounds(&self, geometry: Geometry) -> Option<Bounds> {
+        let mut bounds: Option<Bounds> = None;
+        for step in self.cursor() {
+            if step.line.command.tag() != Tag::Extrusion {
+                continue;
+            }
+            for pos in [step.prev.pos, step.next.pos] {
+                let pos = geometry.world(&pos);
+                match bounds.as_mut() {
+                    Some(bounds) => bounds.include(pos),
+                    None => bounds = Some(Bounds::point(pos)),
+                }
+            }
+        }
+        bounds
+    }
+}
+
+#[test]
+fn bounds_test() {
+    let gcode: GCodeModel = "G1 X5 Y5 Z0.2\nG1 X10 Y5 E1\nG1 X10 Y20 E2\nG1 X50 Y50"
+        .parse()
+        .unwrap();
+    let bounds = gcode.bounds(Geometry::Cartesian).unwrap();
+    let mm = |x: f64, y: f64, z: f64| Position {
+        x: Length::from(x),
+        y: Length::from(y),
+        z: Length::from(z),
+    };
+    assert_eq!(bounds.min, mm(5.0, 5.0, 0.2));
+    assert_eq!(bounds.max, mm(10.0, 20.0, 0.2));
+}
+
+#[test]
+fn belt_geometry_test() {
+    let belt = Geometry::Belt { angle: 45.0 };
+    let pos = Position {
+        x: Length::from(1.0),
+        y: Length::from(10.0),
+        z: Length::from(100.0),
+    };
+    let world = belt.world(&pos);
+    let half_root_2 = 10.0 * std::f64::consts::FRAC_1_SQRT_2;
+    assert_eq!(world.x, pos.x);
+    assert_eq!(world.y, Length::from(100.0 + half_root_2));
+    assert_eq!(world.z, Length::from(half_root_2));
+    // an infinite-Z file stays at a constant height above the belt
+    let gcode: GCodeModel = "G1 X0 Y5 Z0\nG1 X10 Y5 Z500 E10".parse().unwrap();
+    let bounds = gcode.bounds(belt).unwrap();
+    assert_eq!(bounds.min.z, bounds.max.z);
+}
+
+#[test]
+fn polygon_test() {
+    let square = Polygon {
+        points: vec![[0.0, 0.0], [10.0, 0.0], [10.0, 10.0], [0.0, 10.0]],
+    };
+    assert_eq!(square.signed_area(), 100.0);
+    assert_eq!(square.perimeter(), 40.0);
+    assert!(square.contains([5.0, 5.0]));
+    assert!(!square.contains([15.0, 5.0]));
+    let clockwise = Polygon {
+        points: square.points.iter().rev().copied().collect(),
+    };
+    assert_eq!(clockwise.signed_area(), -100.0);
+    assert_eq!(clockwise.area(), 100.0);
+}
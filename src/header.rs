@@ -0,0 +1,64 @@
+use crate::GCodeModel;
+use std::time::Duration;
+
+/// `1h 02m 03s` style duration, as slicers print it
+pub(crate) fn hms(duration: Duration) -> String {
+    let secs = duration.as_secs_f64().round() as u64;
+    format!("{}h {:02}m {:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+impl GCodeModel {
+    /// Comment lines describing the model, like the block slicers write
+    /// at the top of a file, without line endings. See `EmitConfig::header`.
+    pub fn summary_header(&self) -> Vec<String> {
+        let stats = self.stats(None);
+        let mut out = vec![
+            format!("; generated by g-win {}", env!("CARGO_PKG_VERSION")),
+            format!("; lines = {}", stats.lines),
+            format!("; layers = {}", stats.layers),
+            format!("; estimated printing time = {}", hms(stats.estimated_time)),
+            format!("; filament used [mm] = {:.2}", stats.filament),
+        ];
+        if !self.provenance.is_empty() {
+            let names = self.provenance.iter().map(|t| t.name.as_str());
+            out.push(format!(
+                "; transforms = {}",
+                names.collect::<Vec<_>>().join(", ")
+            ));
+        }
+        if let Some(bounds) = stats.bounds {
+            let (min, max) = (bounds.min, bounds.max);
+            out.push(format!(
+                "; bounds = X{}..{} Y{}..{} Z{}..{}",
+                min.x, max.x, min.y, max.y, min.z, max.z
+            ));
+        }
+        out
+    }
+}
+
+#[test]
+fn summary_header_test() {
+    use crate::emit::{Emit, EmitConfig};
+    let gcode: GCodeModel = "G1 Z0.2 F600\nG1 X10 Y5 E1.5\nG1 X20 Y5 E3"
+        .parse()
+        .unwrap();
+    let config = EmitConfig {
+        header: true,
+        ..Default::default()
+    };
+    let emitted = gcode.emit_with_config(&config);
+    let header = emitted.lines().take_while(|line| line.starts_with(';'));
+    assert_eq!(
+        header.skip(1).collect::<Vec<_>>(),
+        [
+            "; lines = 3",
+            "; layers = 1",
+            "; estimated printing time = 0h 00m 02s",
+            "; filament used [mm] = 3.00",
+            "; bounds = X0..20 Y0..5 Z0.2..0.2",
+        ]
+    );
+    assert!(emitted.ends_with(&gcode.emit(false)));
+    assert_eq!(hms(Duration::from_secs(3723)), "1h 02m 03s");
+}
@@ -0,0 +1,121 @@
+use crate::{
+    estimate::move_length, geometry::Polygon, layers::Layer, perimeters::wall_loops,
+    profile::PrinterProfile, roles::Role, skirt::SEAM_GAP, GCodeModel, Tag,
+};
+use std::collections::HashMap;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// How densely one layer is filled, see `GCodeModel::infill_density`
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct InfillDensity {
+    pub layer: Layer,
+    /// area covered by infill extrusions in mm², from their length and
+    /// estimated width
+    pub infill_area: f64,
+    /// area inside the external perimeters, less the area the walls
+    /// cover, in mm²
+    pub interior_area: f64,
+    /// `infill_area` over `interior_area`, or `None` if the layer has no
+    /// closed external perimeter to measure the interior by
+    pub density: Option<f64>,
+}
+
+/// Area enclosed by `loops`, where a loop inside an odd number of others
+/// is a hole
+fn enclosed_area(loops: &[Polygon]) -> f64 {
+    loops
+        .iter()
+        .enumerate()
+        .map(|(i, outline)| {
+            let depth = loops
+                .iter()
+                .enumerate()
+                .filter(|(j, other)| *j != i && other.contains(outline.points[0]))
+                .count();
+            if depth % 2 == 0 {
+                outline.area()
+            } else {
+                -outline.area()
+            }
+        })
+        .sum()
+}
+
+impl GCodeModel {
+    /// Estimate the infill density of every layer by comparing the area
+    /// its infill extrusions cover with the area inside its external
+    /// perimeters, as a check that a file was sliced with the intended
+    /// settings. Solid layers come out near 1.
+    pub fn infill_density(&self, profile: &PrinterProfile) -> Vec<InfillDensity> {
+        let steps = self.cursor().collect::<Vec<_>>();
+        let roles = self.roles();
+        let widths = self
+            .extrusion_widths(profile)
+            .into_iter()
+            .map(|w| (w.id, w.width))
+            .collect::<HashMap<_, _>>();
+        self.layers()
+            .into_iter()
+            .map(|layer| {
+                let (steps, roles) = (&steps[layer.range()], &roles[layer.range()]);
+                let (mut infill_area, mut wall_area) = (0.0, 0.0);
+                for (step, role) in steps.iter().zip(roles) {
+                    if step.line.command.tag() != Tag::Extrusion {
+                        continue;
+                    }
+                    let width = widths.get(&step.line.id).copied().unwrap_or_default();
+                    let area = move_length(&step.prev, &step.next) * width;
+                    match role {
+                        Role::Infill => infill_area += area,
+                        Role::OuterWall | Role::InnerWall => wall_area += area,
+                        _ => {}
+                    }
+                }
+                let loops = wall_loops(steps, roles, layer.start, SEAM_GAP)
+                    .into_iter()
+                    .filter(|wall| wall.role == Role::OuterWall)
+                    .map(|wall| wall.polygon)
+                    .collect::<Vec<_>>();
+                let interior_area = (enclosed_area(&loops) - wall_area).max(0.0);
+                let density =
+                    (!loops.is_empty() && interior_area > 0.0).then(|| infill_area / interior_area);
+                InfillDensity {
+                    layer,
+                    infill_area,
+                    interior_area,
+                    density,
+                }
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn infill_density_test() {
+    // 0.45 mm lines at 0.2 mm layer height take 0.03385 mm of 1.75 mm
+    // filament per mm
+    let e = |mm: f64| format!("{:.5}", mm * 0.03385);
+    let input = format!(
+        "M83\nG1 Z0.2 F600\n;TYPE:External perimeter\nG1 X0 Y0\nG1 X20 E{e20}\nG1 Y20 E{e20}\nG1 X0 E{e20}\nG1 Y0 E{e20}\nG1 X8 Y8\nG1 X12 E{e4}\nG1 Y12 E{e4}\nG1 X8 E{e4}\nG1 Y8 E{e4}\n;TYPE:Internal infill\nG1 X1 Y2\nG1 X19 E{e18}\nG1 Y18\nG1 X1 E{e18}\n",
+        e20 = e(20.0),
+        e4 = e(4.0),
+        e18 = e(18.0)
+    );
+    let gcode: GCodeModel = input.parse().unwrap();
+    let density = gcode.infill_density(&PrinterProfile::prusa_mk4());
+    assert_eq!(density.len(), 1);
+    // 400 mm² square less a 16 mm² hole and 96 mm of 0.45 mm wall
+    assert!((density[0].interior_area - 340.8).abs() < 0.5);
+    assert!((density[0].infill_area - 16.2).abs() < 0.1);
+    let ratio = density[0].density.unwrap();
+    assert!((ratio - 16.2 / 340.8).abs() < 0.002);
+
+    let open: GCodeModel = "G1 Z0.2\n;TYPE:Internal infill\nG1 X10 E1".parse().unwrap();
+    assert_eq!(
+        open.infill_density(&PrinterProfile::prusa_mk4())[0].density,
+        None
+    );
+}
@@ -0,0 +1,99 @@
+use crate::{Feedrate, GCodeModel, Length, Tag, G1};
+
+/// Speed limit for short, sharply turning extrusion moves like zig-zag
+/// infill, where the back and forth shakes the frame and shows up as
+/// vertical fine artifacts (VFA) on machines without input shaping
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ZigZagLimit {
+    /// Direction changes of at least this many degrees count as sharp
+    pub min_angle: f64,
+    /// Only moves shorter than this are slowed
+    pub max_length: Length,
+    pub max_feedrate: Feedrate,
+}
+
+/// Angle in degrees between two XY directions, 0 for straight on and
+/// 180 for a full reversal
+fn direction_change(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let cos = (a.0 * b.0 + a.1 * b.1) / (a.0.hypot(a.1) * b.0.hypot(b.1));
+    cos.clamp(-1.0, 1.0).acos().to_degrees()
+}
+
+impl GCodeModel {
+    /// Cap the feedrate of every extrusion move shorter than
+    /// `limit.max_length` that turns by at least `limit.min_angle` into or
+    /// out of its neighbours in the same run, returning the number of moves
+    /// slowed. The move after each slowed one gets its original feedrate
+    /// back.
+    pub fn limit_zigzag(&mut self, limit: &ZigZagLimit) -> usize {
+        self.record_transform("limit_zigzag", format!("{limit:?}"));
+        // XY direction of each extrusion move, or None to break a run
+        let moves = self
+            .cursor()
+            .map(|step| {
+                let dx = (step.next.pos.x - step.prev.pos.x).to_mm();
+                let dy = (step.next.pos.y - step.prev.pos.y).to_mm();
+                let extruding = step.line.command.tag() == Tag::Extrusion;
+                let moved = step.prev.pos != step.next.pos || step.prev.e != step.next.e;
+                (extruding, moved, (dx, dy), step.next.feedrate)
+            })
+            .collect::<Vec<_>>();
+        let mut sharp = vec![false; moves.len()];
+        let mut last: Option<usize> = None;
+        for (i, &(extruding, moved, dir, _)) in moves.iter().enumerate() {
+            if !moved {
+                continue;
+            }
+            if !extruding || dir == (0.0, 0.0) {
+                last = None;
+                continue;
+            }
+            if let Some(prev) = last {
+                if direction_change(moves[prev].2, dir) >= limit.min_angle {
+                    sharp[prev] = true;
+                    sharp[i] = true;
+                }
+            }
+            last = Some(i);
+        }
+
+        let mut limited = 0;
+        let mut restore = false;
+        for (i, line) in self.lines.iter_mut().enumerate() {
+            let Some(G1 { f, .. }) = line.command.as_move_mut() else {
+                continue;
+            };
+            let (dx, dy) = moves[i].2;
+            let feedrate = moves[i].3;
+            if sharp[i] && dx.hypot(dy) < limit.max_length.to_mm() {
+                *f = Some(feedrate.min(limit.max_feedrate));
+                limited += 1;
+                restore = true;
+            } else if restore {
+                f.get_or_insert(feedrate);
+                restore = false;
+            }
+        }
+        limited
+    }
+}
+
+#[test]
+fn zigzag_test() {
+    let input =
+        "M83\nG1 X0 Y0 F6000\nG1 X2 E0.1\nG1 Y0.4 E0.02\nG1 X0 E0.1\nG1 X100 E5\nG1 X100 Y50";
+    let mut gcode: GCodeModel = input.parse().unwrap();
+    let limit = ZigZagLimit {
+        min_angle: 60.0,
+        max_length: Length::from(5.0),
+        max_feedrate: Feedrate::from_mm_per_min(1800.0),
+    };
+    assert_eq!(gcode.limit_zigzag(&limit), 3);
+    let feedrates = gcode
+        .cursor()
+        .map(|step| step.next.feedrate.mm_per_min())
+        .collect::<Vec<_>>();
+    assert_eq!(feedrates[2..], [1800.0, 1800.0, 1800.0, 6000.0, 6000.0]);
+    // the long move after the zig-zag reverses too, but is left alone
+    assert_eq!(direction_change((1.0, 0.0), (-1.0, 0.0)), 180.0);
+}
@@ -0,0 +1,72 @@
+use crate::{GCodeModel, Length, Tag};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A printed layer, as a range of line indices into `GCodeModel::lines`
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Layer {
+    /// height of the extrusions in this layer
+    pub z: Length,
+    /// index of the first line, the move to this layer's height
+    pub start: usize,
+    /// index one past the last line
+    pub end: usize,
+}
+
+impl Layer {
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.start..self.end
+    }
+}
+
+impl GCodeModel {
+    /// Split the file into layers by the height of its extrusions. Each
+    /// layer starts at the last Z move before its first extrusion, and
+    /// anything before the first layer (start gcode) is part of it.
+    /// Z hops that don't extrude at the new height don't start a layer.
+    pub fn layers(&self) -> Vec<Layer> {
+        let mut layers: Vec<Layer> = Vec::new();
+        let mut last_z_move = 0;
+        for (i, step) in self.cursor().enumerate() {
+            if step.next.pos.z != step.prev.pos.z {
+                last_z_move = i;
+            }
+            if step.line.command.tag() != Tag::Extrusion {
+                continue;
+            }
+            let z = step.next.pos.z;
+            match layers.last_mut() {
+                Some(layer) if layer.z == z => {}
+                Some(layer) => {
+                    layer.end = last_z_move;
+                    layers.push(Layer {
+                        z,
+                        start: last_z_move,
+                        end: self.lines.len(),
+                    });
+                }
+                None => layers.push(Layer {
+                    z,
+                    start: 0,
+                    end: self.lines.len(),
+                }),
+            }
+        }
+        layers
+    }
+}
+
+#[test]
+fn layers_test() {
+    let input = "G28\nG1 Z0.2\nG1 X10 E1\nG1 X20 E2\nG1 Z0.6\nG1 X30\nG1 Z0.4\nG1 X10 E3\nG1 Z0.6\nG1 X20 E4";
+    let gcode: GCodeModel = input.parse().unwrap();
+    let layers = gcode.layers();
+    let summary = layers
+        .iter()
+        .map(|l| (f64::from(l.z), l.start, l.end))
+        .collect::<Vec<_>>();
+    // the hop to 0.6 at line 4 has no extrusion so 0.4 starts at line 6
+    assert_eq!(summary, vec![(0.2, 0, 6), (0.4, 6, 8), (0.6, 8, 10)]);
+}
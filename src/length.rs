@@ -0,0 +1,178 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Internal units per millimetre, giving nanometre resolution
+const UNITS_PER_MM: f64 = 1_000_000.0;
+
+/// Rounding applied when converting a float to fixed point
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Rounding {
+    /// Round to the nearest unit, ties to even
+    #[default]
+    HalfEven,
+    /// Drop the fractional part, matching the original conversion
+    Truncate,
+}
+
+impl Rounding {
+    fn apply(&self, units: f64) -> f64 {
+        match self {
+            Rounding::HalfEven => units.round_ties_even(),
+            Rounding::Truncate => units.trunc(),
+        }
+    }
+}
+
+/// Fixed-point length used for every coordinate and extrusion value.
+///
+/// Values are stored as an `i64` count of nanometres, so large absolute
+/// E values and long runs of small relative moves can be accumulated
+/// without the drift that comes from summing floats.
+///
+/// This type used to be called `Microns`; the old name is kept as a
+/// deprecated alias. It stores nanometres, not microns, so raw values from
+/// `as_nanos`/`from_nanos` and the serde representation are nanometre counts.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Length(i64);
+
+impl Length {
+    pub const ZERO: Length = Length(0);
+    pub const MIN: Length = Length(i64::MIN);
+    pub const MAX: Length = Length(i64::MAX);
+
+    /// Build a length directly from a count of nanometres
+    pub const fn from_nanos(nanos: i64) -> Self {
+        Length(nanos)
+    }
+    /// Raw count of nanometres backing this length
+    pub const fn as_nanos(&self) -> i64 {
+        self.0
+    }
+    /// Convert a value in millimetres to fixed point, rounding half to even
+    pub fn from_mm(mm: f64) -> Self {
+        Length::from_mm_with(mm, Rounding::default())
+    }
+    /// Convert a value in millimetres to fixed point with an explicit rounding mode
+    pub fn from_mm_with(mm: f64, rounding: Rounding) -> Self {
+        Length(rounding.apply(mm * UNITS_PER_MM) as i64)
+    }
+    /// Convert back to millimetres
+    pub fn to_mm(&self) -> f64 {
+        self.0 as f64 / UNITS_PER_MM
+    }
+    pub fn abs(&self) -> Self {
+        Length(self.0.abs())
+    }
+}
+
+impl From<f64> for Length {
+    fn from(mm: f64) -> Self {
+        Length::from_mm(mm)
+    }
+}
+
+impl From<f32> for Length {
+    fn from(mm: f32) -> Self {
+        Length::from_mm(mm as f64)
+    }
+}
+
+impl From<Length> for f64 {
+    fn from(m: Length) -> f64 {
+        m.to_mm()
+    }
+}
+
+impl From<Length> for f32 {
+    fn from(m: Length) -> f32 {
+        m.to_mm() as f32
+    }
+}
+
+impl std::ops::Add for Length {
+    type Output = Length;
+    fn add(self, rhs: Length) -> Length {
+        Length(self.0 + rhs.0)
+    }
+}
+
+impl std::ops::Sub for Length {
+    type Output = Length;
+    fn sub(self, rhs: Length) -> Length {
+        Length(self.0 - rhs.0)
+    }
+}
+
+impl std::ops::Neg for Length {
+    type Output = Length;
+    fn neg(self) -> Length {
+        Length(-self.0)
+    }
+}
+
+impl std::ops::AddAssign for Length {
+    fn add_assign(&mut self, rhs: Length) {
+        self.0 += rhs.0;
+    }
+}
+
+impl std::ops::SubAssign for Length {
+    fn sub_assign(&mut self, rhs: Length) {
+        self.0 -= rhs.0;
+    }
+}
+
+impl std::iter::Sum for Length {
+    fn sum<I: Iterator<Item = Length>>(iter: I) -> Length {
+        iter.fold(Length::ZERO, |acc, m| acc + m)
+    }
+}
+
+impl std::fmt::Display for Length {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_mm())
+    }
+}
+
+#[test]
+fn large_value_precision_test() {
+    let e = Length::from(12345.67891_f64);
+    assert_eq!(e.as_nanos(), 12_345_678_910);
+    assert_eq!(e.to_string(), "12345.67891");
+}
+
+#[test]
+fn cumulative_drift_test() {
+    // a million small relative extrusions should sum exactly
+    let step = Length::from(0.01234_f64);
+    let total: Length = std::iter::repeat_n(step, 1_000_000).sum();
+    assert_eq!(total, Length::from_nanos(12_340 * 1_000_000));
+}
+
+#[test]
+fn rounding_test() {
+    assert_eq!(Rounding::HalfEven.apply(10.5), 10.0);
+    assert_eq!(Rounding::HalfEven.apply(11.5), 12.0);
+    assert_eq!(Rounding::HalfEven.apply(-10.5), -10.0);
+    assert_eq!(Rounding::Truncate.apply(10.9), 10.0);
+    assert_eq!(Rounding::Truncate.apply(-10.9), -10.0);
+    // 0.0000017 mm is 1.7 nm
+    assert_eq!(Length::from_mm(0.0000017).as_nanos(), 2);
+    assert_eq!(
+        Length::from_mm_with(0.0000017, Rounding::Truncate).as_nanos(),
+        1
+    );
+}
+
+#[test]
+fn round_trip_test() {
+    let values = [0.0105, 0.1, -0.02, 1.23456, 215.0, 12345.67891, -0.00001];
+    for v in values {
+        let m = Length::from(v);
+        assert_eq!(Length::from(f64::from(m)), m);
+        assert_eq!(Length::from(m.to_string().parse::<f64>().unwrap()), m);
+        assert_eq!(f64::from(m), v);
+    }
+}
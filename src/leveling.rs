@@ -0,0 +1,343 @@
+use crate::{
+    state::{MachineState, Position},
+    Annotations, Command, ExtrusionLength, GCodeLine, GCodeModel, Id, Length, G1,
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Struct to store M420 bed leveling params
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct M420 {
+    /// S, turn leveling on or off
+    pub enable: Option<bool>,
+    /// Z, height at which leveling correction is fully faded out
+    pub fade_height: Option<Length>,
+    /// L, mesh slot to load
+    pub slot: Option<u32>,
+    /// V, print the mesh to the host
+    pub verbose: bool,
+}
+
+/// Bed mesh embedded in a file as comments, such as Klipper mesh
+/// dumps or `M420 V` output pasted into the start gcode
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct BedMesh {
+    /// Z offsets in mm, one row per probed Y line
+    pub rows: Vec<Vec<f64>>,
+    /// lines the mesh was read from, including its header
+    pub ids: Vec<Id>,
+}
+
+/// comments that introduce a mesh dump
+fn is_mesh_header(comment: &str) -> bool {
+    let comment = comment.to_lowercase();
+    comment.contains("leveling grid") || comment.contains("mesh")
+}
+
+/// read a row of mesh values from a comment. `M420 V` rows start with
+/// an integer row index and the header row holds only column indices,
+/// so integers are only kept when every token in the row is a float.
+fn mesh_row(comment: &str) -> Option<Vec<f64>> {
+    let tokens = comment
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<_>>();
+    if tokens.is_empty() || tokens.iter().any(|t| t.parse::<f64>().is_err()) {
+        return None;
+    }
+    let values = tokens
+        .iter()
+        .filter(|t| t.contains('.'))
+        .map(|t| t.parse::<f64>().unwrap_or_default())
+        .collect::<Vec<_>>();
+    Some(values)
+}
+
+impl BedMesh {
+    /// Place the mesh on the bed with its first probe point at `min` and
+    /// its last at `max`, in mm
+    pub fn placed(&self, min: [f64; 2], max: [f64; 2]) -> MeshGrid {
+        MeshGrid {
+            min,
+            max,
+            rows: self.rows.clone(),
+        }
+    }
+}
+
+/// Z offsets probed on an evenly spaced grid covering `min` to `max` in
+/// XY, one row per Y line from the lowest Y
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeshGrid {
+    pub min: [f64; 2],
+    pub max: [f64; 2],
+    pub rows: Vec<Vec<f64>>,
+}
+
+impl MeshGrid {
+    /// Offset at `x`, `y` by bilinear interpolation between the nearest
+    /// four probe points, holding the edge values outside the grid
+    pub fn offset(&self, x: f64, y: f64) -> f64 {
+        // cell index and the fraction of the way across it
+        let locate = |v: f64, min: f64, max: f64, points: usize| {
+            if points < 2 || max <= min {
+                return (0, 0.0);
+            }
+            let cells = (points - 1) as f64;
+            let f = ((v - min) / (max - min)).clamp(0.0, 1.0) * cells;
+            let i = (f.floor() as usize).min(points - 2);
+            (i, f - i as f64)
+        };
+        let cols = self.rows.iter().map(Vec::len).min().unwrap_or(0);
+        if cols == 0 {
+            return 0.0;
+        }
+        let (i, tx) = locate(x, self.min[0], self.max[0], cols);
+        let (j, ty) = locate(y, self.min[1], self.max[1], self.rows.len());
+        let at = |i: usize, j: usize| {
+            let row = &self.rows[j.min(self.rows.len() - 1)];
+            row[i.min(cols - 1)]
+        };
+        let low = at(i, j) * (1.0 - tx) + at(i + 1, j) * tx;
+        let high = at(i, j + 1) * (1.0 - tx) + at(i + 1, j + 1) * tx;
+        low * (1.0 - ty) + high * ty
+    }
+}
+
+impl GCodeModel {
+    /// Find the first bed mesh embedded in the file's comments
+    pub fn bed_mesh(&self) -> Option<BedMesh> {
+        let mut lines = self.lines.iter();
+        while let Some(line) = lines.next() {
+            if !is_comment_only(&line.command) || !is_mesh_header(self.comment(line)) {
+                continue;
+            }
+            let mut mesh = BedMesh {
+                rows: Vec::new(),
+                ids: vec![line.id],
+            };
+            for line in lines.by_ref() {
+                if !is_comment_only(&line.command) {
+                    break;
+                }
+                let Some(row) = mesh_row(self.comment(line)) else {
+                    break;
+                };
+                mesh.ids.push(line.id);
+                if !row.is_empty() {
+                    mesh.rows.push(row);
+                }
+            }
+            if !mesh.rows.is_empty() {
+                return Some(mesh);
+            }
+        }
+        None
+    }
+    /// Remove every embedded bed mesh dump, returning the meshes removed
+    pub fn strip_bed_meshes(&mut self) -> Vec<BedMesh> {
+        self.record_transform("strip_bed_meshes", String::new());
+        let mut out = Vec::new();
+        while let Some(mesh) = self.bed_mesh() {
+            self.lines.retain(|line| !mesh.ids.contains(&line.id));
+            out.push(mesh);
+        }
+        out
+    }
+}
+
+impl GCodeModel {
+    /// Bake bed leveling into the file for firmware without it: split G0
+    /// and G1 moves into pieces no longer than `segment` in XY and raise each
+    /// piece's end by the mesh offset under it. With a `fade_height` the
+    /// correction shrinks linearly to nothing at that height, like
+    /// `M420 Z`. Returns the number of lines added, leaving the model
+    /// unchanged unless `segment` is positive.
+    pub fn bake_mesh(
+        &mut self,
+        mesh: &MeshGrid,
+        segment: Length,
+        fade_height: Option<Length>,
+    ) -> usize {
+        if segment <= Length::ZERO {
+            return 0;
+        }
+        let parameters = format!("{segment}, {fade_height:?}");
+        self.record_transform("bake_mesh", parameters);
+        let compensate = |mut pos: Position| {
+            let fade = match fade_height {
+                Some(fade) if fade > Length::ZERO => (1.0 - pos.z.to_mm() / fade.to_mm()).max(0.0),
+                _ => 1.0,
+            };
+            let offset = mesh.offset(pos.x.to_mm(), pos.y.to_mm()) * fade;
+            pos.z += Length::from(offset);
+            pos
+        };
+        let steps = self
+            .cursor()
+            .map(|step| (step.prev, step.next))
+            .collect::<Vec<_>>();
+        let mut lines = Vec::with_capacity(self.lines.len());
+        let mut added = 0;
+        for (line, (prev, next)) in self.lines.drain(..).zip(steps) {
+            let (Some(g1), rapid) = (
+                line.command.as_move(),
+                matches!(line.command, Command::G0(_)),
+            ) else {
+                lines.push(line);
+                continue;
+            };
+            // pieces of a rapid stay rapids
+            let command = |piece| match rapid {
+                true => Command::G0(piece),
+                false => Command::G1(piece),
+            };
+            let length = (next.pos.x - prev.pos.x)
+                .to_mm()
+                .hypot((next.pos.y - prev.pos.y).to_mm());
+            let pieces = (length / segment.to_mm()).ceil().max(1.0) as usize;
+            let mut from = MachineState {
+                pos: compensate(prev.pos),
+                ..prev
+            };
+            for k in 1..=pieces {
+                let t = k as f64 / pieces as f64;
+                let lerp = |a: Length, b: Length| Length::from(a.to_mm() + (b - a).to_mm() * t);
+                let (pos, e) = if k == pieces {
+                    (next.pos, next.e)
+                } else {
+                    let pos = Position {
+                        x: lerp(prev.pos.x, next.pos.x),
+                        y: lerp(prev.pos.y, next.pos.y),
+                        z: lerp(prev.pos.z, next.pos.z),
+                    };
+                    let e = prev.e.to_mm() + (next.e - prev.e).to_mm() * t;
+                    (pos, ExtrusionLength::from_mm(e))
+                };
+                let pos = compensate(pos);
+                let to = from.move_to(pos, g1.e.map(|_| e));
+                from.pos = pos;
+                from.e = e;
+                let piece = G1 {
+                    f: if k == 1 { g1.f } else { None },
+                    s: g1.s,
+                    ..to
+                };
+                if k == pieces {
+                    // X and Y end where they did, so words the move had
+                    // still hold even if they don't move the toolhead
+                    let piece = G1 {
+                        x: piece.x.or(g1.x),
+                        y: piece.y.or(g1.y),
+                        ..piece
+                    };
+                    lines.push(GCodeLine {
+                        command: command(piece),
+                        ..line
+                    });
+                    break;
+                }
+                added += 1;
+                lines.push(GCodeLine {
+                    id: self.id_counter.get(),
+                    command: command(piece),
+                    comments: String::new(),
+                    annotations: Annotations::default(),
+                });
+            }
+        }
+        self.lines = lines;
+        self.tag_g1();
+        added
+    }
+}
+
+fn is_comment_only(command: &Command) -> bool {
+    match command {
+        Command::Blank => true,
+        Command::Raw(raw) => raw.trim().is_empty(),
+        _ => false,
+    }
+}
+
+#[test]
+fn leveling_parse_test() {
+    use crate::emit::Emit;
+    let gcode: GCodeModel = "G29 P1 T\nM420 S1 Z10 V\nM420 L2".parse().unwrap();
+    assert_eq!(gcode.lines[0].command, Command::G29(String::from("P1 T")));
+    let expected = M420 {
+        enable: Some(true),
+        fade_height: Some(Length::from(10.0)),
+        slot: None,
+        verbose: true,
+    };
+    assert_eq!(gcode.lines[1].command, Command::M420(expected));
+    assert_eq!(gcode.lines[1].emit(false), "M420 S1 Z10 V ");
+    assert_eq!(gcode.lines[2].emit(false), "M420 L2 ");
+    // words come in any order, and unknown ones keep the line raw
+    let gcode: GCodeModel = "M420 V S1\nM420 V1 S1 Z10\nM420 S1 T3".parse().unwrap();
+    assert_eq!(gcode.lines[0].emit(false), "M420 S1 V ");
+    assert_eq!(gcode.lines[1].emit(false), "M420 S1 Z10 V ");
+    assert_eq!(
+        gcode.lines[2].command,
+        Command::Raw(String::from("M420 S1 T3"))
+    );
+    // the payload starts right after the word even without a space
+    let gcode: GCodeModel = "G29P1\nG29".parse().unwrap();
+    assert_eq!(gcode.lines[0].command, Command::G29(String::from("P1")));
+    assert_eq!(gcode.lines[0].emit(false), "G29 P1");
+    assert_eq!(gcode.lines[1].command, Command::G29(String::new()));
+}
+
+#[test]
+fn bed_mesh_test() {
+    let input = "G28\n; Bilinear Leveling Grid:\n;      0      1      2\n; 0 +0.100 +0.050 -0.020\n; 1 +0.000 -0.010 -0.030\n; end of start gcode\nG1 X1";
+    let mut gcode: GCodeModel = input.parse().unwrap();
+    let mesh = gcode.bed_mesh().unwrap();
+    assert_eq!(
+        mesh.rows,
+        vec![vec![0.1, 0.05, -0.02], vec![0.0, -0.01, -0.03]]
+    );
+    assert_eq!(mesh.ids, vec![Id(1), Id(2), Id(3), Id(4)]);
+    assert_eq!(gcode.strip_bed_meshes(), vec![mesh]);
+    assert_eq!(gcode.lines.len(), 3);
+
+    let klipper = "; bed_mesh default\n; 0.012500, 0.025000\n; -0.005000, 0.000000\nG1 X1";
+    let gcode: GCodeModel = klipper.parse().unwrap();
+    let mesh = gcode.bed_mesh().unwrap();
+    assert_eq!(mesh.rows, vec![vec![0.0125, 0.025], vec![-0.005, 0.0]]);
+}
+
+#[test]
+fn bake_mesh_test() {
+    use crate::emit::Emit;
+    let mesh = BedMesh {
+        rows: vec![vec![0.0, 0.1], vec![0.2, 0.3]],
+        ids: Vec::new(),
+    }
+    .placed([0.0, 0.0], [10.0, 10.0]);
+    assert_eq!(mesh.offset(5.0, 5.0), 0.15);
+    assert_eq!(mesh.offset(-5.0, 20.0), 0.2);
+    let mut gcode: GCodeModel = "G1 X0 Y0 Z0.2 F600
+G1 X10 E1
+G1 Y10 Z5"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        gcode.bake_mesh(&mesh, Length::from(5.0), Some(Length::from(2.0))),
+        2
+    );
+    assert_eq!(
+        gcode.emit(false),
+        "G1 X0 Y0 Z0.2 F600 \nG1 X5 Z0.245 E0.5 \nG1 X10 Z0.29 E1 \nG1 Y5 Z2.6 \nG1 Y10 Z5 \n"
+    );
+    // a segment with no length would never finish splitting
+    let baked = gcode.clone();
+    assert_eq!(gcode.bake_mesh(&mesh, Length::ZERO, None), 0);
+    assert_eq!(gcode.bake_mesh(&mesh, Length::from(-1.0), None), 0);
+    assert_eq!(gcode, baked);
+}
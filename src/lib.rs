@@ -1,16 +1,96 @@
 // include readme in docs
 #![doc = include_str!("../README.md")]
 
+pub mod analyzer;
+mod arc;
+pub mod batch;
+pub mod coasting;
+mod compat;
+pub mod cooling;
+mod corners;
+pub mod custom;
+pub mod derived;
+pub mod diagnostic;
 pub mod emit;
+pub mod energy;
+pub mod estimate;
+pub mod export;
 mod file;
+mod fingerprint;
+mod first_layer;
+mod gap_fill;
+pub mod geometry;
+mod header;
+mod infill;
+pub mod junction;
+pub mod layers;
+mod length;
+mod leveling;
+pub mod lint;
+mod loops;
+pub mod meatpack;
+mod overlap;
+mod overrides;
 mod parsers;
+mod perimeters;
+pub mod plate;
+pub mod preview;
+mod priming;
+pub mod profile;
+pub mod progress;
+mod provenance;
+mod pseudo;
+pub mod query;
+mod region;
+pub mod report;
+pub mod resume;
+mod retractions;
+mod roles;
+mod sections;
+mod skew;
+mod skirt;
+mod slicer;
+mod speeds;
+mod spline;
+pub mod state;
+mod supports;
+mod tags;
 mod tests;
+pub mod timelapse;
+mod units;
+#[cfg(feature = "notify")]
+pub mod watch;
 
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
-use microns::Microns;
+pub use arc::ArcMove;
+pub use compat::{CompatibilityInfo, Incompatibility, M862};
+pub use corners::{CornerStats, ZigZagLimits, ANGLE_BUCKETS, LENGTH_BUCKETS};
+pub use file::{Decoding, ReadError, ReaderConfig};
+pub use first_layer::{FirstLayerReport, Span};
+pub use gap_fill::GapFillLimits;
+pub use infill::InfillDensity;
+pub use length::{Length, Rounding};
+pub use leveling::{BedMesh, MeshGrid, M420};
+pub use loops::UnrollError;
+
+#[deprecated(note = "stores nanometres; renamed to `Length`")]
+pub type Microns = Length;
+pub use parsers::{CommentMode, CommentSyntax, GCodeParseError, Limit, ParserConfig};
+pub use perimeters::PerimeterLoop;
+pub use provenance::AppliedTransform;
+pub use pseudo::PseudoCommand;
+pub use region::{LineHandle, Region, Stale};
+pub use retractions::RetractionRules;
+pub use roles::Role;
+pub use slicer::SlicerKind;
+pub use speeds::{FeatureSpeed, SpeedReport};
+pub use spline::G5;
 use std::{io::Write, path::Path};
+pub use supports::SupportStats;
+pub use tags::{ParseTagError, TagSummary, TagTotals};
+pub use units::{ExtrusionLength, Feedrate};
 /// Default basic annotations for G1 moves, generated automatically
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
@@ -31,14 +111,74 @@ pub enum Tag {
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
 pub struct G1 {
-    pub x: Option<Microns>,
-    pub y: Option<Microns>,
-    pub z: Option<Microns>,
-    pub e: Option<Microns>,
-    pub f: Option<Microns>,
+    pub x: Option<Length>,
+    pub y: Option<Length>,
+    pub z: Option<Length>,
+    pub e: Option<ExtrusionLength>,
+    pub f: Option<Feedrate>,
+    /// inline spindle speed or laser power
+    pub s: Option<u32>,
     pub tag: Tag,
 }
 
+/// Motion word on a `G53` line
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum G53Motion {
+    /// coordinates alone, moved with whichever of G0 or G1 is modal
+    #[default]
+    Bare,
+    G0,
+    G1,
+}
+
+/// Struct to store G92 params, the position each given axis is declared
+/// to be at without moving. Marlin leaves the axes that aren't given alone.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct G92 {
+    pub x: Option<Length>,
+    pub y: Option<Length>,
+    pub z: Option<Length>,
+    pub e: Option<ExtrusionLength>,
+}
+
+/// Struct to store G28 params, the axes to home. A bare G28 homes every
+/// axis, and `W` is Prusa's flag to skip mesh bed leveling afterwards.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Home {
+    pub x: bool,
+    pub y: bool,
+    pub z: bool,
+    pub skip_leveling: bool,
+    /// text written after each of X, Y and Z, like the `0` of `X0`, which
+    /// firmware ignores but is kept so the line reads as it did. Empty
+    /// for a bare flag.
+    pub positions: [String; 3],
+    /// any other words, like Marlin's `O` or `R10`, as written and
+    /// separated by spaces
+    pub options: String,
+}
+
+impl Home {
+    /// Whether X, Y and Z are homed, in that order
+    pub fn axes(&self) -> [bool; 3] {
+        let all = !(self.x || self.y || self.z);
+        [all || self.x, all || self.y, all || self.z]
+    }
+}
+
+/// Struct to store M106 params. `P` picks the fan, the part cooling fan
+/// (fan 0) if not given, and `S` sets its speed from 0 to 255, full speed
+/// if not given.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct M106 {
+    pub index: Option<u8>,
+    pub speed: Option<u8>,
+}
+
 /// Enum to represent all possible gcode commands that we would
 /// like to handle, leaving any unknown commands as raw strings.
 /// Specific structs to store information for each command can
@@ -46,31 +186,158 @@ pub struct G1 {
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Command {
+    /// Rapid move, with the same words as G1. Marlin runs it exactly like
+    /// G1, while CNC controllers move at full speed.
+    G0(G1),
     G1(G1),
+    /// Dwell for a fixed time
+    G4(std::time::Duration),
+    G5(G5),
+    /// G2 or G3 arc
+    Arc(ArcMove),
+    /// Move in machine coordinates, ignoring the work offset for this line
+    /// only, with the motion word it was written with
+    G53(Option<G1>, G53Motion),
+    /// Home some or all axes
+    Home(Home),
+    /// Probe the bed, with any parameters kept verbatim since they vary by firmware
+    G29(String),
+    /// Select a work coordinate system (G54-G59)
+    Wcs(state::Wcs),
     G90,
     G91,
+    /// Set position
+    G92(G92),
+    /// Inverse time feed mode
+    G93,
+    /// Units per minute feed mode
+    G94,
+    /// Units per revolution feed mode
+    G95,
     M82,
     M83,
+    /// Spindle on clockwise, with an optional speed in RPM
+    M3(Option<u32>),
+    /// Spindle on counter-clockwise, with an optional speed in RPM
+    M4(Option<u32>),
+    /// Spindle off
+    M5,
+    /// Select tool `n` with a `T<n>` line
+    ToolChange(u8),
+    /// Set a fan's speed
+    M106(M106),
+    /// Turn off fan `P`, or the part cooling fan if not given
+    M107(Option<u8>),
+    /// Mist coolant on
+    M7,
+    /// Flood coolant on
+    M8,
+    /// All coolant off
+    M9,
+    /// Display a message on the printer's screen, text kept verbatim
+    M117(String),
+    /// Echo a message to the host, text (including any flags) kept verbatim
+    M118(String),
+    /// Bed leveling state
+    M420(M420),
+    /// Prusa printer compatibility check (M862.1-M862.6)
+    M862(M862),
+    /// Repeat marker, `Some(count)` opens a loop and `None` closes it
+    M808(Option<u32>),
     Raw(String),
+    /// Nothing before the comment, for blank and comment-only lines
+    Blank,
+    /// Command parsed by a parser registered on `ParserConfig`, serialized
+    /// as the `Raw` text it emits
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            rename(serialize = "Raw"),
+            serialize_with = "custom::serialize_as_raw",
+            skip_deserializing
+        )
+    )]
+    Custom(custom::Custom),
 }
 
 impl Command {
     pub fn tag(&self) -> Tag {
         match self {
-            Command::G1(g1) => g1.tag,
+            Command::G0(g1) | Command::G1(g1) => g1.tag,
+            Command::Arc(arc) => arc.tag,
             _ => Tag::Uninitialized,
         }
     }
+    /// The words of a G0 or G1 move
+    pub fn as_move(&self) -> Option<&G1> {
+        match self {
+            Command::G0(g1) | Command::G1(g1) => Some(g1),
+            _ => None,
+        }
+    }
+    pub fn as_move_mut(&mut self) -> Option<&mut G1> {
+        match self {
+            Command::G0(g1) | Command::G1(g1) => Some(g1),
+            _ => None,
+        }
+    }
 }
 
 /// Struct to store a single line of gcode, with an id, command,
-/// and comments
+/// comments, and any user annotations
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct GCodeLine {
     pub id: Id,
     pub command: Command,
     pub comments: String,
+    pub annotations: Annotations,
+}
+
+impl GCodeLine {
+    /// Change the G1 on this line in place, keeping its id, comments and
+    /// annotations. Returns false and does nothing for other commands.
+    pub fn map_g1(&mut self, f: impl FnOnce(&mut G1)) -> bool {
+        match &mut self.command {
+            Command::G1(g1) => {
+                f(g1);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Free-form labels that downstream tools can attach to a line,
+/// keyed by name with an optional value. These are never emitted.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Annotations(std::collections::BTreeMap<String, String>);
+
+impl Annotations {
+    /// Attach a label with no value
+    pub fn label(&mut self, key: &str) {
+        self.0.insert(key.to_string(), String::new());
+    }
+    /// Attach a label with a value, returning any previous value
+    pub fn insert(&mut self, key: &str, value: &str) -> Option<String> {
+        self.0.insert(key.to_string(), value.to_string())
+    }
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+    pub fn contains(&self, key: &str) -> bool {
+        self.0.contains_key(key)
+    }
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.0.remove(key)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
 }
 
 /// Struct to store all information for a .gcode file,
@@ -85,23 +352,58 @@ pub struct GCodeModel {
     pub rel_xyz: bool,
     pub rel_e: bool,
     pub id_counter: Counter,
+    /// comments kept apart from their lines, see `CommentMode::Sidecar`
+    pub comments: std::collections::BTreeMap<Id, String>,
+    /// line ending of the parsed file, used again when emitting
+    pub line_ending: emit::LineEnding,
+    /// transforms applied since the model was parsed, oldest first
+    pub provenance: Vec<AppliedTransform>,
+    /// index behind `state_at`, built on first use
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub state_index: state::StateIndexCache,
 }
 
+// a parsed model can be shared read-only between threads behind an `Arc`,
+// e.g. by a GUI and its analysis workers
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<GCodeModel>()
+};
+
 impl std::str::FromStr for GCodeModel {
     type Err = parsers::GCodeParseError;
-    fn from_str(mut s: &str) -> Result<Self, Self::Err> {
-        let gcode = parsers::gcode_parser(&mut s);
-        match gcode {
-            Ok(gcode) => Ok(gcode),
-            Err(e) => Err(e),
-        }
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        GCodeModel::parse_with_config(s, &ParserConfig::default())
     }
 }
 
 impl GCodeModel {
+    /// Parse a gcode string with non-default parser options
+    pub fn parse_with_config(mut s: &str, config: &ParserConfig) -> Result<Self, GCodeParseError> {
+        parsers::gcode_parser(&mut s, config)
+    }
     pub fn from_file(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
         Ok(file::open_gcode_file(path)?.parse()?)
     }
+    /// Read and parse gcode from any reader, failing on the first line
+    /// longer than `reader_config.max_line_length` instead of reading it
+    pub fn from_reader(
+        reader: impl std::io::Read,
+        reader_config: &ReaderConfig,
+        config: &ParserConfig,
+    ) -> Result<Self, ReadError> {
+        let text = file::read_text(reader, reader_config)?;
+        Ok(GCodeModel::parse_with_config(&text, config)?)
+    }
+    /// Read a file with non-default decoding and parser options
+    pub fn from_file_with(
+        path: &Path,
+        decoding: Decoding,
+        config: &ParserConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = file::open_gcode_file_with(path, decoding)?;
+        Ok(GCodeModel::parse_with_config(&text, config)?)
+    }
     pub fn write_to_file(&self, path: &Path) -> Result<(), std::io::Error> {
         use emit::Emit;
         use std::fs::File;
@@ -111,141 +413,245 @@ impl GCodeModel {
         println!("save successful");
         Ok(())
     }
-    pub fn tag_g1(&mut self) {
-        let mut prev = [
-            Microns::ZERO,
-            Microns::ZERO,
-            Microns::ZERO,
-        ];
-        for line in self.lines.iter_mut() {
-            if let Command::G1(G1 { x, y, z, e, f, tag }) = &mut line.command {
-                let curr = [
-                    prev[0] + x.unwrap_or(Microns::ZERO),
-                    prev[1] + y.unwrap_or(Microns::ZERO),
-                    prev[2] + z.unwrap_or(Microns::ZERO),
-                ];
-
-                let dx = curr[0] - prev[0];
-                let dy = curr[1] - prev[1];
-                let dz = curr[2] - prev[2];
-                let de = e.unwrap_or(Microns::ZERO);
-                let f = f.unwrap_or(Microns::ZERO);
-
-                *tag = {
-                    if de > Microns::ZERO {
-                        if dx.abs() > Microns::ZERO || dy.abs() > Microns::ZERO {
-                            Tag::Extrusion
-                        } else { Tag::DeRetraction }
-                    } else if de == Microns::ZERO {
-                        if dx.abs() > Microns::ZERO || dy.abs() > Microns::ZERO {
-                            Tag::Travel
-                        } else if dz > Microns::ZERO {
-                            Tag::RaiseZ
-                        } else if dz < Microns::ZERO {
-                            Tag::LowerZ
-                        } else if f > Microns::ZERO {
-                            Tag::Feedrate
-                        } else { Tag::Uninitialized }
-                    } else if dx.abs() > Microns::ZERO || dy.abs() > Microns::ZERO {
-                            Tag::Wipe
-                    } else {
-                        Tag::Retraction
-                    }
-                };
-                prev = curr;
+    /// Comments of `line`, whether stored on it or in `comments`
+    pub fn comment<'a>(&'a self, line: &'a GCodeLine) -> &'a str {
+        match self.comments.get(&line.id) {
+            Some(comments) if line.comments.is_empty() => comments,
+            _ => &line.comments,
+        }
+    }
+    /// Walk the lines in order while tracking machine state
+    pub fn cursor(&self) -> state::Cursor<'_> {
+        state::Cursor::new(&self.lines)
+    }
+    /// Change every command whose line matches `filter` in place, keeping
+    /// ids, comments and annotations, then re-tag the model. Returns the
+    /// number of lines changed.
+    pub fn map_commands(
+        &mut self,
+        filter: impl Fn(&GCodeLine) -> bool,
+        mut f: impl FnMut(&mut Command),
+    ) -> usize {
+        let mut changed = 0;
+        for line in self.lines.iter_mut().filter(|line| filter(line)) {
+            f(&mut line.command);
+            changed += 1;
+        }
+        self.tag_g1();
+        changed
+    }
+    /// `map_commands` with progress reports. Every change is worked out
+    /// before any is applied, so a cancelled call leaves the model as it
+    /// was.
+    pub fn map_commands_monitored(
+        &mut self,
+        filter: impl Fn(&GCodeLine) -> bool,
+        mut f: impl FnMut(&mut Command),
+        monitor: &mut progress::Monitor,
+    ) -> Result<usize, progress::Cancelled> {
+        use progress::Stage;
+        let total = self.lines.len();
+        let mut changes = Vec::new();
+        for (i, line) in self.lines.iter().enumerate() {
+            monitor.update(Stage::Transform, i, total)?;
+            if filter(line) {
+                let mut command = line.command.clone();
+                f(&mut command);
+                changes.push((i, command));
             }
         }
+        monitor.update(Stage::Transform, total, total)?;
+        let changed = changes.len();
+        for (i, command) in changes {
+            self.lines[i].command = command;
+        }
+        self.tag_g1();
+        Ok(changed)
+    }
+    /// Append the lines of `other`, giving each a new id from this model's
+    /// counter. Returns the new id of each line keyed by its id in `other`.
+    pub fn merge(&mut self, other: GCodeModel) -> std::collections::BTreeMap<Id, Id> {
+        let mut ids = std::collections::BTreeMap::new();
+        for mut line in other.lines {
+            let id = self.id_counter.get();
+            ids.insert(line.id, id);
+            if let Some(comments) = other.comments.get(&line.id) {
+                self.comments.insert(id, comments.clone());
+            }
+            line.id = id;
+            self.lines.push(line);
+        }
+        self.tag_g1();
+        ids
+    }
+    /// Set the tag of every G1 from the move it makes, see `state::classify`
+    pub fn tag_g1(&mut self) {
+        self.state_index.clear();
+        state::tag_lines(&mut self.lines, state::MachineState::default());
+    }
+    /// Tag only the G1s in `range`, starting from the state the machine is
+    /// in before its first line, and return the state after its last.
+    /// Use this to re-tag an edited region without walking the whole file.
+    pub fn tag_range(
+        &mut self,
+        range: std::ops::Range<usize>,
+        start: state::MachineState,
+    ) -> state::MachineState {
+        self.state_index.clear();
+        state::tag_lines(&mut self.lines[range], start)
     }
 }
 
+#[test]
+fn map_commands_test() {
+    use emit::Emit;
+    let mut gcode: GCodeModel = "G1 X10 E1 F600 ; wall\nG1 X20\nM104 S200".parse().unwrap();
+    let changed = gcode.map_commands(
+        |line| line.command.tag() == Tag::Extrusion,
+        |command| {
+            if let Command::G1(g1) = command {
+                g1.f = Some(Feedrate::from_mm_per_min(1200.0));
+            }
+        },
+    );
+    assert_eq!(changed, 1);
+    assert_eq!(gcode.lines[0].emit(false), "G1 X10 E1 F1200 ; wall");
+    let id = gcode.lines[1].id;
+    assert!(gcode.lines[1].map_g1(|g1| g1.e = Some(ExtrusionLength::from_mm(2.0))));
+    assert_eq!(gcode.lines[1].id, id);
+    assert!(!gcode.lines[2].map_g1(|g1| g1.x = None));
+}
+
+#[test]
+fn id_test() {
+    let config = ParserConfig {
+        first_id: 100,
+        ..Default::default()
+    };
+    let mut gcode = GCodeModel::parse_with_config("G90\nM82 ; absolute", &config).unwrap();
+    assert_eq!(gcode.lines[0].id.get(), 100);
+    let reserved = gcode.id_counter.reserve(3).collect::<Vec<_>>();
+    assert_eq!(
+        reserved.iter().map(Id::get).collect::<Vec<_>>(),
+        [102, 103, 104]
+    );
+    assert_eq!(gcode.id_counter.peek().get(), 105);
+
+    let other: GCodeModel = "G91\nG1 X10".parse().unwrap();
+    let ids = gcode.merge(other.clone());
+    assert_eq!(gcode.lines.len(), 4);
+    assert_eq!(ids[&other.lines[1].id], gcode.lines[3].id);
+    assert_eq!(gcode.lines[3].id.get(), 106);
+}
+
 #[test]
 fn tag_test() {
     let mut gcode = GCodeModel::default();
+    for command in [Command::G91, Command::M83] {
+        gcode.lines.push(GCodeLine {
+            id: gcode.id_counter.get(),
+            command,
+            comments: String::new(),
+            annotations: Annotations::default(),
+        });
+    }
     gcode.lines.push(GCodeLine {
         id: gcode.id_counter.get(),
         command: Command::G1(G1 {
-            x: Some(Microns::from(10.0)),
-            y: Some(Microns::from(10.0)),
-            z: Some(Microns::from(10.0)),
-            e: Some(Microns::from(10.0)),
-            f: Some(Microns::from(10.0)),
+            x: Some(Length::from(10.0)),
+            y: Some(Length::from(10.0)),
+            z: Some(Length::from(10.0)),
+            e: Some(ExtrusionLength::from_mm(10.0)),
+            f: Some(Feedrate::from_mm_per_min(10.0)),
+            s: None,
             tag: Tag::Uninitialized,
         }),
         comments: String::new(),
+        annotations: Annotations::default(),
     });
     gcode.tag_g1();
-    assert_eq!(gcode.lines[0].command.tag(), Tag::Extrusion);
+    assert_eq!(gcode.lines.last().unwrap().command.tag(), Tag::Extrusion);
     gcode.lines.push(GCodeLine {
         id: gcode.id_counter.get(),
         command: Command::G1(G1::default()),
         comments: String::new(),
+        annotations: Annotations::default(),
     });
     gcode.tag_g1();
-    assert_eq!(gcode.lines[1].command.tag(), Tag::Uninitialized);
+    assert_eq!(
+        gcode.lines.last().unwrap().command.tag(),
+        Tag::Uninitialized
+    );
     gcode.lines.push(GCodeLine {
         id: gcode.id_counter.get(),
         command: Command::G1(G1 {
-            e: Some(Microns::from(-10.0)),
+            e: Some(ExtrusionLength::from_mm(-10.0)),
             ..Default::default()
         }),
         comments: String::new(),
+        annotations: Annotations::default(),
     });
     gcode.tag_g1();
-    assert_eq!(gcode.lines[2].command.tag(), Tag::Retraction);
+    assert_eq!(gcode.lines.last().unwrap().command.tag(), Tag::Retraction);
     gcode.lines.push(GCodeLine {
         id: gcode.id_counter.get(),
         command: Command::G1(G1 {
-            e: Some(Microns::from(-10.0)),
-            x: Some(Microns::from(10.0)),
-            y: Some(Microns::from(10.0)),
+            e: Some(ExtrusionLength::from_mm(-10.0)),
+            x: Some(Length::from(10.0)),
+            y: Some(Length::from(10.0)),
             ..Default::default()
         }),
         comments: String::new(),
+        annotations: Annotations::default(),
     });
     gcode.tag_g1();
-    assert_eq!(gcode.lines[3].command.tag(), Tag::Wipe);
+    assert_eq!(gcode.lines.last().unwrap().command.tag(), Tag::Wipe);
     gcode.lines.push(GCodeLine {
         id: gcode.id_counter.get(),
         command: Command::G1(G1 {
-            e: Some(Microns::from(-10.0)),
-            z: Some(Microns::from(10.0)),
+            e: Some(ExtrusionLength::from_mm(-10.0)),
+            z: Some(Length::from(10.0)),
             ..Default::default()
         }),
         comments: String::new(),
+        annotations: Annotations::default(),
     });
     gcode.tag_g1();
-    assert_eq!(gcode.lines[4].command.tag(), Tag::Retraction);
+    assert_eq!(gcode.lines.last().unwrap().command.tag(), Tag::Retraction);
     gcode.lines.push(GCodeLine {
         id: gcode.id_counter.get(),
         command: Command::G1(G1 {
-            e: Some(Microns::from(-10.0)),
-            z: Some(Microns::from(-10.0)),
+            e: Some(ExtrusionLength::from_mm(-10.0)),
+            z: Some(Length::from(-10.0)),
             ..Default::default()
         }),
         comments: String::new(),
+        annotations: Annotations::default(),
     });
     gcode.tag_g1();
-    assert_eq!(gcode.lines[5].command.tag(), Tag::Retraction);
+    assert_eq!(gcode.lines.last().unwrap().command.tag(), Tag::Retraction);
     gcode.lines.push(GCodeLine {
         id: gcode.id_counter.get(),
         command: Command::G1(G1 {
-            f: Some(Microns::from(10.0)),
+            f: Some(Feedrate::from_mm_per_min(10.0)),
             ..Default::default()
         }),
         comments: String::new(),
+        annotations: Annotations::default(),
     });
     gcode.tag_g1();
-    assert_eq!(gcode.lines[6].command.tag(), Tag::Feedrate);
+    assert_eq!(gcode.lines.last().unwrap().command.tag(), Tag::Feedrate);
     gcode.lines.push(GCodeLine {
         id: gcode.id_counter.get(),
         command: Command::G1(G1 {
-            e: Some(Microns::from(10.0)),
+            e: Some(ExtrusionLength::from_mm(10.0)),
             ..Default::default()
         }),
         comments: String::new(),
+        annotations: Annotations::default(),
     });
     gcode.tag_g1();
-    assert_eq!(gcode.lines[7].command.tag(), Tag::DeRetraction);
+    assert_eq!(gcode.lines.last().unwrap().command.tag(), Tag::DeRetraction);
 }
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
@@ -254,14 +660,30 @@ pub struct Counter {
 }
 
 impl Counter {
+    /// Counter whose first id is `start`, so that ids don't collide with
+    /// ones an external system already holds
+    pub fn starting_at(start: u32) -> Self {
+        Counter { count: start }
+    }
     fn get(&mut self) -> Id {
         let out = self.count;
         self.count += 1;
         Id(out)
     }
+    /// The id that will be handed out next
+    pub fn peek(&self) -> Id {
+        Id(self.count)
+    }
+    /// Hand out a block of `count` consecutive ids at once, e.g. for lines
+    /// that will be inserted later
+    pub fn reserve(&mut self, count: u32) -> impl Iterator<Item = Id> {
+        let start = self.count;
+        self.count += count;
+        (start..self.count).map(Id)
+    }
 }
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
 pub struct Id(u32);
 
 impl Id {
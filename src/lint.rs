@@ -0,0 +1,939 @@
+use crate::{
+    estimate::move_length,
+    geometry::Bounds,
+    parsers::split_raw,
+    plate::Clearance,
+    profile::{FilamentProfile, PrinterProfile, SoftEndstops},
+    progress::{Cancelled, Monitor, Stage},
+    state::{classify, FeedMode, MachineState, Position, Positioning, Units},
+    Command, ExtrusionLength, GCodeModel, Id, Length, Tag, G1, G92,
+};
+use std::ops::Range;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// How much a finding matters, so callers can decide what to reject
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// the rule is turned off
+    Allow,
+    #[default]
+    Warning,
+    Error,
+}
+
+/// Something suspicious about a file that doesn't stop it from parsing
+#[derive(Clone, Debug, PartialEq)]
+pub enum Lint {
+    /// absolute E went further back than a retraction would, without a
+    /// `G92 E` reset in between
+    EBackwards {
+        /// highest E since the last reset
+        from: ExtrusionLength,
+        to: ExtrusionLength,
+    },
+    /// the file switched to inches with G20, while every length is read
+    /// as millimetres, so positions, speeds and times are all wrong
+    InchUnits,
+    /// the toolhead moved before the first G28
+    MotionBeforeHome,
+    /// the first extrusion came before a target was set for these heaters
+    ExtrusionBeforeTemperature { hotend: bool, bed: bool },
+    /// the bed was probed before the first G28
+    LevelingBeforeHome,
+    /// an F word faster than any axis can move, in mm/s
+    FeedrateAboveLimit { feedrate: f64, limit: f64 },
+    /// a move that would drive `axis` faster than its limit, in mm/s
+    AxisSpeedAboveLimit { axis: char, speed: f64, limit: f64 },
+    /// a move past the soft endstops, which halts the print, in mm from
+    /// machine zero
+    OutsideSoftEndstop {
+        axis: char,
+        position: f64,
+        min: f64,
+        max: f64,
+    },
+    /// in a sequential print, the toolhead comes within its radius of an
+    /// object printed earlier while the nozzle is below that object's top.
+    /// Objects are numbered from 0 in print order.
+    ToolheadCollision { object: usize, with: usize },
+    /// an extrusion move that needs more plastic melted than the filament
+    /// allows, in mm³/s
+    VolumetricSpeedAboveLimit { flow: f64, limit: f64 },
+    /// a heater set outside the range the filament prints at, in °C
+    TemperatureOutOfRange {
+        bed: bool,
+        temperature: f32,
+        min: f32,
+        max: f32,
+    },
+    /// a layer, starting at the reported line, that prints faster than the
+    /// filament can cool, in seconds
+    LayerTimeBelowMinimum { z: f64, time: f64, min: f64 },
+    /// in a sequential print, the gantry sweeps lower than the top of an
+    /// object printed earlier, `height` mm tall
+    GantryCollision {
+        object: usize,
+        with: usize,
+        height: f64,
+    },
+}
+
+impl std::fmt::Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Lint::EBackwards { from, to } => {
+                write!(
+                    f,
+                    "absolute E goes back from {from} to {to} without a reset"
+                )
+            }
+            Lint::InchUnits => write!(f, "switches to inches, which are read as millimetres"),
+            Lint::MotionBeforeHome => write!(f, "moves before homing"),
+            Lint::ExtrusionBeforeTemperature { hotend, bed } => {
+                let heaters = match (hotend, bed) {
+                    (true, true) => "hotend and bed",
+                    (true, false) => "hotend",
+                    _ => "bed",
+                };
+                write!(f, "extrudes before setting the {heaters} temperature")
+            }
+            Lint::LevelingBeforeHome => write!(f, "probes the bed before homing"),
+            Lint::FeedrateAboveLimit { feedrate, limit } => {
+                write!(f, "feedrate {feedrate}mm/s is above the {limit}mm/s limit")
+            }
+            Lint::AxisSpeedAboveLimit { axis, speed, limit } => {
+                write!(
+                    f,
+                    "{axis} moves at {speed}mm/s, above its {limit}mm/s limit"
+                )
+            }
+            Lint::OutsideSoftEndstop {
+                axis,
+                position,
+                min,
+                max,
+            } => write!(
+                f,
+                "{axis} moves to {position}mm, outside the soft endstops at {min}mm to {max}mm"
+            ),
+            Lint::VolumetricSpeedAboveLimit { flow, limit } => write!(
+                f,
+                "extrudes {flow:.1}mm³/s, above the filament's {limit}mm³/s limit"
+            ),
+            Lint::TemperatureOutOfRange {
+                bed,
+                temperature,
+                min,
+                max,
+            } => {
+                let heater = if *bed { "bed" } else { "hotend" };
+                write!(
+                    f,
+                    "{heater} set to {temperature}°C, outside the filament's {min}°C to {max}°C"
+                )
+            }
+            Lint::LayerTimeBelowMinimum { z, time, min } => write!(
+                f,
+                "layer at {z}mm prints in {time:.1}s, under the filament's {min}s minimum"
+            ),
+            Lint::ToolheadCollision { object, with } => write!(
+                f,
+                "toolhead hits object {with} while printing object {object}"
+            ),
+            Lint::GantryCollision {
+                object,
+                with,
+                height,
+            } => write!(
+                f,
+                "gantry hits object {with}, {height}mm tall, while printing object {object}"
+            ),
+        }
+    }
+}
+
+/// A lint reported against a line
+#[derive(Clone, Debug, PartialEq)]
+pub struct Finding {
+    pub id: Id,
+    pub lint: Lint,
+    pub severity: Severity,
+}
+
+/// Severity of each preamble rule checked by `GCodeModel::check_preamble`,
+/// `Severity::Allow` to skip one, e.g. for files that home and heat in a
+/// firmware start macro
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PreambleRules {
+    pub home_before_motion: Severity,
+    pub temperature_before_extrusion: Severity,
+    pub level_after_home: Severity,
+}
+
+impl Default for PreambleRules {
+    fn default() -> Self {
+        PreambleRules {
+            home_before_motion: Severity::Error,
+            temperature_before_extrusion: Severity::Error,
+            level_after_home: Severity::Warning,
+        }
+    }
+}
+
+/// Something a print should switch off when it ends
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Shutdown {
+    Hotend,
+    Bed,
+    Fan,
+    Steppers,
+}
+
+impl Shutdown {
+    pub const ALL: [Shutdown; 4] = [
+        Shutdown::Hotend,
+        Shutdown::Bed,
+        Shutdown::Fan,
+        Shutdown::Steppers,
+    ];
+    /// Command that does it
+    pub fn command(&self) -> &'static str {
+        match self {
+            Shutdown::Hotend => "M104 S0",
+            Shutdown::Bed => "M140 S0",
+            Shutdown::Fan => "M107",
+            Shutdown::Steppers => "M84",
+        }
+    }
+}
+
+impl std::fmt::Display for Shutdown {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let what = match self {
+            Shutdown::Hotend => "hotend is left on",
+            Shutdown::Bed => "bed is left on",
+            Shutdown::Fan => "fan is left on",
+            Shutdown::Steppers => "steppers are left enabled",
+        };
+        write!(f, "{what}, missing {}", self.command())
+    }
+}
+
+const XYZ: [char; 3] = ['X', 'Y', 'Z'];
+
+/// Distance in mm in XY from a move to the footprint `bounds`, zero if
+/// the move passes over it
+fn distance_to_footprint(from: &Position, to: &Position, bounds: &Bounds) -> f64 {
+    let (min, max) = (
+        [bounds.min.x.to_mm(), bounds.min.y.to_mm()],
+        [bounds.max.x.to_mm(), bounds.max.y.to_mm()],
+    );
+    let (a, b) = (
+        [from.x.to_mm(), from.y.to_mm()],
+        [to.x.to_mm(), to.y.to_mm()],
+    );
+    // clip the move to the box, which leaves something if it crosses it
+    let (mut enter, mut exit) = (0.0_f64, 1.0_f64);
+    for axis in 0..2 {
+        let d = b[axis] - a[axis];
+        if d == 0.0 {
+            if a[axis] < min[axis] || a[axis] > max[axis] {
+                enter = f64::INFINITY;
+            }
+            continue;
+        }
+        let (t0, t1) = ((min[axis] - a[axis]) / d, (max[axis] - a[axis]) / d);
+        enter = enter.max(t0.min(t1));
+        exit = exit.min(t0.max(t1));
+    }
+    if enter <= exit {
+        return 0.0;
+    }
+    let outside = |[x, y]: [f64; 2]| {
+        let dx = (min[0] - x).max(x - max[0]).max(0.0);
+        let dy = (min[1] - y).max(y - max[1]).max(0.0);
+        dx.hypot(dy)
+    };
+    // otherwise the closest point is an end of the move or a corner
+    let to_move = |p: [f64; 2]| {
+        let (ab, ap) = ([b[0] - a[0], b[1] - a[1]], [p[0] - a[0], p[1] - a[1]]);
+        let len = ab[0] * ab[0] + ab[1] * ab[1];
+        let t = if len > 0.0 {
+            ((ap[0] * ab[0] + ap[1] * ab[1]) / len).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        (ap[0] - ab[0] * t).hypot(ap[1] - ab[1] * t)
+    };
+    let corners = [min, max, [min[0], max[1]], [max[0], min[1]]];
+    corners
+        .into_iter()
+        .map(to_move)
+        .chain([outside(a), outside(b)])
+        .fold(f64::INFINITY, f64::min)
+}
+
+impl GCodeModel {
+    /// Report absolute E values more than `max_retraction` below the
+    /// highest E since the start or the last `G92 E`, which usually means
+    /// the file was corrupted or two files were merged without a reset.
+    /// Ordinary retractions and wipes stay within that distance.
+    pub fn check_absolute_e(&self, max_retraction: ExtrusionLength) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        let mut peak: Option<ExtrusionLength> = None;
+        for step in self.cursor() {
+            if let Command::G92(G92 { e, .. }) = &step.line.command {
+                if e.is_some() {
+                    peak = None;
+                }
+                continue;
+            }
+            if step.next.e_positioning != Positioning::Absolute || step.next.e == step.prev.e {
+                continue;
+            }
+            let e = step.next.e;
+            let highest = *peak.get_or_insert(e);
+            if e > highest {
+                peak = Some(e);
+            } else if highest - e > max_retraction {
+                findings.push(Finding {
+                    id: step.line.id,
+                    lint: Lint::EBackwards {
+                        from: highest,
+                        to: e,
+                    },
+                    severity: Severity::Warning,
+                });
+                // carry on from here so one bad jump is reported once
+                peak = Some(e);
+            }
+        }
+        findings
+    }
+    /// Report every G20 that switches the file to inches. Lengths are
+    /// always read as millimetres, so nothing worked out from an inch
+    /// file can be trusted.
+    pub fn check_units(&self) -> Vec<Finding> {
+        self.cursor()
+            .filter(|step| step.prev.units != Units::Inches && step.next.units == Units::Inches)
+            .map(|step| Finding {
+                id: step.line.id,
+                lint: Lint::InchUnits,
+                severity: Severity::Error,
+            })
+            .collect()
+    }
+    /// Which of `required` the file doesn't do by the end, in the same
+    /// order. Heaters that were never turned on count as off, and the
+    /// steppers must be disabled with M84 or M18 after the last move.
+    pub fn check_end_state(&self, required: &[Shutdown]) -> Vec<Shutdown> {
+        let mut end = MachineState::default();
+        let mut steppers_off = false;
+        for step in self.cursor() {
+            end = step.next;
+            if step.prev.pos != step.next.pos || step.prev.e != step.next.e {
+                steppers_off = false;
+            } else if let Command::Raw(raw) = &step.line.command {
+                if split_raw(raw).is_some_and(|(word, _)| word == "M84" || word == "M18") {
+                    steppers_off = true;
+                }
+            }
+        }
+        let off = |target: Option<f32>| target.is_none_or(|t| t <= 0.0);
+        required
+            .iter()
+            .copied()
+            .filter(|shutdown| match shutdown {
+                Shutdown::Hotend => !off(end.temps.hotend),
+                Shutdown::Bed => !off(end.temps.bed),
+                Shutdown::Fan => end.fan > 0,
+                Shutdown::Steppers => !steppers_off,
+            })
+            .collect()
+    }
+    /// Report F words faster than the fastest of X and Y allows, and moves
+    /// that would drive any single axis past its maximum feedrate in
+    /// `profile`, as the firmware would silently slow them down
+    pub fn check_feedrates(&self, profile: &PrinterProfile) -> Vec<Finding> {
+        let max = &profile.limits.max_feedrate;
+        let fastest = max.x.max(max.y);
+        let mut findings = Vec::new();
+        for step in self.cursor() {
+            let id = step.line.id;
+            if let Some(G1 { f: Some(f), .. }) = step.line.command.as_move() {
+                if fastest > 0.0 && f.mm_per_sec() > fastest {
+                    findings.push(Finding {
+                        id,
+                        lint: Lint::FeedrateAboveLimit {
+                            feedrate: f.mm_per_sec(),
+                            limit: fastest,
+                        },
+                        severity: Severity::Warning,
+                    });
+                }
+            }
+            let length = move_length(&step.prev, &step.next);
+            if step.next.feed_mode != FeedMode::UnitsPerMinute
+                || classify(&step.prev, &step.line.command) == Tag::Uninitialized
+                || length <= 0.0
+            {
+                continue;
+            }
+            let (prev, next) = (&step.prev, &step.next);
+            let axes = [
+                ('X', (next.pos.x - prev.pos.x).to_mm(), max.x),
+                ('Y', (next.pos.y - prev.pos.y).to_mm(), max.y),
+                ('Z', (next.pos.z - prev.pos.z).to_mm(), max.z),
+                ('E', (next.e - prev.e).to_mm(), max.e),
+            ];
+            for (axis, distance, limit) in axes {
+                let speed = next.feedrate.mm_per_sec() * distance.abs() / length;
+                if limit > 0.0 && speed > limit {
+                    findings.push(Finding {
+                        id,
+                        lint: Lint::AxisSpeedAboveLimit { axis, speed, limit },
+                        severity: Severity::Warning,
+                    });
+                }
+            }
+        }
+        findings
+    }
+    /// Follow the machine position of each homed axis through G28, G92
+    /// and every move, returning the first move that would leave
+    /// `endstops`. Axes that haven't been homed aren't checked, as the
+    /// firmware doesn't know where they are either.
+    pub fn check_soft_endstops(&self, endstops: &SoftEndstops) -> Option<Finding> {
+        Monitor::unmonitored(|monitor| self.check_soft_endstops_monitored(endstops, monitor))
+    }
+    /// `check_soft_endstops` with progress reports, stopping early if
+    /// cancelled
+    pub fn check_soft_endstops_monitored(
+        &self,
+        endstops: &SoftEndstops,
+        monitor: &mut Monitor,
+    ) -> Result<Option<Finding>, Cancelled> {
+        let total = self.lines.len();
+        let axes = |pos: &Position| [pos.x, pos.y, pos.z];
+        let (min, max, home) = (
+            axes(&endstops.min),
+            axes(&endstops.max),
+            axes(&endstops.home),
+        );
+        let mut machine: [Option<Length>; 3] = [None; 3];
+        // machine position minus the position the file writes, set by G92
+        let mut shift = [Length::ZERO; 3];
+        for (i, step) in self.cursor().enumerate() {
+            monitor.update(Stage::Simulate, i, total)?;
+            let (prev, next) = (axes(&step.prev.pos), axes(&step.next.pos));
+            let written = match &step.line.command {
+                Command::G0(G1 { x, y, z, .. }) | Command::G1(G1 { x, y, z, .. }) => {
+                    [x.is_some(), y.is_some(), z.is_some()]
+                }
+                Command::G53(Some(G1 { x, y, z, .. }), _) => {
+                    [x.is_some(), y.is_some(), z.is_some()]
+                }
+                Command::G5(g5) => [g5.x.is_some(), g5.y.is_some(), false],
+                Command::Arc(arc) => [arc.x.is_some(), arc.y.is_some(), arc.z.is_some()],
+                Command::G92(G92 { x, y, z, .. }) => {
+                    for (i, v) in [x, y, z].into_iter().enumerate() {
+                        if let (Some(m), Some(v)) = (machine[i], v) {
+                            shift[i] = m - *v;
+                        }
+                    }
+                    continue;
+                }
+                Command::Home(homed) => {
+                    for (i, homed) in homed.axes().into_iter().enumerate() {
+                        if homed {
+                            machine[i] = Some(home[i]);
+                            shift[i] = Length::ZERO;
+                        }
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            for i in 0..3 {
+                let Some(m) = machine[i].filter(|_| written[i]) else {
+                    continue;
+                };
+                let to = match step.line.command {
+                    Command::G53(..) => next[i],
+                    _ if step.next.positioning == Positioning::Relative => m + (next[i] - prev[i]),
+                    // the position as written, before the state's own G92 shift
+                    _ => next[i] - axes(&step.next.g92_offset)[i] + shift[i],
+                };
+                machine[i] = Some(to);
+                if to < min[i] || to > max[i] {
+                    return Ok(Some(Finding {
+                        id: step.line.id,
+                        lint: Lint::OutsideSoftEndstop {
+                            axis: XYZ[i],
+                            position: to.to_mm(),
+                            min: min[i].to_mm(),
+                            max: max[i].to_mm(),
+                        },
+                        severity: Severity::Error,
+                    }));
+                }
+            }
+        }
+        monitor.update(Stage::Simulate, total, total)?;
+        Ok(None)
+    }
+    /// Check that the file homes before it moves, probes the bed only
+    /// after homing, and sets both heaters before it first extrudes,
+    /// reporting the first line to break each rule
+    pub fn check_preamble(&self, rules: &PreambleRules) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        let mut report = |id: Id, lint: Lint, severity: Severity| {
+            if severity != Severity::Allow {
+                findings.push(Finding { id, lint, severity });
+            }
+        };
+        let mut homed = false;
+        let (mut moved, mut leveled, mut extruded) = (false, false, false);
+        for step in self.cursor() {
+            let id = step.line.id;
+            let word = match &step.line.command {
+                Command::Raw(raw) => split_raw(raw).map(|(word, _)| word),
+                _ => None,
+            };
+            let probes = matches!(step.line.command, Command::G29(_))
+                || word.as_deref() == Some("BED_MESH_CALIBRATE");
+            if matches!(step.line.command, Command::Home(_)) {
+                homed = true;
+            } else if probes && !homed && !leveled {
+                leveled = true;
+                report(id, Lint::LevelingBeforeHome, rules.level_after_home);
+            } else if step.prev.pos != step.next.pos && !homed && !moved {
+                moved = true;
+                report(id, Lint::MotionBeforeHome, rules.home_before_motion);
+            }
+            if step.line.command.tag() == Tag::Extrusion && !extruded {
+                extruded = true;
+                let set = |target: Option<f32>| target.is_some_and(|t| t > 0.0);
+                let (hotend, bed) = (!set(step.next.temps.hotend), !set(step.next.temps.bed));
+                if hotend || bed {
+                    report(
+                        id,
+                        Lint::ExtrusionBeforeTemperature { hotend, bed },
+                        rules.temperature_before_extrusion,
+                    );
+                }
+            }
+        }
+        findings
+    }
+    /// Report where the file asks more of the filament than `filament`
+    /// allows: extrusion moves faster than its maximum volumetric speed,
+    /// heater targets outside its temperature ranges, and layers shorter
+    /// than its minimum layer time. Uses the filament diameter of `printer`.
+    pub fn check_material(
+        &self,
+        filament: &FilamentProfile,
+        printer: &PrinterProfile,
+    ) -> Vec<Finding> {
+        let filament_area = std::f64::consts::PI * (printer.filament_diameter / 2.0).powi(2);
+        let mut findings = Vec::new();
+        let mut report = |id: Id, lint: Lint| {
+            findings.push(Finding {
+                id,
+                lint,
+                severity: Severity::Warning,
+            })
+        };
+        for step in self.cursor() {
+            let (prev, next) = (&step.prev, &step.next);
+            let heaters = [
+                (
+                    false,
+                    prev.temps.hotend,
+                    next.temps.hotend,
+                    Some(filament.temperature),
+                ),
+                (
+                    true,
+                    prev.temps.bed,
+                    next.temps.bed,
+                    filament.bed_temperature,
+                ),
+            ];
+            for (bed, before, after, range) in heaters {
+                let (Some(temperature), Some((min, max))) = (after, range) else {
+                    continue;
+                };
+                // 0 turns the heater off
+                if before != after && temperature > 0.0 && !(min..=max).contains(&temperature) {
+                    let lint = Lint::TemperatureOutOfRange {
+                        bed,
+                        temperature,
+                        min,
+                        max,
+                    };
+                    report(step.line.id, lint);
+                }
+            }
+            if step.line.command.tag() != Tag::Extrusion {
+                continue;
+            }
+            let length = move_length(prev, next);
+            if length <= 0.0 {
+                continue;
+            }
+            let e = (next.e - prev.e).to_mm();
+            let flow = e / length * next.effective_feedrate().mm_per_sec() * filament_area;
+            if flow > filament.max_volumetric_speed {
+                let limit = filament.max_volumetric_speed;
+                report(
+                    step.line.id,
+                    Lint::VolumetricSpeedAboveLimit { flow, limit },
+                );
+            }
+        }
+        let layers = self.layers();
+        for short in self.short_layers(filament.min_layer_time) {
+            let lint = Lint::LayerTimeBelowMinimum {
+                z: short.z.to_mm(),
+                time: short.time.as_secs_f64(),
+                min: filament.min_layer_time.as_secs_f64(),
+            };
+            report(self.lines[layers[short.layer].start].id, lint);
+        }
+        findings
+    }
+    /// Line ranges of the objects in a file that prints them one after
+    /// another, split where extrusion drops back below the height already
+    /// reached. Each range runs from the line after the last extrusion of
+    /// the object before, so it includes the travel to the object.
+    pub(crate) fn sequential_objects(&self) -> Vec<Range<usize>> {
+        let mut objects: Vec<Range<usize>> = Vec::new();
+        let mut top: Option<Length> = None;
+        for (i, step) in self.cursor().enumerate() {
+            if step.line.command.tag() != Tag::Extrusion {
+                continue;
+            }
+            let z = step.next.pos.z;
+            match objects.last_mut() {
+                Some(object) if top.is_some_and(|top| z >= top) => object.end = i + 1,
+                Some(object) => {
+                    let start = object.end;
+                    objects.push(start..i + 1);
+                }
+                None => objects.push(0..i + 1),
+            }
+            top = Some(z);
+        }
+        objects
+    }
+    /// For a file that prints its objects one at a time, find the first
+    /// move that would hit an object already printed: either the toolhead
+    /// coming within `clearance.radius` of it with the nozzle below its top,
+    /// or the gantry sweeping lower than its top
+    pub fn check_sequential(&self, clearance: &Clearance) -> Option<Finding> {
+        let steps = self.cursor().collect::<Vec<_>>();
+        let objects = self.sequential_objects();
+        let mut printed: Vec<Bounds> = Vec::new();
+        for (object, range) in objects.iter().enumerate() {
+            for step in &steps[range.clone()] {
+                let nozzle = step.prev.pos.z.min(step.next.pos.z).to_mm();
+                for (with, done) in printed.iter().enumerate() {
+                    let height = done.max.z.to_mm();
+                    let lint = if nozzle + clearance.gantry_height < height {
+                        Lint::GantryCollision {
+                            object,
+                            with,
+                            height,
+                        }
+                    } else if nozzle < height
+                        && distance_to_footprint(&step.prev.pos, &step.next.pos, done)
+                            < clearance.radius
+                    {
+                        Lint::ToolheadCollision { object, with }
+                    } else {
+                        continue;
+                    };
+                    return Some(Finding {
+                        id: step.line.id,
+                        lint,
+                        severity: Severity::Error,
+                    });
+                }
+            }
+            let mut footprint: Option<Bounds> = None;
+            for step in &steps[range.clone()] {
+                if step.line.command.tag() != Tag::Extrusion {
+                    continue;
+                }
+                for pos in [step.prev.pos, step.next.pos] {
+                    match footprint.as_mut() {
+                        Some(footprint) => footprint.include(pos),
+                        None => footprint = Some(Bounds { min: pos, max: pos }),
+                    }
+                }
+            }
+            printed.extend(footprint);
+        }
+        None
+    }
+}
+
+#[test]
+fn absolute_e_test() {
+    let input = "M82\nG1 X10 E5\nG1 E4.2\nG1 X20 E5\nG92 E0\nG1 X30 E1\nG1 X40 E2\n;merged\nG1 X50 E0.5\nG1 X60 E1";
+    let gcode: GCodeModel = input.parse().unwrap();
+    let findings = gcode.check_absolute_e(ExtrusionLength::from_mm(1.0));
+    assert_eq!(
+        findings,
+        [Finding {
+            id: gcode.lines[8].id,
+            lint: Lint::EBackwards {
+                from: ExtrusionLength::from_mm(2.0),
+                to: ExtrusionLength::from_mm(0.5),
+            },
+            severity: Severity::Warning,
+        }]
+    );
+    // relative E can't go backwards
+    let gcode: GCodeModel = "M83\nG1 X10 E5\nG1 X20 E-3".parse().unwrap();
+    assert!(gcode
+        .check_absolute_e(ExtrusionLength::from_mm(1.0))
+        .is_empty());
+}
+
+#[test]
+fn units_test() {
+    let gcode: GCodeModel = "G21\nG1 X10\nG20\nG1 X1\nG20\nG21\nG20".parse().unwrap();
+    let findings = gcode.check_units();
+    let ids = findings.iter().map(|f| f.id).collect::<Vec<_>>();
+    assert_eq!(ids, [gcode.lines[2].id, gcode.lines[6].id]);
+    assert_eq!(findings[0].lint, Lint::InchUnits);
+    let gcode: GCodeModel = "G21\nG1 X10".parse().unwrap();
+    assert!(gcode.check_units().is_empty());
+}
+
+#[test]
+fn preamble_test() {
+    let input = "G1 Z5 F600\nG29\nG28\nM140 S60\nG1 X10 E1\nM104 S210\nG1 X20 E2";
+    let gcode: GCodeModel = input.parse().unwrap();
+    let lints = |rules: &PreambleRules| {
+        gcode
+            .check_preamble(rules)
+            .into_iter()
+            .map(|finding| (finding.lint, finding.severity))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        lints(&PreambleRules::default()),
+        [
+            (Lint::MotionBeforeHome, Severity::Error),
+            (Lint::LevelingBeforeHome, Severity::Warning),
+            (
+                Lint::ExtrusionBeforeTemperature {
+                    hotend: true,
+                    bed: false
+                },
+                Severity::Error
+            ),
+        ]
+    );
+    let rules = PreambleRules {
+        home_before_motion: Severity::Allow,
+        level_after_home: Severity::Error,
+        ..Default::default()
+    };
+    assert_eq!(
+        lints(&rules)[0],
+        (Lint::LevelingBeforeHome, Severity::Error)
+    );
+    let good: GCodeModel = "M140 S60\nM104 S210\nG28\nG29\nG1 X10 E1".parse().unwrap();
+    assert!(good.check_preamble(&PreambleRules::default()).is_empty());
+}
+
+#[test]
+fn end_state_test() {
+    let gcode: GCodeModel = "M104 S210\nM140 S60\nM106 S255\nG1 X10 E1\nM104 S0\nM84\nG1 Z10"
+        .parse()
+        .unwrap();
+    let missing = gcode.check_end_state(&Shutdown::ALL);
+    // the Z move after M84 enables the steppers again
+    assert_eq!(missing, [Shutdown::Bed, Shutdown::Fan, Shutdown::Steppers]);
+    assert_eq!(missing[0].to_string(), "bed is left on, missing M140 S0");
+    // keeping the bed warm for the next print
+    let gcode: GCodeModel = "M104 S210\nM140 S60\nG1 X10 E1\nM104 S0\nM107\nM18"
+        .parse()
+        .unwrap();
+    let required = [Shutdown::Hotend, Shutdown::Fan, Shutdown::Steppers];
+    assert!(gcode.check_end_state(&required).is_empty());
+}
+
+#[test]
+fn feedrate_lint_test() {
+    let gcode: GCodeModel = "G1 X100 F60000\nG1 X110 F3000\nG1 Z20 F3000\nG1 X120 Y110 E5"
+        .parse()
+        .unwrap();
+    let findings = gcode.check_feedrates(&PrinterProfile::ender_3());
+    let lints = findings
+        .iter()
+        .map(|finding| (finding.id, finding.lint.clone()))
+        .collect::<Vec<_>>();
+    let ids = gcode.lines.iter().map(|line| line.id).collect::<Vec<_>>();
+    assert_eq!(
+        lints,
+        [
+            (
+                ids[0],
+                Lint::FeedrateAboveLimit {
+                    feedrate: 1000.0,
+                    limit: 500.0
+                }
+            ),
+            (
+                ids[0],
+                Lint::AxisSpeedAboveLimit {
+                    axis: 'X',
+                    speed: 1000.0,
+                    limit: 500.0
+                }
+            ),
+            (
+                ids[2],
+                Lint::AxisSpeedAboveLimit {
+                    axis: 'Z',
+                    speed: 50.0,
+                    limit: 5.0
+                }
+            ),
+        ]
+    );
+}
+
+#[test]
+fn soft_endstop_test() {
+    let endstops = PrinterProfile::ender_3().soft_endstops();
+    let check = |input: &str| {
+        let gcode: GCodeModel = input.parse().unwrap();
+        gcode.check_soft_endstops(&endstops).map(|finding| {
+            (
+                gcode.lines.iter().position(|line| line.id == finding.id),
+                finding.lint,
+            )
+        })
+    };
+    // not homed, so the firmware can't tell
+    assert_eq!(check("G1 X-10"), None);
+    // G92 X0 at X10 shifts every later X by 10mm
+    assert_eq!(
+        check("G28\nG1 X10 Y10 Z0.2\nG92 X0\nG1 X205\nG1 X215"),
+        Some((
+            Some(4),
+            Lint::OutsideSoftEndstop {
+                axis: 'X',
+                position: 225.0,
+                min: 0.0,
+                max: 220.0
+            }
+        ))
+    );
+    assert_eq!(
+        check("G28 Z\nG91\nG1 Z1\nG1 Z-1.5").map(|(line, _)| line),
+        Some(Some(3))
+    );
+}
+
+#[test]
+fn sequential_test() {
+    let object = |x: f64, top: f64| {
+        format!(
+            "G1 X{x} Y0\nG1 Z0.2\nG1 X{} E1\nG1 Z{top}\nG1 X{x} E2\n",
+            x + 10.0
+        )
+    };
+    let clearance = Clearance {
+        radius: 5.0,
+        gantry_height: 20.0,
+    };
+    let input = format!(
+        "G28\nM83\n{}G1 Z12\n{}",
+        object(0.0, 10.0),
+        object(30.0, 5.0)
+    );
+    let gcode: GCodeModel = input.parse().unwrap();
+    assert_eq!(gcode.sequential_objects(), [0..7, 7..13]);
+    assert_eq!(gcode.check_sequential(&clearance), None);
+
+    // too close to the first object
+    let input = format!(
+        "G28\nM83\n{}G1 Z12\n{}",
+        object(0.0, 10.0),
+        object(13.0, 5.0)
+    );
+    let gcode: GCodeModel = input.parse().unwrap();
+    let finding = gcode.check_sequential(&clearance).unwrap();
+    assert_eq!(finding.id, gcode.lines[9].id);
+    assert_eq!(finding.lint, Lint::ToolheadCollision { object: 1, with: 0 });
+
+    // travelling across the first object low down
+    let input = format!(
+        "G28\nM83\n{}G1 Z0.2\n{}",
+        object(30.0, 10.0),
+        object(0.0, 5.0)
+    );
+    let gcode: GCodeModel = input.parse().unwrap();
+    let finding = gcode.check_sequential(&clearance).unwrap();
+    assert_eq!(finding.id, gcode.lines[7].id);
+
+    let low = Clearance {
+        radius: 5.0,
+        gantry_height: 8.0,
+    };
+    let input = format!(
+        "G28\nM83\n{}G1 Z12\n{}",
+        object(0.0, 10.0),
+        object(30.0, 5.0)
+    );
+    let gcode: GCodeModel = input.parse().unwrap();
+    assert_eq!(
+        gcode.check_sequential(&low).unwrap().lint,
+        Lint::GantryCollision {
+            object: 1,
+            with: 0,
+            height: 10.0
+        }
+    );
+}
+
+#[test]
+fn material_test() {
+    // 0.4 mm of 1.75 mm filament per mm at 100 mm/s is about 96 mm³/s
+    let input = "M104 S300\nM140 S80\nG1 Z0.2 F6000\nG1 X100 E40\nG1 X200 E0.1 F600\nM104 S0\nG1 Z0.4\nG1 X100 E1";
+    let gcode: GCodeModel = input.parse().unwrap();
+    let findings = gcode.check_material(&FilamentProfile::pla(), &PrinterProfile::prusa_mk4());
+    let lints = findings
+        .iter()
+        .map(|finding| (finding.id, &finding.lint))
+        .collect::<Vec<_>>();
+    assert!(matches!(
+        lints[..],
+        [
+            (_, Lint::TemperatureOutOfRange { bed: false, .. }),
+            (_, Lint::TemperatureOutOfRange { bed: true, temperature: 80.0, .. }),
+            (id, Lint::VolumetricSpeedAboveLimit { .. }),
+        ] if id == gcode.lines[3].id
+    ));
+    // both layers take under PETG's 20s to print
+    let findings = gcode.check_material(&FilamentProfile::petg(), &PrinterProfile::prusa_mk4());
+    assert!(findings
+        .iter()
+        .all(|finding| !matches!(finding.lint, Lint::TemperatureOutOfRange { bed: true, .. })));
+    let short = findings
+        .iter()
+        .filter(|finding| matches!(finding.lint, Lint::LayerTimeBelowMinimum { .. }))
+        .map(|finding| finding.id)
+        .collect::<Vec<_>>();
+    assert_eq!(short, [gcode.lines[0].id, gcode.lines[6].id]);
+}
@@ -0,0 +1,127 @@
+use crate::{Command, GCodeLine, GCodeModel, Id};
+
+/// Reasons a file's M808 loops can't be expanded
+#[derive(Debug, PartialEq, Eq)]
+pub enum UnrollError {
+    /// An M808 end marker with no open loop, or an open loop never closed
+    Unmatched(Id),
+    /// A loop with a count of zero, which firmware repeats forever
+    Infinite(Id),
+}
+
+impl std::fmt::Display for UnrollError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnrollError::Unmatched(id) => {
+                write!(f, "unmatched M808 marker at line id {}", id.get())
+            }
+            UnrollError::Infinite(id) => write!(f, "infinite M808 loop at line id {}", id.get()),
+        }
+    }
+}
+
+impl std::error::Error for UnrollError {}
+
+impl GCodeModel {
+    /// Expand every `M808 L<count>` ... `M808` block into `count` copies of
+    /// its body and drop the markers, so estimates see every repetition.
+    /// The first copy keeps its original ids, later copies get new ones.
+    pub fn unroll_loops(&mut self) -> Result<(), UnrollError> {
+        // each open loop keeps its marker id, count, and the lines collected so far
+        let mut stack: Vec<(Id, u32, Vec<GCodeLine>)> = Vec::new();
+        let mut out = Vec::with_capacity(self.lines.len());
+        let mut ids = self.id_counter.clone();
+        // work from a copy so the model is left untouched on error
+        for line in self.lines.iter().cloned() {
+            match line.command {
+                Command::M808(Some(0)) => return Err(UnrollError::Infinite(line.id)),
+                Command::M808(Some(count)) => stack.push((line.id, count, Vec::new())),
+                Command::M808(None) => {
+                    let (_, count, body) = stack.pop().ok_or(UnrollError::Unmatched(line.id))?;
+                    let mut expanded = body.clone();
+                    for _ in 1..count {
+                        expanded.extend(body.iter().map(|line| GCodeLine {
+                            id: ids.get(),
+                            ..line.clone()
+                        }));
+                    }
+                    match stack.last_mut() {
+                        Some((_, _, outer)) => outer.extend(expanded),
+                        None => out.extend(expanded),
+                    }
+                }
+                _ => match stack.last_mut() {
+                    Some((_, _, body)) => body.push(line),
+                    None => out.push(line),
+                },
+            }
+        }
+        if let Some((id, _, _)) = stack.pop() {
+            return Err(UnrollError::Unmatched(id));
+        }
+        self.lines = out;
+        self.id_counter = ids;
+        self.record_transform("unroll_loops", String::new());
+        self.tag_g1();
+        Ok(())
+    }
+}
+
+#[test]
+fn unroll_loops_test() {
+    use crate::{
+        emit::Emit,
+        Tag::{Extrusion, Travel},
+    };
+    let mut gcode: GCodeModel = "G28\nM808 L3\nG1 X1\nM808 L2\nG1 Y1\nM808\nM808\nM84"
+        .parse()
+        .unwrap();
+    assert_eq!(gcode.lines[1].command, Command::M808(Some(3)));
+    assert_eq!(gcode.lines[5].emit(false), "M808");
+    gcode.unroll_loops().unwrap();
+    let body = "G1 X1 \nG1 Y1 \nG1 Y1 \n";
+    assert_eq!(gcode.emit(false), format!("G28\n{body}{body}{body}M84\n"));
+    let ids = gcode
+        .lines
+        .iter()
+        .map(|l| l.id)
+        .collect::<std::collections::HashSet<_>>();
+    assert_eq!(ids.len(), gcode.lines.len());
+
+    // with absolute E, repeats of an extrusion don't extrude again
+    let mut gcode: GCodeModel = "M82\nM808 L2\nG1 X10 E1\nG1 X0\nM808".parse().unwrap();
+    gcode.unroll_loops().unwrap();
+    let tags = gcode
+        .lines
+        .iter()
+        .map(|l| l.command.tag())
+        .collect::<Vec<_>>();
+    assert_eq!(tags[1..], [Extrusion, Travel, Travel, Travel]);
+}
+
+#[test]
+fn unroll_errors_test() {
+    let mut gcode: GCodeModel = "M808 L2\nG1 X1".parse().unwrap();
+    assert_eq!(gcode.unroll_loops(), Err(UnrollError::Unmatched(Id(0))));
+    let mut gcode: GCodeModel = "G1 X1\nM808".parse().unwrap();
+    assert_eq!(gcode.unroll_loops(), Err(UnrollError::Unmatched(Id(1))));
+    let mut gcode: GCodeModel = "M808 L0\nG1 X1\nM808".parse().unwrap();
+    assert_eq!(gcode.unroll_loops(), Err(UnrollError::Infinite(Id(0))));
+    assert_eq!(gcode.lines.len(), 3);
+    // ids handed out for a loop before the error aren't used up
+    let mut gcode: GCodeModel = "M808 L2\nG1 X1\nM808\nM808".parse().unwrap();
+    let counter = gcode.id_counter.clone();
+    assert_eq!(gcode.unroll_loops(), Err(UnrollError::Unmatched(Id(3))));
+    assert_eq!(gcode.id_counter, counter);
+}
+
+#[test]
+fn bad_loop_count_test() {
+    use crate::emit::Emit;
+    let gcode: GCodeModel = "M808 L2.5\nG1 X1\nM808".parse().unwrap();
+    assert_eq!(
+        gcode.lines[0].command,
+        Command::Raw(String::from("M808 L2.5"))
+    );
+    assert_eq!(gcode.lines[0].emit(false), "M808 L2.5");
+}
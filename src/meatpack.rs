@@ -0,0 +1,305 @@
+use crate::{emit::EmitConfig, GCodeModel, GCodeParseError, ParserConfig};
+
+/// Byte that, sent twice, announces a `Signal`
+const SIGNAL: u8 = 0xFF;
+
+/// Nibble marking a character sent whole in the following byte
+const LITERAL: u8 = 0xF;
+
+/// Characters with a 4 bit code, by code. In no-spaces mode `E` takes
+/// the place of the space.
+const TABLE: [u8; 15] = *b"0123456789. \nGX";
+
+/// Commands to the firmware's MeatPack decoder
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Signal {
+    EnablePacking,
+    DisablePacking,
+    ResetAll,
+    QueryConfig,
+    EnableNoSpaces,
+    DisableNoSpaces,
+}
+
+impl Signal {
+    pub fn code(&self) -> u8 {
+        match self {
+            Signal::EnablePacking => 0xFB,
+            Signal::DisablePacking => 0xFA,
+            Signal::ResetAll => 0xF9,
+            Signal::QueryConfig => 0xF8,
+            Signal::EnableNoSpaces => 0xF7,
+            Signal::DisableNoSpaces => 0xF6,
+        }
+    }
+    pub fn from_code(code: u8) -> Option<Self> {
+        [
+            Signal::EnablePacking,
+            Signal::DisablePacking,
+            Signal::ResetAll,
+            Signal::QueryConfig,
+            Signal::EnableNoSpaces,
+            Signal::DisableNoSpaces,
+        ]
+        .into_iter()
+        .find(|signal| signal.code() == code)
+    }
+    /// The signal as sent on the wire
+    pub fn bytes(&self) -> [u8; 3] {
+        [SIGNAL, SIGNAL, self.code()]
+    }
+}
+
+/// Why a MeatPack stream couldn't be read
+#[derive(Debug, PartialEq)]
+pub enum MeatPackError {
+    /// a signal with an unknown command code
+    UnknownSignal(u8),
+    /// the stream stops part way through a signal or packed pair
+    Truncated,
+    InvalidUtf8,
+    Parse(GCodeParseError),
+}
+
+impl std::fmt::Display for MeatPackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MeatPackError::UnknownSignal(code) => write!(f, "unknown MeatPack signal {code:#04x}"),
+            MeatPackError::Truncated => write!(f, "MeatPack stream ends part way through a byte"),
+            MeatPackError::InvalidUtf8 => write!(f, "unpacked MeatPack stream is not valid UTF-8"),
+            MeatPackError::Parse(e) => write!(f, "failed to parse unpacked gcode: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for MeatPackError {}
+
+impl From<GCodeParseError> for MeatPackError {
+    fn from(e: GCodeParseError) -> Self {
+        MeatPackError::Parse(e)
+    }
+}
+
+/// Options for MeatPack, the packing of the most common characters of
+/// G-code into 4 bits each that Prusa and Marlin firmware accept over
+/// serial
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MeatPack {
+    /// drop the spaces between words so `E` can take their 4 bit code.
+    /// Spaces in M117 and M118 messages are kept and sent whole.
+    pub no_spaces: bool,
+}
+
+impl MeatPack {
+    fn code(&self, c: u8) -> Option<u8> {
+        match c {
+            b' ' if self.no_spaces => None,
+            b'E' if self.no_spaces => Some(11),
+            c => TABLE.iter().position(|t| *t == c).map(|i| i as u8),
+        }
+    }
+    /// The signals that put the firmware's decoder in this mode
+    pub fn preamble(&self) -> Vec<u8> {
+        let spaces = match self.no_spaces {
+            true => Signal::EnableNoSpaces,
+            false => Signal::DisableNoSpaces,
+        };
+        [Signal::EnablePacking, spaces]
+            .iter()
+            .flat_map(Signal::bytes)
+            .collect()
+    }
+    /// Pack one line, which is sent trimmed and without its comment.
+    /// Blank lines aren't sent at all.
+    pub fn encode_line(&self, line: &str, out: &mut Vec<u8>) {
+        let line = line.split(';').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            return;
+        }
+        let is_message = ["M117", "M118"].iter().any(|m| {
+            line.get(..4)
+                .is_some_and(|word| word.eq_ignore_ascii_case(m))
+        });
+        let mut bytes = match self.no_spaces && !is_message {
+            true => line.bytes().filter(|c| *c != b' ').collect::<Vec<_>>(),
+            false => line.as_bytes().to_vec(),
+        };
+        bytes.push(b'\n');
+        // the decoder drops whatever is packed after a newline
+        if bytes.len() % 2 == 1 {
+            bytes.push(b'0');
+        }
+        for pair in bytes.chunks(2) {
+            let (low, high) = (self.code(pair[0]), self.code(pair[1]));
+            out.push(high.unwrap_or(LITERAL) << 4 | low.unwrap_or(LITERAL));
+            out.extend(low.is_none().then_some(pair[0]));
+            out.extend(high.is_none().then_some(pair[1]));
+        }
+    }
+    /// `text` packed a line at a time, after the `preamble`
+    pub fn encode(&self, text: &str) -> Vec<u8> {
+        let mut out = self.preamble();
+        for line in text.lines() {
+            self.encode_line(line, &mut out);
+        }
+        out
+    }
+}
+
+/// Unpacks a MeatPack stream a byte at a time like the firmware does,
+/// starting with packing off
+#[derive(Clone, Debug, Default)]
+pub struct Decoder {
+    packing: bool,
+    no_spaces: bool,
+    /// a first signal byte was seen
+    signal: bool,
+    /// the next byte is a signal's command code
+    command: bool,
+    /// whole characters still to come after a packed byte
+    literals: u8,
+    /// packed character to write after the pending literal
+    second: Option<u8>,
+    out: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Decoder::default()
+    }
+    pub fn push(&mut self, byte: u8) -> Result<(), MeatPackError> {
+        if self.command {
+            self.command = false;
+            return self.apply(byte);
+        }
+        if byte == SIGNAL {
+            self.command = self.signal;
+            self.signal = !self.signal;
+            return Ok(());
+        }
+        if self.signal {
+            // a lone signal byte was data after all
+            self.signal = false;
+            self.unpack(SIGNAL);
+        }
+        self.unpack(byte);
+        Ok(())
+    }
+    fn apply(&mut self, code: u8) -> Result<(), MeatPackError> {
+        match Signal::from_code(code).ok_or(MeatPackError::UnknownSignal(code))? {
+            Signal::EnablePacking => self.packing = true,
+            Signal::DisablePacking => self.packing = false,
+            Signal::ResetAll => (self.packing, self.no_spaces) = (false, false),
+            Signal::QueryConfig => {}
+            Signal::EnableNoSpaces => self.no_spaces = true,
+            Signal::DisableNoSpaces => self.no_spaces = false,
+        }
+        Ok(())
+    }
+    fn lookup(&self, code: u8) -> u8 {
+        match code {
+            11 if self.no_spaces => b'E',
+            code => TABLE[code as usize],
+        }
+    }
+    fn unpack(&mut self, byte: u8) {
+        if !self.packing {
+            self.out.push(byte);
+            return;
+        }
+        if self.literals > 0 {
+            self.out.push(byte);
+            self.literals -= 1;
+            if self.literals == 0 {
+                self.out.extend(self.second.take());
+            }
+            return;
+        }
+        let (low, high) = (byte & LITERAL, byte >> 4);
+        if low == LITERAL {
+            self.literals = 1;
+            match high {
+                LITERAL => self.literals = 2,
+                high => self.second = Some(self.lookup(high)),
+            }
+            return;
+        }
+        let first = self.lookup(low);
+        self.out.push(first);
+        if first == b'\n' {
+            return;
+        }
+        match high {
+            LITERAL => self.literals = 1,
+            high => self.out.push(self.lookup(high)),
+        }
+    }
+    /// The text unpacked so far, failing if the stream stopped part way
+    /// through a signal or a packed pair
+    pub fn finish(self) -> Result<String, MeatPackError> {
+        if self.signal || self.command || self.literals > 0 {
+            return Err(MeatPackError::Truncated);
+        }
+        String::from_utf8(self.out).map_err(|_| MeatPackError::InvalidUtf8)
+    }
+}
+
+/// Unpack a whole MeatPack stream, including its signals
+pub fn decode(bytes: &[u8]) -> Result<String, MeatPackError> {
+    let mut decoder = Decoder::new();
+    for byte in bytes {
+        decoder.push(*byte)?;
+    }
+    decoder.finish()
+}
+
+impl GCodeModel {
+    /// The model emitted with `config` and packed for a printer with
+    /// MeatPack enabled, starting with the signals that set it up.
+    /// Comments and blank lines aren't sent.
+    pub fn emit_meatpack(&self, config: &EmitConfig, meatpack: &MeatPack) -> Vec<u8> {
+        meatpack.encode(&self.emit_with_config(config))
+    }
+    /// Parse a MeatPack stream as sent to the printer
+    pub fn parse_meatpack(bytes: &[u8], config: &ParserConfig) -> Result<Self, MeatPackError> {
+        Ok(GCodeModel::parse_with_config(&decode(bytes)?, config)?)
+    }
+}
+
+#[test]
+fn meatpack_test() {
+    use crate::emit::Emit;
+    let input = "G28 ; home\nG1 X10.5 Y2 E0.4 F1200\n\nM117 Hello world\nG1 Z.2\n";
+    let gcode: GCodeModel = input.parse().unwrap();
+    let stripped = "G28\nG1 X10.5 Y2 E0.4 F1200\nM117 Hello world\nG1 Z.2\n";
+    let packed = MeatPack::default().encode(input);
+    assert_eq!(packed[..6], [0xFF, 0xFF, 0xFB, 0xFF, 0xFF, 0xF6]);
+    // "G2" and "8\n" each pack into one byte
+    assert_eq!(packed[6..8], [0x2D, 0xC8]);
+    // "M" goes whole after the byte it shares with "8"
+    let mut line = Vec::new();
+    MeatPack::default().encode_line("M84 ; off", &mut line);
+    assert_eq!(line, [0x8F, b'M', 0xC4]);
+    assert_eq!(decode(&packed).unwrap(), stripped);
+    assert!(packed.len() < stripped.len());
+
+    let meatpack = MeatPack { no_spaces: true };
+    let packed = gcode.emit_meatpack(&EmitConfig::default(), &meatpack);
+    assert_eq!(
+        decode(&packed).unwrap(),
+        "G28\nG1X10.5Y2E0.4F1200\nM117 Hello world\nG1Z0.2\n"
+    );
+    let parsed = GCodeModel::parse_meatpack(&packed, &ParserConfig::default()).unwrap();
+    assert_eq!(parsed.lines[1].command, gcode.lines[1].command);
+    assert_eq!(parsed.lines[2].emit(false), "M117 Hello world");
+
+    // without signals bytes pass through
+    assert_eq!(decode(b"G1 X1\n").unwrap(), "G1 X1\n");
+    assert_eq!(
+        decode(&[0xFF, 0xFF, 0x01]),
+        Err(MeatPackError::UnknownSignal(1))
+    );
+    let mut truncated = MeatPack::default().encode("M84");
+    truncated.truncate(truncated.len() - 2);
+    assert_eq!(decode(&truncated), Err(MeatPackError::Truncated));
+}
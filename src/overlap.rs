@@ -0,0 +1,136 @@
+use crate::{GCodeModel, Id, Tag};
+use std::collections::{BTreeSet, HashMap};
+
+/// Side of the grid cells segments are bucketed into, in mm
+const CELL: f64 = 5.0;
+
+/// Endpoints closer than this in mm are the same point, so moves that
+/// continue one another aren't reported
+const JOINED: f64 = 1e-3;
+
+type Point = [f64; 2];
+
+fn sub([x0, y0]: Point, [x1, y1]: Point) -> Point {
+    [x0 - x1, y0 - y1]
+}
+
+fn cross([x0, y0]: Point, [x1, y1]: Point) -> f64 {
+    x0 * y1 - y0 * x1
+}
+
+fn dot([x0, y0]: Point, [x1, y1]: Point) -> f64 {
+    x0 * x1 + y0 * y1
+}
+
+fn point_distance(p: Point, [a, b]: [Point; 2]) -> f64 {
+    let (ab, ap) = (sub(b, a), sub(p, a));
+    let len = dot(ab, ab);
+    let t = if len > 0.0 {
+        (dot(ap, ab) / len).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let [dx, dy] = sub(ap, [ab[0] * t, ab[1] * t]);
+    dx.hypot(dy)
+}
+
+/// Shortest distance between two segments, zero if they cross
+fn segment_distance(s: [Point; 2], t: [Point; 2]) -> f64 {
+    let side = |seg: [Point; 2], p: Point| cross(sub(seg[1], seg[0]), sub(p, seg[0]));
+    let crosses = |s: [Point; 2], t: [Point; 2]| side(s, t[0]) * side(s, t[1]) < 0.0;
+    if crosses(s, t) && crosses(t, s) {
+        return 0.0;
+    }
+    [
+        point_distance(s[0], t),
+        point_distance(s[1], t),
+        point_distance(t[0], s),
+        point_distance(t[1], s),
+    ]
+    .into_iter()
+    .fold(f64::INFINITY, f64::min)
+}
+
+fn joined(s: [Point; 2], t: [Point; 2]) -> bool {
+    let close = |p: Point, q: Point| {
+        let [dx, dy] = sub(p, q);
+        dx.hypot(dy) < JOINED
+    };
+    s.iter().any(|p| t.iter().any(|q| close(*p, *q)))
+}
+
+impl GCodeModel {
+    /// Pairs of extrusion moves on the same layer whose paths come within
+    /// `tolerance` mm of each other, including moves that cross. Adjacent
+    /// lines sit about one line width apart, so a tolerance of around half
+    /// the nozzle diameter finds spots that are printed twice, which are
+    /// over-extruded or point to slicing bugs. Moves that share an endpoint
+    /// are not compared. Each pair is listed once, earlier line first.
+    pub fn overlapping_extrusions(&self, tolerance: f64) -> Vec<(Id, Id)> {
+        let steps = self.cursor().collect::<Vec<_>>();
+        let mut pairs = BTreeSet::new();
+        for layer in self.layers() {
+            let segments = steps[layer.range()]
+                .iter()
+                .filter(|step| step.line.command.tag() == Tag::Extrusion)
+                .filter(|step| {
+                    step.prev.pos.x != step.next.pos.x || step.prev.pos.y != step.next.pos.y
+                })
+                .map(|step| {
+                    let (a, b) = (step.prev.pos, step.next.pos);
+                    let segment = [[a.x.to_mm(), a.y.to_mm()], [b.x.to_mm(), b.y.to_mm()]];
+                    (step.line.id, segment)
+                })
+                .collect::<Vec<_>>();
+            let mut grid: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+            for (n, (_, [a, b])) in segments.iter().enumerate() {
+                let cell = |v: f64| (v / CELL).floor() as i64;
+                let (x0, x1) = (a[0].min(b[0]) - tolerance, a[0].max(b[0]) + tolerance);
+                let (y0, y1) = (a[1].min(b[1]) - tolerance, a[1].max(b[1]) + tolerance);
+                for x in cell(x0)..=cell(x1) {
+                    for y in cell(y0)..=cell(y1) {
+                        grid.entry((x, y)).or_default().push(n);
+                    }
+                }
+            }
+            let mut checked = BTreeSet::new();
+            for cell in grid.values() {
+                for (k, &i) in cell.iter().enumerate() {
+                    for &j in &cell[k + 1..] {
+                        if !checked.insert((i, j)) {
+                            continue;
+                        }
+                        let ((s_id, s), (t_id, t)) = (segments[i], segments[j]);
+                        if !joined(s, t) && segment_distance(s, t) < tolerance {
+                            pairs.insert((s_id, t_id));
+                        }
+                    }
+                }
+            }
+        }
+        pairs.into_iter().collect()
+    }
+}
+
+#[test]
+fn overlapping_extrusions_test() {
+    let input = "G1 Z0.2 F600\nG1 X0 Y0\nG1 X20 E1\nG1 Y0.45\nG1 X0 E2\nG1 Y0.6\nG1 X20 Y0.6 E3\nG1 X10 Y-5\nG1 Y5 E4\nG1 Z0.4\nG1 X0 Y0\nG1 X20 E5";
+    let gcode: GCodeModel = input.parse().unwrap();
+    let ids = |lines: &[usize]| lines.iter().map(|&i| gcode.lines[i].id).collect::<Vec<_>>();
+    let pairs = gcode.overlapping_extrusions(0.2);
+    let [first, second, third, cross] = ids(&[2, 4, 6, 8])[..] else {
+        unreachable!()
+    };
+    // 0.15 mm between the second and third line, and a move crossing all
+    // three; the next layer retracing the first is not compared
+    assert_eq!(
+        pairs,
+        [
+            (first, cross),
+            (second, third),
+            (second, cross),
+            (third, cross)
+        ]
+    );
+    assert_eq!(gcode.overlapping_extrusions(0.1).len(), 3);
+}
@@ -0,0 +1,117 @@
+use crate::{
+    parsers::{raw_param, split_raw},
+    state::Positioning,
+    Command, ExtrusionLength, Feedrate, GCodeModel, G1, G92,
+};
+
+/// An M220 or M221 line with its S percentage set back to 100, `None` for
+/// other lines and ones that don't set a percentage
+fn neutralized(raw: &str) -> Option<String> {
+    let (word, rest) = split_raw(raw)?;
+    if !matches!(word.as_str(), "M220" | "M221") || raw_param(rest, 'S').is_none() {
+        return None;
+    }
+    let params = rest.split_whitespace().map(|param| {
+        if param.starts_with(['S', 's']) {
+            "S100"
+        } else {
+            param
+        }
+    });
+    Some(
+        std::iter::once(word.as_str())
+            .chain(params)
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+impl GCodeModel {
+    /// Copy of the model with the M220 speed and M221 flow percentages
+    /// active at each line baked into its F and E values, so that time,
+    /// speed and filament statistics run on it report what the machine
+    /// actually does rather than what the slicer wrote. The M220 and M221
+    /// lines are kept but set back to 100%, so checks that read the
+    /// overrides from `MachineState` don't apply them a second time.
+    pub fn effective(&self) -> GCodeModel {
+        let mut model = self.clone();
+        // E as written by the file and as the machine runs it, both reset by G92 E
+        let mut written = ExtrusionLength::ZERO;
+        let mut effective = ExtrusionLength::ZERO;
+        let mut last_f: Option<Feedrate> = None;
+        let steps = self.cursor().map(|step| (step.prev, step.next));
+        for (line, (prev, next)) in model.lines.iter_mut().zip(steps) {
+            if let Command::G92(G92 { e: Some(e), .. }) = &line.command {
+                written = *e;
+                effective = written;
+                continue;
+            }
+            if let Command::Raw(raw) = &mut line.command {
+                if let Some(neutral) = neutralized(raw) {
+                    *raw = neutral;
+                }
+                continue;
+            }
+            let (e, f) = match &mut line.command {
+                Command::G0(G1 { e, f, .. })
+                | Command::G1(G1 { e, f, .. })
+                | Command::G53(Some(G1 { e, f, .. }), _) => (e, f),
+                Command::G5(g5) => (&mut g5.e, &mut g5.f),
+                Command::Arc(arc) => (&mut arc.e, &mut arc.f),
+                _ => continue,
+            };
+            let flow = f64::from(next.overrides.flow) / 100.0;
+            if let Some(e) = e {
+                let delta = match next.e_positioning {
+                    Positioning::Absolute => next.e - written,
+                    Positioning::Relative => next.e - prev.e,
+                };
+                written = next.e;
+                let scaled = ExtrusionLength::from_mm(delta.to_mm() * flow);
+                effective += scaled;
+                *e = match next.e_positioning {
+                    Positioning::Absolute => effective,
+                    Positioning::Relative => scaled,
+                };
+            }
+            let feedrate = next.effective_feedrate();
+            let moves = prev.pos != next.pos || prev.e != next.e;
+            if f.is_some() || (moves && last_f != Some(feedrate)) {
+                *f = Some(feedrate);
+                last_f = Some(feedrate);
+            }
+        }
+        model.tag_g1();
+        model.record_transform("effective", String::new());
+        model
+    }
+}
+
+#[test]
+fn effective_test() {
+    use crate::emit::Emit;
+    let gcode: GCodeModel = "M83\nG1 X10 E1 F600\nM220 S50\nM221 S110\nG1 X20 E1\nG1 X30 E1"
+        .parse()
+        .unwrap();
+    let effective = gcode.effective();
+    assert_eq!(
+        effective.emit(false),
+        "M83\nG1 X10 E1 F600 \nM220 S100\nM221 S100\nG1 X20 E1.1 F300 \nG1 X30 E1.1 \n"
+    );
+    // the machine state agrees with what's written
+    let end = effective.cursor().last().unwrap().next;
+    assert_eq!(end.effective_feedrate(), Feedrate::from_mm_per_min(300.0));
+    assert_eq!(gcode.estimate_time(), std::time::Duration::from_secs(3));
+    assert_eq!(effective.estimate_time(), std::time::Duration::from_secs(5));
+
+    // absolute E keeps counting from the scaled total, until a reset
+    let gcode: GCodeModel = "G1 X10 E1 F600\nM221 S200\nG1 X20 E2\nG92 E0\nG1 X30 E1"
+        .parse()
+        .unwrap();
+    let e = gcode
+        .effective()
+        .cursor()
+        .map(|step| step.next.e.to_mm())
+        .collect::<Vec<_>>();
+    assert_eq!(e, [1.0, 1.0, 3.0, 0.0, 2.0]);
+}
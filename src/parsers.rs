@@ -1,31 +1,39 @@
-use crate::{Command, GCodeLine, GCodeModel, G1};
-use microns::Microns;
+use crate::{
+    compat::M862,
+    custom::CommandRegistry,
+    emit::LineEnding,
+    leveling::M420,
+    profile::Firmware,
+    progress::{Monitor, ParseFailure, Stage},
+    state::Wcs,
+    Annotations, ArcMove, Command, Counter, ExtrusionLength, Feedrate, G53Motion, GCodeLine,
+    GCodeModel, Home, Length, Rounding, G1, G5, G92, M106,
+};
 use winnow::{
-    ascii::multispace1,
-    combinator::separated_pair,
+    combinator::{alt, separated_pair},
     error::InputError,
-    token::{rest, one_of, take, take_till, take_while},
+    token::{one_of, rest, take, take_till, take_while},
     ModalResult, Parser,
 };
 
-/// parse a line until '\n' or '\r' and then clear all following whitespace
+/// parse a line until '\n' or '\r' and then consume its line ending
 fn parse_line<'a>(input: &mut &'a str) -> ModalResult<&'a str> {
     // this must always consume at least one character
     let line = take_till(0.., |c| c == '\n' || c == '\r').parse_next(input)?;
-    let _: ModalResult<&str> = multispace1.parse_next(input);
+    let _: ModalResult<&str> = alt(("\r\n", "\n", "\r")).parse_next(input);
     Ok(line)
 }
 
-/// repeat the parse_line fn until the input is empty and collect to Vec
-fn parse_lines<'a>(input: &mut &'a str) -> ModalResult<Vec<&'a str>> {
-    let mut out = Vec::new();
-    loop {
-        if input.is_empty() {
-            break;
+/// repeat the parse_line fn until the input is empty or `max` lines have
+/// been collected and collect to Vec, leaving anything after them unparsed
+fn parse_lines<'a>(max: usize) -> impl FnMut(&mut &'a str) -> ModalResult<Vec<&'a str>> {
+    move |input| {
+        let mut out = Vec::new();
+        while !input.is_empty() && out.len() < max {
+            out.push(parse_line.parse_next(input)?);
         }
-        out.push(parse_line.parse_next(input)?);
+        Ok(out)
     }
-    Ok(out)
 }
 
 /// parse the first word of a line by taking the first char
@@ -40,34 +48,405 @@ fn parse_word<'a>(input: &mut &'a str) -> ModalResult<(&'a str, &'a str, &'a str
         .parse_next(input)
 }
 
+/// take the free text following a command word (e.g. "M117" or "G29")
+/// exactly as written, dropping only the single separating space
+fn message_payload(line: &str) -> String {
+    let line = line.trim_start();
+    let rest = line.get(1..).unwrap_or("");
+    let rest = rest.trim_start_matches(|c: char| c.is_ascii_digit());
+    String::from(rest.strip_prefix(' ').unwrap_or(rest))
+}
+
+/// parses the S word of a spindle command, rounded to a whole number,
+/// `None` if there are any other words, so those lines stay raw
+fn spindle_parse(mut rest: &str, rounding: Rounding) -> Option<Option<u32>> {
+    let params = parameter_parse(&mut rest, &['S'], rounding).ok()?;
+    if !rest.is_empty() {
+        return None;
+    }
+    Some(
+        params
+            .last()
+            .map(|(_, speed)| speed.to_mm().round().max(0.0) as u32),
+    )
+}
+
+/// Split a command kept as raw text into its uppercased first word and
+/// the rest of the line, e.g. `("M104", "S0")`
+pub(crate) fn split_raw(raw: &str) -> Option<(String, &str)> {
+    let raw = raw.trim();
+    let (word, rest) = raw.split_once(char::is_whitespace).unwrap_or((raw, ""));
+    (!word.is_empty()).then(|| (word.to_uppercase(), rest.trim()))
+}
+
+/// Value of the `letter` word among the parameters of a raw command
+pub(crate) fn raw_param(rest: &str, letter: char) -> Option<f32> {
+    rest.split_whitespace()
+        .find_map(|param| param.strip_prefix([letter, letter.to_ascii_lowercase()]))
+        .and_then(|value| value.parse().ok())
+}
+
+/// Value of a Klipper style `KEY=value` parameter of a raw command
+pub(crate) fn raw_key(rest: &str, key: &str) -> Option<f32> {
+    rest.split_whitespace()
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.eq_ignore_ascii_case(key))
+        .and_then(|(_, value)| value.parse().ok())
+}
+
+/// Whether `command` leaves G1 as the modal motion command, `None` for
+/// commands that don't change it
+pub(crate) fn sets_g1_mode(command: &Command) -> Option<bool> {
+    match command {
+        Command::G1(_) | Command::G53(Some(_), G53Motion::G1) => Some(true),
+        Command::G0(_) | Command::G53(_, G53Motion::G0) | Command::G5(_) | Command::Arc(_) => {
+            Some(false)
+        }
+        Command::Raw(raw) => {
+            let raw = raw.trim_start();
+            let digits = raw
+                .strip_prefix(['G', 'g'])?
+                .split(|c: char| !c.is_ascii_digit())
+                .next()?;
+            // moves kept raw for their extra words still set the modal
+            // motion, and canned cycles take over bare coordinates too
+            match digits.parse::<u32>() {
+                Ok(1) => Some(true),
+                Ok(0 | 2 | 3 | 5 | 80..=89) => Some(false),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Number of words on a line, each starting at a letter that follows a
+/// space, a number or the start of the line, so `G1X1Y2` counts as three
+fn count_words(line: &str) -> usize {
+    let mut prev = ' ';
+    line.chars()
+        .filter(|&c| {
+            let starts = c.is_alphabetic() && (prev.is_whitespace() || is_number_char(prev));
+            prev = c;
+            starts
+        })
+        .count()
+}
+
 /// Helper function to check if a character is part of a number
 fn is_number_char(c: char) -> bool {
     c.is_numeric() || c == '.' || c == '-' || c == '+'
 }
 
-/// parses g1 params once the first word ("G1") has been parsed
-fn g1_parameter_parse(input: &mut &str) -> ModalResult<G1> {
-    let mut out = G1::default();
+/// parses letter/number parameter pairs for any of `letters`,
+/// stopping at the first word that doesn't match
+fn parameter_parse(
+    input: &mut &str,
+    letters: &[char],
+    rounding: Rounding,
+) -> ModalResult<Vec<(char, Length)>> {
+    let mut out = Vec::new();
     while let Ok((c, val)) = separated_pair(
-        one_of::<_, _, InputError<_>>(['X', 'Y', 'Z', 'E', 'F']),
+        one_of::<_, _, InputError<_>>(|c: char| letters.contains(&c)),
         winnow::combinator::empty,
         take_while(1.., is_number_char).parse_to::<String>(),
     )
     .parse_next(input)
     {
-        if let Ok(val) = val.parse::<f32>() {
-            let val = Microns::from(val);
+        if let Ok(val) = val.parse::<f64>() {
+            out.push((c, Length::from_mm_with(val, rounding)));
+        }
+    }
+    Ok(out)
+}
+
+/// parses g1 params once the first word ("G1") has been parsed
+fn g1_parameter_parse(input: &mut &str, rounding: Rounding) -> ModalResult<G1> {
+    let mut out = G1::default();
+    for (c, val) in parameter_parse(input, &['X', 'Y', 'Z', 'E', 'F', 'S'], rounding)? {
+        match c {
+            'X' => out.x = Some(val),
+            'Y' => out.y = Some(val),
+            'Z' => out.z = Some(val),
+            'E' => out.e = Some(ExtrusionLength::new(val)),
+            'F' => out.f = Some(Feedrate::new(val)),
+            'S' => out.s = Some(val.to_mm().round().max(0.0) as u32),
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+/// parses g92 params once the first word ("G92") has been parsed
+fn g92_parameter_parse(input: &mut &str, rounding: Rounding) -> ModalResult<G92> {
+    let mut out = G92::default();
+    for (c, val) in parameter_parse(input, &['X', 'Y', 'Z', 'E'], rounding)? {
+        match c {
+            'X' => out.x = Some(val),
+            'Y' => out.y = Some(val),
+            'Z' => out.z = Some(val),
+            'E' => out.e = Some(ExtrusionLength::new(val)),
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+/// parses the params of G28, `rest` being the collapsed text after "G28".
+/// X, Y and Z are axis flags even with a position after them, which
+/// firmware ignores, and every other word is kept as an option. `None`
+/// unless the params start with a letter, so `G28.1` and the like stay
+/// raw.
+fn home_parse(rest: &str) -> Option<Home> {
+    if !rest.is_empty() && !rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let mut out = Home::default();
+    let mut options = Vec::new();
+    let mut rest = rest;
+    while !rest.is_empty() {
+        // a word runs from one letter up to the next
+        let end = rest
+            .char_indices()
+            .skip(1)
+            .find(|(_, c)| c.is_ascii_alphabetic())
+            .map_or(rest.len(), |(i, _)| i);
+        let (word, tail) = rest.split_at(end);
+        rest = tail;
+        let (letter, value) = word.split_at(1);
+        let axis = match letter.to_ascii_uppercase().as_str() {
+            "X" => 0,
+            "Y" => 1,
+            "Z" => 2,
+            "W" if value.is_empty() => {
+                out.skip_leveling = true;
+                continue;
+            }
+            _ => {
+                options.push(word);
+                continue;
+            }
+        };
+        *[&mut out.x, &mut out.y, &mut out.z][axis] = true;
+        out.positions[axis] = String::from(value);
+    }
+    out.options = options.join(" ");
+    Some(out)
+}
+
+/// parses arc params once the first word ("G2" or "G3") has been parsed
+fn arc_parameter_parse(input: &mut &str, rounding: Rounding) -> ModalResult<ArcMove> {
+    let mut out = ArcMove::default();
+    let letters = ['X', 'Y', 'Z', 'I', 'J', 'R', 'E', 'F'];
+    for (c, val) in parameter_parse(input, &letters, rounding)? {
+        match c {
+            'X' => out.x = Some(val),
+            'Y' => out.y = Some(val),
+            'Z' => out.z = Some(val),
+            'I' => out.i = Some(val),
+            'J' => out.j = Some(val),
+            'R' => out.r = Some(val),
+            'E' => out.e = Some(ExtrusionLength::new(val)),
+            'F' => out.f = Some(Feedrate::new(val)),
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+/// parses g5 params once the first word ("G5") has been parsed
+fn g5_parameter_parse(input: &mut &str, rounding: Rounding) -> ModalResult<G5> {
+    let mut out = G5::default();
+    let letters = ['I', 'J', 'P', 'Q', 'X', 'Y', 'E', 'F'];
+    for (c, val) in parameter_parse(input, &letters, rounding)? {
+        match c {
+            'I' => out.i = Some(val),
+            'J' => out.j = Some(val),
+            'P' => out.p = Some(val),
+            'Q' => out.q = Some(val),
+            'X' => out.x = Some(val),
+            'Y' => out.y = Some(val),
+            'E' => out.e = Some(ExtrusionLength::new(val)),
+            'F' => out.f = Some(Feedrate::new(val)),
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+/// parses the P (milliseconds) and S (seconds) of a G4 dwell, rounded to
+/// the nanosecond, `None` if there are any other words
+fn dwell_parse(mut rest: &str, rounding: Rounding) -> Option<std::time::Duration> {
+    let params = parameter_parse(&mut rest, &['P', 'S'], rounding).ok()?;
+    if !rest.is_empty() {
+        return None;
+    }
+    let seconds = params
+        .iter()
+        .map(|(c, val)| match c {
+            'P' => val.to_mm() / 1000.0,
+            _ => val.to_mm(),
+        })
+        .sum::<f64>();
+    let nanos = (seconds.max(0.0) * 1e9).round() as u64;
+    Some(std::time::Duration::from_nanos(nanos))
+}
+
+/// parses the P (fan index) and S (speed) of M106 or M107, `None` unless
+/// every word is one of them with a whole number from 0 to 255, so the
+/// fractional speeds some firmware takes stay raw
+fn fan_parse(mut rest: &str, rounding: Rounding) -> Option<(Option<u8>, Option<u8>)> {
+    let params = parameter_parse(&mut rest, &['P', 'S'], rounding).ok()?;
+    if !rest.is_empty() {
+        return None;
+    }
+    let (mut index, mut speed) = (None, None);
+    for (c, val) in params {
+        let val = val.to_mm();
+        if val.fract() != 0.0 || !(0.0..=255.0).contains(&val) {
+            return None;
+        }
+        match c {
+            'P' => index = Some(val as u8),
+            _ => speed = Some(val as u8),
+        }
+    }
+    Some((index, speed))
+}
+
+/// parses an M862.x check, `rest` being the collapsed text after "M862"
+/// and `line` the original text so quoted values keep their spaces
+fn m862_parse(rest: &str, line: &str) -> Option<M862> {
+    let check = rest.strip_prefix('.')?.chars().next()?.to_digit(10)? as u8;
+    let args = line.trim_start().get(6..)?;
+    let args = args.strip_prefix(' ').unwrap_or(args);
+    Some(M862 {
+        check,
+        args: String::from(args),
+    })
+}
+
+/// parses M420 params in any order, where V is a flag whatever value
+/// follows it, `None` if there are any other words
+fn m420_parameter_parse(mut input: &str, rounding: Rounding) -> Option<M420> {
+    let mut out = M420::default();
+    while !input.is_empty() {
+        let params = parameter_parse(&mut input, &['S', 'Z', 'L'], rounding).ok()?;
+        for (c, val) in params {
             match c {
-                'X' => out.x = Some(val),
-                'Y' => out.y = Some(val),
-                'Z' => out.z = Some(val),
-                'E' => out.e = Some(val),
-                'F' => out.f = Some(val),
+                'S' => out.enable = Some(val != Length::ZERO),
+                'Z' => out.fade_height = Some(val),
+                'L' => out.slot = Some(val.to_mm().round().max(0.0) as u32),
                 _ => {}
             }
         }
+        if let Some(rest) = input.strip_prefix('V') {
+            out.verbose = true;
+            input = rest.trim_start_matches(is_number_char);
+        } else if !input.is_empty() {
+            return None;
+        }
     }
-    Ok(out)
+    Some(out)
+}
+
+/// Options controlling how a gcode file is parsed
+#[derive(Clone, Debug, Default)]
+pub struct ParserConfig {
+    /// rounding used when converting parameter values to fixed point
+    pub rounding: Rounding,
+    /// parsers for commands that would otherwise be stored as `Command::Raw`
+    pub commands: CommandRegistry,
+    pub comments: CommentMode,
+    pub comment_syntax: CommentSyntax,
+    /// id of the first line, for ids that must not collide with ones
+    /// handed out in an earlier session
+    pub first_id: u32,
+    /// largest input accepted in bytes, for untrusted input
+    pub max_bytes: Option<usize>,
+    /// most lines accepted, for untrusted input
+    pub max_lines: Option<usize>,
+    /// most words accepted after a line's command word, for untrusted
+    /// input. A word starts at each letter after a space or a number, so
+    /// compact lines like `G1X1Y2` are counted the same as spaced ones.
+    pub max_params_per_line: Option<usize>,
+    /// read lines made only of coordinates, e.g. `X10 Y5`, as G1 moves
+    /// when G1 is the modal motion command, as CNC dialects allow
+    pub modal_moves: bool,
+}
+
+/// A `ParserConfig` limit that the input went over
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Limit {
+    Bytes(usize),
+    Lines(usize),
+    ParamsPerLine(usize),
+}
+
+/// How the parser finds the `;` that starts a line's comment, which
+/// differs between firmware dialects
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum CommentSyntax {
+    /// at the first `;` on the line
+    #[default]
+    Plain,
+    /// at the first `;` outside double quotes, e.g. RepRapFirmware's
+    /// `M587 S"my;ssid"`
+    Quoted,
+    /// like `Quoted`, but M117 and M118 messages run to the end of the
+    /// line, e.g. Klipper's `M117 Hello; world`
+    Messages,
+}
+
+impl CommentSyntax {
+    pub fn for_firmware(firmware: Firmware) -> Self {
+        match firmware {
+            Firmware::Klipper => CommentSyntax::Messages,
+            Firmware::RepRapFirmware => CommentSyntax::Quoted,
+            Firmware::Marlin | Firmware::Prusa | Firmware::Grbl => CommentSyntax::Plain,
+        }
+    }
+}
+
+/// Split a line into its command and the comment after the `;`
+fn split_comment(line: &str, syntax: CommentSyntax) -> (&str, &str) {
+    let word = line.trim_start().get(..4).unwrap_or("");
+    let is_message = ["M117", "M118"]
+        .iter()
+        .any(|m| word.eq_ignore_ascii_case(m))
+        && !line.trim_start()[4..].starts_with(|c: char| c.is_ascii_digit());
+    let start = match syntax {
+        CommentSyntax::Plain => line.find(';'),
+        CommentSyntax::Messages if is_message => None,
+        CommentSyntax::Quoted | CommentSyntax::Messages => {
+            let mut quoted = false;
+            line.char_indices().find_map(|(i, c)| {
+                match c {
+                    '"' => quoted = !quoted,
+                    ';' if !quoted => return Some(i),
+                    _ => {}
+                }
+                None
+            })
+        }
+    };
+    match start {
+        Some(i) => (&line[..i], &line[i + 1..]),
+        None => (line, ""),
+    }
+}
+
+/// Where the parser keeps the comment after each line's `;`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum CommentMode {
+    /// on the line, in `GCodeLine::comments`
+    #[default]
+    Inline,
+    /// nowhere, to save memory when the comments will never be read
+    Strip,
+    /// in `GCodeModel::comments` keyed by line id, leaving each line's own
+    /// comments empty. Emitting the model still writes them back out.
+    Sidecar,
 }
 
 /// Custom error type for integrating winnow errors
@@ -81,6 +460,8 @@ pub struct GCodeParseError {
     // which can depend on the output medium and application.
     pub span: std::ops::Range<usize>,
     pub input: String,
+    /// the limit that stopped parsing, `None` for invalid gcode
+    pub limit: Option<Limit>,
 }
 
 impl GCodeParseError {
@@ -102,6 +483,22 @@ impl GCodeParseError {
             message,
             span: start..end,
             input,
+            limit: None,
+        }
+    }
+    /// Error for input over one of the `ParserConfig` limits, spanning
+    /// all of `input`, which is only the offending part
+    fn over_limit(limit: Limit, input: &str) -> Self {
+        let message = match limit {
+            Limit::Bytes(n) => format!("input is longer than {n} bytes"),
+            Limit::Lines(n) => format!("input has more than {n} lines"),
+            Limit::ParamsPerLine(n) => format!("line has more than {n} parameters"),
+        };
+        Self {
+            message,
+            span: 0..input.len(),
+            input: input.to_owned(),
+            limit: Some(limit),
         }
     }
 }
@@ -124,15 +521,52 @@ impl std::fmt::Display for GCodeParseError {
 impl std::error::Error for GCodeParseError {}
 
 /// Outermost parser for gcode files
-pub fn gcode_parser(input: &mut &str) -> Result<GCodeModel, GCodeParseError> {
-    let mut gcode = GCodeModel::default();
-    let lines = parse_lines
+pub fn gcode_parser(
+    input: &mut &str,
+    config: &ParserConfig,
+) -> Result<GCodeModel, GCodeParseError> {
+    gcode_parser_monitored(input, config, &mut Monitor::default()).map_err(|e| match e {
+        ParseFailure::Invalid(e) => e,
+        ParseFailure::Cancelled => unreachable!("no cancellation flag to set"),
+    })
+}
+
+/// `gcode_parser` with progress reports and cancellation
+pub(crate) fn gcode_parser_monitored(
+    input: &mut &str,
+    config: &ParserConfig,
+    monitor: &mut Monitor,
+) -> Result<GCodeModel, ParseFailure> {
+    let mut gcode = GCodeModel {
+        id_counter: Counter::starting_at(config.first_id),
+        line_ending: LineEnding::detect(input),
+        ..Default::default()
+    };
+    if let Some(max) = config.max_bytes.filter(|max| input.len() > *max) {
+        return Err(GCodeParseError::over_limit(Limit::Bytes(max), "").into());
+    }
+    // stop splitting as soon as the line limit is passed, rather than
+    // collecting every line of an oversized file first
+    let max = config.max_lines.unwrap_or(usize::MAX);
+    let (lines, unparsed) = (parse_lines(max), rest)
         .parse(input)
         .map_err(|e| GCodeParseError::from_parse(e, input))?;
+    if !unparsed.is_empty() {
+        return Err(GCodeParseError::over_limit(Limit::Lines(max), "").into());
+    }
+    let total = lines.len();
+    let mut g1_mode = false;
     // split a file into lines
-    for line in lines {
+    for (i, line) in lines.into_iter().enumerate() {
+        monitor.update(Stage::Parse, i, total)?;
         // split off comments before parsing
-        let (line, comments) = line.split_once(';').unwrap_or((line, ""));
+        let (line, comments) = split_comment(line, config.comment_syntax);
+        if let Some(max) = config.max_params_per_line {
+            if count_words(line) > max + 1 {
+                let limit = Limit::ParamsPerLine(max);
+                return Err(GCodeParseError::over_limit(limit, line).into());
+            }
+        }
 
         // store a copy of the original line for unsupported commands
         let string_copy = String::from(line);
@@ -143,14 +577,76 @@ pub fn gcode_parser(input: &mut &str) -> Result<GCodeModel, GCodeParseError> {
 
         // generate id and check first word of command
         let id = gcode.id_counter.get();
+        let bare = config.modal_moves && g1_mode && line.starts_with(['X', 'Y', 'Z', 'E', 'F']);
+        let words = line;
         let command = match parse_word.parse_next(&mut line) {
-            // process rest of command based on first word
-            Ok(("G", "1", rest)) => {
-                let g1 = g1_parameter_parse
+            // a continuation of the last G1
+            _ if bare => (|i: &mut &str| g1_parameter_parse(i, config.rounding))
+                .parse(words)
+                .map(Command::G1)
+                .unwrap_or_else(|_| Command::Raw(string_copy)),
+            // process rest of command based on first word. Words outside
+            // the move grammar, e.g. a rotary A axis, keep the line raw
+            // rather than failing the file
+            Ok(("G", "1" | "01", rest)) => (|i: &mut &str| g1_parameter_parse(i, config.rounding))
+                .parse(rest)
+                .map(Command::G1)
+                .unwrap_or_else(|_| Command::Raw(string_copy)),
+            Ok(("G", "0" | "00", rest)) => (|i: &mut &str| g1_parameter_parse(i, config.rounding))
+                .parse(rest)
+                .map(Command::G0)
+                .unwrap_or_else(|_| Command::Raw(string_copy)),
+            // full circle counts (P) and K offsets keep the line raw
+            Ok(("G", number @ ("2" | "02" | "3" | "03"), rest)) => {
+                (|i: &mut &str| arc_parameter_parse(i, config.rounding))
                     .parse(rest)
-                    .map_err(|e| GCodeParseError::from_parse(e, input))?;
-                Command::G1(g1)
+                    .map(|arc| {
+                        Command::Arc(ArcMove {
+                            clockwise: number.ends_with('2'),
+                            ..arc
+                        })
+                    })
+                    .unwrap_or_else(|_| Command::Raw(string_copy))
             }
+            // any word besides P and S keeps the line raw
+            Ok(("G", "4", rest)) => dwell_parse(rest, config.rounding)
+                .map(Command::G4)
+                .unwrap_or_else(|| Command::Raw(string_copy)),
+            Ok(("G", "5", rest)) => (|i: &mut &str| g5_parameter_parse(i, config.rounding))
+                .parse(rest)
+                .map(Command::G5)
+                .unwrap_or_else(|_| Command::Raw(string_copy)),
+            Ok(("G", "53", rest)) => {
+                // an optional G0/G1 move may follow on the same line, or
+                // coordinates alone for the modal motion
+                let motion = [
+                    ("G01", G53Motion::G1),
+                    ("G00", G53Motion::G0),
+                    ("G1", G53Motion::G1),
+                    ("G0", G53Motion::G0),
+                ]
+                .iter()
+                .find_map(|(word, motion)| Some((rest.strip_prefix(word)?, *motion)));
+                match motion {
+                    Some((rest, motion)) => (|i: &mut &str| g1_parameter_parse(i, config.rounding))
+                        .parse(rest)
+                        .map(|g1| Command::G53(Some(g1), motion))
+                        .unwrap_or_else(|_| Command::Raw(string_copy)),
+                    None if rest.is_empty() => Command::G53(None, G53Motion::Bare),
+                    None => (|i: &mut &str| g1_parameter_parse(i, config.rounding))
+                        .parse(rest)
+                        .map(|g1| Command::G53(Some(g1), G53Motion::Bare))
+                        .unwrap_or_else(|_| Command::Raw(string_copy)),
+                }
+            }
+            // anything after the word, like a move or the `.1` of G54.1,
+            // keeps the line raw
+            Ok(("G", "54", "")) => Command::Wcs(Wcs::G54),
+            Ok(("G", "55", "")) => Command::Wcs(Wcs::G55),
+            Ok(("G", "56", "")) => Command::Wcs(Wcs::G56),
+            Ok(("G", "57", "")) => Command::Wcs(Wcs::G57),
+            Ok(("G", "58", "")) => Command::Wcs(Wcs::G58),
+            Ok(("G", "59", "")) => Command::Wcs(Wcs::G59),
             Ok(("G", "90", _)) => {
                 gcode.rel_xyz = false;
                 Command::G90
@@ -159,6 +655,14 @@ pub fn gcode_parser(input: &mut &str) -> Result<GCodeModel, GCodeParseError> {
                 gcode.rel_xyz = true;
                 Command::G91
             }
+            Ok(("G", "92", rest)) => (|i: &mut &str| g92_parameter_parse(i, config.rounding))
+                .parse(rest)
+                .map(Command::G92)
+                .unwrap_or_else(|_| Command::Raw(string_copy)),
+            // a move on the same line keeps it raw
+            Ok(("G", "93", "")) => Command::G93,
+            Ok(("G", "94", "")) => Command::G94,
+            Ok(("G", "95", "")) => Command::G95,
             Ok(("M", "82", _)) => {
                 gcode.rel_e = false;
                 Command::M82
@@ -167,15 +671,81 @@ pub fn gcode_parser(input: &mut &str) -> Result<GCodeModel, GCodeParseError> {
                 gcode.rel_e = true;
                 Command::M83
             }
-            _ => Command::Raw(string_copy),
+            Ok(("M", "3", rest)) => spindle_parse(rest, config.rounding)
+                .map(Command::M3)
+                .unwrap_or_else(|| Command::Raw(string_copy)),
+            Ok(("M", "4", rest)) => spindle_parse(rest, config.rounding)
+                .map(Command::M4)
+                .unwrap_or_else(|| Command::Raw(string_copy)),
+            Ok(("M", "5", "")) => Command::M5,
+            Ok(("M", "106", rest)) => fan_parse(rest, config.rounding)
+                .map(|(index, speed)| Command::M106(M106 { index, speed }))
+                .unwrap_or_else(|| Command::Raw(string_copy)),
+            Ok(("M", "107", rest)) => fan_parse(rest, config.rounding)
+                .filter(|(_, speed)| speed.is_none())
+                .map(|(index, _)| Command::M107(index))
+                .unwrap_or_else(|| Command::Raw(string_copy)),
+            Ok(("M", "7", "")) => Command::M7,
+            Ok(("M", "8", "")) => Command::M8,
+            Ok(("M", "9", "")) => Command::M9,
+            Ok(("G", "28", rest)) => home_parse(rest)
+                .map(Command::Home)
+                .unwrap_or_else(|| Command::Raw(string_copy)),
+            Ok(("G", "29", _)) => Command::G29(message_payload(&string_copy)),
+            Ok(("M", "862", rest)) => m862_parse(rest, &string_copy)
+                .map(Command::M862)
+                .unwrap_or_else(|| Command::Raw(string_copy)),
+            Ok(("M", "420", rest)) => m420_parameter_parse(rest, config.rounding)
+                .map(Command::M420)
+                .unwrap_or_else(|| Command::Raw(string_copy)),
+            Ok(("M", "808", "")) => Command::M808(None),
+            // a count that doesn't parse mustn't turn a loop start into an end
+            Ok(("M", "808", rest)) => rest
+                .strip_prefix('L')
+                .and_then(|n| n.parse().ok())
+                .map(|n| Command::M808(Some(n)))
+                .unwrap_or_else(|| Command::Raw(string_copy)),
+            Ok(("M", "117", _)) => Command::M117(message_payload(&string_copy)),
+            Ok(("M", "118", _)) => Command::M118(message_payload(&string_copy)),
+            Ok(("T", number, "")) => number
+                .parse()
+                .map(Command::ToolChange)
+                .unwrap_or_else(|_| Command::Raw(string_copy)),
+            // fall back to any registered custom parser before storing raw,
+            // looked up by the line's first word so macros like
+            // `PRINT_START` and dotted words like `M862.3` can be found,
+            // then by the command word for compact lines like `M900K0.1`
+            Ok((letter, number, _)) => split_raw(&string_copy)
+                .and_then(|(word, _)| config.commands.parse(&word, &string_copy))
+                .or_else(|| {
+                    let code = format!("{letter}{number}");
+                    config.commands.parse(&code, &string_copy)
+                })
+                .map(Command::Custom)
+                .unwrap_or_else(|| Command::Raw(string_copy)),
+            Err(_) if string_copy.trim().is_empty() => Command::Blank,
+            Err(_) => Command::Raw(string_copy),
+        };
+        g1_mode = sets_g1_mode(&command).unwrap_or(g1_mode);
+        let comments = match config.comments {
+            CommentMode::Inline => String::from(comments),
+            CommentMode::Strip => String::new(),
+            CommentMode::Sidecar => {
+                if !comments.is_empty() {
+                    gcode.comments.insert(id, String::from(comments));
+                }
+                String::new()
+            }
         };
         gcode.lines.push(GCodeLine {
             id,
             command,
-            comments: String::from(comments),
+            comments,
+            annotations: Annotations::default(),
         });
     }
-    gcode.tag_g1();
+    monitor.update(Stage::Parse, total, total)?;
+    gcode.tag_g1_monitored(monitor)?;
     Ok(gcode)
 }
 
@@ -184,48 +754,62 @@ fn gcode_parser_test() {
     use crate::Tag;
     let input = "G1 X1.0 Y2.0 Z3.0 E4.0 F5.0;hello world\nG28 W ; hello world\nG90; hello world\nG91; hello world\nM82\n; asdf".to_string();
     let mut input = input.as_str();
-    let result = gcode_parser(&mut input).unwrap();
+    let result = gcode_parser(&mut input, &ParserConfig::default()).unwrap();
     let expected = GCodeModel {
         id_counter: crate::Counter { count: 5 },
         rel_xyz: true,
         rel_e: false,
+        comments: Default::default(),
+        line_ending: Default::default(),
+        provenance: Vec::new(),
+        state_index: Default::default(),
         lines: vec![
             GCodeLine {
                 id: crate::Id(0),
                 command: Command::G1(G1 {
-                    x: Some(Microns::from(1.0)),
-                    y: Some(Microns::from(2.0)),
-                    z: Some(Microns::from(3.0)),
-                    e: Some(Microns::from(4.0)),
-                    f: Some(Microns::from(5.0)),
-                    tag: Tag::Uninitialized,
+                    x: Some(Length::from(1.0)),
+                    y: Some(Length::from(2.0)),
+                    z: Some(Length::from(3.0)),
+                    e: Some(ExtrusionLength::from_mm(4.0)),
+                    f: Some(Feedrate::from_mm_per_min(5.0)),
+                    s: None,
+                    tag: Tag::Extrusion,
                 }),
                 comments: String::from("hello world"),
+                annotations: crate::Annotations::default(),
             },
             GCodeLine {
                 id: crate::Id(1),
-                command: Command::Raw(String::from("G28 W ")),
+                command: Command::Home(crate::Home {
+                    skip_leveling: true,
+                    ..Default::default()
+                }),
                 comments: String::from(" hello world"),
+                annotations: crate::Annotations::default(),
             },
             GCodeLine {
                 id: crate::Id(2),
                 command: Command::G90,
                 comments: String::from(" hello world"),
+                annotations: crate::Annotations::default(),
             },
             GCodeLine {
                 id: crate::Id(3),
                 command: Command::G91,
                 comments: String::from(" hello world"),
+                annotations: crate::Annotations::default(),
             },
             GCodeLine {
                 id: crate::Id(4),
                 command: Command::M82,
                 comments: String::from(""),
+                annotations: crate::Annotations::default(),
             },
             GCodeLine {
                 id: crate::Id(5),
-                command: Command::Raw(String::from("")),
+                command: Command::Blank,
                 comments: String::from(" asdf"),
+                annotations: crate::Annotations::default(),
             },
         ],
     };
@@ -260,16 +844,21 @@ fn parse_lines_test() {
     let mut tests = [
         ("hello\nworld\nmore\n", vec!["hello", "world", "more"]),
         ("hello\nworld\nmore", vec!["hello", "world", "more"]),
-        ("hello\nworld\nmore\n\n", vec!["hello", "world", "more"]),
+        ("hello\nworld\nmore\n\n", vec!["hello", "world", "more", ""]),
+        ("hello\r\n\r\nworld", vec!["hello", "", "world"]),
         ("hello", vec!["hello"]),
         ("hello\n", vec!["hello"]),
         ("\n", vec![""]),
         ("", vec![]),
     ];
     for (input, expected) in tests.iter_mut() {
-        let result = parse_lines(input).unwrap();
+        let result = parse_lines(usize::MAX)(input).unwrap();
         assert_eq!(result, *expected);
     }
+    // stops at the limit without splitting the rest
+    let mut input = "hello\nworld\nmore\n";
+    assert_eq!(parse_lines(2)(&mut input).unwrap(), ["hello", "world"]);
+    assert_eq!(input, "more\n");
 }
 #[test]
 fn parse_word_test() {
@@ -304,55 +893,60 @@ fn g1_parameter_parse_test() {
         (
             "X1.0Y2.0Z3.0E4.0F5.0",
             G1 {
-                x: Some(Microns::from(1.0)),
-                y: Some(Microns::from(2.0)),
-                z: Some(Microns::from(3.0)),
-                e: Some(Microns::from(4.0)),
-                f: Some(Microns::from(5.0)),
+                x: Some(Length::from(1.0)),
+                y: Some(Length::from(2.0)),
+                z: Some(Length::from(3.0)),
+                e: Some(ExtrusionLength::from_mm(4.0)),
+                f: Some(Feedrate::from_mm_per_min(5.0)),
+                s: None,
                 tag: crate::Tag::Uninitialized,
             },
         ),
         (
             "X1.0Y2.0Z3.0E4.0",
             G1 {
-                x: Some(Microns::from(1.0)),
-                y: Some(Microns::from(2.0)),
-                z: Some(Microns::from(3.0)),
-                e: Some(Microns::from(4.0)),
+                x: Some(Length::from(1.0)),
+                y: Some(Length::from(2.0)),
+                z: Some(Length::from(3.0)),
+                e: Some(ExtrusionLength::from_mm(4.0)),
                 f: None,
+                s: None,
                 tag: crate::Tag::Uninitialized,
             },
         ),
         (
             "X1.0Y2.0Z3.0",
             G1 {
-                x: Some(Microns::from(1.0)),
-                y: Some(Microns::from(2.0)),
-                z: Some(Microns::from(3.0)),
+                x: Some(Length::from(1.0)),
+                y: Some(Length::from(2.0)),
+                z: Some(Length::from(3.0)),
                 e: None,
                 f: None,
+                s: None,
                 tag: crate::Tag::Uninitialized,
             },
         ),
         (
             "X1.0Y2.0",
             G1 {
-                x: Some(Microns::from(1.0)),
-                y: Some(Microns::from(2.0)),
+                x: Some(Length::from(1.0)),
+                y: Some(Length::from(2.0)),
                 z: None,
                 e: None,
                 f: None,
+                s: None,
                 tag: crate::Tag::Uninitialized,
             },
         ),
         (
             "X1.0",
             G1 {
-                x: Some(Microns::from(1.0)),
+                x: Some(Length::from(1.0)),
                 y: None,
                 z: None,
                 e: None,
                 f: None,
+                s: None,
                 tag: crate::Tag::Uninitialized,
             },
         ),
@@ -360,10 +954,11 @@ fn g1_parameter_parse_test() {
             "Y-2.0",
             G1 {
                 x: None,
-                y: Some(Microns::from(-2.0)),
+                y: Some(Length::from(-2.0)),
                 z: None,
                 e: None,
                 f: None,
+                s: None,
                 tag: crate::Tag::Uninitialized,
             },
         ),
@@ -372,20 +967,22 @@ fn g1_parameter_parse_test() {
             G1 {
                 x: None,
                 y: None,
-                z: Some(Microns::from(0.000000001)),
+                z: Some(Length::from(0.000000001)),
                 e: None,
                 f: None,
+                s: None,
                 tag: crate::Tag::Uninitialized,
             },
         ),
     ];
     for (mut input, expected) in tests.iter_mut() {
-        let result = g1_parameter_parse(&mut input).unwrap();
+        let result = g1_parameter_parse(&mut input, Rounding::default()).unwrap();
         assert_eq!(result, *expected);
     }
 }
 #[test]
 fn gcode_parse_error_test() {
+    use winnow::ascii::multispace1;
     let test = "0";
     let error = multispace1.parse(test).unwrap_err();
     let error = GCodeParseError::from_parse(error, test);
@@ -393,8 +990,284 @@ fn gcode_parse_error_test() {
         GCodeParseError {
             message: "".to_string(),
             span: 0..1,
-            input: "0".to_string()
+            input: "0".to_string(),
+            limit: None,
         },
         error
     );
 }
+
+#[test]
+fn parser_rounding_test() {
+    let input = "G1 X0.0000017";
+    let config = ParserConfig {
+        rounding: Rounding::Truncate,
+        ..Default::default()
+    };
+    let gcode = gcode_parser(&mut &*input, &config).unwrap();
+    let Command::G1(g1) = &gcode.lines[0].command else {
+        panic!("expected G1");
+    };
+    assert_eq!(g1.x, Some(Length::from_nanos(1)));
+    let gcode = gcode_parser(&mut &*input, &ParserConfig::default()).unwrap();
+    let Command::G1(g1) = &gcode.lines[0].command else {
+        panic!("expected G1");
+    };
+    assert_eq!(g1.x, Some(Length::from_nanos(2)));
+}
+
+#[test]
+fn message_command_test() {
+    let input = "M117 Layer  2 of 10 \nM118 A1 P0 action:pause\nM117";
+    let gcode = gcode_parser(&mut &*input, &ParserConfig::default()).unwrap();
+    assert_eq!(
+        gcode.lines[0].command,
+        Command::M117(String::from("Layer  2 of 10 "))
+    );
+    assert_eq!(
+        gcode.lines[1].command,
+        Command::M118(String::from("A1 P0 action:pause"))
+    );
+    assert_eq!(gcode.lines[2].command, Command::M117(String::new()));
+}
+
+#[test]
+fn spindle_coolant_parse_test() {
+    let input = "M3 S12000\nM4\nM5\nM7\nM8\nM9\nM30\nM3 S1000 P2\nM3 M8\nM9 P1";
+    let gcode = gcode_parser(&mut &*input, &ParserConfig::default()).unwrap();
+    let commands = gcode
+        .lines
+        .into_iter()
+        .map(|l| l.command)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        commands,
+        vec![
+            Command::M3(Some(12000)),
+            Command::M4(None),
+            Command::M5,
+            Command::M7,
+            Command::M8,
+            Command::M9,
+            Command::Raw(String::from("M30")),
+            // words the commands can't hold keep the line as written
+            Command::Raw(String::from("M3 S1000 P2")),
+            Command::Raw(String::from("M3 M8")),
+            Command::Raw(String::from("M9 P1")),
+        ]
+    );
+}
+
+#[test]
+fn comment_mode_test() {
+    use crate::emit::Emit;
+    let input = "G90 ; absolute\nM104 S200\n; note";
+    let parse = |comments| {
+        let config = ParserConfig {
+            comments,
+            ..Default::default()
+        };
+        gcode_parser(&mut &*input, &config).unwrap()
+    };
+    let inline = parse(CommentMode::Inline);
+    let sidecar = parse(CommentMode::Sidecar);
+    assert!(sidecar.lines.iter().all(|line| line.comments.is_empty()));
+    assert_eq!(sidecar.comments.len(), 2);
+    assert_eq!(sidecar.comment(&sidecar.lines[2]), " note");
+    assert_eq!(sidecar.emit(false), inline.emit(false));
+    let stripped = parse(CommentMode::Strip);
+    assert!(stripped.comments.is_empty());
+    assert_eq!(stripped.emit(false), "G90\nM104 S200\n\n");
+}
+
+#[test]
+fn comment_syntax_test() {
+    let tests = [
+        ("G1 X1 ; move", CommentSyntax::Plain, ("G1 X1 ", " move")),
+        (
+            "M587 S\"my;ssid\" ; wifi",
+            CommentSyntax::Plain,
+            ("M587 S\"my", "ssid\" ; wifi"),
+        ),
+        (
+            "M587 S\"my;ssid\" ; wifi",
+            CommentSyntax::Quoted,
+            ("M587 S\"my;ssid\" ", " wifi"),
+        ),
+        (
+            "M117 Hello; world",
+            CommentSyntax::Quoted,
+            ("M117 Hello", " world"),
+        ),
+        (
+            "M117 Hello; world",
+            CommentSyntax::Messages,
+            ("M117 Hello; world", ""),
+        ),
+        ("M1170 a; b", CommentSyntax::Messages, ("M1170 a", " b")),
+        ("; only", CommentSyntax::Messages, ("", " only")),
+    ];
+    for (line, syntax, expected) in tests {
+        assert_eq!(split_comment(line, syntax), expected);
+    }
+    let config = ParserConfig {
+        comment_syntax: CommentSyntax::for_firmware(Firmware::Klipper),
+        ..Default::default()
+    };
+    let gcode = gcode_parser(&mut "M117 Hello; world", &config).unwrap();
+    assert_eq!(
+        gcode.lines[0].command,
+        Command::M117(String::from("Hello; world"))
+    );
+}
+
+#[test]
+fn raw_roundtrip_test() {
+    use crate::emit::Emit;
+    let input = "PRINT_START  BED=60 EXTRUDER=215\n  SET_GCODE_OFFSET Z=0.1\tMOVE=1 ; nudge\n\tM300  S440 P200\nm117 lower case\nG28 W \n";
+    let gcode: GCodeModel = input.parse().unwrap();
+    assert_eq!(
+        gcode.lines[1].command,
+        Command::Raw(String::from("  SET_GCODE_OFFSET Z=0.1\tMOVE=1 "))
+    );
+    assert_eq!(gcode.emit(false), input);
+}
+
+#[test]
+fn g53_roundtrip_test() {
+    use crate::emit::Emit;
+    let input = "G53 X10 Y5 \nG53 G0 X0 \nG53 G1 Z5 F300 \nG53\nG53 G1 X1 Q2\nG53 X1 Q2\n";
+    let gcode: GCodeModel = input.parse().unwrap();
+    assert!(matches!(
+        gcode.lines[0].command,
+        Command::G53(Some(_), G53Motion::Bare)
+    ));
+    assert!(matches!(
+        gcode.lines[1].command,
+        Command::G53(Some(_), G53Motion::G0)
+    ));
+    // unknown words keep the line as written, with or without a motion word
+    assert!(matches!(gcode.lines[4].command, Command::Raw(_)));
+    assert!(matches!(gcode.lines[5].command, Command::Raw(_)));
+    assert_eq!(gcode.emit(false), input);
+}
+
+#[test]
+fn wcs_parse_test() {
+    use crate::emit::Emit;
+    let input = "G55\nG54 G0 X1 Y1\nG59.3\nG54.1 P3\n";
+    let gcode: GCodeModel = input.parse().unwrap();
+    assert_eq!(gcode.lines[0].command, Command::Wcs(Wcs::G55));
+    assert_eq!(
+        gcode.lines[1].command,
+        Command::Raw(String::from("G54 G0 X1 Y1"))
+    );
+    assert_eq!(gcode.lines[2].command, Command::Raw(String::from("G59.3")));
+    assert_eq!(
+        gcode.lines[3].command,
+        Command::Raw(String::from("G54.1 P3"))
+    );
+    assert_eq!(gcode.emit(false), input);
+}
+
+#[test]
+fn blank_line_test() {
+    use crate::emit::Emit;
+    let input = "G90\n\n; note\n  \n\t\n  ; indented\nM82\n";
+    let gcode: GCodeModel = input.parse().unwrap();
+    assert_eq!(gcode.lines.len(), input.lines().count());
+    assert!(gcode.lines[1..6]
+        .iter()
+        .all(|line| line.command == Command::Blank));
+    // whitespace before nothing but a comment isn't kept
+    assert_eq!(gcode.emit(false), "G90\n\n; note\n\n\n; indented\nM82\n");
+}
+
+#[test]
+fn parser_limits_test() {
+    let input = "G90\nG1 X1 Y2 Z3 E4 F5\nM82";
+    let parse = |config: ParserConfig| gcode_parser(&mut &*input, &config).map_err(|e| e.limit);
+    assert!(parse(ParserConfig::default()).is_ok());
+    let config = ParserConfig {
+        max_bytes: Some(10),
+        ..Default::default()
+    };
+    assert_eq!(parse(config).unwrap_err(), Some(Limit::Bytes(10)));
+    let config = ParserConfig {
+        max_lines: Some(2),
+        ..Default::default()
+    };
+    assert_eq!(parse(config).unwrap_err(), Some(Limit::Lines(2)));
+    let config = ParserConfig {
+        max_params_per_line: Some(4),
+        ..Default::default()
+    };
+    let error = gcode_parser(&mut &*input, &config).unwrap_err();
+    assert_eq!(error.limit, Some(Limit::ParamsPerLine(4)));
+    assert_eq!(error.input, "G1 X1 Y2 Z3 E4 F5");
+    assert!(error.to_string().contains("more than 4 parameters"));
+    // words written without spaces count the same
+    let error = gcode_parser(&mut "G1X1Y2Z3E4F5", &config).unwrap_err();
+    assert_eq!(error.limit, Some(Limit::ParamsPerLine(4)));
+    assert_eq!(count_words("PRINT_START BED=60"), 2);
+}
+
+#[test]
+fn modal_moves_test() {
+    let input = "G1 X1 F600\nX2 Y3\nE1.5\nG0 X0\nX5\nG1 Z1\nM104 S200\nZ2 ; up";
+    let config = ParserConfig {
+        modal_moves: true,
+        ..Default::default()
+    };
+    let gcode = gcode_parser(&mut &*input, &config).unwrap();
+    let is_g1 = gcode
+        .lines
+        .iter()
+        .map(|line| matches!(line.command, Command::G1(_)))
+        .collect::<Vec<_>>();
+    assert_eq!(is_g1, [true, true, true, false, false, true, false, true]);
+    assert_eq!(gcode.lines[7].comments, " up");
+    let Command::G1(g1) = &gcode.lines[1].command else {
+        unreachable!()
+    };
+    assert_eq!(
+        (g1.x, g1.y),
+        (Some(Length::from(2.0)), Some(Length::from(3.0)))
+    );
+    // unknown words keep a bare move raw too
+    let gcode = gcode_parser(&mut "G1 X1\nX2 A1", &config).unwrap();
+    assert_eq!(gcode.lines[1].command, Command::Raw(String::from("X2 A1")));
+    // off by default
+    let gcode: GCodeModel = input.parse().unwrap();
+    assert!(matches!(gcode.lines[1].command, Command::Raw(_)));
+}
+
+#[test]
+fn g0_test() {
+    use crate::{emit::Emit, Tag};
+    let mut gcode: GCodeModel = "G0 X10 Y5 F6000\nG00 Z1\nG1 X20 E1".parse().unwrap();
+    assert!(matches!(gcode.lines[0].command, Command::G0(_)));
+    assert_eq!(gcode.lines[0].command.tag(), Tag::Travel);
+    assert_eq!(gcode.lines[1].command.tag(), Tag::RaiseZ);
+    assert_eq!(gcode.estimate_time().as_millis(), 221);
+    gcode.translate(Length::from(1.0), Length::ZERO);
+    assert_eq!(gcode.emit(false), "G0 X11 Y5 F6000 \nG0 Z1 \nG1 X21 E1 \n");
+    // unknown words keep the line as written
+    let input = "G0 X10 A5\nG1 X1\n";
+    let gcode: GCodeModel = input.parse().unwrap();
+    assert_eq!(
+        gcode.lines[0].command,
+        Command::Raw(String::from("G0 X10 A5"))
+    );
+    assert_eq!(gcode.emit(false), "G0 X10 A5\nG1 X1 \n");
+    let gcode: GCodeModel = "G01 X1\nG0 X1 Q5\nG1 X1 Q5".parse().unwrap();
+    assert!(matches!(gcode.lines[0].command, Command::G1(_)));
+    assert_eq!(
+        gcode.lines[1].command,
+        Command::Raw(String::from("G0 X1 Q5"))
+    );
+    assert_eq!(
+        gcode.lines[2].command,
+        Command::Raw(String::from("G1 X1 Q5"))
+    );
+}
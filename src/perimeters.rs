@@ -0,0 +1,126 @@
+use crate::{geometry::Polygon, layers::Layer, roles::Role, state::Step, GCodeModel};
+use std::ops::Range;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// One closed wall loop, see `GCodeModel::perimeter_loops`
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct PerimeterLoop {
+    /// `Role::OuterWall` or `Role::InnerWall`
+    pub role: Role,
+    /// vertices in print order, starting where the loop starts
+    pub polygon: Polygon,
+    /// lines from the loop's first move to its last, including any
+    /// travel or retraction inside it
+    pub lines: Range<usize>,
+}
+
+/// Distance in XY between two points in mm
+fn gap([x0, y0]: [f64; 2], [x1, y1]: [f64; 2]) -> f64 {
+    (x1 - x0).hypot(y1 - y0)
+}
+
+fn xy(step: &Step, next: bool) -> [f64; 2] {
+    let pos = if next { step.next.pos } else { step.prev.pos };
+    [pos.x.to_mm(), pos.y.to_mm()]
+}
+
+/// Closed wall loops among `steps`, whose indices start at `offset` in the
+/// file. Runs of the same wall broken by a short travel, like a wipe or a
+/// retraction at the seam, are joined when the gap is within `tolerance`.
+pub(crate) fn wall_loops(
+    steps: &[Step],
+    roles: &[Role],
+    offset: usize,
+    tolerance: f64,
+) -> Vec<PerimeterLoop> {
+    // runs of consecutive moves of one wall, as (role, first, last) indices
+    let mut runs: Vec<(Role, usize, usize)> = Vec::new();
+    let mut open = false;
+    for (i, role) in roles.iter().enumerate() {
+        match role {
+            Role::OuterWall | Role::InnerWall => match runs.last_mut() {
+                Some((r, _, last)) if open && r == role => *last = i,
+                _ => {
+                    runs.push((*role, i, i));
+                    open = true;
+                }
+            },
+            Role::None => {}
+            _ => open = false,
+        }
+    }
+    let mut loops = Vec::new();
+    let mut runs = runs.into_iter().peekable();
+    while let Some((role, first, mut last)) = runs.next() {
+        let start = xy(&steps[first], false);
+        while gap(start, xy(&steps[last], true)) > tolerance {
+            match runs.peek() {
+                Some(&(r, next, end))
+                    if r == role
+                        && gap(xy(&steps[last], true), xy(&steps[next], false)) <= tolerance =>
+                {
+                    last = end;
+                    runs.next();
+                }
+                _ => break,
+            }
+        }
+        if gap(start, xy(&steps[last], true)) > tolerance {
+            continue;
+        }
+        let mut points = vec![start];
+        for (step, r) in steps[first..=last].iter().zip(&roles[first..=last]) {
+            let point = xy(step, true);
+            if *r == role && points.last() != Some(&point) {
+                points.push(point);
+            }
+        }
+        if points.len() > 1 && gap(points[0], points[points.len() - 1]) <= tolerance {
+            points.pop();
+        }
+        if points.len() < 3 {
+            continue;
+        }
+        loops.push(PerimeterLoop {
+            role,
+            polygon: Polygon { points },
+            lines: offset + first..offset + last + 1,
+        });
+    }
+    loops
+}
+
+impl GCodeModel {
+    /// Closed polygons traced by the external and internal walls of
+    /// `layer`, in print order. Wall runs split by a travel shorter than
+    /// `tolerance` mm are merged, and a loop counts as closed when it ends
+    /// within `tolerance` of its start; open runs are left out.
+    pub fn perimeter_loops(&self, layer: &Layer, tolerance: f64) -> Vec<PerimeterLoop> {
+        let steps = self.cursor().take(layer.end).collect::<Vec<_>>();
+        let roles = self.roles();
+        let range = layer.range();
+        wall_loops(&steps[range.clone()], &roles[range], layer.start, tolerance)
+    }
+}
+
+#[test]
+fn perimeter_loops_test() {
+    let input = "G1 Z0.2 F600\n;TYPE:External perimeter\nG1 X0 Y0\nG1 X20 E1\nG1 Y20 E2\nG1 E1.2\nG1 X19.9 Y20.1\nG1 E2\nG1 X0 Y20 E3\nG1 Y0.3 E4\n;TYPE:Perimeter\nG1 X1 Y1\nG1 X19 E5\nG1 Y19 E6\nG1 X1 E7\nG1 Y1 E8\nG1 X5 Y5\nG1 X10 E9\n";
+    let gcode: GCodeModel = input.parse().unwrap();
+    let layer = &gcode.layers()[0];
+    let loops = gcode.perimeter_loops(layer, 0.5);
+    assert_eq!(loops.len(), 2);
+    // the retraction and short hop at the corner are merged over
+    assert_eq!(loops[0].role, Role::OuterWall);
+    assert_eq!(loops[0].lines, 3..10);
+    assert_eq!(loops[0].polygon.points.len(), 4);
+    assert_eq!(loops[0].polygon.area(), 400.0);
+    assert_eq!(loops[1].role, Role::InnerWall);
+    assert_eq!(loops[1].polygon.area(), 324.0);
+    assert_eq!(loops[1].polygon.points.len(), 4);
+    // a tight tolerance leaves the outer wall open
+    assert_eq!(gcode.perimeter_loops(layer, 0.1).len(), 1);
+}
@@ -0,0 +1,445 @@
+use crate::{
+    geometry::{Bounds, Geometry},
+    skirt::is_motion,
+    state::{MachineState, Position, Positioning},
+    Annotations, Command, GCodeLine, GCodeModel, Length, Tag, G1, G92,
+};
+use std::{collections::BTreeMap, ops::Range};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Height in mm the nozzle travels above the tallest finished object when
+/// moving to the next one in a sequential print
+const LIFT: f64 = 2.0;
+
+/// Where objects may be placed and how far apart, in mm
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PlateLayout {
+    /// corners of the printable area
+    pub min: [f64; 2],
+    pub max: [f64; 2],
+    /// gap left between the footprints of neighbouring objects
+    pub spacing: f64,
+}
+
+/// Size of the toolhead, checked against finished objects when printing
+/// them one at a time
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Clearance {
+    /// distance in mm from the nozzle to the furthest edge of the carriage
+    /// around it
+    pub radius: f64,
+    /// height in mm of the X gantry above the nozzle tip, which every
+    /// object but the last must stay under
+    pub gantry_height: f64,
+}
+
+/// How the objects on a plate are printed
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PlateMode {
+    /// every object together, layer by layer
+    Combined,
+    /// each object to completion before starting the next
+    Sequential(Clearance),
+}
+
+/// Why a set of objects couldn't be put on one plate. `object` is an index
+/// into the objects passed in.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PlateError {
+    /// the object extrudes nothing, so it has no footprint
+    Empty { object: usize },
+    /// there is no room left for the object
+    DoesNotFit { object: usize },
+    /// the object is taller than the gantry but isn't printed last
+    TooTall { object: usize },
+    /// the toolhead would hit `with`, printed earlier, while printing
+    /// `object`
+    Collision { object: usize, with: usize },
+}
+
+impl std::fmt::Display for PlateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlateError::Empty { object } => write!(f, "object {object} extrudes nothing"),
+            PlateError::DoesNotFit { object } => write!(f, "object {object} doesn't fit"),
+            PlateError::TooTall { object } => {
+                write!(f, "object {object} is taller than the gantry")
+            }
+            PlateError::Collision { object, with } => {
+                write!(
+                    f,
+                    "toolhead hits object {with} while printing object {object}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for PlateError {}
+
+/// Corner each footprint goes at, filling rows from `min` along X
+fn arrange(footprints: &[Bounds], layout: &PlateLayout) -> Result<Vec<[f64; 2]>, PlateError> {
+    let [mut x, mut y] = layout.min;
+    let mut row_depth: f64 = 0.0;
+    let mut corners = Vec::new();
+    for (object, bounds) in footprints.iter().enumerate() {
+        let width = (bounds.max.x - bounds.min.x).to_mm();
+        let depth = (bounds.max.y - bounds.min.y).to_mm();
+        if x + width > layout.max[0] && x > layout.min[0] {
+            x = layout.min[0];
+            y += row_depth + layout.spacing;
+            row_depth = 0.0;
+        }
+        if x + width > layout.max[0] || y + depth > layout.max[1] {
+            return Err(PlateError::DoesNotFit { object });
+        }
+        corners.push([x, y]);
+        x += width + layout.spacing;
+        row_depth = row_depth.max(depth);
+    }
+    Ok(corners)
+}
+
+/// Gap in mm between two footprints in XY, zero if they overlap
+fn gap(a: &Bounds, b: &Bounds) -> f64 {
+    let dx = (a.min.x - b.max.x).max(b.min.x - a.max.x).to_mm().max(0.0);
+    let dy = (a.min.y - b.max.y).max(b.min.y - a.max.y).to_mm().max(0.0);
+    dx.hypot(dy)
+}
+
+/// An object moved to its place on the plate
+struct Placed {
+    model: GCodeModel,
+    bounds: Bounds,
+    /// state before each line
+    states: Vec<MachineState>,
+    body: Range<usize>,
+    /// index of the first line that moves
+    first_move: Option<usize>,
+}
+
+/// Lines copied onto a plate, with the state the plate is left in
+struct Builder {
+    plate: GCodeModel,
+    state: MachineState,
+}
+
+impl Builder {
+    fn push(&mut self, command: Command, comments: String) {
+        self.state.apply(&command);
+        let line = GCodeLine {
+            id: self.plate.id_counter.get(),
+            command,
+            comments,
+            annotations: Annotations::default(),
+        };
+        self.plate.lines.push(line);
+    }
+    /// Copy `range` of `object`, setting the modes, extruder position and
+    /// feedrate the object had before its first move in the range
+    fn copy(&mut self, object: &Placed, range: Range<usize>) {
+        let mut synced = false;
+        for i in range {
+            let line = &object.model.lines[i];
+            if !synced && is_motion(&line.command) {
+                let moved = object.first_move.is_some_and(|first| i > first);
+                self.sync(&object.states[i], &line.command, moved);
+                synced = true;
+            }
+            let comments = object.model.comment(line).to_string();
+            self.push(line.command.clone(), comments);
+        }
+    }
+    /// Bring the plate to `state` ahead of `next`, travelling to where the
+    /// object left off if it has `moved` yet
+    fn sync(&mut self, state: &MachineState, next: &Command, moved: bool) {
+        if state.positioning != self.state.positioning {
+            self.push(
+                match state.positioning {
+                    Positioning::Absolute => Command::G90,
+                    Positioning::Relative => Command::G91,
+                },
+                String::new(),
+            );
+        }
+        if state.e_positioning != self.state.e_positioning {
+            self.push(
+                match state.e_positioning {
+                    Positioning::Absolute => Command::M82,
+                    Positioning::Relative => Command::M83,
+                },
+                String::new(),
+            );
+        }
+        if state.e_positioning == Positioning::Absolute && state.e != self.state.e {
+            let g92 = G92 {
+                e: Some(state.e),
+                ..Default::default()
+            };
+            self.push(Command::G92(g92), String::new());
+            self.state.e = state.e;
+        }
+        if moved && state.pos != self.state.pos {
+            // cross over at the higher of the two heights and leave any
+            // lowering to the object's own moves
+            let z = state.pos.z.max(self.state.pos.z);
+            let mut to = self.state.pos;
+            to.z = z;
+            let over = Position { z, ..state.pos };
+            for to in [to, over] {
+                if to != self.state.pos {
+                    let travel = self.state.move_to(to, None);
+                    self.push(Command::G1(travel), String::new());
+                }
+            }
+        }
+        let sets_feedrate = matches!(next.as_move(), Some(G1 { f: Some(_), .. }));
+        if state.feedrate != self.state.feedrate && !sets_feedrate {
+            let f = G1 {
+                f: Some(state.feedrate),
+                ..Default::default()
+            };
+            self.push(Command::G1(f), String::new());
+        }
+    }
+}
+
+impl GCodeModel {
+    /// Move every absolute G0 and G1 by `dx`, `dy`, returning the number of moves
+    /// changed. Relative moves already follow along.
+    pub fn translate(&mut self, dx: Length, dy: Length) -> usize {
+        self.record_transform("translate", format!("{dx}, {dy}"));
+        let absolute = self
+            .cursor()
+            .map(|step| step.prev.positioning == Positioning::Absolute)
+            .collect::<Vec<_>>();
+        let mut changed = 0;
+        for (line, absolute) in self.lines.iter_mut().zip(absolute) {
+            // arc centres are relative to the start, so only the ends move
+            let (x, y) = match &mut line.command {
+                Command::G0(g1) | Command::G1(g1) => (&mut g1.x, &mut g1.y),
+                Command::Arc(arc) => (&mut arc.x, &mut arc.y),
+                _ => continue,
+            };
+            if !absolute || (x.is_none() && y.is_none()) {
+                continue;
+            }
+            *x = x.map(|x| x + dx);
+            *y = y.map(|y| y + dy);
+            changed += 1;
+        }
+        if changed > 0 {
+            self.tag_g1();
+        }
+        changed
+    }
+    /// Arrange several single-object files on one bed, in rows from
+    /// `layout.min` in the order given, and merge them into one file. The
+    /// start and end gcode come from the first object. `Combined` prints
+    /// all objects a layer at a time, while `Sequential` prints them one
+    /// after another, checking that the toolhead clears the objects already
+    /// printed.
+    pub fn plate(
+        objects: &[GCodeModel],
+        layout: &PlateLayout,
+        mode: &PlateMode,
+    ) -> Result<GCodeModel, PlateError> {
+        let Some(first) = objects.first() else {
+            return Ok(GCodeModel::default());
+        };
+        let footprints = objects
+            .iter()
+            .enumerate()
+            .map(|(object, model)| {
+                model
+                    .bounds(Geometry::Cartesian)
+                    .ok_or(PlateError::Empty { object })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let corners = arrange(&footprints, layout)?;
+        let placed = objects
+            .iter()
+            .zip(&footprints)
+            .zip(&corners)
+            .map(|((model, bounds), [x, y])| {
+                let mut model = model.clone();
+                let dx = Length::from(*x) - bounds.min.x;
+                let dy = Length::from(*y) - bounds.min.y;
+                model.translate(dx, dy);
+                let mut bounds = *bounds;
+                for corner in [&mut bounds.min, &mut bounds.max] {
+                    corner.x += dx;
+                    corner.y += dy;
+                }
+                Placed {
+                    states: model.cursor().map(|step| step.prev).collect(),
+                    body: model.body_range().unwrap_or(0..0),
+                    first_move: model.lines.iter().position(|line| is_motion(&line.command)),
+                    model,
+                    bounds,
+                }
+            })
+            .collect::<Vec<_>>();
+        if let PlateMode::Sequential(clearance) = mode {
+            for (object, placed_object) in placed.iter().enumerate() {
+                let bounds = &placed_object.bounds;
+                if object + 1 < placed.len() && bounds.max.z.to_mm() > clearance.gantry_height {
+                    return Err(PlateError::TooTall { object });
+                }
+                for (with, earlier) in placed[..object].iter().enumerate() {
+                    if gap(bounds, &earlier.bounds) < clearance.radius {
+                        return Err(PlateError::Collision { object, with });
+                    }
+                }
+            }
+        }
+
+        let mut builder = Builder {
+            plate: GCodeModel {
+                line_ending: first.line_ending,
+                ..Default::default()
+            },
+            state: MachineState::default(),
+        };
+        let first = &placed[0];
+        builder.copy(first, first.model.start_gcode_range().unwrap_or(0..0));
+        match mode {
+            PlateMode::Combined => {
+                // chunks of each object's body, keyed by layer height
+                let mut layers: BTreeMap<Length, Vec<(usize, Range<usize>)>> = BTreeMap::new();
+                for (object, placed) in placed.iter().enumerate() {
+                    let body = &placed.body;
+                    for layer in placed.model.layers() {
+                        let chunk = layer.start.max(body.start)..layer.end.min(body.end);
+                        if !chunk.is_empty() {
+                            layers.entry(layer.z).or_default().push((object, chunk));
+                        }
+                    }
+                }
+                for (object, chunk) in layers.into_values().flatten() {
+                    builder.copy(&placed[object], chunk);
+                }
+            }
+            PlateMode::Sequential(_) => {
+                let mut tallest: f64 = 0.0;
+                for (object, placed) in placed.iter().enumerate() {
+                    if object > 0 {
+                        let z = Length::from(tallest + LIFT);
+                        let lift = G1 {
+                            z: Some(z),
+                            ..Default::default()
+                        };
+                        if builder.state.positioning == Positioning::Relative {
+                            builder.push(Command::G90, String::new());
+                        }
+                        builder.push(Command::G1(lift), String::new());
+                        let start = placed.model.cursor().find_map(|step| {
+                            (step.line.command.tag() == Tag::Extrusion).then_some(step.prev.pos)
+                        });
+                        if let Some(start) = start {
+                            let travel = builder.state.move_to(Position { z, ..start }, None);
+                            builder.push(Command::G1(travel), String::new());
+                        }
+                    }
+                    builder.copy(placed, placed.body.clone());
+                    tallest = tallest.max(placed.bounds.max.z.to_mm());
+                }
+            }
+        }
+        builder.copy(first, first.model.end_gcode_range().unwrap_or(0..0));
+        let mut plate = builder.plate;
+        plate.tag_g1();
+        plate.record_transform("plate", format!("{} objects, {mode:?}", objects.len()));
+        Ok(plate)
+    }
+}
+
+#[test]
+fn plate_test() {
+    use crate::emit::Emit;
+    let object = |size: f64, top: f64| {
+        format!(
+            "G28\nM83\nG1 Z0.2 F600\nG1 X0 Y0\nG1 X{size} E1\nG1 Z{top}\nG1 X0 E1\nM104 S0\nM84"
+        )
+        .parse::<GCodeModel>()
+        .unwrap()
+    };
+    let objects = [object(10.0, 0.4), object(20.0, 0.6)];
+    let layout = PlateLayout {
+        min: [0.0, 0.0],
+        max: [50.0, 50.0],
+        spacing: 5.0,
+    };
+    let plate = GCodeModel::plate(&objects, &layout, &PlateMode::Combined).unwrap();
+    let body = plate
+        .lines
+        .iter()
+        .map(|line| line.emit(false))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        body,
+        [
+            "G28",
+            "M83",
+            "G1 Z0.2 F600 ",
+            "G1 X0 Y0 ",
+            "G1 X10 E1 ",
+            "M83",
+            "G1 Z0.2 F600 ",
+            "G1 X15 Y0 ",
+            "G1 X35 E1 ",
+            "G1 X10 ",
+            "G1 Z0.4 ",
+            "G1 X0 E1 ",
+            "G1 X35 ",
+            "G1 Z0.6 ",
+            "G1 X15 E1 ",
+            "M104 S0",
+            "M84",
+        ]
+    );
+
+    // the second object goes on a new row
+    let narrow = PlateLayout {
+        max: [25.0, 50.0],
+        ..layout
+    };
+    let sequential = PlateMode::Sequential(Clearance {
+        radius: 3.0,
+        gantry_height: 20.0,
+    });
+    let plate = GCodeModel::plate(&objects, &narrow, &sequential).unwrap();
+    let emitted = plate.emit(false);
+    assert!(emitted.contains("G1 X0 E1 \nG1 Z2.4 \nG1 Y5 \nM83\nG1 Z0.2 F600 \n"));
+    assert_eq!(plate.provenance.last().unwrap().name, "plate");
+
+    let tight = PlateMode::Sequential(Clearance {
+        radius: 10.0,
+        gantry_height: 20.0,
+    });
+    assert_eq!(
+        GCodeModel::plate(&objects, &narrow, &tight),
+        Err(PlateError::Collision { object: 1, with: 0 })
+    );
+    let low = PlateMode::Sequential(Clearance {
+        radius: 3.0,
+        gantry_height: 0.3,
+    });
+    assert_eq!(
+        GCodeModel::plate(&objects, &narrow, &low),
+        Err(PlateError::TooTall { object: 0 })
+    );
+    let small = PlateLayout {
+        max: [15.0, 15.0],
+        ..layout
+    };
+    assert_eq!(
+        GCodeModel::plate(&objects, &small, &PlateMode::Combined),
+        Err(PlateError::DoesNotFit { object: 1 })
+    );
+}
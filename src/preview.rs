@@ -0,0 +1,174 @@
+use crate::{emit::Emit, GCodeLine, GCodeModel, Id};
+use std::collections::{HashMap, HashSet};
+
+/// One line a transform would change, see `GCodeModel::preview`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    /// a new line at `index` in the transformed model
+    Added { index: usize, line: GCodeLine },
+    /// the line at `index` in the current model would be dropped
+    Removed { index: usize, line: GCodeLine },
+    /// the line at `index` in the current model would keep its id but
+    /// change its command or comments
+    Modified {
+        index: usize,
+        before: GCodeLine,
+        after: GCodeLine,
+    },
+}
+
+/// Every change a transform would make, in file order
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Preview {
+    pub changes: Vec<Change>,
+}
+
+impl Preview {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+    /// Number of lines added, removed and modified
+    pub fn counts(&self) -> (usize, usize, usize) {
+        self.changes
+            .iter()
+            .fold((0, 0, 0), |(a, r, m), change| match change {
+                Change::Added { .. } => (a + 1, r, m),
+                Change::Removed { .. } => (a, r + 1, m),
+                Change::Modified { .. } => (a, r, m + 1),
+            })
+    }
+}
+
+impl std::fmt::Display for Preview {
+    /// A diff with `-` for old lines and `+` for new ones
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for change in &self.changes {
+            match change {
+                Change::Added { line, .. } => writeln!(f, "+{}", line.emit(false))?,
+                Change::Removed { line, .. } => writeln!(f, "-{}", line.emit(false))?,
+                Change::Modified { before, after, .. } => {
+                    writeln!(f, "-{}", before.emit(false))?;
+                    writeln!(f, "+{}", after.emit(false))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Lines added, removed or changed between two versions of a model,
+/// matched by id
+fn diff(before: &GCodeModel, after: &GCodeModel) -> Preview {
+    let old = before
+        .lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| (line.id, i))
+        .collect::<HashMap<Id, usize>>();
+    let new = after
+        .lines
+        .iter()
+        .map(|line| line.id)
+        .collect::<HashSet<Id>>();
+    // sort by the position in the old file, with added lines after the
+    // last old line before them
+    let mut keyed = before
+        .lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !new.contains(&line.id))
+        .map(|(index, line)| {
+            let change = Change::Removed {
+                index,
+                line: line.clone(),
+            };
+            ((index, 0), change)
+        })
+        .collect::<Vec<_>>();
+    let mut anchor = None;
+    for (index, line) in after.lines.iter().enumerate() {
+        match old.get(&line.id) {
+            Some(&i) => {
+                anchor = Some(i);
+                let previous = &before.lines[i];
+                if previous.command != line.command || previous.comments != line.comments {
+                    let change = Change::Modified {
+                        index: i,
+                        before: previous.clone(),
+                        after: line.clone(),
+                    };
+                    keyed.push(((i, 1), change));
+                }
+            }
+            None => {
+                let change = Change::Added {
+                    index,
+                    line: line.clone(),
+                };
+                // before the first old line if nothing old comes earlier
+                let key = anchor.map_or((0, 0), |i| (i, 2));
+                keyed.push(((key.0, key.1 + index), change));
+            }
+        }
+    }
+    keyed.sort_by_key(|(key, _)| *key);
+    Preview {
+        changes: keyed.into_iter().map(|(_, change)| change).collect(),
+    }
+}
+
+impl GCodeModel {
+    /// Run `transform` on a copy of the model and report the lines it
+    /// would add, remove or change, leaving this model untouched. Works
+    /// with any transform, e.g. `gcode.preview(|g| g.strip_skirt())`.
+    pub fn preview<T>(&self, transform: impl FnOnce(&mut GCodeModel) -> T) -> (T, Preview) {
+        let mut after = self.clone();
+        let out = transform(&mut after);
+        (out, diff(self, &after))
+    }
+}
+
+#[test]
+fn preview_test() {
+    use crate::{coasting::AntiStringing, Command};
+    let gcode: GCodeModel = "G90\nG1 X10 E1 F600\nG1 E0.2\nG1 X20\nG1 E1\nG1 X30 E2"
+        .parse()
+        .unwrap();
+    let before = gcode.clone();
+    let (count, preview) = gcode.preview(|g| {
+        g.map_commands(
+            |line| matches!(line.command, Command::G90),
+            |c| *c = Command::G91,
+        )
+    });
+    assert_eq!(count, 1);
+    assert_eq!(preview.counts(), (0, 0, 1));
+    assert_eq!(preview.to_string(), "-G90\n+G91\n");
+    assert_eq!(gcode, before);
+
+    let (_, preview) = gcode.preview(|g| {
+        g.lines.remove(0);
+        let line = g.lines[0].clone();
+        g.lines.insert(
+            1,
+            GCodeLine {
+                id: g.id_counter.get(),
+                comments: String::from(" added"),
+                ..line
+            },
+        );
+    });
+    assert_eq!(preview.counts(), (1, 1, 0));
+    assert!(matches!(
+        preview.changes[0],
+        Change::Removed { index: 0, .. }
+    ));
+    assert!(matches!(preview.changes[1], Change::Added { index: 1, .. }));
+
+    let (_, preview) = gcode.preview(|g| {
+        g.anti_stringing(AntiStringing::Coast {
+            distance: crate::Length::from(0.5),
+        })
+    });
+    assert!(!preview.is_empty());
+}
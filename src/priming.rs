@@ -0,0 +1,145 @@
+use crate::{
+    geometry::Bounds,
+    skirt::{extrusion_runs, run_bounds},
+    Annotations, Command, GCodeLine, GCodeModel, GCodeParseError, Tag, G1,
+};
+use std::ops::Range;
+
+/// Whether two boxes don't overlap in XY
+fn disjoint(a: &Bounds, b: &Bounds) -> bool {
+    a.max.x < b.min.x || b.max.x < a.min.x || a.max.y < b.min.y || b.max.y < a.min.y
+}
+
+impl GCodeModel {
+    /// Lines of the slicer's intro line or purge sequence, from its first
+    /// extrusion to its last. With `;TYPE:` feature comments this is every
+    /// extrusion before the first feature other than custom gcode. Files
+    /// without them use the extrusion runs at the start of the first layer
+    /// that don't overlap anything printed after them on that layer.
+    pub fn priming_range(&self) -> Option<Range<usize>> {
+        let steps = self.cursor().collect::<Vec<_>>();
+        let first_feature = self.lines.iter().position(|line| {
+            self.feature(line)
+                .is_some_and(|feature| !feature.eq_ignore_ascii_case("custom"))
+        });
+        let runs = match first_feature {
+            Some(end) => extrusion_runs(&steps[..end]),
+            None => {
+                let first = self.layers().into_iter().next()?;
+                let runs = extrusion_runs(&steps[..first.end]);
+                let count = (0..runs.len())
+                    .take_while(|&n| {
+                        match (
+                            run_bounds(&steps, &runs[..=n]),
+                            run_bounds(&steps, &runs[n + 1..]),
+                        ) {
+                            (Some(prime), Some(rest)) => disjoint(&prime, &rest),
+                            _ => false,
+                        }
+                    })
+                    .count();
+                runs[..count].to_vec()
+            }
+        };
+        Some(runs.first()?.start..runs.last()?.end)
+    }
+    /// Swap the slicer's priming for `sequence`, or put it before the first
+    /// extrusion if there is none, returning the lines it now occupies.
+    /// `{min_x}`, `{min_y}`, `{max_x}` and `{max_y}` in the sequence are
+    /// replaced with the bounds of the rest of the print in mm so the routine
+    /// can be placed next to the part. The toolhead travels back to where the
+    /// sequence started once it's done, and in absolute E mode later E values
+    /// are moved up by the filament it used.
+    pub fn replace_priming(&mut self, sequence: &str) -> Result<Range<usize>, GCodeParseError> {
+        let priming = self.priming_range();
+        let steps = self.cursor().collect::<Vec<_>>();
+        let print = extrusion_runs(&steps)
+            .into_iter()
+            .filter(|run| {
+                priming
+                    .as_ref()
+                    .is_none_or(|priming| run.start >= priming.end || run.end <= priming.start)
+            })
+            .collect::<Vec<_>>();
+        let mut sequence = sequence.to_string();
+        if let Some(bounds) = run_bounds(&steps, &print) {
+            for (key, value) in [
+                ("{min_x}", bounds.min.x),
+                ("{min_y}", bounds.min.y),
+                ("{max_x}", bounds.max.x),
+                ("{max_y}", bounds.max.y),
+            ] {
+                sequence = sequence.replace(key, &value.to_string());
+            }
+        }
+        let primer: GCodeModel = sequence.parse()?;
+
+        let index = match priming {
+            Some(range) => {
+                let start = range.start;
+                self.strip_moves(range);
+                start
+            }
+            None => steps
+                .iter()
+                .position(|step| step.line.command.tag() == Tag::Extrusion)
+                .unwrap_or(self.lines.len()),
+        };
+        let prev = match self.cursor().nth(index) {
+            Some(step) => step.prev,
+            None => self
+                .cursor()
+                .last()
+                .map(|step| step.next)
+                .unwrap_or_default(),
+        };
+        let mut state = prev;
+        let mut lines = Vec::with_capacity(primer.lines.len() + 1);
+        for line in primer.lines {
+            state.apply(&line.command);
+            lines.push(GCodeLine {
+                id: self.id_counter.get(),
+                ..line
+            });
+        }
+        if state.pos != prev.pos {
+            lines.push(GCodeLine {
+                id: self.id_counter.get(),
+                command: Command::G1(G1 {
+                    f: Some(prev.feedrate),
+                    ..state.move_to(prev.pos, None)
+                }),
+                comments: String::new(),
+                annotations: Annotations::default(),
+            });
+        }
+        self.shift_absolute_e(index, state.e - prev.e);
+        let inserted = index..index + lines.len();
+        self.lines.splice(index..index, lines);
+        self.tag_g1();
+        self.record_transform("replace_priming", format!("{sequence:?}"));
+        Ok(inserted)
+    }
+}
+
+#[test]
+fn replace_priming_test() {
+    use crate::emit::Emit;
+    let input = "M83\nG1 Z0.2 F720\nG1 Y-3 F1000\nG1 X60 E9\nG1 X100 E12.5\n;TYPE:Skirt\nG1 X20 Y20\nG1 X30 E1\n;TYPE:Perimeter\nG1 X40 Y40\nG1 X50 E1";
+    let mut gcode: GCodeModel = input.parse().unwrap();
+    assert_eq!(gcode.priming_range(), Some(3..5));
+    let inserted = gcode
+        .replace_priming("G1 X{min_x} Y{min_y} ; prime\nG1 X{max_x} E5")
+        .unwrap();
+    assert_eq!(inserted, 3..6);
+    assert_eq!(gcode.lines[3].emit(false), "G1 X20 Y20 ; prime");
+    assert_eq!(gcode.lines[4].emit(false), "G1 X50 E5 ");
+    let end = gcode.cursor().last().unwrap().next;
+    assert_eq!(end.e.to_mm(), 7.0);
+
+    // without an intro line the sequence goes before the first extrusion
+    let mut gcode: GCodeModel = "G1 Z0.2\nG1 X10 Y10\nG1 X20 E1".parse().unwrap();
+    assert_eq!(gcode.priming_range(), None);
+    let inserted = gcode.replace_priming("G1 Y0 X0\nG1 X5 E1").unwrap();
+    assert_eq!(inserted, 2..5);
+}
@@ -0,0 +1,379 @@
+use crate::{
+    geometry::{Bounds, Geometry},
+    state::Position,
+    Length,
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Printable area of the bed, in mm from the machine origin
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BedShape {
+    /// rectangle with one corner at the origin
+    Rectangle { width: f64, depth: f64 },
+    /// circle centered on the origin, as on most delta printers
+    Circle { diameter: f64 },
+}
+
+impl Default for BedShape {
+    fn default() -> Self {
+        BedShape::Rectangle {
+            width: 200.0,
+            depth: 200.0,
+        }
+    }
+}
+
+impl BedShape {
+    /// Printable area in mm²
+    pub fn area(&self) -> f64 {
+        match self {
+            BedShape::Rectangle { width, depth } => width * depth,
+            BedShape::Circle { diameter } => std::f64::consts::PI * (diameter / 2.0).powi(2),
+        }
+    }
+    /// Whether a point in mm lies on the bed
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        match self {
+            BedShape::Rectangle { width, depth } => {
+                (0.0..=*width).contains(&x) && (0.0..=*depth).contains(&y)
+            }
+            BedShape::Circle { diameter } => x.hypot(y) <= diameter / 2.0,
+        }
+    }
+}
+
+/// Firmware family, which decides how several commands are interpreted
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Firmware {
+    #[default]
+    Marlin,
+    Prusa,
+    Klipper,
+    RepRapFirmware,
+    Grbl,
+}
+
+/// One value per axis
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct AxisLimits {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub e: f64,
+}
+
+/// How fast the toolhead may take a corner, which differs per firmware
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum CornerModel {
+    /// Marlin's classic jerk, an instant speed change of up to
+    /// `MachineLimits::jerk` on each axis
+    #[default]
+    Jerk,
+    /// Marlin's junction deviation in mm, set with M205 J
+    JunctionDeviation(f64),
+    /// Klipper's square corner velocity, the speed through a 90° corner
+    /// in mm/s
+    SquareCornerVelocity(f64),
+}
+
+/// Correction for Klipper's input shaper, which smooths out every speed
+/// change
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct InputShaper {
+    /// how much longer each speed change takes than the bare acceleration
+    /// allows, e.g. 0.1 for 10%
+    pub smoothing: f64,
+}
+
+/// Kinematic limits of a machine, matching what Marlin sets with
+/// M203/M201/M204/M205
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct MachineLimits {
+    /// maximum speed of each axis in mm/s
+    pub max_feedrate: AxisLimits,
+    /// maximum acceleration of each axis in mm/s²
+    pub max_acceleration: AxisLimits,
+    /// acceleration for printing moves in mm/s²
+    pub print_acceleration: f64,
+    /// acceleration for retractions in mm/s²
+    pub retract_acceleration: f64,
+    /// acceleration for travel moves in mm/s²
+    pub travel_acceleration: f64,
+    /// instantaneous speed change allowed on each axis in mm/s. Only
+    /// `CornerModel::Jerk` uses it, through corners and when starting from
+    /// or stopping to rest. Junction deviation and square corner velocity
+    /// ignore it, and start and stop every move at zero speed.
+    pub jerk: AxisLimits,
+    /// how fast moves may flow into each other through a corner
+    pub corner: CornerModel,
+    /// stretches every speed change in the time estimate when set
+    pub input_shaper: Option<InputShaper>,
+}
+
+/// Range each axis may move in once homed, and where homing leaves it,
+/// in machine coordinates
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SoftEndstops {
+    pub min: Position,
+    pub max: Position,
+    pub home: Position,
+}
+
+/// Description of the machine a file is meant to run on, shared by the
+/// compatibility checks, time estimate and bounds checks
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PrinterProfile {
+    /// model name as slicers write it, e.g. "MK4" or "Bambu Lab X1 Carbon"
+    pub model: String,
+    pub bed_shape: BedShape,
+    /// maximum printable height in mm
+    pub max_z: f64,
+    pub limits: MachineLimits,
+    /// nozzle diameter in mm
+    pub nozzle_diameter: f64,
+    /// filament diameter in mm
+    pub filament_diameter: f64,
+    pub firmware: Firmware,
+    pub geometry: Geometry,
+}
+
+impl PrinterProfile {
+    /// Original Prusa MK4
+    pub fn prusa_mk4() -> Self {
+        PrinterProfile {
+            model: String::from("MK4"),
+            bed_shape: BedShape::Rectangle {
+                width: 250.0,
+                depth: 210.0,
+            },
+            max_z: 220.0,
+            limits: MachineLimits {
+                max_feedrate: AxisLimits {
+                    x: 400.0,
+                    y: 400.0,
+                    z: 12.0,
+                    e: 100.0,
+                },
+                max_acceleration: AxisLimits {
+                    x: 4000.0,
+                    y: 4000.0,
+                    z: 200.0,
+                    e: 2500.0,
+                },
+                print_acceleration: 2000.0,
+                retract_acceleration: 1250.0,
+                travel_acceleration: 4000.0,
+                jerk: AxisLimits {
+                    x: 8.0,
+                    y: 8.0,
+                    z: 2.0,
+                    e: 10.0,
+                },
+                corner: CornerModel::Jerk,
+                input_shaper: None,
+            },
+            nozzle_diameter: 0.4,
+            filament_diameter: 1.75,
+            firmware: Firmware::Prusa,
+            geometry: Geometry::Cartesian,
+        }
+    }
+    /// Creality Ender 3 on stock Marlin
+    pub fn ender_3() -> Self {
+        PrinterProfile {
+            model: String::from("Ender-3"),
+            bed_shape: BedShape::Rectangle {
+                width: 220.0,
+                depth: 220.0,
+            },
+            max_z: 250.0,
+            limits: MachineLimits {
+                max_feedrate: AxisLimits {
+                    x: 500.0,
+                    y: 500.0,
+                    z: 5.0,
+                    e: 25.0,
+                },
+                max_acceleration: AxisLimits {
+                    x: 500.0,
+                    y: 500.0,
+                    z: 100.0,
+                    e: 5000.0,
+                },
+                print_acceleration: 500.0,
+                retract_acceleration: 500.0,
+                travel_acceleration: 1000.0,
+                jerk: AxisLimits {
+                    x: 8.0,
+                    y: 8.0,
+                    z: 0.4,
+                    e: 5.0,
+                },
+                corner: CornerModel::Jerk,
+                input_shaper: None,
+            },
+            nozzle_diameter: 0.4,
+            filament_diameter: 1.75,
+            firmware: Firmware::Marlin,
+            geometry: Geometry::Cartesian,
+        }
+    }
+    /// Voron 2.4 in the 300mm size on Klipper
+    pub fn voron_2_4() -> Self {
+        PrinterProfile {
+            model: String::from("Voron 2.4"),
+            bed_shape: BedShape::Rectangle {
+                width: 300.0,
+                depth: 300.0,
+            },
+            max_z: 280.0,
+            limits: MachineLimits {
+                max_feedrate: AxisLimits {
+                    x: 300.0,
+                    y: 300.0,
+                    z: 15.0,
+                    e: 120.0,
+                },
+                max_acceleration: AxisLimits {
+                    x: 3000.0,
+                    y: 3000.0,
+                    z: 350.0,
+                    e: 3000.0,
+                },
+                print_acceleration: 3000.0,
+                retract_acceleration: 3000.0,
+                travel_acceleration: 3000.0,
+                // Klipper has no jerk, moves start from rest
+                jerk: AxisLimits::default(),
+                corner: CornerModel::SquareCornerVelocity(5.0),
+                input_shaper: Some(InputShaper { smoothing: 0.1 }),
+            },
+            nozzle_diameter: 0.4,
+            filament_diameter: 1.75,
+            firmware: Firmware::Klipper,
+            geometry: Geometry::Cartesian,
+        }
+    }
+    /// Soft endstops matching the bed and maximum height, homing to the
+    /// origin corner, or to the top for a round bed
+    pub fn soft_endstops(&self) -> SoftEndstops {
+        let mm = Length::from_mm;
+        let (min, max) = match self.bed_shape {
+            BedShape::Rectangle { width, depth } => ((0.0, 0.0), (width, depth)),
+            BedShape::Circle { diameter } => {
+                let r = diameter / 2.0;
+                ((-r, -r), (r, r))
+            }
+        };
+        let home_z = match self.bed_shape {
+            BedShape::Rectangle { .. } => 0.0,
+            BedShape::Circle { .. } => self.max_z,
+        };
+        SoftEndstops {
+            min: Position {
+                x: mm(min.0),
+                y: mm(min.1),
+                z: Length::ZERO,
+            },
+            max: Position {
+                x: mm(max.0),
+                y: mm(max.1),
+                z: mm(self.max_z),
+            },
+            home: Position {
+                x: mm(min.0.max(0.0)),
+                y: mm(min.1.max(0.0)),
+                z: mm(home_z),
+            },
+        }
+    }
+    /// Whether a bounding box in world coordinates fits on the bed
+    /// and under the maximum height
+    pub fn fits(&self, bounds: &Bounds) -> bool {
+        let (min, max) = (&bounds.min, &bounds.max);
+        let corners = [
+            (min.x, min.y),
+            (min.x, max.y),
+            (max.x, min.y),
+            (max.x, max.y),
+        ];
+        corners
+            .iter()
+            .all(|(x, y)| self.bed_shape.contains(x.to_mm(), y.to_mm()))
+            && min.z.to_mm() >= 0.0
+            && max.z.to_mm() <= self.max_z
+    }
+}
+
+/// Limits of a filament, for `GCodeModel::check_material`
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct FilamentProfile {
+    /// material name, e.g. "PLA" or "PETG"
+    pub material: String,
+    /// most plastic the hotend can melt, in mm³/s
+    pub max_volumetric_speed: f64,
+    /// hotend temperatures the material prints at, in °C
+    pub temperature: (f32, f32),
+    /// bed temperatures it sticks at, if it matters
+    pub bed_temperature: Option<(f32, f32)>,
+    /// shortest time a layer needs to cool before the next one lands
+    pub min_layer_time: std::time::Duration,
+}
+
+impl FilamentProfile {
+    /// Generic PLA, as in PrusaSlicer's stock filament profiles
+    pub fn pla() -> Self {
+        FilamentProfile {
+            material: String::from("PLA"),
+            max_volumetric_speed: 15.0,
+            temperature: (190.0, 230.0),
+            bed_temperature: Some((50.0, 65.0)),
+            min_layer_time: std::time::Duration::from_secs(4),
+        }
+    }
+    /// Generic PETG
+    pub fn petg() -> Self {
+        FilamentProfile {
+            material: String::from("PETG"),
+            max_volumetric_speed: 8.0,
+            temperature: (220.0, 260.0),
+            bed_temperature: Some((70.0, 90.0)),
+            min_layer_time: std::time::Duration::from_secs(20),
+        }
+    }
+    /// Generic ABS
+    pub fn abs() -> Self {
+        FilamentProfile {
+            material: String::from("ABS"),
+            max_volumetric_speed: 11.0,
+            temperature: (230.0, 260.0),
+            bed_temperature: Some((90.0, 110.0)),
+            min_layer_time: std::time::Duration::from_secs(15),
+        }
+    }
+}
+
+#[test]
+fn profile_fits_test() {
+    let gcode: crate::GCodeModel = "G1 X10 Y10 Z0.2\nG1 X240 Y200 E5".parse().unwrap();
+    let bounds = gcode.bounds(Geometry::Cartesian).unwrap();
+    assert!(PrinterProfile::prusa_mk4().fits(&bounds));
+    assert!(!PrinterProfile::ender_3().fits(&bounds));
+    let delta = PrinterProfile {
+        bed_shape: BedShape::Circle { diameter: 200.0 },
+        max_z: 300.0,
+        ..Default::default()
+    };
+    assert!(!delta.fits(&bounds));
+}
@@ -0,0 +1,221 @@
+use crate::{
+    emit::EmitConfig,
+    parsers::{gcode_parser_monitored, GCodeParseError, ParserConfig},
+    state::{tag_lines, MachineState},
+    GCodeModel,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Lines between progress reports and checks of the cancellation flag
+const INTERVAL: usize = 4096;
+
+/// Which pass over the file a `Progress` report is about
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Stage {
+    Parse,
+    Tag,
+    Simulate,
+    Estimate,
+    Transform,
+    Emit,
+}
+
+/// Lines processed so far in one stage of a long operation
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Progress {
+    pub stage: Stage,
+    pub done: usize,
+    pub total: usize,
+}
+
+/// The operation was stopped through its cancellation flag
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Why a monitored parse stopped
+#[derive(Debug, PartialEq)]
+pub enum ParseFailure {
+    Invalid(GCodeParseError),
+    Cancelled,
+}
+
+impl From<GCodeParseError> for ParseFailure {
+    fn from(e: GCodeParseError) -> Self {
+        ParseFailure::Invalid(e)
+    }
+}
+
+impl From<Cancelled> for ParseFailure {
+    fn from(_: Cancelled) -> Self {
+        ParseFailure::Cancelled
+    }
+}
+
+/// Optional progress callback and cancellation flag for a long operation.
+/// The callback runs every few thousand lines and once at the end of each
+/// stage, and setting the flag from another thread stops the operation at
+/// the next report.
+#[derive(Default)]
+pub struct Monitor<'a> {
+    progress: Option<Box<dyn FnMut(Progress) + 'a>>,
+    cancel: Option<&'a AtomicBool>,
+}
+
+impl<'a> Monitor<'a> {
+    pub fn new() -> Self {
+        Monitor::default()
+    }
+    pub fn progress(mut self, progress: impl FnMut(Progress) + 'a) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+    pub fn cancel(mut self, cancel: &'a AtomicBool) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+    /// Run the monitored version of an operation for its plain version,
+    /// which has no flag and so can't be cancelled
+    pub(crate) fn unmonitored<T>(f: impl FnOnce(&mut Monitor) -> Result<T, Cancelled>) -> T {
+        f(&mut Monitor::default()).unwrap_or_else(|_| unreachable!("no cancellation flag to set"))
+    }
+    /// Report `done` of `total` lines if it is time to, and check the flag
+    pub(crate) fn update(
+        &mut self,
+        stage: Stage,
+        done: usize,
+        total: usize,
+    ) -> Result<(), Cancelled> {
+        if !done.is_multiple_of(INTERVAL) && done != total {
+            return Ok(());
+        }
+        if let Some(progress) = &mut self.progress {
+            progress(Progress { stage, done, total });
+        }
+        match self.cancel {
+            Some(cancel) if cancel.load(Ordering::Relaxed) => Err(Cancelled),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl GCodeModel {
+    /// Parse like `parse_with_config`, reporting progress through the
+    /// parse and the tagging that follows it
+    pub fn parse_monitored(
+        mut s: &str,
+        config: &ParserConfig,
+        monitor: &mut Monitor,
+    ) -> Result<Self, ParseFailure> {
+        gcode_parser_monitored(&mut s, config, monitor)
+    }
+    /// `tag_g1` with progress reports. The model is left partly tagged if
+    /// it is cancelled.
+    pub fn tag_g1_monitored(&mut self, monitor: &mut Monitor) -> Result<(), Cancelled> {
+        self.state_index.clear();
+        let total = self.lines.len();
+        let mut state = MachineState::default();
+        monitor.update(Stage::Tag, 0, total)?;
+        for (n, chunk) in self.lines.chunks_mut(INTERVAL).enumerate() {
+            state = tag_lines(chunk, state);
+            monitor.update(Stage::Tag, (n * INTERVAL + chunk.len()).min(total), total)?;
+        }
+        Ok(())
+    }
+    /// Machine state after each line, parallel to `lines`
+    pub fn simulate(&self, monitor: &mut Monitor) -> Result<Vec<MachineState>, Cancelled> {
+        let total = self.lines.len();
+        let mut states = Vec::with_capacity(total);
+        monitor.update(Stage::Simulate, 0, total)?;
+        for step in self.cursor() {
+            states.push(step.next);
+            monitor.update(Stage::Simulate, states.len(), total)?;
+        }
+        Ok(states)
+    }
+    /// `emit_with_config` with progress reports
+    pub fn emit_monitored(
+        &self,
+        config: &EmitConfig,
+        monitor: &mut Monitor,
+    ) -> Result<String, Cancelled> {
+        let total = self.lines.len();
+        let ending = config.line_ending.unwrap_or(self.line_ending).as_str();
+        let mut out = String::new();
+        monitor.update(Stage::Emit, 0, total)?;
+        if config.header {
+            for line in self.summary_header() {
+                out += &line;
+                out += ending;
+            }
+        }
+        for (i, (line, _)) in self.emit_iter(config).enumerate() {
+            out += &String::from_utf8(line).expect("emitted from strings");
+            monitor.update(Stage::Emit, i + 1, total)?;
+        }
+        if config.provenance {
+            for transform in &self.provenance {
+                out += &format!("; transform {transform}{ending}");
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[test]
+fn progress_test() {
+    use crate::emit::Emit;
+    let input = "G1 X1 E1 F600\n".repeat(10_000);
+    let mut reports = Vec::new();
+    let mut monitor = Monitor::new().progress(|progress| reports.push(progress));
+    let gcode =
+        GCodeModel::parse_monitored(&input, &ParserConfig::default(), &mut monitor).unwrap();
+    let config = EmitConfig {
+        line_ending: Some(crate::emit::LineEnding::CrLf),
+        elide_feedrate: true,
+        provenance: true,
+        ..Default::default()
+    };
+    let emitted = gcode.emit_monitored(&config, &mut monitor).unwrap();
+    drop(monitor);
+    assert_eq!(emitted, gcode.emit_with_config(&config));
+    assert!(emitted.starts_with("G1 X1 E1 F600 \r\nG1 X1 E1 \r\n"));
+    let parse = reports
+        .iter()
+        .filter(|p| p.stage == Stage::Parse)
+        .map(|p| p.done)
+        .collect::<Vec<_>>();
+    assert_eq!(parse, [0, 4096, 8192, 10_000]);
+    assert_eq!(
+        reports.last().map(|p| (p.stage, p.done)),
+        Some((Stage::Emit, 10_000))
+    );
+    assert!(reports.iter().any(|p| p.stage == Stage::Tag));
+
+    // the flag is checked at the next report
+    let cancel = AtomicBool::new(false);
+    let mut monitor = Monitor::new()
+        .progress(|progress| {
+            if progress.done >= 4096 {
+                cancel.store(true, Ordering::Relaxed);
+            }
+        })
+        .cancel(&cancel);
+    assert_eq!(gcode.simulate(&mut monitor), Err(Cancelled));
+
+    // a cancelled transform changes nothing
+    let mut changed = gcode.clone();
+    let cancel = AtomicBool::new(true);
+    let mut monitor = Monitor::new().cancel(&cancel);
+    let result =
+        changed.map_commands_monitored(|_| true, |c| *c = crate::Command::G90, &mut monitor);
+    assert_eq!(result, Err(Cancelled));
+    assert_eq!(changed.emit(false), gcode.emit(false));
+}
@@ -0,0 +1,69 @@
+use crate::GCodeModel;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A transform that was applied to a model, see `GCodeModel::provenance`
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AppliedTransform {
+    /// name of the method, e.g. `strip_skirt`
+    pub name: String,
+    /// the arguments it was called with, as written by `Debug`
+    pub parameters: String,
+    /// seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+impl std::fmt::Display for AppliedTransform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}({}) at {}",
+            self.name, self.parameters, self.timestamp
+        )
+    }
+}
+
+impl GCodeModel {
+    /// Add a transform to the end of `provenance`, timestamped now, and
+    /// drop the index behind `state_at`. The built in transforms call this
+    /// themselves.
+    pub fn record_transform(&mut self, name: &str, parameters: String) {
+        self.state_index.clear();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        self.provenance.push(AppliedTransform {
+            name: name.to_string(),
+            parameters,
+            timestamp,
+        });
+    }
+}
+
+#[test]
+fn provenance_test() {
+    use crate::emit::{Emit, EmitConfig};
+    let mut gcode: GCodeModel = "G1 X10 E1 F600\nG1 X20 E2".parse().unwrap();
+    gcode.linearize_splines(4);
+    gcode.strip_skirt();
+    let names = gcode
+        .provenance
+        .iter()
+        .map(|t| (t.name.as_str(), t.parameters.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(names, [("linearize_splines", "4"), ("strip_skirt", "")]);
+    assert!(gcode.provenance[0].timestamp > 0);
+
+    let config = EmitConfig {
+        provenance: true,
+        ..Default::default()
+    };
+    let emitted = gcode.emit_with_config(&config);
+    let trailer = emitted.lines().skip(2).collect::<Vec<_>>();
+    assert_eq!(trailer.len(), 2);
+    assert!(trailer[0].starts_with("; transform linearize_splines(4) at "));
+    assert_eq!(gcode.emit(false).lines().count(), 2);
+}
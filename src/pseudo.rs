@@ -0,0 +1,155 @@
+use crate::{GCodeModel, Id, Length, SlicerKind};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// An event a slicer writes as a comment rather than a command, see
+/// `GCodeModel::pseudo_commands`
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PseudoCommand {
+    /// start of a feature, e.g. `;TYPE:External perimeter`
+    Feature(String),
+    /// start of a layer, with its number if the slicer writes one
+    LayerChange(Option<u32>),
+    /// height of the layer being started, e.g. `;Z:0.4`
+    Z(Length),
+    /// thickness of the extrusions that follow, e.g. `;HEIGHT:0.2`
+    Height(Length),
+    /// width of the extrusions that follow, e.g. `;WIDTH:0.45`
+    Width(Length),
+    WipeStart,
+    WipeEnd,
+    /// PrusaSlicer's marker before a tool change, `;_TOOLCHANGE T1`
+    ToolChange(u8),
+}
+
+/// Slicers whose pseudo-commands follow PrusaSlicer's
+fn prusa_like(dialect: SlicerKind) -> bool {
+    matches!(
+        dialect,
+        SlicerKind::PrusaSlicer | SlicerKind::SuperSlicer | SlicerKind::Slic3r
+    )
+}
+
+fn orca_like(dialect: SlicerKind) -> bool {
+    matches!(dialect, SlicerKind::OrcaSlicer | SlicerKind::BambuStudio)
+}
+
+fn mm(value: &str) -> Option<Length> {
+    value.trim().parse::<f64>().ok().map(Length::from)
+}
+
+impl PseudoCommand {
+    /// The pseudo-command in `comment`, a line's comment without its `;`,
+    /// if it is one `dialect` writes. Comments that merely look like
+    /// another slicer's markers are left alone.
+    pub fn parse(comment: &str, dialect: SlicerKind) -> Option<Self> {
+        use PseudoCommand::*;
+        let comment = comment.trim();
+        let value = |key: &str| comment.strip_prefix(key);
+        if dialect == SlicerKind::Simplify3D {
+            if let Some(feature) = value("feature ") {
+                return Some(Feature(feature.trim().to_string()));
+            }
+            let layer = value("layer ")?;
+            let index = layer.split([',', ' ']).next()?.parse().ok();
+            return Some(LayerChange(index));
+        }
+        if let Some(feature) = value("TYPE:") {
+            return Some(Feature(feature.trim().to_string()));
+        }
+        if matches!(dialect, SlicerKind::Cura | SlicerKind::IdeaMaker) {
+            if let Some(layer) = value("LAYER:") {
+                return Some(LayerChange(layer.trim().parse().ok()));
+            }
+        }
+        if dialect == SlicerKind::Cura {
+            return None;
+        }
+        if prusa_like(dialect) || orca_like(dialect) {
+            match comment {
+                "LAYER_CHANGE" => return Some(LayerChange(None)),
+                "WIPE_START" => return Some(WipeStart),
+                "WIPE_END" => return Some(WipeEnd),
+                _ => {}
+            }
+            if let Some(width) = value("WIDTH:") {
+                return mm(width).map(Width);
+            }
+        }
+        if orca_like(dialect) {
+            if comment == "CHANGE_LAYER" {
+                return Some(LayerChange(None));
+            }
+            if let Some(z) = value("Z_HEIGHT:") {
+                return mm(z).map(Z);
+            }
+            if let Some(height) = value("LAYER_HEIGHT:") {
+                return mm(height).map(Height);
+            }
+        }
+        if prusa_like(dialect) {
+            if let Some(tool) = value("_TOOLCHANGE") {
+                let tool = tool.trim();
+                return tool.strip_prefix(['T', 't'])?.parse().ok().map(ToolChange);
+            }
+        }
+        if let Some(z) = value("Z:") {
+            return mm(z).map(Z);
+        }
+        value("HEIGHT:").and_then(mm).map(Height)
+    }
+}
+
+impl GCodeModel {
+    /// Every pseudo-command `dialect` writes in the comments of the file,
+    /// with the id of its line, for analyses that need them as events.
+    /// `detect_slicer` finds the dialect of a file.
+    pub fn pseudo_commands(&self, dialect: SlicerKind) -> Vec<(Id, PseudoCommand)> {
+        self.lines
+            .iter()
+            .filter_map(|line| {
+                PseudoCommand::parse(self.comment(line), dialect).map(|pseudo| (line.id, pseudo))
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn pseudo_command_test() {
+    use PseudoCommand::*;
+    let parse = |comment, dialect| PseudoCommand::parse(comment, dialect);
+    let prusa = SlicerKind::PrusaSlicer;
+    assert_eq!(parse("LAYER_CHANGE", prusa), Some(LayerChange(None)));
+    assert_eq!(parse("Z:0.4", prusa), Some(Z(Length::from(0.4))));
+    assert_eq!(parse(" _TOOLCHANGE T1", prusa), Some(ToolChange(1)));
+    assert_eq!(parse("WIPE_END", prusa), Some(WipeEnd));
+    assert_eq!(
+        parse("TYPE:External perimeter", prusa),
+        Some(Feature(String::from("External perimeter")))
+    );
+    // gated by dialect
+    assert_eq!(parse("LAYER:3", prusa), None);
+    assert_eq!(
+        parse("LAYER:3", SlicerKind::Cura),
+        Some(LayerChange(Some(3)))
+    );
+    assert_eq!(parse("WIPE_START", SlicerKind::Cura), None);
+    assert_eq!(
+        parse("Z_HEIGHT:1.2", SlicerKind::BambuStudio),
+        Some(Z(Length::from(1.2)))
+    );
+    assert_eq!(
+        parse("layer 12, Z = 2.6", SlicerKind::Simplify3D),
+        Some(LayerChange(Some(12)))
+    );
+    assert_eq!(parse("generated by PrusaSlicer", prusa), None);
+
+    let gcode: GCodeModel = ";LAYER_CHANGE\n;Z:0.2\n;WIPE_START\nG1 X1 E-0.1\n;WIPE_END"
+        .parse()
+        .unwrap();
+    let events = gcode.pseudo_commands(prusa);
+    assert_eq!(events.len(), 4);
+    assert_eq!(events[2], (gcode.lines[2].id, WipeStart));
+}
@@ -0,0 +1,116 @@
+use crate::{GCodeModel, Id, Length, Role, Tag};
+use std::ops::{Bound, Range, RangeBounds};
+
+/// Filters over the lines of a model, built with `GCodeModel::select`.
+/// Every filter that is set must match; repeated `tag` or `role` filters
+/// match any of the given values.
+#[derive(Clone, Debug)]
+pub struct Query<'a> {
+    pub(crate) model: &'a GCodeModel,
+    tags: Vec<Tag>,
+    roles: Vec<Role>,
+    z: Option<(Bound<Length>, Bound<Length>)>,
+    layers: Option<(Bound<usize>, Bound<usize>)>,
+}
+
+impl GCodeModel {
+    /// Start a query over every line of the model
+    pub fn select(&self) -> Query<'_> {
+        Query {
+            model: self,
+            tags: Vec::new(),
+            roles: Vec::new(),
+            z: None,
+            layers: None,
+        }
+    }
+}
+
+impl<'a> Query<'a> {
+    /// Lines with this motion tag
+    pub fn tag(mut self, tag: Tag) -> Self {
+        self.tags.push(tag);
+        self
+    }
+    /// Lines with this role, see `GCodeModel::roles`
+    pub fn role(mut self, role: Role) -> Self {
+        self.roles.push(role);
+        self
+    }
+    /// Lines that leave the nozzle at a height in `z`
+    pub fn z_range(mut self, z: impl RangeBounds<Length>) -> Self {
+        self.z = Some((z.start_bound().cloned(), z.end_bound().cloned()));
+        self
+    }
+    /// Lines in the layers with these indices into `GCodeModel::layers`
+    pub fn layer(mut self, layers: impl RangeBounds<usize>) -> Self {
+        self.layers = Some((layers.start_bound().cloned(), layers.end_bound().cloned()));
+        self
+    }
+    /// Indices of the matching lines, in order
+    pub fn indices(&self) -> Vec<usize> {
+        let model = self.model;
+        let roles = (!self.roles.is_empty()).then(|| model.roles());
+        let mut layer_of = vec![None; model.lines.len()];
+        if self.layers.is_some() {
+            for (n, layer) in model.layers().iter().enumerate() {
+                layer_of[layer.range()].fill(Some(n));
+            }
+        }
+        model
+            .cursor()
+            .enumerate()
+            .filter(|(i, step)| {
+                (self.tags.is_empty() || self.tags.contains(&step.line.command.tag()))
+                    && roles
+                        .as_ref()
+                        .is_none_or(|roles| self.roles.contains(&roles[*i]))
+                    && self.z.is_none_or(|z| z.contains(&step.next.pos.z))
+                    && self
+                        .layers
+                        .is_none_or(|layers| layer_of[*i].is_some_and(|n| layers.contains(&n)))
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+    /// Ids of the matching lines, in order
+    pub fn ids(&self) -> Vec<Id> {
+        self.indices()
+            .into_iter()
+            .map(|i| self.model.lines[i].id)
+            .collect()
+    }
+    /// Matching lines merged into contiguous index ranges
+    pub fn ranges(&self) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        for i in self.indices() {
+            match ranges.last_mut() {
+                Some(range) if range.end == i => range.end = i + 1,
+                _ => ranges.push(i..i + 1),
+            }
+        }
+        ranges
+    }
+}
+
+#[test]
+fn query_test() {
+    let input = "G1 Z0.2 F600\nG1 X10 E1\nG1 X20 E2\nG1 Z0.4\nG1 X10 E3\nG1 X0\nG1 X10 E4\nG1 Z0.6\nG1 X0 E5";
+    let gcode: GCodeModel = input.parse().unwrap();
+    let query = gcode.select().tag(Tag::Extrusion);
+    assert_eq!(query.indices(), [1, 2, 4, 6, 8]);
+    assert_eq!(query.ranges(), [1..3, 4..5, 6..7, 8..9]);
+    let z = Length::from(0.3)..=Length::from(0.6);
+    assert_eq!(query.clone().z_range(z).indices(), [4, 6, 8]);
+    assert_eq!(query.clone().layer(1..2).indices(), [4, 6]);
+    assert_eq!(
+        gcode
+            .select()
+            .tag(Tag::Travel)
+            .tag(Tag::RaiseZ)
+            .layer(1..)
+            .ids(),
+        [gcode.lines[3].id, gcode.lines[5].id, gcode.lines[7].id]
+    );
+    assert_eq!(gcode.select().role(Role::OtherExtrusion).indices().len(), 5);
+}
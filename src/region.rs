@@ -0,0 +1,188 @@
+use crate::{emit::Emit, fingerprint::Fnv, query::Query, GCodeLine, GCodeModel, Id};
+use std::ops::Range;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A contiguous run of lines, like a layer or an object, held by the ids
+/// of its first and last lines rather than by index. Lines inserted or
+/// removed outside the region don't change what it refers to, and lines
+/// inserted inside it become part of it.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Region {
+    pub first: Id,
+    pub last: Id,
+}
+
+impl Region {
+    /// Current line indices of the region, or `None` if either end has been
+    /// removed or they are no longer in order
+    pub fn resolve(&self, model: &GCodeModel) -> Option<Range<usize>> {
+        let first = model.lines.iter().position(|line| line.id == self.first)?;
+        let last = model.lines[first..]
+            .iter()
+            .position(|line| line.id == self.last)?;
+        Some(first..first + last + 1)
+    }
+    /// Whether the line with `id` currently falls in the region
+    pub fn contains(&self, model: &GCodeModel, id: Id) -> bool {
+        self.resolve(model)
+            .is_some_and(|range| model.lines[range].iter().any(|line| line.id == id))
+    }
+}
+
+impl GCodeModel {
+    /// Anchor the lines at `range` so they can be found again after edits,
+    /// or `None` if the range is empty or out of bounds
+    pub fn region(&self, range: Range<usize>) -> Option<Region> {
+        let lines = self.lines.get(range)?;
+        Some(Region {
+            first: lines.first()?.id,
+            last: lines.last()?.id,
+        })
+    }
+    /// A region for each of `layers`
+    pub fn layer_regions(&self) -> Vec<Region> {
+        self.layers()
+            .into_iter()
+            .filter_map(|layer| self.region(layer.range()))
+            .collect()
+    }
+}
+
+/// A reference to one line that can tell when it has gone stale. It holds
+/// the line's id, where it was, and a stamp of its command and comments,
+/// so a line that was removed or rewritten, or an id that now belongs to
+/// a different line (e.g. after the file was parsed again), is reported
+/// rather than silently resolving to the wrong line.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LineHandle {
+    pub id: Id,
+    index: usize,
+    stamp: u64,
+}
+
+/// Why a `LineHandle` no longer resolves
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Stale {
+    /// no line has the id any more
+    Removed,
+    /// the line with the id has a different command or comments
+    Changed,
+}
+
+impl std::fmt::Display for Stale {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stale::Removed => write!(f, "line was removed"),
+            Stale::Changed => write!(f, "line was changed"),
+        }
+    }
+}
+
+impl std::error::Error for Stale {}
+
+/// Hash of a line's command and comments as written, stable across Rust
+/// versions since handles may be stored
+fn stamp(model: &GCodeModel, line: &GCodeLine) -> u64 {
+    let mut hash = Fnv::new();
+    hash.write(line.command.emit(false).as_bytes());
+    hash.write(b";");
+    hash.write(model.comment(line).as_bytes());
+    hash.0
+}
+
+impl LineHandle {
+    /// Current index of the line, checking where it was first
+    pub fn resolve(&self, model: &GCodeModel) -> Result<usize, Stale> {
+        let index = match model.lines.get(self.index) {
+            Some(line) if line.id == self.id => self.index,
+            _ => model
+                .lines
+                .iter()
+                .position(|line| line.id == self.id)
+                .ok_or(Stale::Removed)?,
+        };
+        if stamp(model, &model.lines[index]) != self.stamp {
+            return Err(Stale::Changed);
+        }
+        Ok(index)
+    }
+}
+
+impl GCodeModel {
+    /// A handle to the line at `index`, or `None` if it is out of bounds
+    pub fn handle(&self, index: usize) -> Option<LineHandle> {
+        let line = self.lines.get(index)?;
+        Some(LineHandle {
+            id: line.id,
+            index,
+            stamp: stamp(self, line),
+        })
+    }
+}
+
+impl<'a> Query<'a> {
+    /// Matching lines as regions, one per contiguous run
+    pub fn regions(&self) -> Vec<Region> {
+        let model = self.model;
+        self.ranges()
+            .into_iter()
+            .filter_map(|range| model.region(range))
+            .collect()
+    }
+}
+
+#[test]
+fn region_test() {
+    let input = "G1 Z0.2 F600\nG1 X10 E1\nG1 Z0.4\nG1 X0 E2\nG1 X10 E3\nG1 Z0.6\nG1 X0 E4";
+    let mut gcode: GCodeModel = input.parse().unwrap();
+    let layers = gcode.layer_regions();
+    assert_eq!(layers[1].resolve(&gcode), Some(2..5));
+
+    // cutting the first layer doesn't move the second
+    gcode.lines.drain(0..2);
+    assert_eq!(layers[1].resolve(&gcode), Some(0..3));
+    assert_eq!(layers[0].resolve(&gcode), None);
+    // a line inserted inside the region becomes part of it
+    let inserted = gcode.lines[0].clone();
+    let id = crate::Id(100);
+    gcode.lines.insert(1, crate::GCodeLine { id, ..inserted });
+    assert_eq!(layers[1].resolve(&gcode), Some(0..4));
+    assert!(layers[1].contains(&gcode, id));
+    assert!(!layers[2].contains(&gcode, id));
+
+    let extrusions = gcode.select().tag(crate::Tag::Extrusion).regions();
+    assert_eq!(extrusions.len(), 2);
+}
+
+#[test]
+fn line_handle_test() {
+    let mut gcode: GCodeModel = "G90\nG1 X10 ; wall\nG1 X20".parse().unwrap();
+    let handle = gcode.handle(1).unwrap();
+    assert_eq!(handle.resolve(&gcode), Ok(1));
+    gcode.lines.remove(0);
+    assert_eq!(handle.resolve(&gcode), Ok(0));
+    gcode.lines[0].comments = String::from(" infill");
+    assert_eq!(handle.resolve(&gcode), Err(Stale::Changed));
+    gcode.lines.remove(0);
+    assert_eq!(handle.resolve(&gcode), Err(Stale::Removed));
+
+    // ids restart when a file is parsed again
+    let reparsed: GCodeModel = "G90\nG1 X30\nG1 X20".parse().unwrap();
+    assert_eq!(reparsed.lines[1].id, handle.id);
+    assert_eq!(handle.resolve(&reparsed), Err(Stale::Changed));
+
+    // comments kept apart from their lines count too
+    let config = crate::ParserConfig {
+        comments: crate::CommentMode::Sidecar,
+        ..Default::default()
+    };
+    let mut sidecar = GCodeModel::parse_with_config("G90\nG1 X10 ; wall", &config).unwrap();
+    let handle = sidecar.handle(1).unwrap();
+    assert_eq!(handle.resolve(&sidecar), Ok(1));
+    sidecar.comments.insert(handle.id, String::from(" infill"));
+    assert_eq!(handle.resolve(&sidecar), Err(Stale::Changed));
+}
@@ -0,0 +1,252 @@
+use crate::{
+    batch::Stats,
+    diagnostic::{Diagnostic, Location},
+    geometry::Bounds,
+    header::hms,
+    layers::Layer,
+    state::Position,
+    GCodeModel, Tag,
+};
+use std::fmt::Write;
+
+/// Width of each layer preview in pixels
+const PREVIEW_SIZE: f64 = 240.0;
+
+/// Layers previewed by `to_html_with_previews`: first, middle and last
+const PREVIEWS: usize = 3;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em}table{border-collapse:collapse;margin-bottom:2em}td,th{border:1px solid #ccc;padding:4px 8px;text-align:left}.error{color:#b00}.warning{color:#a60}figure{display:inline-block;margin:0 1em 1em 0}svg{border:1px solid #ccc;background:#fafafa}";
+
+/// `s` with the characters HTML treats specially escaped
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out += "&amp;",
+            '<' => out += "&lt;",
+            '>' => out += "&gt;",
+            '"' => out += "&quot;",
+            '\'' => out += "&#39;",
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn stats_table(stats: &Stats) -> String {
+    let mut rows = vec![
+        ("Lines", stats.lines.to_string()),
+        ("Layers", stats.layers.to_string()),
+        ("Estimated time", hms(stats.estimated_time)),
+        ("Filament", format!("{:.2} mm", stats.filament)),
+    ];
+    if let Some(Bounds { min, max }) = stats.bounds {
+        let size = format!(
+            "{} × {} × {} mm",
+            max.x - min.x,
+            max.y - min.y,
+            max.z - min.z
+        );
+        rows.push(("Size", size));
+    }
+    let compat = &stats.compatibility;
+    if let Some(model) = &compat.printer_model {
+        rows.push(("Printer", model.clone()));
+    }
+    if let Some(nozzle) = compat.nozzle_diameter {
+        rows.push(("Nozzle", format!("{nozzle} mm")));
+    }
+    let mut out = String::from("<h2>Summary</h2><table>");
+    for (name, value) in rows {
+        let _ = write!(out, "<tr><th>{name}</th><td>{}</td></tr>", escape(&value));
+    }
+    out + "</table>"
+}
+
+fn diagnostics_table(diagnostics: &[Diagnostic], model: Option<&GCodeModel>) -> String {
+    if diagnostics.is_empty() {
+        return String::from("<h2>Diagnostics</h2><p>No problems found.</p>");
+    }
+    let line = |location: &Location| match location {
+        Location::Line(id) => model
+            .and_then(|model| model.lines.iter().position(|line| line.id == *id))
+            .map_or_else(|| format!("id {}", id.get()), |i| (i + 1).to_string()),
+        Location::Span(span) => format!("bytes {}..{}", span.start, span.end),
+        Location::File => String::new(),
+    };
+    let mut out = String::from(
+        "<h2>Diagnostics</h2><table><tr><th>Severity</th><th>Code</th><th>Line</th><th>Message</th></tr>",
+    );
+    for d in diagnostics {
+        let _ = write!(
+            out,
+            "<tr class=\"{0}\"><td>{0}</td><td>{1}</td><td>{2}</td><td>{3}</td></tr>",
+            d.severity,
+            escape(&d.code),
+            line(&d.location),
+            escape(&d.message)
+        );
+    }
+    out + "</table>"
+}
+
+/// An SVG drawing of the extrusion moves of `layer` from above, scaled so
+/// `bounds` fills the picture
+pub fn layer_svg(model: &GCodeModel, layer: &Layer, bounds: &Bounds) -> String {
+    svg(model, layer, bounds, false)
+}
+
+/// `layer_svg` with the travels drawn dashed over the extrusions, filled
+/// dots where the filament is retracted and hollow ones where it is pushed
+/// back, to see how the toolhead gets between the parts of a layer when
+/// tracking down stringing
+pub fn travel_svg(model: &GCodeModel, layer: &Layer, bounds: &Bounds) -> String {
+    svg(model, layer, bounds, true)
+}
+
+fn svg(model: &GCodeModel, layer: &Layer, bounds: &Bounds, travels: bool) -> String {
+    let (x0, y0) = (bounds.min.x.to_mm(), bounds.min.y.to_mm());
+    let width = (bounds.max.x.to_mm() - x0).max(1.0);
+    let depth = (bounds.max.y.to_mm() - y0).max(1.0);
+    let scale = PREVIEW_SIZE / width.max(depth);
+    // SVG y grows downwards, so flip it to look at the bed from above
+    let point = |pos: Position| {
+        let (x, y) = (pos.x.to_mm(), pos.y.to_mm());
+        ((x - x0) * scale, (depth - (y - y0)) * scale)
+    };
+    let (mut path, mut travel, mut dots) = (String::new(), String::new(), String::new());
+    let (mut extruding_at, mut travelling_at) = (None, None);
+    for step in model
+        .cursor()
+        .skip(layer.start)
+        .take(layer.end - layer.start)
+    {
+        let (from, to) = (step.prev.pos, step.next.pos);
+        let (d, last) = match step.line.command.tag() {
+            Tag::Extrusion => (&mut path, &mut extruding_at),
+            Tag::Travel if travels => (&mut travel, &mut travelling_at),
+            // a wipe retracts from where it starts
+            tag @ (Tag::Retraction | Tag::Wipe | Tag::DeRetraction) if travels => {
+                let (pos, fill) = match tag {
+                    Tag::Wipe => (from, "#c00"),
+                    Tag::Retraction => (to, "#c00"),
+                    _ => (to, "none"),
+                };
+                let (x, y) = point(pos);
+                let _ = write!(
+                    dots,
+                    "<circle cx=\"{x:.2}\" cy=\"{y:.2}\" r=\"2\" fill=\"{fill}\" stroke=\"#c00\" stroke-width=\"0.5\"/>"
+                );
+                continue;
+            }
+            _ => continue,
+        };
+        if *last != Some(from) {
+            let (x, y) = point(from);
+            let _ = write!(d, "M{x:.2} {y:.2}");
+        }
+        let (x, y) = point(to);
+        let _ = write!(d, "L{x:.2} {y:.2}");
+        *last = Some(to);
+    }
+    let mut out = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w:.0}\" height=\"{h:.0}\" viewBox=\"0 0 {w:.2} {h:.2}\"><path d=\"{path}\" fill=\"none\" stroke=\"#e65c00\" stroke-width=\"1\" stroke-linejoin=\"round\"/>",
+        w = width * scale,
+        h = depth * scale,
+    );
+    if travels {
+        let _ = write!(
+            out,
+            "<path d=\"{travel}\" fill=\"none\" stroke=\"#36c\" stroke-width=\"0.75\" stroke-dasharray=\"3 2\"/>{dots}"
+        );
+    }
+    out + "</svg>"
+}
+
+fn page(body: &str) -> String {
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>G-code report</title><style>{STYLE}</style></head><body><h1>G-code report</h1>{body}</body></html>\n"
+    )
+}
+
+/// A standalone HTML page with a table of `stats` and one of `diagnostics`
+pub fn to_html(stats: &Stats, diagnostics: &[Diagnostic]) -> String {
+    page(&(stats_table(stats) + &diagnostics_table(diagnostics, None)))
+}
+
+/// `to_html` with line numbers for the diagnostics and SVG previews of the
+/// first, middle and last layers of `model`, which `stats` describes
+pub fn to_html_with_previews(
+    stats: &Stats,
+    diagnostics: &[Diagnostic],
+    model: &GCodeModel,
+) -> String {
+    let mut body = stats_table(stats) + &diagnostics_table(diagnostics, Some(model));
+    let layers = model.layers();
+    if let (Some(bounds), false) = (stats.bounds, layers.is_empty()) {
+        let last = layers.len() - 1;
+        let mut picks = (0..PREVIEWS)
+            .map(|n| n * last / (PREVIEWS - 1))
+            .collect::<Vec<_>>();
+        picks.dedup();
+        body += "<h2>Layers</h2>";
+        for i in picks {
+            let layer = &layers[i];
+            let _ = write!(
+                body,
+                "<figure>{}<figcaption>Layer {} at {} mm</figcaption></figure>",
+                layer_svg(model, layer, &bounds),
+                i + 1,
+                layer.z
+            );
+        }
+    }
+    page(&body)
+}
+
+#[test]
+fn html_report_test() {
+    use crate::diagnostic::Severity;
+    let gcode: GCodeModel = "G1 Z0.2 F600\nG1 X10 E1\nG1 Y10 E2\nG1 Z0.4\nG1 X0 E3"
+        .parse()
+        .unwrap();
+    let stats = gcode.stats(None);
+    let diagnostics = [Diagnostic {
+        severity: Severity::Warning,
+        code: String::from("lint/hotend-left-on"),
+        message: String::from("hotend <on>"),
+        location: Location::Line(gcode.lines[2].id),
+    }];
+    let html = to_html(&stats, &diagnostics);
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<tr><th>Layers</th><td>2</td></tr>"));
+    assert!(html.contains("<td>hotend &lt;on&gt;</td>"));
+    assert!(!html.contains("<svg"));
+
+    let html = to_html_with_previews(&stats, &diagnostics, &gcode);
+    assert!(html.contains("<td>lint/hotend-left-on</td><td>3</td>"));
+    assert_eq!(html.matches("<svg").count(), 2);
+    let bounds = stats.bounds.unwrap();
+    assert!(layer_svg(&gcode, &gcode.layers()[0], &bounds)
+        .contains("d=\"M0.00 240.00L240.00 240.00L240.00 0.00\""));
+}
+
+#[test]
+fn travel_svg_test() {
+    let gcode: GCodeModel =
+        "G1 Z0.2 F600\nG1 X10 E1\nG1 E0.2\nG1 X0 Y10\nG1 E1\nG1 X10 E2\nG1 Y0 E1.5"
+            .parse()
+            .unwrap();
+    let layer = &gcode.layers()[0];
+    let bounds = gcode.stats(None).bounds.unwrap();
+    let plain = layer_svg(&gcode, layer, &bounds);
+    assert!(!plain.contains("stroke-dasharray"));
+    let svg = travel_svg(&gcode, layer, &bounds);
+    assert!(svg.starts_with(&plain[..plain.len() - "</svg>".len()]));
+    assert!(svg.contains("d=\"M240.00 240.00L0.00 0.00\" fill=\"none\" stroke=\"#36c\""));
+    // the retraction, the wipe's start and the hollow de-retraction
+    assert_eq!(svg.matches("<circle").count(), 3);
+    assert_eq!(svg.matches("fill=\"#c00\"").count(), 2);
+    assert!(svg.contains("<circle cx=\"0.00\" cy=\"0.00\" r=\"2\" fill=\"none\""));
+    assert!(svg.ends_with("</svg>"));
+}
@@ -0,0 +1,197 @@
+use crate::{
+    state::Positioning, Annotations, Command, GCodeLine, GCodeModel, Home, Id, Length, G1, G92,
+    M106,
+};
+
+/// Where a print stopped
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ResumeAt {
+    /// Reprint the first layer at or above this height
+    Z(Length),
+    /// Pick up from this line
+    Line(Id),
+}
+
+/// How to find X and Y again without touching the part
+#[derive(Clone, Debug, PartialEq)]
+pub enum Homing {
+    /// `G28 X Y`, leaving Z where it stopped
+    XyOnly,
+    /// A firmware specific routine, e.g. sensorless homing with reduced
+    /// current, inserted verbatim
+    Custom(String),
+}
+
+impl GCodeModel {
+    /// Build a file that finishes an interrupted print. It reheats, raises
+    /// the nozzle by `z_hop` off the part, homes X and Y, restores the
+    /// positioning modes, position and extruder state from before the
+    /// resume point, then carries on with the rest of the lines. Z is never
+    /// homed, so the nozzle must still be at the height it stopped at.
+    /// `None` if the resume point isn't in the file.
+    pub fn resume_from(&self, at: ResumeAt, homing: &Homing, z_hop: Length) -> Option<GCodeModel> {
+        let index = match at {
+            ResumeAt::Z(z) => self.layers().into_iter().find(|layer| layer.z >= z)?.start,
+            ResumeAt::Line(id) => self.lines.iter().position(|line| line.id == id)?,
+        };
+        let state = self
+            .cursor()
+            .nth(index)
+            .map(|step| step.prev)
+            .unwrap_or_default();
+        let (hotend, bed) = (state.temps.hotend, state.temps.bed);
+
+        let mut commands = Vec::new();
+        let raw = |s: String| Command::Raw(s);
+        if let Some(bed) = bed {
+            commands.push(raw(format!("M140 S{bed}")));
+        }
+        if let Some(hotend) = hotend {
+            commands.push(raw(format!("M104 S{hotend}")));
+        }
+        if let Some(bed) = bed {
+            commands.push(raw(format!("M190 S{bed}")));
+        }
+        if let Some(hotend) = hotend {
+            commands.push(raw(format!("M109 S{hotend}")));
+        }
+        // the nozzle is where it stopped, so declare that height, in the
+        // coordinates the rest of the file uses, and lift off the part
+        let offset = state.work_offset();
+        commands.push(Command::G92(G92 {
+            z: Some(state.pos.z - offset.z),
+            ..Default::default()
+        }));
+        commands.push(Command::G90);
+        let safe_z = state.pos.z - offset.z + z_hop;
+        commands.push(Command::G1(G1 {
+            z: Some(safe_z),
+            ..Default::default()
+        }));
+        commands.push(match homing {
+            Homing::XyOnly => Command::Home(Home {
+                x: true,
+                y: true,
+                ..Default::default()
+            }),
+            Homing::Custom(routine) => raw(routine.clone()),
+        });
+        if state.fan > 0 {
+            commands.push(Command::M106(M106 {
+                index: None,
+                speed: Some(state.fan),
+            }));
+        }
+        commands.push(Command::G1(G1 {
+            x: Some(state.pos.x - offset.x),
+            y: Some(state.pos.y - offset.y),
+            f: Some(state.feedrate),
+            ..Default::default()
+        }));
+        commands.push(Command::G1(G1 {
+            z: Some(state.pos.z - offset.z),
+            ..Default::default()
+        }));
+        commands.push(match state.e_positioning {
+            Positioning::Absolute => Command::M82,
+            Positioning::Relative => Command::M83,
+        });
+        if state.e_positioning == Positioning::Absolute {
+            commands.push(Command::G92(G92 {
+                e: Some(state.e),
+                ..Default::default()
+            }));
+        }
+        if state.positioning == Positioning::Relative {
+            commands.push(Command::G91);
+        }
+
+        let mut resumed = GCodeModel {
+            line_ending: self.line_ending,
+            ..Default::default()
+        };
+        for command in commands {
+            resumed.lines.push(GCodeLine {
+                id: resumed.id_counter.get(),
+                command,
+                comments: String::new(),
+                annotations: Annotations::default(),
+            });
+        }
+        if let Some(first) = resumed.lines.first_mut() {
+            first.comments = format!(
+                " resume from line {}",
+                self.emit_line(&self.lines[index], false)
+            );
+        }
+        for line in &self.lines[index..] {
+            let id = resumed.id_counter.get();
+            if let Some(comments) = self.comments.get(&line.id) {
+                resumed.comments.insert(id, comments.clone());
+            }
+            resumed.lines.push(GCodeLine { id, ..line.clone() });
+        }
+        resumed.tag_g1();
+        Some(resumed)
+    }
+}
+
+#[test]
+fn resume_test() {
+    use crate::emit::Emit;
+    let input = "M140 S60\nM104 S215\nG28\nM190 S60\nM109 S215\nM104 S210\nG1 Z0.2 F1200\nG1 X10 Y10\nG1 X20 E1\nG1 Z0.4\nG1 X10 E2\nG1 Z0.6\nG1 X20 E3";
+    let gcode: GCodeModel = input.parse().unwrap();
+    let resumed = gcode
+        .resume_from(
+            ResumeAt::Z(Length::from(0.3)),
+            &Homing::XyOnly,
+            Length::from(5.0),
+        )
+        .unwrap();
+    let emitted = resumed.emit(false);
+    assert!(emitted.starts_with("M140 S60; resume from line G1 Z0.4 \nM104 S210\nM190 S60\nM109 S210\nG92 Z0.2\nG90\nG1 Z5.2 \nG28 X Y \nG1 X20 Y10 F1200 \nG1 Z0.2 \nM82\nG92 E1\nG1 Z0.4 \n"));
+    // picking up from the same line ends up in the same place, as written
+    // since the resumed file declares its height with G92
+    let written = |state: crate::state::MachineState| {
+        let (pos, offset) = (state.pos, state.work_offset());
+        let axes = [pos.x - offset.x, pos.y - offset.y, pos.z - offset.z];
+        (axes, state.feedrate)
+    };
+    let original = gcode.cursor().last().unwrap().next;
+    let end = resumed.cursor().last().unwrap().next;
+    assert_eq!(written(end), written(original));
+
+    let id = gcode.lines[10].id;
+    let resumed = gcode
+        .resume_from(
+            ResumeAt::Line(id),
+            &Homing::Custom(String::from("G28 X")),
+            Length::ZERO,
+        )
+        .unwrap();
+    assert_eq!(resumed.lines.len(), 12 + 3);
+    assert!(gcode
+        .resume_from(
+            ResumeAt::Z(Length::from(1.0)),
+            &Homing::XyOnly,
+            Length::ZERO
+        )
+        .is_none());
+
+    // a G92 shift and sidecar comments carry over, as do CRLF line endings
+    let config = crate::ParserConfig {
+        comments: crate::CommentMode::Sidecar,
+        ..Default::default()
+    };
+    let input = "G1 Z0.2 F1200\r\nG92 Z0\r\nG1 X10 E1\r\nG1 Z0.4\r\n;TYPE:Wall\r\nG1 X20 E2\r\n";
+    let gcode = GCodeModel::parse_with_config(input, &config).unwrap();
+    let id = gcode.lines[4].id;
+    let resumed = gcode
+        .resume_from(ResumeAt::Line(id), &Homing::XyOnly, Length::from(5.0))
+        .unwrap();
+    let emitted = resumed.emit(false);
+    assert!(emitted.starts_with("G92 Z0.4; resume from line ;TYPE:Wall\r\nG90\r\nG1 Z5.4 \r\n"));
+    assert!(emitted.ends_with("G1 Z0.4 \r\nM82\r\nG92 E1\r\n;TYPE:Wall\r\nG1 X20 E2 \r\n"));
+    let end = resumed.cursor().last().unwrap().next;
+    assert_eq!(written(end), written(gcode.cursor().last().unwrap().next));
+}
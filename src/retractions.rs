@@ -0,0 +1,308 @@
+use crate::{
+    parsers::split_raw, state::MachineState, Annotations, Command, ExtrusionLength, Feedrate,
+    GCodeLine, GCodeModel, Length, Tag, G1,
+};
+use std::{collections::BTreeSet, ops::Range};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Thresholds for `GCodeModel::normalize_retractions`
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RetractionRules {
+    /// travels this short or shorter lose their retraction
+    pub skip_up_to: Length,
+    /// travels this long or longer get a retraction if they don't have one
+    pub retract_from: Length,
+    /// filament pulled back by an added retraction
+    pub length: ExtrusionLength,
+    /// feedrate of added retractions and de-retractions
+    pub speed: Feedrate,
+}
+
+impl Default for RetractionRules {
+    fn default() -> Self {
+        RetractionRules {
+            skip_up_to: Length::from(1.0),
+            retract_from: Length::from(2.0),
+            length: ExtrusionLength::from_mm(0.8),
+            speed: Feedrate::from_mm_per_min(2100.0),
+        }
+    }
+}
+
+/// The lines between two extrusions
+struct Gap {
+    range: Range<usize>,
+    /// XY distance travelled in mm
+    travel: f64,
+    retractions: Vec<usize>,
+    de_retractions: Vec<usize>,
+    /// nothing but the retractions and de-retractions touches the
+    /// extruder, so they can be taken out or added without changing
+    /// any other line's E
+    plain: bool,
+}
+
+/// The feedrate a move sets
+fn feedrate(command: &Command) -> Option<Feedrate> {
+    match command {
+        Command::G0(g1) | Command::G1(g1) | Command::G53(Some(g1), _) => g1.f,
+        Command::G5(g5) => g5.f,
+        Command::Arc(arc) => arc.f,
+        _ => None,
+    }
+}
+
+/// The F word of a move, if `command` is one
+fn feedrate_mut(command: &mut Command) -> Option<&mut Option<Feedrate>> {
+    match command {
+        Command::G0(g1) | Command::G1(g1) | Command::G53(Some(g1), _) => Some(&mut g1.f),
+        Command::G5(g5) => Some(&mut g5.f),
+        Command::Arc(arc) => Some(&mut arc.f),
+        _ => None,
+    }
+}
+
+/// Whether `command` writes an E value or otherwise works the extruder
+fn touches_e(command: &Command) -> bool {
+    match command {
+        Command::G0(g1) | Command::G1(g1) | Command::G53(Some(g1), _) => g1.e.is_some(),
+        Command::G5(g5) => g5.e.is_some(),
+        Command::Arc(arc) => arc.e.is_some(),
+        Command::G92(_) => true,
+        // firmware retraction
+        Command::Raw(raw) => split_raw(raw).is_some_and(|(word, _)| word == "G10" || word == "G11"),
+        _ => false,
+    }
+}
+
+fn moves(step: &(MachineState, MachineState)) -> bool {
+    step.0.pos != step.1.pos || step.0.e != step.1.e
+}
+
+impl GCodeModel {
+    /// Make retractions depend only on the length of the travel they go
+    /// with, for files merged from slicers or profiles that disagree. The
+    /// retraction and de-retraction around a travel no longer than
+    /// `rules.skip_up_to` are taken out, and a travel at least
+    /// `rules.retract_from` long without either gets them added. Returns
+    /// the number of travels that lost and gained a retraction.
+    ///
+    /// Travels with a wipe, firmware retraction (G10/G11), a `G92` or any
+    /// other line setting E are left alone, as are retractions the
+    /// de-retraction doesn't exactly undo, so the E of every other line
+    /// stays as it was.
+    pub fn normalize_retractions(&mut self, rules: &RetractionRules) -> (usize, usize) {
+        self.record_transform("normalize_retractions", format!("{rules:?}"));
+        let states = self
+            .cursor()
+            .map(|step| (step.prev, step.next))
+            .collect::<Vec<_>>();
+        let gaps = self.gaps(&states);
+        let (mut removed, mut added) = (0, 0);
+        // last gap first, since adding or removing lines shifts everything after
+        for gap in gaps.iter().rev().filter(|gap| gap.plain) {
+            let retracted = !gap.retractions.is_empty() || !gap.de_retractions.is_empty();
+            if retracted && gap.travel <= rules.skip_up_to.to_mm() {
+                if self.remove_retraction(gap, &states) {
+                    removed += 1;
+                }
+            } else if !retracted && gap.travel >= rules.retract_from.to_mm() {
+                self.add_retraction(gap, &states, rules);
+                added += 1;
+            }
+        }
+        if removed + added > 0 {
+            self.tag_g1();
+        }
+        (removed, added)
+    }
+    fn gaps(&self, states: &[(MachineState, MachineState)]) -> Vec<Gap> {
+        let mut gaps = Vec::new();
+        let mut last = None;
+        for (i, line) in self.lines.iter().enumerate() {
+            if line.command.tag() != Tag::Extrusion {
+                continue;
+            }
+            if let Some(start) = last.filter(|&start| start < i) {
+                let mut gap = Gap {
+                    range: start..i,
+                    travel: 0.0,
+                    retractions: Vec::new(),
+                    de_retractions: Vec::new(),
+                    plain: true,
+                };
+                for j in gap.range.clone() {
+                    let command = &self.lines[j].command;
+                    let (prev, next) = states[j];
+                    match command.tag() {
+                        Tag::Travel => {
+                            let dx = (next.pos.x - prev.pos.x).to_mm();
+                            let dy = (next.pos.y - prev.pos.y).to_mm();
+                            gap.travel += dx.hypot(dy);
+                        }
+                        Tag::Retraction if command.as_move().is_some() => {
+                            gap.retractions.push(j);
+                            continue;
+                        }
+                        Tag::DeRetraction if command.as_move().is_some() => {
+                            gap.de_retractions.push(j);
+                            continue;
+                        }
+                        Tag::Retraction | Tag::DeRetraction | Tag::Wipe => gap.plain = false,
+                        _ => {}
+                    }
+                    if touches_e(command) {
+                        gap.plain = false;
+                    }
+                }
+                gaps.push(gap);
+            }
+            last = Some(i + 1);
+        }
+        gaps
+    }
+    /// Take E off the retractions and de-retractions in `gap`, dropping the
+    /// lines left with nothing to do
+    fn remove_retraction(&mut self, gap: &Gap, states: &[(MachineState, MachineState)]) -> bool {
+        let lines = gap
+            .retractions
+            .iter()
+            .chain(&gap.de_retractions)
+            .copied()
+            .collect::<BTreeSet<_>>();
+        let net = lines
+            .iter()
+            .map(|&i| states[i].1.e - states[i].0.e)
+            .sum::<ExtrusionLength>();
+        if gap.retractions.is_empty()
+            || gap.de_retractions.is_empty()
+            || net != ExtrusionLength::ZERO
+        {
+            return false;
+        }
+        // decide which lines go before any are removed, while the indices
+        // still match `states`
+        let mut dropped = Vec::new();
+        for &i in &lines {
+            let Some(g1) = self.lines[i].command.as_move() else {
+                continue;
+            };
+            if g1.x.is_some() || g1.y.is_some() || g1.z.is_some() {
+                continue;
+            }
+            // an F left on its own still matters if the next move doesn't
+            // set its own
+            let next = (i + 1..self.lines.len()).find(|j| !lines.contains(j) && moves(&states[*j]));
+            let superseded = next.is_none_or(|j| feedrate(&self.lines[j].command).is_some());
+            if g1.f.is_none() || superseded {
+                dropped.push(i);
+            }
+        }
+        for &i in &lines {
+            if let Some(g1) = self.lines[i].command.as_move_mut() {
+                g1.e = None;
+            }
+        }
+        for i in dropped.into_iter().rev() {
+            self.lines.remove(i);
+        }
+        true
+    }
+    /// Retract before the first travel in `gap` and push the filament back
+    /// after the last
+    fn add_retraction(
+        &mut self,
+        gap: &Gap,
+        states: &[(MachineState, MachineState)],
+        rules: &RetractionRules,
+    ) {
+        let travels = gap
+            .range
+            .clone()
+            .filter(|&i| self.lines[i].command.tag() == Tag::Travel)
+            .collect::<Vec<_>>();
+        let (Some(&first), Some(&last)) = (travels.first(), travels.last()) else {
+            return;
+        };
+        // the moves after each inserted line keep the feedrate they had
+        let after = (last + 1..self.lines.len()).find(|&j| moves(&states[j]));
+        for j in after.into_iter().chain([first]) {
+            let feedrate = states[j].0.feedrate;
+            if let Some(f @ None) = feedrate_mut(&mut self.lines[j].command) {
+                if feedrate > Feedrate::ZERO {
+                    *f = Some(feedrate);
+                }
+            }
+        }
+        let end = states[last].1;
+        let mut retracted = end;
+        retracted.e = end.e - rules.length;
+        let de_retract = retracted.move_to(end.pos, Some(end.e));
+        let start = states[first].0;
+        let retract = start.move_to(start.pos, Some(start.e - rules.length));
+        for (i, g1, comment) in [
+            (last + 1, de_retract, " unretract"),
+            (first, retract, " retract"),
+        ] {
+            let line = GCodeLine {
+                id: self.id_counter.get(),
+                command: Command::G1(G1 {
+                    f: Some(rules.speed),
+                    ..g1
+                }),
+                comments: String::from(comment),
+                annotations: Annotations::default(),
+            };
+            self.lines.insert(i, line);
+        }
+    }
+}
+
+#[test]
+fn normalize_retractions_test() {
+    use crate::emit::Emit;
+    let rules = RetractionRules::default();
+    // a retraction for a 0.5mm hop and none for a 20mm travel
+    let mut gcode: GCodeModel = "M83\nG1 X10 E1 F1200\nG1 E-0.8 F2100\nG1 X10.5 F9000\nG1 E0.8 F2100\nG1 X20 E1 F1200\nG1 X40 F9000\nG1 X50 E1 F1200"
+        .parse()
+        .unwrap();
+    let filament = gcode.filament_used();
+    assert_eq!(gcode.normalize_retractions(&rules), (1, 1));
+    assert_eq!(
+        gcode.emit(false),
+        "M83\nG1 X10 E1 F1200 \nG1 X10.5 F9000 \nG1 X20 E1 F1200 \nG1 E-0.8 F2100 ; retract\nG1 X40 F9000 \nG1 E0.8 F2100 ; unretract\nG1 X50 E1 F1200 \n"
+    );
+    assert_eq!(gcode.filament_used(), filament);
+
+    // in absolute E, and keeping the feedrates the moves had
+    let mut gcode: GCodeModel = "G1 X10 E1 F1200\nG1 X40\nG1 X50 E2".parse().unwrap();
+    assert_eq!(gcode.normalize_retractions(&rules), (0, 1));
+    assert_eq!(
+        gcode.emit(false),
+        "G1 X10 E1 F1200 \nG1 E0.2 F2100 ; retract\nG1 X40 F1200 \nG1 E1 F2100 ; unretract\nG1 X50 E2 F1200 \n"
+    );
+    assert_eq!(gcode.lines[1].command.tag(), Tag::Retraction);
+
+    // an F left behind by a removed retraction is kept if it's still needed
+    let mut gcode: GCodeModel =
+        "M83\nG1 X10 E1 F1200\nG1 E-0.8 F2100\nG1 X10.5\nG1 E0.8\nG1 X20 E1"
+            .parse()
+            .unwrap();
+    assert_eq!(gcode.normalize_retractions(&rules), (1, 0));
+    assert_eq!(
+        gcode.emit(false),
+        "M83\nG1 X10 E1 F1200 \nG1 F2100 \nG1 X10.5 \nG1 X20 E1 \n"
+    );
+
+    // firmware retraction, and a prime that adds to the retraction
+    let mut gcode: GCodeModel =
+        "G1 X10 E1\nG10\nG1 X40\nG11\nG1 X50 E2\nG1 E1.6\nG1 X50.5\nG1 E2.5\nG1 X60 E3"
+            .parse()
+            .unwrap();
+    let before = gcode.clone();
+    assert_eq!(gcode.normalize_retractions(&rules), (0, 0));
+    assert_eq!(gcode.lines, before.lines);
+}
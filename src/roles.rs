@@ -0,0 +1,135 @@
+use crate::{GCodeLine, GCodeModel, Tag};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// What a line contributes to the print, from the slicer's feature
+/// comments combined with the motion level `Tag`
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Role {
+    OuterWall,
+    InnerWall,
+    /// sparse and solid infill, top and bottom surfaces and gap fill
+    Infill,
+    Support,
+    Skirt,
+    Bridge,
+    /// extrusion in a feature that isn't one of the above, or in a file
+    /// without feature comments
+    OtherExtrusion,
+    /// travel, retraction and other moves that don't extrude
+    NonPrinting,
+    /// lines that don't move the toolhead or extruder
+    #[default]
+    None,
+}
+
+impl Role {
+    /// Role of extrusions in a feature as named by PrusaSlicer, OrcaSlicer
+    /// or Cura, e.g. `External perimeter`, `Sparse infill` or `WALL-INNER`
+    pub fn from_feature(feature: &str) -> Role {
+        let feature = feature.to_lowercase();
+        let has = |words: &[&str]| words.iter().any(|word| feature.contains(word));
+        if has(&["bridge"]) {
+            Role::Bridge
+        } else if has(&["skirt", "brim"]) {
+            Role::Skirt
+        } else if has(&["support"]) {
+            Role::Support
+        } else if has(&["external perimeter", "outer wall", "wall-outer", "overhang"]) {
+            Role::OuterWall
+        } else if has(&["perimeter", "inner wall", "wall-inner"]) {
+            Role::InnerWall
+        } else if has(&["fill", "skin", "surface"]) {
+            Role::Infill
+        } else {
+            Role::OtherExtrusion
+        }
+    }
+}
+
+impl GCodeModel {
+    /// Slicer feature named by a `;TYPE:` comment, as written by
+    /// PrusaSlicer, OrcaSlicer and Cura
+    pub(crate) fn feature<'a>(&'a self, line: &'a GCodeLine) -> Option<&'a str> {
+        self.comment(line)
+            .trim()
+            .strip_prefix("TYPE:")
+            .map(str::trim)
+    }
+    /// Role of every line, using the feature from the last `;TYPE:` comment
+    /// for extrusion moves. Relies on the tags from `tag_g1`.
+    pub fn roles(&self) -> Vec<Role> {
+        let mut current = Role::OtherExtrusion;
+        self.cursor()
+            .map(|step| {
+                if let Some(feature) = self.feature(step.line) {
+                    current = Role::from_feature(feature);
+                }
+                if step.line.command.tag() == Tag::Extrusion {
+                    current
+                } else if step.prev.pos != step.next.pos || step.prev.e != step.next.e {
+                    Role::NonPrinting
+                } else {
+                    Role::None
+                }
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn roles_test() {
+    let input = "G1 Z0.2\nG1 X5 E1\n;TYPE:Skirt/Brim\nG1 X10 E2\n;TYPE:External perimeter\nG1 Y10 E3\n;TYPE:Perimeter\nG1 X0 E4\nG1 E3.2\n;TYPE:Bridge infill\nG1 Y0 E5\n;TYPE:SUPPORT\nG1 X5 E6\n;TYPE:Top solid infill\nG1 X6 E7";
+    let gcode: GCodeModel = input.parse().unwrap();
+    let roles = gcode.roles();
+    assert_eq!(
+        roles,
+        [
+            Role::NonPrinting,
+            Role::OtherExtrusion,
+            Role::None,
+            Role::Skirt,
+            Role::None,
+            Role::OuterWall,
+            Role::None,
+            Role::InnerWall,
+            Role::NonPrinting,
+            Role::None,
+            Role::Bridge,
+            Role::None,
+            Role::Support,
+            Role::None,
+            Role::Infill,
+        ]
+    );
+    assert_eq!(Role::from_feature("WALL-INNER"), Role::InnerWall);
+    assert_eq!(Role::from_feature("Sparse infill"), Role::Infill);
+}
+
+#[test]
+fn sidecar_comments_test() {
+    use crate::{CommentMode, ParserConfig};
+    let input = "; printer_model = MK4\n; bed_mesh default\n; 0.0125, 0.025\nG1 Z0.2\n;TYPE:Skirt/Brim\nG1 X10 E1 F1200\n;TYPE:Support material\nG1 Y10 E2\n;TYPE:External perimeter\nG1 X0 E3 F900";
+    let parse = |comments| {
+        let config = ParserConfig {
+            comments,
+            ..Default::default()
+        };
+        GCodeModel::parse_with_config(input, &config).unwrap()
+    };
+    let (inline, sidecar) = (parse(CommentMode::Inline), parse(CommentMode::Sidecar));
+    assert!(sidecar.lines.iter().all(|line| line.comments.is_empty()));
+    assert!(sidecar.roles().contains(&Role::OuterWall));
+    assert_eq!(sidecar.roles(), inline.roles());
+    assert_eq!(sidecar.skirt_ranges(), vec![4..6]);
+    assert_eq!(sidecar.support_ranges(None), inline.support_ranges(None));
+    assert_eq!(sidecar.feature_speeds().0.len(), 3);
+    assert_eq!(sidecar.feature_speeds(), inline.feature_speeds());
+    assert!(sidecar.bed_mesh().is_some());
+    assert_eq!(
+        sidecar.compatibility().printer_model.as_deref(),
+        Some("MK4")
+    );
+}
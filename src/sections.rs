@@ -0,0 +1,97 @@
+use crate::{
+    parsers::{raw_param, split_raw},
+    Command, GCodeModel, Tag,
+};
+use std::ops::Range;
+
+/// First word and parameters of a command kept as raw text
+fn split_command(command: &Command) -> Option<(String, &str)> {
+    match command {
+        Command::Raw(raw) => split_raw(raw),
+        _ => None,
+    }
+}
+
+/// Homing, leveling and heating commands that belong to start gcode
+fn is_setup(command: &Command) -> bool {
+    if matches!(
+        command,
+        Command::Home(_) | Command::G29(_) | Command::M420(_)
+    ) {
+        return true;
+    }
+    split_command(command).is_some_and(|(word, _)| {
+        matches!(
+            word.as_str(),
+            "M104" | "M109" | "M140" | "M190" | "M141" | "M191"
+        )
+    })
+}
+
+/// Homing, motor off and heater off commands that belong to end gcode
+fn is_teardown(command: &Command) -> bool {
+    if matches!(command, Command::Home(_)) {
+        return true;
+    }
+    split_command(command).is_some_and(|(word, rest)| match word.as_str() {
+        "M84" | "M18" => true,
+        "M104" | "M140" => raw_param(rest, 'S').is_none_or(|s| s == 0.0),
+        _ => false,
+    })
+}
+
+impl GCodeModel {
+    /// Lines of the start gcode, from the top of the file to the end of the
+    /// intro line or the last homing or heating command before the first
+    /// extrusion, whichever is later. Without either it runs up to the first
+    /// extrusion. `None` if nothing is extruded.
+    pub fn start_gcode_range(&self) -> Option<Range<usize>> {
+        let first = self
+            .lines
+            .iter()
+            .position(|line| line.command.tag() == Tag::Extrusion)?;
+        let setup = self.lines[..first]
+            .iter()
+            .rposition(|line| is_setup(&line.command))
+            .map(|i| i + 1);
+        let priming = self.priming_range().map(|range| range.end);
+        Some(0..setup.max(priming).unwrap_or(first))
+    }
+    /// Lines of the end gcode, from the first cooldown, homing or motors off
+    /// command after the last extrusion to the end of the file. Without any
+    /// of those it starts right after the last extrusion. `None` if nothing
+    /// is extruded.
+    pub fn end_gcode_range(&self) -> Option<Range<usize>> {
+        let last = self
+            .lines
+            .iter()
+            .rposition(|line| line.command.tag() == Tag::Extrusion)?;
+        let start = self.lines[last + 1..]
+            .iter()
+            .position(|line| is_teardown(&line.command))
+            .map_or(last + 1, |i| last + 1 + i);
+        Some(start..self.lines.len())
+    }
+    /// Lines between the start and end gcode, where the part itself is printed
+    pub fn body_range(&self) -> Option<Range<usize>> {
+        let start = self.start_gcode_range()?;
+        let end = self.end_gcode_range()?;
+        Some(start.end..end.start)
+    }
+}
+
+#[test]
+fn sections_test() {
+    let input = "M140 S60\nM104 S215\nG28\nM190 S60\nM109 S215\nG1 Z0.2 F720\nG1 Y-3 F1000\nG1 X60 E9\nG1 X100 E12.5\n;TYPE:Perimeter\nG1 X20 Y20\nG1 X30 E13\nG1 E12 ; retract\nG1 Z10\nM104 S0\nM140 S0\nM84";
+    let gcode: GCodeModel = input.parse().unwrap();
+    // the intro line is part of the start gcode
+    assert_eq!(gcode.start_gcode_range(), Some(0..9));
+    assert_eq!(gcode.end_gcode_range(), Some(14..17));
+    assert_eq!(gcode.body_range(), Some(9..14));
+
+    let gcode: GCodeModel = "G28\nG1 Z0.2\nG1 X10 E1\nG1 Z5\nM104 S200".parse().unwrap();
+    assert_eq!(gcode.start_gcode_range(), Some(0..1));
+    // setting a temperature isn't cooling down
+    assert_eq!(gcode.end_gcode_range(), Some(3..5));
+    assert_eq!("G1 X10".parse::<GCodeModel>().unwrap().body_range(), None);
+}
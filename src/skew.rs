@@ -0,0 +1,64 @@
+use crate::{state::Position, GCodeModel, Length, G1};
+
+/// Where the firmware would send the toolhead for a target of `pos`, with
+/// skew factors as set by Marlin's `M852 I J K`
+fn skew(pos: Position, xy: f64, xz: f64, yz: f64) -> Position {
+    let (x, y, z) = (pos.x.to_mm(), pos.y.to_mm(), pos.z.to_mm());
+    Position {
+        x: Length::from(x - y * xy - z * (xz - xy * yz)),
+        y: Length::from(y - z * yz),
+        z: pos.z,
+    }
+}
+
+impl GCodeModel {
+    /// Correct for a frame whose axes aren't square by moving every G0
+    /// and G1 target the way firmware skew correction (`M852`) would, for printers
+    /// whose firmware lacks it. `xy`, `xz` and `yz` are the tangents of the
+    /// skew angle in each plane, as for `M852 I J K`. A move along Y or Z
+    /// can gain an X or Y word. Returns the number of moves changed.
+    pub fn apply_skew_correction(&mut self, xy: f64, xz: f64, yz: f64) -> usize {
+        self.record_transform("apply_skew_correction", format!("{xy}, {xz}, {yz}"));
+        let moves = self
+            .cursor()
+            .map(|step| {
+                let g1 = step.line.command.as_move()?;
+                let mut from = step.prev;
+                from.pos = skew(step.prev.pos, xy, xz, yz);
+                let to = from.move_to(skew(step.next.pos, xy, xz, yz), None);
+                let corrected = G1 {
+                    x: to.x,
+                    y: to.y,
+                    z: to.z,
+                    ..g1.clone()
+                };
+                (corrected != *g1).then_some(corrected)
+            })
+            .collect::<Vec<_>>();
+        let mut changed = 0;
+        for (line, corrected) in self.lines.iter_mut().zip(moves) {
+            if let (Some(g1), Some(corrected)) = (line.command.as_move_mut(), corrected) {
+                *g1 = corrected;
+                changed += 1;
+            }
+        }
+        if changed > 0 {
+            self.tag_g1();
+        }
+        changed
+    }
+}
+
+#[test]
+fn skew_correction_test() {
+    use crate::emit::Emit;
+    let mut gcode: GCodeModel = "G28\nG1 X10 Y10 Z1 F600\nG1 Y20 E1\nG91\nG1 X5 E1\nG1 Z1"
+        .parse()
+        .unwrap();
+    assert_eq!(gcode.apply_skew_correction(0.0, 0.0, 0.0), 0);
+    assert_eq!(gcode.apply_skew_correction(0.01, 0.0, 0.02), 3);
+    assert_eq!(
+        gcode.emit(false),
+        "G28\nG1 X9.9002 Y9.98 Z1 F600 \nG1 X9.8002 Y19.98 E1 \nG91\nG1 X5 E1 \nG1 X0.0002 Y-0.02 Z1 \n"
+    );
+}
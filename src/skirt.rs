@@ -0,0 +1,343 @@
+use crate::{
+    geometry::Bounds,
+    profile::PrinterProfile,
+    roles::Role,
+    state::{Position, Positioning, Step},
+    Annotations, Command, ExtrusionLength, GCodeLine, GCodeModel, Length, Tag, G1, G92,
+};
+use std::ops::Range;
+
+/// How far apart the ends of a run can be for it to count as a closed loop,
+/// in mm, since slicers often leave a small gap at the seam
+pub(crate) const SEAM_GAP: f64 = 1.0;
+
+pub(crate) fn is_motion(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::G0(_)
+            | Command::G1(_)
+            | Command::G5(_)
+            | Command::Arc(_)
+            | Command::G53(Some(_), _)
+    )
+}
+
+/// Whether `outer` contains `inner` in XY with room to spare on every side
+fn encloses(outer: &Bounds, inner: &Bounds) -> bool {
+    outer.min.x < inner.min.x
+        && outer.min.y < inner.min.y
+        && outer.max.x > inner.max.x
+        && outer.max.y > inner.max.y
+}
+
+/// Ranges of consecutive extrusion moves in `steps`. Lines that don't move
+/// the toolhead or extruder don't break a run.
+pub(crate) fn extrusion_runs(steps: &[Step]) -> Vec<Range<usize>> {
+    let mut runs = Vec::new();
+    let mut run: Option<Range<usize>> = None;
+    for (i, step) in steps.iter().enumerate() {
+        if step.line.command.tag() == Tag::Extrusion {
+            match run.as_mut() {
+                Some(run) => run.end = i + 1,
+                None => run = Some(i..i + 1),
+            }
+        } else if step.prev.pos != step.next.pos || step.prev.e != step.next.e {
+            runs.extend(run.take());
+        }
+    }
+    runs.extend(run);
+    runs
+}
+
+/// Box around every position visited by the steps in `runs`
+pub(crate) fn run_bounds(steps: &[Step], runs: &[Range<usize>]) -> Option<Bounds> {
+    let mut bounds: Option<Bounds> = None;
+    for step in runs.iter().flat_map(|run| &steps[run.clone()]) {
+        for pos in [step.prev.pos, step.next.pos] {
+            match bounds.as_mut() {
+                Some(bounds) => bounds.include(pos),
+                None => bounds = Some(Bounds { min: pos, max: pos }),
+            }
+        }
+    }
+    bounds
+}
+
+impl GCodeModel {
+    /// Line ranges that print the skirt or brim. Ranges come from `;TYPE:`
+    /// feature comments when the file has them. Otherwise the closed
+    /// extrusion loops at the start of the first layer that enclose
+    /// everything printed after them on that layer are taken as the skirt.
+    pub fn skirt_ranges(&self) -> Vec<Range<usize>> {
+        if !self.lines.iter().any(|line| self.feature(line).is_some()) {
+            return self.skirt_loops().into_iter().collect();
+        }
+        let mut ranges = Vec::new();
+        let mut start = None;
+        for (i, line) in self.lines.iter().enumerate() {
+            let Some(feature) = self.feature(line) else {
+                continue;
+            };
+            if let Some(start) = start.take() {
+                ranges.push(start..i);
+            }
+            if Role::from_feature(feature) == Role::Skirt {
+                start = Some(i);
+            }
+        }
+        if let Some(start) = start {
+            ranges.push(start..self.lines.len());
+        }
+        ranges
+    }
+    fn skirt_loops(&self) -> Option<Range<usize>> {
+        let first = self.layers().into_iter().next()?;
+        let steps = self.cursor().take(first.end).collect::<Vec<_>>();
+        let runs = extrusion_runs(&steps);
+        let bounds = |runs: &[Range<usize>]| run_bounds(&steps, runs);
+        let closed = |run: &Range<usize>| {
+            let (start, end) = (steps[run.start].prev.pos, steps[run.end - 1].next.pos);
+            (end.x - start.x).to_mm().hypot((end.y - start.y).to_mm()) <= SEAM_GAP
+        };
+        let mut skirt: Option<Range<usize>> = None;
+        for (n, run) in runs.iter().enumerate() {
+            let (Some(outer), Some(inner)) = (bounds(&runs[n..=n]), bounds(&runs[n + 1..])) else {
+                break;
+            };
+            if !closed(run) || !encloses(&outer, &inner) {
+                break;
+            }
+            match skirt.as_mut() {
+                Some(skirt) => skirt.end = run.end,
+                None => skirt = Some(run.clone()),
+            }
+        }
+        skirt
+    }
+    /// Remove the moves that print the skirt and brim, returning the number
+    /// of lines removed. Other lines in those ranges, like fan or temperature
+    /// changes, are kept.
+    pub fn strip_skirt(&mut self) -> usize {
+        self.record_transform("strip_skirt", String::new());
+        let removed = self
+            .skirt_ranges()
+            .into_iter()
+            .rev()
+            .map(|range| self.strip_moves(range))
+            .sum();
+        if removed > 0 {
+            self.tag_g1();
+        }
+        removed
+    }
+    /// Remove the motion lines in `range`, returning how many were removed.
+    /// The toolhead travels to where the range ended so the rest of the file
+    /// resolves the same way, and in absolute E mode later E values are
+    /// lowered by the filament the range used. Tags are left stale.
+    pub(crate) fn strip_moves(&mut self, range: Range<usize>) -> usize {
+        let states = self
+            .cursor()
+            .map(|step| (step.prev, step.next))
+            .collect::<Vec<_>>();
+        let (start, end) = (states[range.start].0, states[range.end - 1].1);
+        self.shift_absolute_e(range.end, start.e - end.e);
+        let mut removed = 0;
+        let mut kept = Vec::new();
+        for line in self.lines.drain(range.clone()) {
+            if is_motion(&line.command) {
+                removed += 1;
+            } else {
+                kept.push(line);
+            }
+        }
+        if start.pos != end.pos {
+            let mut from = end;
+            from.pos = start.pos;
+            kept.push(GCodeLine {
+                id: self.id_counter.get(),
+                command: Command::G1(from.move_to(end.pos, None)),
+                comments: String::new(),
+                annotations: Annotations::default(),
+            });
+        }
+        self.lines.splice(range.start..range.start, kept);
+        removed
+    }
+    /// Print `loops` rectangular loops around the first layer, the innermost
+    /// `distance` away from it and each further one a nozzle width out.
+    /// The skirt uses the average flow and the first feedrate of the first
+    /// layer, and goes in just before its first extrusion. Returns the number
+    /// of lines added; call `strip_skirt` first to replace an existing skirt.
+    pub fn generate_skirt(
+        &mut self,
+        profile: &PrinterProfile,
+        distance: Length,
+        loops: usize,
+    ) -> usize {
+        let parameters = format!("{distance}, {loops}");
+        self.record_transform("generate_skirt", parameters);
+        let Some(first) = self.layers().into_iter().next() else {
+            return 0;
+        };
+        let steps = self
+            .cursor()
+            .take(first.end)
+            .filter(|step| step.line.command.tag() == Tag::Extrusion)
+            .collect::<Vec<_>>();
+        let Some(start) = steps.first().map(|step| step.line.id) else {
+            return 0;
+        };
+        let (prev, feedrate) = (steps[0].prev, steps[0].next.feedrate);
+        let mut footprint = Bounds {
+            min: prev.pos,
+            max: prev.pos,
+        };
+        let (mut length, mut filament) = (0.0, 0.0);
+        for step in &steps {
+            footprint.include(step.next.pos);
+            let (dx, dy) = (
+                step.next.pos.x - step.prev.pos.x,
+                step.next.pos.y - step.prev.pos.y,
+            );
+            length += dx.to_mm().hypot(dy.to_mm());
+            filament += (step.next.e - step.prev.e).to_mm();
+        }
+        let flow = if length > 0.0 { filament / length } else { 0.0 };
+        let index = self
+            .lines
+            .iter()
+            .position(|line| line.id == start)
+            .expect("extrusion came from this model");
+
+        let mut state = prev;
+        let mut lines = Vec::new();
+        let mut push = |state: &mut crate::state::MachineState, g1: G1, comment: &str| {
+            state.apply(&Command::G1(g1.clone()));
+            lines.push(GCodeLine {
+                id: self.id_counter.get(),
+                command: Command::G1(g1),
+                comments: String::from(comment),
+                annotations: Annotations::default(),
+            });
+        };
+        for n in (0..loops).rev() {
+            let offset = distance + Length::from_mm(profile.nozzle_diameter * n as f64);
+            let (min, max) = (footprint.min, footprint.max);
+            let corner = |x: Length, y: Length| Position {
+                x,
+                y,
+                z: prev.pos.z,
+            };
+            let corners = [
+                corner(min.x - offset, min.y - offset),
+                corner(max.x + offset, min.y - offset),
+                corner(max.x + offset, max.y + offset),
+                corner(min.x - offset, max.y + offset),
+                corner(min.x - offset, min.y - offset),
+            ];
+            let comment = if n + 1 == loops { " skirt" } else { "" };
+            let travel = state.move_to(corners[0], None);
+            push(&mut state, travel, comment);
+            for (i, &corner) in corners[1..].iter().enumerate() {
+                let side = (corner.x - state.pos.x)
+                    .to_mm()
+                    .hypot((corner.y - state.pos.y).to_mm());
+                let e = state.e + ExtrusionLength::from_mm(side * flow);
+                let f = (n + 1 == loops && i == 0).then_some(feedrate);
+                let g1 = G1 {
+                    f,
+                    ..state.move_to(corner, Some(e))
+                };
+                push(&mut state, g1, "");
+            }
+        }
+        let back = G1 {
+            f: Some(prev.feedrate),
+            ..state.move_to(prev.pos, None)
+        };
+        push(&mut state, back, "");
+
+        if state.e_positioning == Positioning::Absolute {
+            self.shift_absolute_e(index, state.e - prev.e);
+        }
+        let added = lines.len();
+        self.lines.splice(index..index, lines);
+        self.tag_g1();
+        added
+    }
+    /// Add `delta` to every absolute E value from line `from` on, stopping
+    /// at a `G92 E` since it resets the extruder position
+    pub(crate) fn shift_absolute_e(&mut self, from: usize, delta: ExtrusionLength) {
+        let modes = self
+            .cursor()
+            .map(|step| step.prev.e_positioning)
+            .collect::<Vec<_>>();
+        for (line, mode) in self.lines.iter_mut().zip(modes).skip(from) {
+            if let Command::G92(G92 { e: Some(_), .. }) = line.command {
+                break;
+            }
+            if mode != Positioning::Absolute {
+                continue;
+            }
+            let e = match &mut line.command {
+                Command::G0(G1 { e, .. })
+                | Command::G1(G1 { e, .. })
+                | Command::G53(Some(G1 { e, .. }), _) => e,
+                Command::G5(g5) => &mut g5.e,
+                Command::Arc(arc) => &mut arc.e,
+                _ => continue,
+            };
+            if let Some(e) = e {
+                *e += delta;
+            }
+        }
+    }
+}
+
+#[test]
+fn strip_skirt_test() {
+    let input = "G1 Z0.2\n;TYPE:Skirt\nG1 X0 Y0\nG1 X10 E1\nM106 S255\nG1 Y10 E2\n;TYPE:Perimeter\nG1 X5 Y5\nG1 X6 E3";
+    let mut gcode: GCodeModel = input.parse().unwrap();
+    assert_eq!(gcode.skirt_ranges(), vec![1..6]);
+    assert_eq!(gcode.strip_skirt(), 3);
+    let states = gcode.cursor().map(|step| step.next).collect::<Vec<_>>();
+    assert_eq!(
+        gcode.lines[2].command,
+        Command::M106(crate::M106 {
+            index: None,
+            speed: Some(255)
+        })
+    );
+    // the skirt's filament no longer comes out at the start of the perimeter
+    let last = states.len() - 1;
+    assert_eq!(
+        states[last].e - states[last - 1].e,
+        ExtrusionLength::from_mm(1.0)
+    );
+    assert_eq!(states[last].e, ExtrusionLength::from_mm(1.0));
+}
+
+#[test]
+fn generate_skirt_test() {
+    let input =
+        "G1 Z0.2 F1200\nG1 X10 Y10\nG1 X20 E1\nG1 Y20 E2\nG1 X10 E3\nG1 Y10 E4\nG1 Z0.4\nG1 X20 E5";
+    let original: GCodeModel = input.parse().unwrap();
+    let mut gcode = original.clone();
+    let profile = PrinterProfile::prusa_mk4();
+    let added = gcode.generate_skirt(&profile, Length::from(3.0), 2);
+    assert_eq!(added, 11);
+    let bounds = gcode.bounds(Default::default()).unwrap();
+    assert_eq!(bounds.min.x, Length::from(6.6));
+    // same flow as the part, 0.1mm of filament per mm over 67.2 + 64mm
+    let end = gcode.cursor().last().unwrap().next;
+    assert!((end.e.to_mm() - 18.12).abs() < 1e-6);
+    assert!(gcode.skirt_ranges().len() == 1);
+
+    gcode.strip_skirt();
+    let end = gcode.cursor().last().unwrap().next;
+    assert_eq!(end.e, ExtrusionLength::from_mm(5.0));
+    assert_eq!(
+        gcode.bounds(Default::default()),
+        original.bounds(Default::default())
+    );
+}
@@ -0,0 +1,117 @@
+use crate::GCodeModel;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Slicer that produced a file, see `GCodeModel::detect_slicer`
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SlicerKind {
+    PrusaSlicer,
+    SuperSlicer,
+    OrcaSlicer,
+    BambuStudio,
+    Cura,
+    Simplify3D,
+    IdeaMaker,
+    Slic3r,
+}
+
+/// Names as they appear in "generated by" style header comments. Forks
+/// come before the slicer they were forked from, whose name they may
+/// repeat.
+const HEADERS: [(&str, SlicerKind); 8] = [
+    ("superslicer", SlicerKind::SuperSlicer),
+    ("prusaslicer", SlicerKind::PrusaSlicer),
+    ("orcaslicer", SlicerKind::OrcaSlicer),
+    ("bambustudio", SlicerKind::BambuStudio),
+    ("cura", SlicerKind::Cura),
+    ("simplify3d", SlicerKind::Simplify3D),
+    ("ideamaker", SlicerKind::IdeaMaker),
+    ("slic3r", SlicerKind::Slic3r),
+];
+
+/// Lines searched for a header at each end of the file, since some
+/// slicers write their settings block at the end
+const HEADER_LINES: usize = 200;
+
+/// Slicer family identified by its feature and layer markers alone
+fn from_markers(comment: &str) -> Option<SlicerKind> {
+    let comment = comment.trim();
+    if comment.starts_with("LAYER:") || comment.starts_with("TYPE:WALL-") {
+        return Some(SlicerKind::Cura);
+    }
+    if comment == "LAYER_CHANGE"
+        || comment.starts_with("TYPE:External perimeter")
+        || comment.starts_with("TYPE:Perimeter")
+    {
+        return Some(SlicerKind::PrusaSlicer);
+    }
+    if comment == "CHANGE_LAYER" || comment.starts_with("TYPE:Outer wall") {
+        return Some(SlicerKind::OrcaSlicer);
+    }
+    if comment.starts_with("feature ") || comment.starts_with("layer ") {
+        return Some(SlicerKind::Simplify3D);
+    }
+    None
+}
+
+impl GCodeModel {
+    /// Best guess at the slicer that produced the file, from the name in
+    /// its header comments, or failing that from the style of its layer
+    /// and feature markers. Markers can't tell forks apart, so a file
+    /// stripped of its header is reported as the slicer the markers came
+    /// from.
+    pub fn detect_slicer(&self) -> Option<SlicerKind> {
+        let n = self.lines.len();
+        let head = n.min(HEADER_LINES);
+        let tail = n.saturating_sub(HEADER_LINES).max(head);
+        let ends = self.lines[..head].iter().chain(&self.lines[tail..]);
+        for line in ends {
+            let comment = self
+                .comment(line)
+                .to_lowercase()
+                .replace([' ', '_', '-'], "");
+            if !["generated", "sliced", "slicer", "flavor"]
+                .iter()
+                .any(|word| comment.contains(word))
+            {
+                continue;
+            }
+            if let Some((_, kind)) = HEADERS.iter().find(|(name, _)| comment.contains(name)) {
+                return Some(*kind);
+            }
+        }
+        self.lines
+            .iter()
+            .find_map(|line| from_markers(self.comment(line)))
+    }
+}
+
+#[test]
+fn detect_slicer_test() {
+    let detect = |input: &str| input.parse::<GCodeModel>().unwrap().detect_slicer();
+    assert_eq!(
+        detect("; generated by PrusaSlicer 2.7.1 on 2024-01-01\nG90"),
+        Some(SlicerKind::PrusaSlicer)
+    );
+    assert_eq!(
+        detect("; generated by SuperSlicer 2.5 based on PrusaSlicer\nG90"),
+        Some(SlicerKind::SuperSlicer)
+    );
+    assert_eq!(
+        detect(";FLAVOR:Marlin\n;Generated with Cura_SteamEngine 5.6.0\nG90"),
+        Some(SlicerKind::Cura)
+    );
+    assert_eq!(
+        detect("; BambuStudio 01.08.00.57\n; generated by BambuStudio\nG90"),
+        Some(SlicerKind::BambuStudio)
+    );
+    // markers alone
+    assert_eq!(
+        detect("G90\n;LAYER_CHANGE\n;Z:0.2\n;TYPE:External perimeter\nG1 X1 E1"),
+        Some(SlicerKind::PrusaSlicer)
+    );
+    assert_eq!(detect("G90\n;LAYER:0\nG1 X1 E1"), Some(SlicerKind::Cura));
+    assert_eq!(detect("G90\nG1 X1 E1 ; first move"), None);
+}
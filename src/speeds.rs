@@ -0,0 +1,129 @@
+use crate::{estimate::move_length, GCodeModel, Role, Tag};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Commanded speeds of the extrusions in one slicer feature
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeatureSpeed {
+    /// feature as named by its `;TYPE:` comment, `None` for extrusions
+    /// before the first one
+    pub feature: Option<String>,
+    pub role: Role,
+    pub moves: usize,
+    /// extruded length in mm
+    pub length: f64,
+    /// slowest, length weighted average and fastest speed in mm/s
+    pub min: f64,
+    pub avg: f64,
+    pub max: f64,
+}
+
+/// Speeds per feature, see `GCodeModel::feature_speeds`
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SpeedReport(pub Vec<FeatureSpeed>);
+
+impl SpeedReport {
+    pub fn get(&self, feature: &str) -> Option<&FeatureSpeed> {
+        self.0
+            .iter()
+            .find(|speed| speed.feature.as_deref() == Some(feature))
+    }
+}
+
+impl std::fmt::Display for SpeedReport {
+    /// One row per feature, e.g.
+    /// `External perimeter     min   25.0  avg   25.0  max   25.0 mm/s     1234.5 mm`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for speed in &self.0 {
+            writeln!(
+                f,
+                "{:<22} min {:>6.1}  avg {:>6.1}  max {:>6.1} mm/s {:>10.1} mm",
+                speed.feature.as_deref().unwrap_or("-"),
+                speed.min,
+                speed.avg,
+                speed.max,
+                speed.length
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl GCodeModel {
+    /// Commanded speed and length of the extrusions in each feature named
+    /// by the slicer's `;TYPE:` comments, in the order the features first
+    /// appear, to check the slicer's speed settings made it into the file.
+    /// Speeds are the F in effect, without M220 overrides or acceleration.
+    pub fn feature_speeds(&self) -> SpeedReport {
+        let mut speeds: Vec<FeatureSpeed> = Vec::new();
+        let mut current = None;
+        for step in self.cursor() {
+            if let Some(feature) = self.feature(step.line) {
+                current = Some(feature.to_string());
+            }
+            if step.line.command.tag() != Tag::Extrusion {
+                continue;
+            }
+            let length = move_length(&step.prev, &step.next);
+            let speed = step.next.feedrate.mm_per_sec();
+            let i = match speeds.iter().position(|s| s.feature == current) {
+                Some(i) => i,
+                None => {
+                    let role = current
+                        .as_deref()
+                        .map_or(Role::OtherExtrusion, Role::from_feature);
+                    speeds.push(FeatureSpeed {
+                        feature: current.clone(),
+                        role,
+                        moves: 0,
+                        length: 0.0,
+                        min: speed,
+                        avg: 0.0,
+                        max: speed,
+                    });
+                    speeds.len() - 1
+                }
+            };
+            let totals = &mut speeds[i];
+            totals.moves += 1;
+            totals.length += length;
+            // a running total of speed times length until the end
+            totals.avg += speed * length;
+            totals.min = totals.min.min(speed);
+            totals.max = totals.max.max(speed);
+        }
+        for speed in &mut speeds {
+            speed.avg = if speed.length > 0.0 {
+                speed.avg / speed.length
+            } else {
+                0.0
+            };
+        }
+        SpeedReport(speeds)
+    }
+}
+
+#[test]
+fn feature_speeds_test() {
+    let gcode: GCodeModel = "G1 X5 E0.5 F600\n;TYPE:External perimeter\nG1 X10 E1 F1500\nG1 X20 E2\n;TYPE:Solid infill\nG1 X30 E3 F6000\nG1 X60 E4 F3000\n;TYPE:External perimeter\nG1 Y10 E5 F1200"
+        .parse()
+        .unwrap();
+    let report = gcode.feature_speeds();
+    assert_eq!(report.0.len(), 3);
+    assert_eq!(report.0[0].feature, None);
+    let outer = report.get("External perimeter").unwrap();
+    assert_eq!(outer.role, Role::OuterWall);
+    assert_eq!(outer.moves, 3);
+    assert_eq!(outer.length, 25.0);
+    assert_eq!((outer.min, outer.max), (20.0, 25.0));
+    assert_eq!(outer.avg, (15.0 * 25.0 + 10.0 * 20.0) / 25.0);
+    let infill = report.get("Solid infill").unwrap();
+    assert_eq!(infill.avg, (10.0 * 100.0 + 30.0 * 50.0) / 40.0);
+    assert_eq!(
+        report.to_string().lines().nth(2),
+        Some("Solid infill           min   50.0  avg   62.5  max  100.0 mm/s       40.0 mm")
+    );
+}
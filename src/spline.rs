@@ -0,0 +1,185 @@
+use crate::{
+    state::{MachineState, Positioning},
+    Annotations, Command, ExtrusionLength, Feedrate, GCodeLine, GCodeModel, Length, G1,
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Struct to store G5 cubic Bézier spline params. `I`/`J` offset the
+/// first control point from the start of the move, `P`/`Q` offset the
+/// second control point from the end of the move.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct G5 {
+    pub i: Option<Length>,
+    pub j: Option<Length>,
+    pub p: Option<Length>,
+    pub q: Option<Length>,
+    pub x: Option<Length>,
+    pub y: Option<Length>,
+    pub e: Option<ExtrusionLength>,
+    pub f: Option<Feedrate>,
+}
+
+impl G5 {
+    /// Split the curve into `segments` G1 moves starting from `start`.
+    /// Coordinates and E are written in the positioning modes of `start`,
+    /// with E shared out in proportion to each segment's length.
+    pub fn linearize(&self, start: &MachineState, segments: usize) -> Vec<G1> {
+        let segments = segments.max(1);
+        let mut end = *start;
+        end.apply(&Command::G5(self.clone()));
+        let mm = |m: Length| m.to_mm();
+        let p0 = (mm(start.pos.x), mm(start.pos.y));
+        let p3 = (mm(end.pos.x), mm(end.pos.y));
+        let offset = |m: Option<Length>| m.map(mm).unwrap_or(0.0);
+        let p1 = (p0.0 + offset(self.i), p0.1 + offset(self.j));
+        let p2 = (p3.0 + offset(self.p), p3.1 + offset(self.q));
+        let points = (0..=segments)
+            .map(|n| {
+                let t = n as f64 / segments as f64;
+                let u = 1.0 - t;
+                let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+                (
+                    a * p0.0 + b * p1.0 + c * p2.0 + d * p3.0,
+                    a * p0.1 + b * p1.1 + c * p2.1 + d * p3.1,
+                )
+            })
+            .collect::<Vec<_>>();
+        let lengths = points
+            .windows(2)
+            .map(|w| (w[1].0 - w[0].0).hypot(w[1].1 - w[0].1))
+            .collect::<Vec<_>>();
+        let total = lengths.iter().sum::<f64>();
+        let de = end.e - start.e;
+
+        // work in fixed point from here, snapping the final point and E total
+        // to the exact end of the spline so nothing drifts
+        let mut done = 0.0;
+        let targets = (1..=segments).map(|n| {
+            done += lengths[n - 1];
+            if n == segments {
+                (end.pos.x, end.pos.y, de)
+            } else {
+                let share = if total > 0.0 {
+                    done / total
+                } else {
+                    n as f64 / segments as f64
+                };
+                (
+                    Length::from(points[n].0),
+                    Length::from(points[n].1),
+                    ExtrusionLength::from_mm(de.to_mm() * share),
+                )
+            }
+        });
+        let mut out = Vec::with_capacity(segments);
+        let mut prev = (start.pos.x, start.pos.y, ExtrusionLength::ZERO);
+        for (n, (x, y, e)) in targets.enumerate() {
+            let (dx, dy) = match start.positioning {
+                Positioning::Absolute => (x, y),
+                Positioning::Relative => (x - prev.0, y - prev.1),
+            };
+            let e_param = match start.e_positioning {
+                Positioning::Absolute => start.e + e,
+                Positioning::Relative => e - prev.2,
+            };
+            out.push(G1 {
+                x: Some(dx),
+                y: Some(dy),
+                e: self.e.map(|_| e_param),
+                f: if n == 0 { self.f } else { None },
+                ..Default::default()
+            });
+            prev = (x, y, e);
+        }
+        out
+    }
+}
+
+impl GCodeModel {
+    /// Replace every G5 spline with `segments` G1 moves so the rest
+    /// of the crate can analyze it. Comments stay on the first segment.
+    pub fn linearize_splines(&mut self, segments: usize) {
+        self.record_transform("linearize_splines", segments.to_string());
+        let mut state = MachineState::default();
+        let mut lines = Vec::with_capacity(self.lines.len());
+        for line in std::mem::take(&mut self.lines) {
+            let prev = state;
+            state.apply(&line.command);
+            let Command::G5(g5) = &line.command else {
+                lines.push(line);
+                continue;
+            };
+            for (n, g1) in g5.linearize(&prev, segments).into_iter().enumerate() {
+                let first = n == 0;
+                lines.push(GCodeLine {
+                    id: if first {
+                        line.id
+                    } else {
+                        self.id_counter.get()
+                    },
+                    command: Command::G1(g1),
+                    comments: if first {
+                        line.comments.clone()
+                    } else {
+                        String::new()
+                    },
+                    annotations: if first {
+                        line.annotations.clone()
+                    } else {
+                        Annotations::default()
+                    },
+                });
+            }
+        }
+        self.lines = lines;
+        self.tag_g1();
+    }
+}
+
+#[test]
+fn g5_parse_test() {
+    use crate::emit::Emit;
+    let gcode: GCodeModel = "G5 I10 J0 P0 Q-10 X20 Y20 E1.5 F600".parse().unwrap();
+    let expected = G5 {
+        i: Some(Length::from(10.0)),
+        j: Some(Length::ZERO),
+        p: Some(Length::ZERO),
+        q: Some(Length::from(-10.0)),
+        x: Some(Length::from(20.0)),
+        y: Some(Length::from(20.0)),
+        e: Some(ExtrusionLength::from_mm(1.5)),
+        f: Some(Feedrate::from_mm_per_min(600.0)),
+    };
+    assert_eq!(gcode.lines[0].command, Command::G5(expected));
+    assert_eq!(
+        gcode.lines[0].emit(false),
+        "G5 I10 J0 P0 Q-10 X20 Y20 E1.5 F600 "
+    );
+    // words outside the spline grammar keep the line as written
+    let gcode: GCodeModel = "G5 I10 J0 P0 Q-10 X20 Y20 Z1".parse().unwrap();
+    assert_eq!(
+        gcode.lines[0].command,
+        Command::Raw(String::from("G5 I10 J0 P0 Q-10 X20 Y20 Z1"))
+    );
+}
+
+#[test]
+fn linearize_test() {
+    let mut gcode: GCodeModel = "M83\nG1 X10 Y10\nG5 I5 J0 P0 Q-5 X20 Y20 E2 ; curve\nG1 X0"
+        .parse()
+        .unwrap();
+    gcode.linearize_splines(8);
+    assert_eq!(gcode.lines.len(), 4 + 7);
+    assert_eq!(gcode.lines[2].comments, " curve");
+    let states = gcode.cursor().map(|step| step.next).collect::<Vec<_>>();
+    // the curve ends exactly where the spline did, with all of E used
+    assert_eq!(states[9].pos.x, Length::from(20.0));
+    assert_eq!(states[9].pos.y, Length::from(20.0));
+    assert_eq!(states[9].e, ExtrusionLength::from_mm(2.0));
+    assert!(gcode.lines[2..10]
+        .iter()
+        .all(|line| line.command.tag() == crate::Tag::Extrusion));
+}
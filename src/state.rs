@@ -0,0 +1,857 @@
+use crate::{
+    parsers::{raw_key, raw_param, split_raw},
+    ArcMove, Command, ExtrusionLength, Feedrate, GCodeLine, GCodeModel, Home, Id, Length, Tag, G1,
+    G5, G92, M106,
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Absolute toolhead position
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Position {
+    pub x: Length,
+    pub y: Length,
+    pub z: Length,
+}
+
+/// Whether move parameters are absolute targets or offsets from
+/// the current position
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Positioning {
+    #[default]
+    Absolute,
+    Relative,
+}
+
+/// Length units selected by G20/G21
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Units {
+    #[default]
+    Millimeters,
+    Inches,
+}
+
+/// Last commanded heater targets in °C, `None` until first set
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Temperatures {
+    pub hotend: Option<f32>,
+    pub bed: Option<f32>,
+}
+
+/// Accelerations in mm/s² set in the file with M204 or Klipper's
+/// `SET_VELOCITY_LIMIT`, `None` until first set, in which case the
+/// machine's configured values apply
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Acceleration {
+    pub print: Option<f32>,
+    pub retract: Option<f32>,
+    pub travel: Option<f32>,
+}
+
+/// Speed and flow percentages set with M220 and M221
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Overrides {
+    pub speed: f32,
+    pub flow: f32,
+}
+
+impl Default for Overrides {
+    fn default() -> Self {
+        Overrides {
+            speed: 100.0,
+            flow: 100.0,
+        }
+    }
+}
+
+/// Direction the spindle is turning
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SpindleDirection {
+    #[default]
+    Off,
+    Clockwise,
+    CounterClockwise,
+}
+
+/// Spindle direction and last commanded speed in RPM
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Spindle {
+    pub direction: SpindleDirection,
+    pub speed: u32,
+}
+
+/// Which coolant outputs are on
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Coolant {
+    pub mist: bool,
+    pub flood: bool,
+}
+
+/// How the F word is interpreted, selected by G93/G94/G95
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum FeedMode {
+    /// G93, F is the reciprocal of the move time in minutes
+    InverseTime,
+    /// G94, F is in mm/min
+    #[default]
+    UnitsPerMinute,
+    /// G95, F is in mm per spindle revolution
+    UnitsPerRevolution,
+}
+
+/// Work coordinate systems selected by G54-G59
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Wcs {
+    #[default]
+    G54,
+    G55,
+    G56,
+    G57,
+    G58,
+    G59,
+}
+
+/// Full state of the machine at a point in a file, starting from
+/// firmware defaults (absolute positioning, millimetres, tool 0).
+/// Lengths are always read as millimetres, even after a G20, which
+/// `GCodeModel::check_units` reports.
+/// `pos` is always in machine coordinates, with the active work offset
+/// already applied.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct MachineState {
+    pub pos: Position,
+    /// absolute position of the extruder axis
+    pub e: ExtrusionLength,
+    pub feedrate: Feedrate,
+    pub feed_mode: FeedMode,
+    pub tool: u8,
+    /// units selected by the last G20 or G21. Positions aren't scaled by
+    /// them, so they are only right in millimetres.
+    pub units: Units,
+    /// positioning mode for X, Y and Z
+    pub positioning: Positioning,
+    /// positioning mode for E
+    pub e_positioning: Positioning,
+    pub temps: Temperatures,
+    pub accel: Acceleration,
+    pub overrides: Overrides,
+    /// top speed in mm/s set by `SET_VELOCITY_LIMIT VELOCITY=`
+    pub velocity_limit: Option<f32>,
+    /// part cooling fan speed, 0-255, as set by typed M106/M107. Lines
+    /// left raw, like the fractional speeds some firmware takes, leave it
+    /// as it was since their scale isn't known.
+    pub fan: u8,
+    pub spindle: Spindle,
+    pub coolant: Coolant,
+    /// active work coordinate system
+    pub wcs: Wcs,
+    /// offsets from machine zero for each of G54-G59
+    pub work_offsets: [Position; 6],
+    /// shift set by G92, on top of the offset of whichever work
+    /// coordinate system is active
+    pub g92_offset: Position,
+}
+
+impl MachineState {
+    /// Update the state with the effects of a single command
+    pub fn apply(&mut self, command: &Command) {
+        match command {
+            Command::G0(G1 {
+                x, y, z, e, f, s, ..
+            })
+            | Command::G1(G1 {
+                x, y, z, e, f, s, ..
+            }) => {
+                self.apply_move(self.work_offset(), *x, *y, *z, *e, *f);
+                if let Some(s) = s {
+                    self.spindle.speed = *s;
+                }
+            }
+            Command::G5(G5 { x, y, e, f, .. }) => {
+                self.apply_move(self.work_offset(), *x, *y, None, *e, *f)
+            }
+            Command::Arc(ArcMove { x, y, z, e, f, .. }) => {
+                self.apply_move(self.work_offset(), *x, *y, *z, *e, *f)
+            }
+            Command::G53(Some(G1 { x, y, z, e, f, .. }), _) => {
+                self.apply_move(Position::default(), *x, *y, *z, *e, *f)
+            }
+            Command::G53(None, _) => {}
+            Command::Wcs(wcs) => self.wcs = *wcs,
+            Command::G93 => self.feed_mode = FeedMode::InverseTime,
+            Command::G94 => self.feed_mode = FeedMode::UnitsPerMinute,
+            Command::G95 => self.feed_mode = FeedMode::UnitsPerRevolution,
+            Command::G92(g92) => self.set_position(g92),
+            Command::Home(home) => self.home(home),
+            Command::G90 => self.positioning = Positioning::Absolute,
+            Command::G91 => self.positioning = Positioning::Relative,
+            Command::M82 => self.e_positioning = Positioning::Absolute,
+            Command::M83 => self.e_positioning = Positioning::Relative,
+            Command::M3(speed) => self.set_spindle(SpindleDirection::Clockwise, *speed),
+            Command::M4(speed) => self.set_spindle(SpindleDirection::CounterClockwise, *speed),
+            Command::M5 => self.spindle.direction = SpindleDirection::Off,
+            Command::ToolChange(tool) => self.tool = *tool,
+            // only the part cooling fan is tracked
+            Command::M106(M106 {
+                index: None | Some(0),
+                speed,
+            }) => self.fan = speed.unwrap_or(255),
+            Command::M107(None | Some(0)) => self.fan = 0,
+            Command::M106(_) | Command::M107(_) => {}
+            Command::M7 => self.coolant.mist = true,
+            Command::M8 => self.coolant.flood = true,
+            Command::M9 => self.coolant = Coolant::default(),
+            Command::G4(_)
+            | Command::G29(_)
+            | Command::M420(_)
+            | Command::M862(_)
+            | Command::M117(_)
+            | Command::M118(_)
+            | Command::M808(_)
+            | Command::Blank
+            | Command::Custom(_) => {}
+            Command::Raw(raw) => self.apply_raw(raw),
+        }
+    }
+    /// Track heater targets, which are still kept as raw text
+    fn apply_raw(&mut self, raw: &str) {
+        let Some((word, rest)) = split_raw(raw) else {
+            return;
+        };
+        let target = || raw_param(rest, 'S').or_else(|| raw_param(rest, 'R'));
+        match word.as_str() {
+            "G20" => self.units = Units::Inches,
+            "G21" => self.units = Units::Millimeters,
+            "M104" | "M109" => self.temps.hotend = target().or(self.temps.hotend),
+            "M140" | "M190" => self.temps.bed = target().or(self.temps.bed),
+            "M220" => self.overrides.speed = raw_param(rest, 'S').unwrap_or(self.overrides.speed),
+            "M221" => self.overrides.flow = raw_param(rest, 'S').unwrap_or(self.overrides.flow),
+            "M204" => {
+                // S is Marlin's older form, setting both print and travel
+                let both = raw_param(rest, 'S');
+                let accel = &mut self.accel;
+                accel.print = raw_param(rest, 'P').or(both).or(accel.print);
+                accel.travel = raw_param(rest, 'T').or(both).or(accel.travel);
+                accel.retract = raw_param(rest, 'R').or(accel.retract);
+            }
+            "SET_VELOCITY_LIMIT" => {
+                // Klipper has one acceleration for every kind of move
+                if let Some(accel) = raw_key(rest, "ACCEL") {
+                    self.accel = Acceleration {
+                        print: Some(accel),
+                        retract: Some(accel),
+                        travel: Some(accel),
+                    };
+                }
+                self.velocity_limit = raw_key(rest, "VELOCITY").or(self.velocity_limit);
+            }
+            _ => {}
+        }
+    }
+    fn set_spindle(&mut self, direction: SpindleDirection, speed: Option<u32>) {
+        self.spindle.direction = direction;
+        if let Some(speed) = speed {
+            self.spindle.speed = speed;
+        }
+    }
+    /// Feedrate the machine actually runs at with the M220 speed factor
+    pub fn effective_feedrate(&self) -> Feedrate {
+        Feedrate::from_mm_per_min(
+            self.feedrate.mm_per_min() * f64::from(self.overrides.speed) / 100.0,
+        )
+    }
+    /// Offset of the active work coordinate system from machine zero,
+    /// including any G92 shift
+    pub fn work_offset(&self) -> Position {
+        let wcs = self.work_offsets[self.wcs as usize];
+        let g92 = self.g92_offset;
+        Position {
+            x: wcs.x + g92.x,
+            y: wcs.y + g92.y,
+            z: wcs.z + g92.z,
+        }
+    }
+    /// Declare the current position to be the given coordinates by moving
+    /// the G92 shift, and reset the extruder position
+    fn set_position(&mut self, g92: &G92) {
+        let wcs = self.work_offsets[self.wcs as usize];
+        if let Some(x) = g92.x {
+            self.g92_offset.x = self.pos.x - wcs.x - x;
+        }
+        if let Some(y) = g92.y {
+            self.g92_offset.y = self.pos.y - wcs.y - y;
+        }
+        if let Some(z) = g92.z {
+            self.g92_offset.z = self.pos.z - wcs.z - z;
+        }
+        if let Some(e) = g92.e {
+            self.e = e;
+        }
+    }
+    /// Move the homed axes to machine zero, clearing their G92 shift
+    fn home(&mut self, home: &Home) {
+        let [x, y, z] = home.axes();
+        if x {
+            self.pos.x = Length::ZERO;
+            self.g92_offset.x = Length::ZERO;
+        }
+        if y {
+            self.pos.y = Length::ZERO;
+            self.g92_offset.y = Length::ZERO;
+        }
+        if z {
+            self.pos.z = Length::ZERO;
+            self.g92_offset.z = Length::ZERO;
+        }
+    }
+    /// Set the offset of a work coordinate system from machine zero
+    pub fn set_work_offset(&mut self, wcs: Wcs, offset: Position) {
+        self.work_offsets[wcs as usize] = offset;
+    }
+    /// A G1 from this state to the machine position `to`, leaving the
+    /// extruder at `e` if given, written in the active positioning modes
+    pub(crate) fn move_to(&self, to: Position, e: Option<ExtrusionLength>) -> G1 {
+        let offset = self.work_offset();
+        let axis = |curr: Length, origin: Length, target: Length| {
+            (curr != target).then(|| match self.positioning {
+                Positioning::Absolute => target - origin,
+                Positioning::Relative => target - curr,
+            })
+        };
+        G1 {
+            x: axis(self.pos.x, offset.x, to.x),
+            y: axis(self.pos.y, offset.y, to.y),
+            z: axis(self.pos.z, offset.z, to.z),
+            e: e.map(|e| match self.e_positioning {
+                Positioning::Absolute => e,
+                Positioning::Relative => e - self.e,
+            }),
+            ..Default::default()
+        }
+    }
+    /// Resolve a move, with absolute targets measured from `origin`
+    fn apply_move(
+        &mut self,
+        origin: Position,
+        x: Option<Length>,
+        y: Option<Length>,
+        z: Option<Length>,
+        e: Option<ExtrusionLength>,
+        f: Option<Feedrate>,
+    ) {
+        let positioning = self.positioning;
+        let axis = |curr: Length, origin: Length, param: Option<Length>| match (param, positioning)
+        {
+            (Some(val), Positioning::Absolute) => origin + val,
+            (Some(val), Positioning::Relative) => curr + val,
+            (None, _) => curr,
+        };
+        self.pos = Position {
+            x: axis(self.pos.x, origin.x, x),
+            y: axis(self.pos.y, origin.y, y),
+            z: axis(self.pos.z, origin.z, z),
+        };
+        self.e = match (e, self.e_positioning) {
+            (Some(e), Positioning::Absolute) => e,
+            (Some(e), Positioning::Relative) => self.e + e,
+            (None, _) => self.e,
+        };
+        if let Some(f) = f {
+            self.feedrate = f;
+        }
+    }
+}
+
+/// Classify the move `command` makes when run from `prev`. Commands that
+/// can't move the machine are `Tag::Uninitialized`.
+pub fn classify(prev: &MachineState, command: &Command) -> Tag {
+    let f = match command {
+        Command::G0(G1 { f, .. })
+        | Command::G1(G1 { f, .. })
+        | Command::G53(Some(G1 { f, .. }), _)
+        | Command::G5(G5 { f, .. })
+        | Command::Arc(ArcMove { f, .. }) => *f,
+        _ => return Tag::Uninitialized,
+    };
+    let mut next = *prev;
+    next.apply(command);
+    let dx = next.pos.x - prev.pos.x;
+    let dy = next.pos.y - prev.pos.y;
+    let dz = next.pos.z - prev.pos.z;
+    let de = next.e - prev.e;
+    let planar = match command {
+        Command::Arc(arc) => arc.is_planar(prev),
+        _ => dx != Length::ZERO || dy != Length::ZERO,
+    };
+    if de > ExtrusionLength::ZERO {
+        if planar {
+            Tag::Extrusion
+        } else {
+            Tag::DeRetraction
+        }
+    } else if de < ExtrusionLength::ZERO {
+        if planar {
+            Tag::Wipe
+        } else {
+            Tag::Retraction
+        }
+    } else if planar {
+        Tag::Travel
+    } else if dz > Length::ZERO {
+        Tag::RaiseZ
+    } else if dz < Length::ZERO {
+        Tag::LowerZ
+    } else if f.is_some_and(|f| f > Feedrate::ZERO) {
+        Tag::Feedrate
+    } else {
+        Tag::Uninitialized
+    }
+}
+
+/// Whether `command` run from `prev` lays down filament while moving in XY
+pub fn is_extrusion(prev: &MachineState, command: &Command) -> bool {
+    classify(prev, command) == Tag::Extrusion
+}
+
+/// Whether `command` run from `prev` moves in XY without extruding or retracting
+pub fn is_travel(prev: &MachineState, command: &Command) -> bool {
+    classify(prev, command) == Tag::Travel
+}
+
+/// Tag the G1s in `lines` starting from `start`, returning the state after
+/// the last line. Chunks of a file can be tagged separately, or on separate
+/// threads, given the state each chunk starts in.
+pub fn tag_lines(lines: &mut [GCodeLine], start: MachineState) -> MachineState {
+    let mut state = start;
+    for line in lines {
+        let tag = classify(&state, &line.command);
+        state.apply(&line.command);
+        match &mut line.command {
+            Command::G0(g1) | Command::G1(g1) => g1.tag = tag,
+            Command::Arc(arc) => arc.tag = tag,
+            _ => {}
+        }
+    }
+    state
+}
+
+/// A line along with the machine state before and after it runs
+#[derive(Clone, Debug)]
+pub struct Step<'a> {
+    pub line: &'a GCodeLine,
+    pub prev: MachineState,
+    pub next: MachineState,
+}
+
+/// Walks the lines of a model in order while tracking machine state
+#[derive(Clone, Debug)]
+pub struct Cursor<'a> {
+    lines: std::slice::Iter<'a, GCodeLine>,
+    state: MachineState,
+}
+
+impl<'a> Cursor<'a> {
+    pub fn new(lines: &'a [GCodeLine]) -> Self {
+        Cursor::with_state(lines, MachineState::default())
+    }
+    /// Start walking from a known state instead of firmware defaults
+    pub fn with_state(lines: &'a [GCodeLine], state: MachineState) -> Self {
+        Cursor {
+            lines: lines.iter(),
+            state,
+        }
+    }
+    /// State after the most recently visited line
+    pub fn state(&self) -> &MachineState {
+        &self.state
+    }
+}
+
+impl<'a> Iterator for Cursor<'a> {
+    type Item = Step<'a>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let line = self.lines.next()?;
+        let prev = self.state;
+        self.state.apply(&line.command);
+        Some(Step {
+            line,
+            prev,
+            next: self.state,
+        })
+    }
+}
+
+/// Machine states saved every `interval` lines so the state at any line can
+/// be found by walking from the nearest checkpoint instead of from the top
+/// of the file. `GCodeModel::state_at` keeps one for its lookups; build
+/// one with `StateIndex::new` to pick the interval. It goes stale once the
+/// model is edited.
+#[derive(Clone, Debug)]
+pub struct StateIndex {
+    interval: usize,
+    /// state before lines 0, interval, 2 * interval, ...
+    checkpoints: Vec<MachineState>,
+    positions: std::collections::HashMap<Id, usize>,
+}
+
+impl StateIndex {
+    pub fn new(model: &GCodeModel, interval: usize) -> Self {
+        let interval = interval.max(1);
+        let mut checkpoints = Vec::with_capacity(model.lines.len() / interval + 1);
+        let mut positions = std::collections::HashMap::with_capacity(model.lines.len());
+        for (i, step) in model.cursor().enumerate() {
+            if i % interval == 0 {
+                checkpoints.push(step.prev);
+            }
+            positions.insert(step.line.id, i);
+        }
+        StateIndex {
+            interval,
+            checkpoints,
+            positions,
+        }
+    }
+    /// The line with `id` and the machine states (position, positioning
+    /// modes, feedrate, tool, temperatures and so on) before and after it
+    pub fn state_at<'a>(&self, model: &'a GCodeModel, id: Id) -> Option<Step<'a>> {
+        let index = *self.positions.get(&id)?;
+        let checkpoint = index / self.interval;
+        let start = checkpoint * self.interval;
+        Cursor::with_state(
+            model.lines.get(start..)?,
+            *self.checkpoints.get(checkpoint)?,
+        )
+        .nth(index - start)
+        .filter(|step| step.line.id == id)
+    }
+}
+
+/// Lines between the checkpoints of the index `GCodeModel::state_at` builds
+const CACHED_INTERVAL: usize = 256;
+
+/// `StateIndex` that `GCodeModel::state_at` builds on first use and keeps
+/// until the model is re-tagged or transformed. Cloning a model leaves the
+/// copy without one, as the copy is usually about to be edited.
+#[derive(Debug, Default)]
+pub struct StateIndexCache(std::sync::Mutex<Option<std::sync::Arc<StateIndex>>>);
+
+impl StateIndexCache {
+    pub(crate) fn clear(&mut self) {
+        *self.0.get_mut().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+    fn get(&self, model: &GCodeModel) -> std::sync::Arc<StateIndex> {
+        let mut index = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        index
+            .get_or_insert_with(|| StateIndex::new(model, CACHED_INTERVAL).into())
+            .clone()
+    }
+}
+
+impl Clone for StateIndexCache {
+    fn clone(&self) -> Self {
+        StateIndexCache::default()
+    }
+}
+
+// a cached index says nothing about the model's contents
+impl PartialEq for StateIndexCache {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for StateIndexCache {}
+
+impl GCodeModel {
+    /// The line with `id` and the machine states before and after it,
+    /// walked from the nearest checkpoint of a `StateIndex` built on the
+    /// first call. Transforms and `tag_g1` drop the index; call `tag_g1`
+    /// after editing `lines` directly so later lookups see the edit.
+    pub fn state_at(&self, id: Id) -> Option<Step<'_>> {
+        self.state_index.get(self).state_at(self, id)
+    }
+}
+
+#[test]
+fn state_index_test() {
+    let gcode: GCodeModel = "G1 X1 F600\nG1 X2\nG91\nG1 X3\nM83\nG1 X4 E1\nG1 X5 E1"
+        .parse()
+        .unwrap();
+    let index = StateIndex::new(&gcode, 3);
+    for line in &gcode.lines {
+        let step = gcode.cursor().find(|step| step.line.id == line.id).unwrap();
+        let indexed = index.state_at(&gcode, line.id).unwrap();
+        assert_eq!((step.prev, step.next), (indexed.prev, indexed.next));
+        let cached = gcode.state_at(line.id).unwrap();
+        assert_eq!((step.prev, step.next), (cached.prev, cached.next));
+    }
+    let step = index.state_at(&gcode, gcode.lines[5].id).unwrap();
+    assert_eq!(step.prev.pos.x, Length::from(5.0));
+    assert_eq!(step.next.e, ExtrusionLength::from_mm(1.0));
+    assert_eq!(step.prev.feedrate, Feedrate::from_mm_per_min(600.0));
+    // a stale index won't hand back the wrong line after an edit
+    let mut edited = gcode.clone();
+    edited.lines.remove(0);
+    assert!(index.state_at(&edited, gcode.lines[6].id).is_none());
+    // the cached index is rebuilt once the edit is re-tagged
+    let mut gcode = gcode;
+    let id = gcode.lines[6].id;
+    assert!(gcode.state_at(id).is_some());
+    gcode.lines.remove(0);
+    gcode.tag_g1();
+    assert_eq!(gcode.state_at(id).unwrap().next.pos.x, Length::from(14.0));
+}
+
+#[test]
+fn temperature_state_test() {
+    let gcode: GCodeModel = "M140 S60\nM104 S215\nM109 R200\nM104\nM190 S0"
+        .parse()
+        .unwrap();
+    let states = gcode
+        .cursor()
+        .map(|step| step.next.temps)
+        .collect::<Vec<_>>();
+    assert_eq!(states[0].bed, Some(60.0));
+    assert_eq!(states[1].hotend, Some(215.0));
+    assert_eq!(states[2].hotend, Some(200.0));
+    // a target without a temperature doesn't change it
+    assert_eq!(states[3].hotend, Some(200.0));
+    assert_eq!(states[4].bed, Some(0.0));
+}
+
+#[test]
+fn fan_state_test() {
+    let gcode: GCodeModel = "M106 S127\nM106\nM107".parse().unwrap();
+    let fans = gcode.cursor().map(|step| step.next.fan).collect::<Vec<_>>();
+    assert_eq!(fans, [127, 255, 0]);
+
+    // other fans don't change the part cooling fan
+    let gcode: GCodeModel = "M106 P0 S200\nM106 P1 S255\nM107 P1\nM106 S0.5\nM107 P0"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        gcode.lines[1].command,
+        Command::M106(M106 {
+            index: Some(1),
+            speed: Some(255)
+        })
+    );
+    assert_eq!(gcode.lines[2].command, Command::M107(Some(1)));
+    // a fraction of full speed, as some firmware takes it, stays raw and
+    // leaves the tracked speed alone
+    assert!(matches!(gcode.lines[3].command, Command::Raw(_)));
+    let fans = gcode.cursor().map(|step| step.next.fan).collect::<Vec<_>>();
+    assert_eq!(fans, [200, 200, 200, 200, 0]);
+
+    // cooling can be rewritten without touching the text
+    let mut gcode = gcode;
+    gcode.map_commands(
+        |line| {
+            matches!(
+                line.command,
+                Command::M106(M106 {
+                    index: None | Some(0),
+                    ..
+                })
+            )
+        },
+        |command| {
+            if let Command::M106(m106) = command {
+                m106.speed = m106.speed.map(|s| s / 2);
+            }
+        },
+    );
+    assert_eq!(
+        crate::emit::Emit::emit(&gcode, false),
+        "M106 P0 S100\nM106 P1 S255\nM107 P1\nM106 S0.5\nM107 P0\n"
+    );
+}
+
+#[test]
+fn spindle_coolant_state_test() {
+    let gcode: crate::GCodeModel = "M3 S1000\nM8\nM7\nM4\nM5\nM9".parse().unwrap();
+    let states = gcode.cursor().map(|step| step.next).collect::<Vec<_>>();
+    assert_eq!(states[0].spindle.direction, SpindleDirection::Clockwise);
+    assert_eq!(states[0].spindle.speed, 1000);
+    assert!(states[2].coolant.mist && states[2].coolant.flood);
+    // speed is modal across direction changes
+    assert_eq!(
+        states[3].spindle.direction,
+        SpindleDirection::CounterClockwise
+    );
+    assert_eq!(states[3].spindle.speed, 1000);
+    assert_eq!(states[4].spindle.direction, SpindleDirection::Off);
+    assert_eq!(states[5].coolant, Coolant::default());
+}
+
+#[test]
+fn work_offset_test() {
+    use crate::emit::Emit;
+    let gcode: crate::GCodeModel = "G1 X10 Y10\nG55\nG1 X10\nG53 G0 X0\nG1 Y5\nG54\nG1 X1"
+        .parse()
+        .unwrap();
+    let mut start = MachineState::default();
+    let offset = Position {
+        x: Length::from(100.0),
+        y: Length::from(50.0),
+        z: Length::ZERO,
+    };
+    start.set_work_offset(Wcs::G55, offset);
+    let states = Cursor::with_state(&gcode.lines, start)
+        .map(|step| step.next)
+        .collect::<Vec<_>>();
+    assert_eq!(states[0].pos.x, Length::from(10.0));
+    assert_eq!(states[2].wcs, Wcs::G55);
+    assert_eq!(states[2].pos.x, Length::from(110.0));
+    // unspecified axes don't move when the work offset changes
+    assert_eq!(states[2].pos.y, Length::from(10.0));
+    // G53 moves in machine coordinates without changing the active system
+    assert_eq!(states[3].pos.x, Length::ZERO);
+    assert_eq!(states[3].wcs, Wcs::G55);
+    assert_eq!(states[4].pos.y, Length::from(55.0));
+    assert_eq!(states[6].pos.x, Length::from(1.0));
+    assert_eq!(gcode.lines[1].emit(false), "G55");
+    assert_eq!(gcode.lines[5].emit(false), "G54");
+}
+
+#[test]
+fn g92_test() {
+    use crate::emit::Emit;
+    let gcode: crate::GCodeModel = "G1 X10 E5\nG92 E0\nG1 X20 E1\nG92 X0\nG1 X5\nG55\nG1 X0"
+        .parse()
+        .unwrap();
+    assert!(matches!(gcode.lines[1].command, Command::G92(_)));
+    assert_eq!(gcode.emit(false).lines().nth(3), Some("G92 X0"));
+    let states = gcode.cursor().map(|step| step.next).collect::<Vec<_>>();
+    assert_eq!(states[1].e, ExtrusionLength::ZERO);
+    assert_eq!(states[1].pos.x, Length::from(10.0));
+    assert_eq!(gcode.lines[2].command.tag(), Tag::Extrusion);
+    // X0 is declared at X20, so X5 moves to X25
+    assert_eq!(states[3].pos.x, Length::from(20.0));
+    assert_eq!(states[4].pos.x, Length::from(25.0));
+    // the shift carries over into other work coordinate systems
+    assert_eq!(states[6].pos.x, Length::from(20.0));
+    assert_eq!(gcode.filament_used(), ExtrusionLength::from_mm(6.0));
+}
+
+#[test]
+fn machine_state_test() {
+    let gcode: crate::GCodeModel = "G1 X10 Y10 E1 F600\nG91\nM83\nG1 X5 E0.5\nG90\nG1 Y0"
+        .parse()
+        .unwrap();
+    let states = Cursor::new(&gcode.lines)
+        .map(|step| step.next)
+        .collect::<Vec<_>>();
+    assert_eq!(states[0].pos.x, Length::from(10.0));
+    assert_eq!(states[0].feedrate, Feedrate::from_mm_per_min(600.0));
+    assert_eq!(states[3].pos.x, Length::from(15.0));
+    assert_eq!(states[3].e, ExtrusionLength::from_mm(1.5));
+    assert_eq!(states[3].e_positioning, Positioning::Relative);
+    assert_eq!(states[5].pos.x, Length::from(15.0));
+    assert_eq!(states[5].pos.y, Length::ZERO);
+    assert_eq!(states[5].positioning, Positioning::Absolute);
+}
+
+#[test]
+fn home_test() {
+    use crate::emit::Emit;
+    let gcode: GCodeModel = "G1 X10 Y10 Z5\nG92 X0\nG28 X W\nG1 Y20\nG28\nG28 X0 R10 o\nG28.1 X0"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        gcode.lines[2].command,
+        Command::Home(Home {
+            x: true,
+            skip_leveling: true,
+            ..Default::default()
+        })
+    );
+    // positions still flag their axis, and options are kept
+    assert_eq!(
+        gcode.lines[5].command,
+        Command::Home(Home {
+            x: true,
+            positions: [String::from("0"), String::new(), String::new()],
+            options: String::from("R10 o"),
+            ..Default::default()
+        })
+    );
+    assert_eq!(gcode.lines[5].emit(false), "G28 X0 R10 o ");
+    // G28.1 is a different command
+    assert_eq!(
+        gcode.lines[6].command,
+        Command::Raw(String::from("G28.1 X0"))
+    );
+    let states = gcode.cursor().map(|step| step.next).collect::<Vec<_>>();
+    // only X is homed, and loses its G92 shift
+    assert_eq!(states[2].pos.x, Length::ZERO);
+    assert_eq!(states[2].g92_offset.x, Length::ZERO);
+    assert_eq!(states[2].pos.y, Length::from(10.0));
+    assert_eq!(states[2].pos.z, Length::from(5.0));
+    assert_eq!(states[4].pos, Position::default());
+    assert_eq!(gcode.lines[2].emit(false), "G28 X W ");
+}
+
+#[test]
+fn classify_test() {
+    let prev = MachineState {
+        e_positioning: Positioning::Relative,
+        ..Default::default()
+    };
+    let cases = [
+        ("G1 X10 E1", Tag::Extrusion),
+        ("G1 E1", Tag::DeRetraction),
+        ("G1 E-1", Tag::Retraction),
+        ("G1 X10 E-1", Tag::Wipe),
+        ("G1 X10", Tag::Travel),
+        ("G1 Z1", Tag::RaiseZ),
+        ("G1 F600", Tag::Feedrate),
+        ("G5 I1 J0 P0 Q1 X5 Y5 E1", Tag::Extrusion),
+        ("G53 G1 X10", Tag::Travel),
+        ("G28", Tag::Uninitialized),
+    ];
+    for (line, tag) in cases {
+        let gcode: crate::GCodeModel = line.parse().unwrap();
+        assert_eq!(classify(&prev, &gcode.lines[0].command), tag, "{line}");
+    }
+    let mut lowered = prev;
+    lowered.pos.z = Length::from(1.0);
+    let gcode: crate::GCodeModel = "G1 Z0.2".parse().unwrap();
+    assert_eq!(classify(&lowered, &gcode.lines[0].command), Tag::LowerZ);
+    assert!(!is_travel(&lowered, &gcode.lines[0].command));
+    assert!(!is_extrusion(&lowered, &gcode.lines[0].command));
+}
+
+#[test]
+fn chunked_tagging_test() {
+    let input = "M83\nG1 X10 E1\nG1 E-1\nG1 Z1\nG1 X0\nG1 E1\nG1 X10 E1";
+    let tagged: crate::GCodeModel = input.parse().unwrap();
+    let mut gcode = tagged.clone();
+    for line in gcode.lines.iter_mut() {
+        if let Command::G1(g1) = &mut line.command {
+            g1.tag = Tag::Uninitialized;
+        }
+    }
+    let (first, second) = gcode.lines.split_at_mut(3);
+    // the state a chunk starts in is known without tagging what's before it
+    let mut middle = MachineState::default();
+    first.iter().for_each(|line| middle.apply(&line.command));
+    let end = tag_lines(second, middle);
+    tag_lines(first, MachineState::default());
+    assert_eq!(gcode, tagged);
+    assert_eq!(end, tagged.cursor().last().unwrap().next);
+    assert_eq!(gcode.tag_range(3..5, middle).pos.z, Length::from(1.0));
+}
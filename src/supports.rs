@@ -0,0 +1,156 @@
+use crate::{
+    estimate::move_length, roles::Role, Command, ExtrusionLength, GCodeLine, GCodeModel, Length,
+    Tag,
+};
+use std::ops::Range;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Totals for one stretch of support printing, see
+/// `GCodeModel::support_stats`
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SupportStats {
+    pub range: Range<usize>,
+    /// height of the first extrusion in the range
+    pub z: Length,
+    /// number of extrusion moves
+    pub moves: usize,
+    /// length extruded along in mm
+    pub distance: f64,
+    /// filament used, net of retractions
+    pub filament: ExtrusionLength,
+}
+
+/// Tool selected by a `T<n>` line
+fn tool_change(line: &GCodeLine) -> Option<u8> {
+    match line.command {
+        Command::ToolChange(tool) => Some(tool),
+        _ => None,
+    }
+}
+
+impl GCodeModel {
+    /// Line ranges that print support material, from `;TYPE:` feature
+    /// comments and, if `support_tool` is given, everything that tool
+    /// prints for files with soluble supports on their own extruder.
+    /// Overlapping ranges are merged.
+    pub fn support_ranges(&self, support_tool: Option<u8>) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        let (mut feature_start, mut tool_start) = (None, None);
+        for (i, line) in self.lines.iter().enumerate() {
+            if let Some(feature) = self.feature(line) {
+                if let Some(start) = feature_start.take() {
+                    ranges.push(start..i);
+                }
+                if Role::from_feature(feature) == Role::Support {
+                    feature_start = Some(i);
+                }
+            }
+            if let (Some(support_tool), Some(tool)) = (support_tool, tool_change(line)) {
+                if let Some(start) = tool_start.take() {
+                    ranges.push(start..i);
+                }
+                if tool == support_tool {
+                    tool_start = Some(i);
+                }
+            }
+        }
+        let end = self.lines.len();
+        ranges.extend(
+            [feature_start, tool_start]
+                .into_iter()
+                .flatten()
+                .map(|start| start..end),
+        );
+        ranges.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range<usize>> = Vec::new();
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+    /// Moves and filament for each range from `support_ranges`, to
+    /// estimate how much of a print is support waste
+    pub fn support_stats(&self, support_tool: Option<u8>) -> Vec<SupportStats> {
+        let ranges = self.support_ranges(support_tool);
+        if ranges.is_empty() {
+            return Vec::new();
+        }
+        let steps = self.cursor().collect::<Vec<_>>();
+        let deltas = self.extrusion_deltas();
+        ranges
+            .into_iter()
+            .map(|range| {
+                let mut stats = SupportStats {
+                    range: range.clone(),
+                    z: steps[range.start].next.pos.z,
+                    moves: 0,
+                    distance: 0.0,
+                    filament: ExtrusionLength::ZERO,
+                };
+                for (step, e) in steps[range.clone()].iter().zip(&deltas[range]) {
+                    stats.filament += *e;
+                    if step.line.command.tag() != Tag::Extrusion {
+                        continue;
+                    }
+                    if stats.moves == 0 {
+                        stats.z = step.next.pos.z;
+                    }
+                    stats.moves += 1;
+                    stats.distance += move_length(&step.prev, &step.next);
+                }
+                stats
+            })
+            .collect()
+    }
+    /// Remove the moves that print supports, for a "no support" variant of
+    /// a file, returning the number of lines removed. Tool changes and
+    /// other lines in those ranges are kept.
+    pub fn strip_supports(&mut self, support_tool: Option<u8>) -> usize {
+        let parameters = support_tool
+            .map(|tool| format!("T{tool}"))
+            .unwrap_or_default();
+        self.record_transform("strip_supports", parameters);
+        let removed = self
+            .support_ranges(support_tool)
+            .into_iter()
+            .rev()
+            .map(|range| self.strip_moves(range))
+            .sum();
+        if removed > 0 {
+            self.tag_g1();
+        }
+        removed
+    }
+}
+
+#[test]
+fn supports_test() {
+    use crate::emit::Emit;
+    let input = "G1 Z0.2\n;TYPE:Perimeter\nG1 X0 Y0\nG1 X10 E1 F600\n;TYPE:Support material\nG1 X20 Y20\nG1 X30 E2\nG1 Y30 E3\n;TYPE:Perimeter\nG1 X0 Y10\nG1 X10 E4";
+    let mut gcode: GCodeModel = input.parse().unwrap();
+    assert_eq!(gcode.support_ranges(None), vec![4..8]);
+    let stats = gcode.support_stats(None);
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].moves, 2);
+    assert_eq!(stats[0].distance, 20.0);
+    assert_eq!(stats[0].filament, ExtrusionLength::from_mm(2.0));
+    assert_eq!(stats[0].z, Length::from(0.2));
+
+    assert_eq!(gcode.strip_supports(None), 3);
+    assert_eq!(gcode.support_stats(None)[0].moves, 0);
+    // later absolute E values drop by the support's filament
+    assert!(gcode.emit(false).ends_with("G1 X10 E2 \n"));
+
+    // soluble supports printed by a second tool
+    let input = "G1 Z0.2\nT0\nG1 X10 E1 F600\nT1\nG1 X20 E2\nT0\nG1 X30 E3";
+    let gcode: GCodeModel = input.parse().unwrap();
+    assert!(gcode.support_ranges(None).is_empty());
+    assert_eq!(gcode.support_ranges(Some(1)), vec![3..5]);
+    assert_eq!(gcode.support_stats(Some(1))[0].distance, 10.0);
+}
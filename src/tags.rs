@@ -0,0 +1,178 @@
+use crate::{ExtrusionLength, GCodeModel, Tag};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+impl Tag {
+    /// Every tag, in the order reports list them
+    pub const ALL: [Tag; 9] = [
+        Tag::Extrusion,
+        Tag::Travel,
+        Tag::Retraction,
+        Tag::DeRetraction,
+        Tag::Wipe,
+        Tag::RaiseZ,
+        Tag::LowerZ,
+        Tag::Feedrate,
+        Tag::Uninitialized,
+    ];
+    /// Name used by `Display` and accepted by `FromStr`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tag::Retraction => "retraction",
+            Tag::DeRetraction => "de-retraction",
+            Tag::Travel => "travel",
+            Tag::RaiseZ => "raise-z",
+            Tag::LowerZ => "lower-z",
+            Tag::Wipe => "wipe",
+            Tag::Extrusion => "extrusion",
+            Tag::Feedrate => "feedrate",
+            Tag::Uninitialized => "uninitialized",
+        }
+    }
+}
+
+impl std::fmt::Display for Tag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+/// A string that doesn't name a tag
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseTagError(pub String);
+
+impl std::fmt::Display for ParseTagError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown tag: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseTagError {}
+
+impl std::str::FromStr for Tag {
+    type Err = ParseTagError;
+    /// Accepts the `Display` name or the variant name, ignoring case,
+    /// dashes, underscores and spaces
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalize = |s: &str| {
+            s.chars()
+                .filter(|c| !matches!(c, '-' | '_' | ' '))
+                .collect::<String>()
+                .to_lowercase()
+        };
+        let key = normalize(s);
+        Tag::ALL
+            .into_iter()
+            .find(|tag| normalize(tag.name()) == key)
+            .ok_or_else(|| ParseTagError(s.to_string()))
+    }
+}
+
+/// Totals over every line with one tag
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct TagTotals {
+    pub lines: usize,
+    /// XYZ distance moved in mm
+    pub distance: f64,
+    /// filament pushed, net of retractions
+    pub extrusion: ExtrusionLength,
+}
+
+/// Line counts, distance and filament per tag, see `GCodeModel::tag_summary`
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TagSummary(pub Vec<(Tag, TagTotals)>);
+
+impl TagSummary {
+    pub fn get(&self, tag: Tag) -> TagTotals {
+        self.0
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, totals)| *totals)
+            .unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for TagSummary {
+    /// One row per tag that appears, e.g.
+    /// `extrusion          120 lines     345.600 mm  E 12.345`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (tag, totals) in &self.0 {
+            writeln!(
+                f,
+                "{:<14} {:>8} lines {:>11.3} mm  E {:.3}",
+                tag,
+                totals.lines,
+                totals.distance,
+                totals.extrusion.to_mm()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl GCodeModel {
+    /// Totals per tag for every tag that appears, in the order of
+    /// `Tag::ALL`. Relies on the tags from `tag_g1`.
+    pub fn tag_summary(&self) -> TagSummary {
+        let mut totals = [TagTotals::default(); Tag::ALL.len()];
+        let deltas = self.extrusion_deltas();
+        for (step, e) in self.cursor().zip(deltas) {
+            let tag = step.line.command.tag();
+            let i = Tag::ALL
+                .iter()
+                .position(|t| *t == tag)
+                .expect("every tag is in Tag::ALL");
+            let (prev, next) = (step.prev.pos, step.next.pos);
+            let totals = &mut totals[i];
+            totals.lines += 1;
+            totals.distance += [next.x - prev.x, next.y - prev.y, next.z - prev.z]
+                .iter()
+                .map(|d| d.to_mm().powi(2))
+                .sum::<f64>()
+                .sqrt();
+            totals.extrusion += e;
+        }
+        TagSummary(
+            Tag::ALL
+                .into_iter()
+                .zip(totals)
+                .filter(|(_, totals)| totals.lines > 0)
+                .collect(),
+        )
+    }
+}
+
+#[test]
+fn tag_name_test() {
+    for tag in Tag::ALL {
+        assert_eq!(tag.to_string().parse::<Tag>(), Ok(tag));
+        assert_eq!(format!("{tag:?}").parse::<Tag>(), Ok(tag));
+    }
+    assert_eq!("RAISE_Z".parse::<Tag>(), Ok(Tag::RaiseZ));
+    assert!("jump".parse::<Tag>().is_err());
+}
+
+#[test]
+fn tag_summary_test() {
+    let gcode: GCodeModel = "G1 X10 E1 F600\nG1 E0.2\nG1 X20\nG92 E0\nG1 X30 E1"
+        .parse()
+        .unwrap();
+    let summary = gcode.tag_summary();
+    let extrusion = summary.get(Tag::Extrusion);
+    assert_eq!(extrusion.lines, 2);
+    assert_eq!(extrusion.distance, 20.0);
+    assert_eq!(extrusion.extrusion, ExtrusionLength::from_mm(2.0));
+    assert_eq!(
+        summary.get(Tag::Retraction).extrusion,
+        ExtrusionLength::from_mm(-0.8)
+    );
+    assert_eq!(summary.get(Tag::Travel).distance, 10.0);
+    assert_eq!(summary.get(Tag::Wipe), TagTotals::default());
+    assert_eq!(
+        summary.to_string().lines().next(),
+        Some("extrusion             2 lines      20.000 mm  E 2.000")
+    );
+}
@@ -54,3 +54,17 @@ fn test_counter() {
     assert_eq!(c.get(), Id(1));
     assert_eq!(c.get(), Id(2));
 }
+
+#[test]
+fn annotations_test() {
+    use crate::emit::Emit;
+    let mut gcode: GCodeModel = "G1 X1 Y2\nG1 X3 Y4 E1".parse().unwrap();
+    let emitted = gcode.emit(false);
+    gcode.lines[1].annotations.label("repaired");
+    gcode.lines[1].annotations.insert("object", "part_1");
+    assert!(gcode.lines[1].annotations.contains("repaired"));
+    assert_eq!(gcode.lines[1].annotations.get("object"), Some("part_1"));
+    assert!(gcode.lines[0].annotations.is_empty());
+    // annotations are metadata only and never change the output
+    assert_eq!(gcode.emit(false), emitted);
+}
@@ -0,0 +1,173 @@
+use crate::{
+    state::{MachineState, Position},
+    Annotations, Command, ExtrusionLength, Feedrate, GCodeLine, GCodeModel, Length, G1,
+};
+
+/// Frame taken at every layer change, see `GCodeModel::insert_timelapse`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Timelapse {
+    /// Lines that take the frame, inserted verbatim, e.g.
+    /// `TIMELAPSE_TAKE_FRAME` for Klipper or `M240` for Marlin
+    pub trigger: String,
+    /// X and Y to park at for the frame, or `None` to take it in place
+    pub park: Option<(Length, Length)>,
+    pub travel_feedrate: Feedrate,
+    /// Filament pulled back before parking and pushed back after
+    pub retract: ExtrusionLength,
+    pub retract_feedrate: Feedrate,
+    /// Layers at the start of the print that don't get a frame
+    pub skip_first: usize,
+    /// Layers at the end of the print that don't get a frame
+    pub skip_last: usize,
+}
+
+impl Default for Timelapse {
+    fn default() -> Self {
+        Timelapse {
+            trigger: String::from("TIMELAPSE_TAKE_FRAME"),
+            park: None,
+            travel_feedrate: Feedrate::from_mm_per_min(9000.0),
+            retract: ExtrusionLength::ZERO,
+            retract_feedrate: Feedrate::from_mm_per_min(2100.0),
+            skip_first: 0,
+            skip_last: 0,
+        }
+    }
+}
+
+fn push_move(state: &mut MachineState, commands: &mut Vec<Command>, g1: G1) {
+    let command = Command::G1(g1);
+    state.apply(&command);
+    commands.push(command);
+}
+
+impl GCodeModel {
+    /// Take a frame after each layer is finished, right before the move to
+    /// the next one, returning the number of frames added. The toolhead
+    /// returns to where it was, the filament is pushed back and the
+    /// feedrate is restored before printing carries on.
+    pub fn insert_timelapse(&mut self, timelapse: &Timelapse) -> usize {
+        self.record_transform("insert_timelapse", format!("{timelapse:?}"));
+        let layers = self.layers();
+        let states = self.cursor().map(|step| step.prev).collect::<Vec<_>>();
+        let frames = (0..layers.len().saturating_sub(1))
+            .filter(|&i| i >= timelapse.skip_first && i + 1 + timelapse.skip_last < layers.len())
+            .map(|i| layers[i + 1].start)
+            .collect::<Vec<_>>();
+        // last frame first, so the layer starts before it stay put
+        for &index in frames.iter().rev() {
+            let start = states[index];
+            let mut state = start;
+            let mut commands = Vec::new();
+            let retracted = start.e - timelapse.retract;
+            if timelapse.retract > ExtrusionLength::ZERO {
+                let g1 = G1 {
+                    f: Some(timelapse.retract_feedrate),
+                    ..state.move_to(start.pos, Some(retracted))
+                };
+                push_move(&mut state, &mut commands, g1);
+            }
+            if let Some((x, y)) = timelapse.park {
+                let park = Position { x, y, ..start.pos };
+                let g1 = G1 {
+                    f: Some(timelapse.travel_feedrate),
+                    ..state.move_to(park, None)
+                };
+                push_move(&mut state, &mut commands, g1);
+            }
+            commands.extend(
+                timelapse
+                    .trigger
+                    .lines()
+                    .map(|line| Command::Raw(line.to_string())),
+            );
+            if state.pos != start.pos {
+                let g1 = G1 {
+                    f: Some(timelapse.travel_feedrate),
+                    ..state.move_to(start.pos, None)
+                };
+                push_move(&mut state, &mut commands, g1);
+            }
+            if timelapse.retract > ExtrusionLength::ZERO {
+                let g1 = G1 {
+                    f: Some(timelapse.retract_feedrate),
+                    ..state.move_to(start.pos, Some(start.e))
+                };
+                push_move(&mut state, &mut commands, g1);
+            }
+            if state.feedrate != start.feedrate {
+                push_move(
+                    &mut state,
+                    &mut commands,
+                    G1 {
+                        f: Some(start.feedrate),
+                        ..Default::default()
+                    },
+                );
+            }
+            let lines = commands
+                .into_iter()
+                .map(|command| GCodeLine {
+                    id: self.id_counter.get(),
+                    command,
+                    comments: String::new(),
+                    annotations: Annotations::default(),
+                })
+                .collect::<Vec<_>>();
+            self.lines.splice(index..index, lines);
+        }
+        self.tag_g1();
+        frames.len()
+    }
+}
+
+#[test]
+fn timelapse_test() {
+    use crate::emit::Emit;
+    let input =
+        "G1 Z0.2 F1200\nG1 X10 E1\nG1 Z0.4\nG1 X0 E2\nG1 Z0.6\nG1 X10 E3\nG1 Z0.8\nG1 X0 E4";
+    let gcode: GCodeModel = input.parse().unwrap();
+    let mut plain = gcode.clone();
+    assert_eq!(plain.insert_timelapse(&Timelapse::default()), 3);
+    assert_eq!(
+        plain.lines[2].command,
+        Command::Raw(String::from("TIMELAPSE_TAKE_FRAME"))
+    );
+
+    let mut trimmed = gcode.clone();
+    let timelapse = Timelapse {
+        skip_last: 1,
+        ..Default::default()
+    };
+    assert_eq!(trimmed.insert_timelapse(&timelapse), 2);
+
+    let mut parked = gcode.clone();
+    let timelapse = Timelapse {
+        trigger: String::from("M240"),
+        park: Some((Length::from(200.0), Length::from(200.0))),
+        retract: ExtrusionLength::from_mm(0.8),
+        skip_first: 1,
+        skip_last: 1,
+        ..Default::default()
+    };
+    assert_eq!(parked.insert_timelapse(&timelapse), 1);
+    let frame = parked.lines[4..10]
+        .iter()
+        .map(|line| line.emit(false))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        frame,
+        [
+            "G1 E1.2 F2100 ",
+            "G1 X200 Y200 F9000 ",
+            "M240",
+            "G1 X0 Y0 F9000 ",
+            "G1 E2 F2100 ",
+            "G1 F1200 "
+        ]
+    );
+    // printing carries on the same way
+    let end = parked.cursor().last().unwrap().next;
+    let original = gcode.cursor().last().unwrap().next;
+    assert_eq!((end.pos, end.e), (original.pos, original.e));
+}
@@ -0,0 +1,120 @@
+use crate::Length;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Feedrate in mm/min as given by the F word of a move.
+///
+/// Kept distinct from `Length` so a speed can't be added to a
+/// coordinate or compared against an extrusion length by accident.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Feedrate(Length);
+
+impl Feedrate {
+    pub const ZERO: Feedrate = Feedrate(Length::ZERO);
+
+    /// Wrap a fixed-point value read from an F word
+    pub const fn new(mm_per_min: Length) -> Self {
+        Feedrate(mm_per_min)
+    }
+    pub fn from_mm_per_min(mm_per_min: f64) -> Self {
+        Feedrate(Length::from_mm(mm_per_min))
+    }
+    pub fn from_mm_per_sec(mm_per_sec: f64) -> Self {
+        Feedrate::from_mm_per_min(mm_per_sec * 60.0)
+    }
+    pub fn mm_per_min(&self) -> f64 {
+        self.0.to_mm()
+    }
+    pub fn mm_per_sec(&self) -> f64 {
+        self.0.to_mm() / 60.0
+    }
+    /// Underlying fixed-point value, in mm/min
+    pub fn as_microns(&self) -> Length {
+        self.0
+    }
+}
+
+impl std::fmt::Display for Feedrate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Length of filament pushed (or pulled, when negative) by the extruder in mm
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ExtrusionLength(Length);
+
+impl ExtrusionLength {
+    pub const ZERO: ExtrusionLength = ExtrusionLength(Length::ZERO);
+
+    /// Wrap a fixed-point value read from an E word
+    pub const fn new(length: Length) -> Self {
+        ExtrusionLength(length)
+    }
+    pub fn from_mm(mm: f64) -> Self {
+        ExtrusionLength(Length::from_mm(mm))
+    }
+    pub fn to_mm(&self) -> f64 {
+        self.0.to_mm()
+    }
+    /// Underlying fixed-point value, in mm of filament
+    pub fn as_microns(&self) -> Length {
+        self.0
+    }
+    pub fn abs(&self) -> Self {
+        ExtrusionLength(self.0.abs())
+    }
+}
+
+impl std::ops::Add for ExtrusionLength {
+    type Output = ExtrusionLength;
+    fn add(self, rhs: ExtrusionLength) -> ExtrusionLength {
+        ExtrusionLength(self.0 + rhs.0)
+    }
+}
+
+impl std::ops::Sub for ExtrusionLength {
+    type Output = ExtrusionLength;
+    fn sub(self, rhs: ExtrusionLength) -> ExtrusionLength {
+        ExtrusionLength(self.0 - rhs.0)
+    }
+}
+
+impl std::ops::Neg for ExtrusionLength {
+    type Output = ExtrusionLength;
+    fn neg(self) -> ExtrusionLength {
+        ExtrusionLength(-self.0)
+    }
+}
+
+impl std::ops::AddAssign for ExtrusionLength {
+    fn add_assign(&mut self, rhs: ExtrusionLength) {
+        self.0 += rhs.0;
+    }
+}
+
+impl std::iter::Sum for ExtrusionLength {
+    fn sum<I: Iterator<Item = ExtrusionLength>>(iter: I) -> ExtrusionLength {
+        iter.fold(ExtrusionLength::ZERO, |acc, e| acc + e)
+    }
+}
+
+impl std::fmt::Display for ExtrusionLength {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[test]
+fn unit_conversion_test() {
+    let f = Feedrate::from_mm_per_min(3000.0);
+    assert_eq!(f.mm_per_sec(), 50.0);
+    assert_eq!(Feedrate::from_mm_per_sec(50.0), f);
+    let e = ExtrusionLength::from_mm(0.5) + ExtrusionLength::from_mm(-0.75);
+    assert_eq!(e.to_mm(), -0.25);
+    assert_eq!(e.abs(), ExtrusionLength::from_mm(0.25));
+    assert_eq!(e.as_microns(), Length::from(-0.25));
+}
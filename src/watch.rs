@@ -0,0 +1,113 @@
+use crate::batch::{analyze_file, AnalysisConfig, Error, Stats};
+use notify::{EventKind, RecursiveMode, Watcher as _};
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    time::Duration,
+};
+
+/// Change to a file under a watched directory
+#[derive(Debug)]
+pub enum WatchEvent {
+    /// a file was created or changed and has been analyzed again
+    Analyzed {
+        path: PathBuf,
+        result: Result<Stats, Error>,
+    },
+    Removed(PathBuf),
+    /// the platform watcher reported a problem, events may have been missed
+    Failed(notify::Error),
+}
+
+/// Watches `.gcode` files under a path, re-parsing and re-analyzing each
+/// one on the watcher's thread as it changes. Dropping it stops watching.
+pub struct Watcher {
+    _watcher: notify::RecommendedWatcher,
+    events: Receiver<WatchEvent>,
+}
+
+fn is_gcode(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gcode"))
+}
+
+impl Watcher {
+    /// Start watching `path`, and everything below it if it is a directory
+    pub fn new(path: &Path, config: AnalysisConfig) -> Result<Self, notify::Error> {
+        let (tx, events) = mpsc::channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(e) => {
+                        let _ = tx.send(WatchEvent::Failed(e));
+                        return;
+                    }
+                };
+                if !matches!(
+                    event.kind,
+                    EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+                ) {
+                    return;
+                }
+                for path in event.paths.into_iter().filter(|path| is_gcode(path)) {
+                    // renames and removals both show up as paths that are gone
+                    let event = if path.is_file() {
+                        let result = analyze_file(&path, &config);
+                        WatchEvent::Analyzed { path, result }
+                    } else {
+                        WatchEvent::Removed(path)
+                    };
+                    if tx.send(event).is_err() {
+                        return;
+                    }
+                }
+            })?;
+        watcher.watch(path, RecursiveMode::Recursive)?;
+        Ok(Watcher {
+            _watcher: watcher,
+            events,
+        })
+    }
+    /// Next event, waiting for one
+    pub fn recv(&self) -> Option<WatchEvent> {
+        self.events.recv().ok()
+    }
+    /// Next event, waiting at most `timeout`
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<WatchEvent> {
+        self.events.recv_timeout(timeout).ok()
+    }
+    /// Next event if one is ready
+    pub fn try_recv(&self) -> Option<WatchEvent> {
+        self.events.try_recv().ok()
+    }
+}
+
+#[test]
+fn watcher_test() {
+    let dir = std::env::temp_dir().join(format!("g-win-watch-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let watcher = Watcher::new(&dir, AnalysisConfig::default()).unwrap();
+    let path = dir.join("part.gcode");
+    std::fs::write(dir.join("notes.txt"), "not gcode").unwrap();
+    std::fs::write(&path, "G1 X10 E1 F600").unwrap();
+    let mut analyzed = None;
+    while let Some(event) = watcher.recv_timeout(Duration::from_secs(5)) {
+        // the file may be seen half written first
+        if let WatchEvent::Analyzed {
+            path,
+            result: Ok(stats),
+        } = event
+        {
+            if stats.lines == 1 {
+                analyzed = Some((path, stats));
+                break;
+            }
+        }
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+    let (changed, stats) = analyzed.expect("no event for the new file");
+    assert_eq!(changed.file_name(), path.file_name());
+    assert_eq!(stats.filament, 1.0);
+}